@@ -0,0 +1,104 @@
+// git bundle (v2) のファイル
+// `# v2 git bundle` の行, 前提となる commit (`-<hash>`) と ref (`<hash> <refname>`) の行,
+// 空行の後に pack が続く
+const SIGNATURE: &str = "# v2 git bundle\n";
+
+#[derive(Debug, PartialEq)]
+pub struct Bundle {
+    // 受け取る側に既にあるはずの commit
+    pub prerequisites: Vec<String>,
+    // (hash, refname)
+    pub refs: Vec<(String, String)>,
+    pub pack: Vec<u8>,
+}
+
+impl Bundle {
+    pub fn new(refs: Vec<(String, String)>, pack: Vec<u8>) -> Self {
+        Self {
+            prerequisites: Vec::new(),
+            refs,
+            pack,
+        }
+    }
+
+    pub fn from(bytes: &[u8]) -> Option<Self> {
+        let mut rest = bytes.strip_prefix(SIGNATURE.as_bytes())?;
+
+        let mut prerequisites = Vec::new();
+        let mut refs = Vec::new();
+        loop {
+            let pos = rest.iter().position(|&x| x == b'\n')?;
+            let line = std::str::from_utf8(&rest[..pos]).ok()?;
+            rest = &rest[(pos + 1)..];
+            if line.is_empty() {
+                break;
+            }
+
+            match line.strip_prefix('-') {
+                // `-<hash> <comment>`
+                Some(x) => prerequisites.push(x.split(' ').next()?.to_string()),
+                None => {
+                    let (hash, refname) = line.split_once(' ')?;
+                    refs.push((hash.to_string(), refname.to_string()));
+                }
+            }
+        }
+
+        Some(Self {
+            prerequisites,
+            refs,
+            pack: rest.to_vec(),
+        })
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut header = SIGNATURE.to_string();
+        for hash in self.prerequisites.iter() {
+            header.push_str(&format!("-{}\n", hash));
+        }
+        for (hash, refname) in self.refs.iter() {
+            header.push_str(&format!("{} {}\n", hash, refname));
+        }
+        header.push('\n');
+
+        [header.as_bytes(), &self.pack].concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundle_round_trip() {
+        let hash = "3edbc45b9a7f744c2345cd2cd073c3de091341ac".to_string();
+        let bundle = Bundle::new(
+            vec![
+                (hash.clone(), "HEAD".to_string()),
+                (hash.clone(), "refs/heads/master".to_string()),
+            ],
+            b"PACK\0\n\n".to_vec(),
+        );
+        let bytes = bundle.as_bytes();
+        assert!(bytes.starts_with(
+            format!(
+                "# v2 git bundle\n{} HEAD\n{} refs/heads/master\n\nPACK",
+                hash, hash
+            )
+            .as_bytes()
+        ));
+        assert_eq!(Bundle::from(&bytes).unwrap(), bundle);
+
+        let bytes = format!(
+            "# v2 git bundle\n-{} base commit\n{} refs/heads/x\n\n",
+            hash, hash
+        );
+        let bundle = Bundle::from(bytes.as_bytes()).unwrap();
+        assert_eq!(bundle.prerequisites, vec![hash]);
+        assert!(bundle.pack.is_empty());
+
+        assert!(Bundle::from(b"# v3 git bundle\n\n").is_none());
+        // header の終わりの空行が無い
+        assert!(Bundle::from(b"# v2 git bundle\n").is_none());
+    }
+}
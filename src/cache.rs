@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+// 最後に使ってから最も時間の経ったものを捨てる cache
+// 値ごとに大きさを持たせ, 大きさの合計が capacity を超えないように捨てる
+// 数百程度の数で使うので, 捨てる時は全体から一番古いものを探す
+pub struct LruCache<K, V> {
+    capacity: usize,
+    // 値と大きさと最後に使った時の番号
+    entries: HashMap<K, (V, usize, u64)>,
+    // entries の大きさの合計
+    size: usize,
+    tick: u64,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> LruCache<K, V> {
+    // capacity が 0 なら何も覚えない
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            size: 0,
+            tick: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // 小さくした時は古いものから捨てる
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.size > capacity {
+            self.evict();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // 覚えている値の大きさの合計
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        self.tick += 1;
+        let tick = self.tick;
+        self.entries.get_mut(key).map(|(value, _, used)| {
+            *used = tick;
+            value.clone()
+        })
+    }
+
+    // capacity より大きな値は覚えない
+    pub fn insert(&mut self, key: K, value: V, size: usize) {
+        self.remove(&key);
+        if self.capacity == 0 || size > self.capacity {
+            return;
+        }
+        while self.size + size > self.capacity {
+            self.evict();
+        }
+        self.tick += 1;
+        self.size += size;
+        self.entries.insert(key, (value, size, self.tick));
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        if let Some((_, size, _)) = self.entries.remove(key) {
+            self.size -= size;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.size = 0;
+    }
+
+    fn evict(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, (_, _, used))| *used)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            self.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lru_cache() {
+        let mut cache = LruCache::new(2);
+        cache.insert("a", 1, 1);
+        cache.insert("b", 2, 1);
+        // a を使ったので次に捨てるのは b
+        assert_eq!(cache.get(&"a"), Some(1));
+        cache.insert("c", 3, 1);
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(cache.get(&"c"), Some(3));
+
+        // 同じ key の上書きでは捨てない
+        cache.insert("c", 4, 1);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&"c"), Some(4));
+
+        cache.set_capacity(1);
+        assert_eq!(cache.get(&"a"), None);
+        assert_eq!(cache.get(&"c"), Some(4));
+
+        let mut cache = LruCache::new(0);
+        cache.insert("a", 1, 0);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn lru_cache_size() {
+        let mut cache = LruCache::new(10);
+        cache.insert("a", 1, 4);
+        cache.insert("b", 2, 4);
+        assert_eq!(cache.size(), 8);
+
+        // 合計が capacity を超えるので古い a を捨てる
+        cache.insert("c", 3, 4);
+        assert_eq!(cache.get(&"a"), None);
+        assert_eq!(cache.size(), 8);
+
+        // 大きな値の前には古いものを全部捨てる
+        cache.insert("d", 4, 10);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get(&"d"), Some(4));
+
+        // capacity より大きな値は覚えず, 同じ key の古い値も残さない
+        cache.insert("d", 5, 11);
+        assert!(cache.is_empty());
+        assert_eq!(cache.size(), 0);
+
+        // 上書きすると大きさも置き換わる
+        cache.insert("e", 6, 3);
+        cache.insert("e", 7, 5);
+        assert_eq!(cache.size(), 5);
+
+        cache.clear();
+        assert_eq!(cache.size(), 0);
+    }
+}
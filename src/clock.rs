@@ -0,0 +1,67 @@
+use chrono::{DateTime, FixedOffset, Local};
+
+// commit などに記録する今の時刻. テストでは固定の時刻を返すものに差し替える
+pub trait Clock {
+    fn now(&self) -> DateTime<FixedOffset>;
+}
+
+// OS の時計とローカルのタイムゾーン
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<FixedOffset> {
+        let now = Local::now();
+        now.with_timezone(now.offset())
+    }
+}
+
+// いつ呼んでも同じ時刻を返す. commit の hash を固定したいテストで使う
+pub struct FixedClock(pub DateTime<FixedOffset>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<FixedOffset> {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::object::GitObject;
+    use crate::{ident, GitBuilder};
+    use chrono::TimeZone;
+
+    #[test]
+    fn fixed_clock_commit() {
+        let ts = FixedOffset::west(5 * 60 * 60).timestamp(1609642799, 0);
+        let git = GitBuilder::new(InMemFileSystem::init())
+            .user("rgit", "rgit@example.com")
+            .clock(FixedClock(ts))
+            .build();
+        let tree = "4b825dc642cb6eb9a060e54bf8d69288fbee4904".parse().unwrap();
+        let commit = || {
+            let (author, committer) = ident::idents(&git).unwrap();
+            let commit = git
+                .commit_tree(author, Some(committer), &tree, "message".to_string())
+                .unwrap();
+            GitObject::Commit(commit)
+        };
+
+        let first = commit();
+        let second = commit();
+        assert_eq!(first.calc_hash(), second.calc_hash());
+        assert!(String::from_utf8(first.as_bytes())
+            .unwrap()
+            .contains("rgit <rgit@example.com> 1609642799 -0500"));
+
+        // 時計を進めれば別の commit になる
+        let mut git = git;
+        git.clock = Box::new(FixedClock(ts + chrono::Duration::seconds(1)));
+        let (author, committer) = ident::idents(&git).unwrap();
+        let third = git
+            .commit_tree(author, Some(committer), &tree, "message".to_string())
+            .unwrap();
+        assert_ne!(GitObject::Commit(third).calc_hash(), first.calc_hash());
+    }
+}
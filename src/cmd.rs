@@ -1,12 +1,35 @@
+use chrono::{TimeZone, Utc};
 use libflate::zlib::Decoder;
 
-use crate::Git;
-use crate::{fs::FileSystem, object::blob::Blob, object::GitObject};
+use crate::bundle::Bundle;
+use crate::fast_import::{self, Change, Command, CommitCommand};
+use crate::hash::{short_hash, ObjectId, DEFAULT_ABBREV};
+use crate::ident;
+use crate::ignore::IgnoreMatcher;
+use crate::index::{Entry, Index};
+use crate::notes;
+use crate::object::commit::{Commit, User};
+use crate::object::tree::{self, FileMode, MODE_EXECUTABLE, MODE_FILE, MODE_GITLINK, MODE_SYMLINK};
+use crate::pack::{self, PackWriter};
+use crate::patch::{self, FilePatch, Mail};
+use crate::pathspec::Matcher;
+use crate::protocol::fetch::{self, Transport};
+use crate::protocol::pktline;
+use crate::protocol::push::{self, PushTransport};
+use crate::protocol::receive_pack::{self, Command as RefCommand};
+use crate::protocol::upload_pack::{self, Have};
+use crate::reachable;
+use crate::reflog::ReflogEntry;
+use crate::remote::Remote;
+use crate::revwalk::{CommitWalker, RevWalk};
+use crate::{fs::FileSystem, object::blob::Blob, object::GitObject, object::ObjectType};
+use crate::{Git, ObjectSizes};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fs::File;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 
 pub fn cat_file_p(hash: String) -> io::Result<GitObject> {
-    let (sub_dir, file) = hash.split_at(2);
+    let (sub_dir, file) = hash.parse::<ObjectId>()?.split_at();
     let path = format!(".git/objects/{}/{}", sub_dir, file);
 
     let mut file = File::open(path)?;
@@ -20,105 +43,5790 @@ pub fn cat_file_p(hash: String) -> io::Result<GitObject> {
     GitObject::new(&buf).ok_or(io::Error::from(io::ErrorKind::InvalidData))
 }
 
+// git cat-file -t
+// header だけを展開して object の型を返す
+pub fn cat_file_t<F: FileSystem>(git: &Git<F>, hash: String) -> io::Result<ObjectType> {
+    let bytes = git.read_object(&hash.parse()?)?;
+    // "<type> <size>\0" は短いので先頭だけ展開すれば足りる
+    let mut head = Vec::new();
+    Decoder::new(&bytes[..])?.take(32).read_to_end(&mut head)?;
+    GitObject::peek_type(&head).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} has a broken header", hash),
+        )
+    })
+}
+
+// git cat-file -e の型の確認
+// object が expected の型かを返す
+pub fn cat_file_check<F: FileSystem>(
+    git: &Git<F>,
+    hash: String,
+    expected: ObjectType,
+) -> io::Result<bool> {
+    Ok(cat_file_t(git, hash)? == expected)
+}
+
 pub fn hash_object(path: String) -> io::Result<Blob> {
     let mut file = File::open(path)?;
     let mut buf = Vec::new();
     file.read_to_end(&mut buf)?;
 
-    Blob::from(&buf).ok_or(io::Error::from(io::ErrorKind::InvalidData))
+    Ok(Blob::new(buf))
 }
 
 pub fn add<F: FileSystem>(git: &mut Git<F>, filename: String, bytes: &[u8]) -> io::Result<()> {
+    stage(git, vec![(filename, bytes.to_vec())], &[])
+}
+
+// git add <pathspec>
+// ディレクトリなら中のファイルを全て追加し, `*.rs` のようなパターンは worktree のファイルに当てはめる
+// 追加したファイルの名前を返す
+pub fn add_path<F: FileSystem>(git: &mut Git<F>, path: String) -> io::Result<Vec<String>> {
+    let matcher = Matcher::new(&path);
+    let files = if matcher.is_literal() {
+        match git.filesystem.is_dir(path.clone()) {
+            Ok(true) => untracked_ignored(git, git.worktree_files(&path)?)?,
+            Ok(false) => vec![path],
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(unmatched(&path)),
+            Err(e) => return Err(e),
+        }
+    } else {
+        let files = git
+            .worktree_files("")?
+            .into_iter()
+            .filter(|x| matcher.matches(x))
+            .collect::<Vec<_>>();
+        let files = untracked_ignored(git, files)?;
+        if files.is_empty() {
+            return Err(unmatched(&path));
+        }
+        files
+    };
+
+    // 1つのファイルなら index の entry を1つだけ置き換える
+    if let [file] = files.as_slice() {
+        git.stage_file(file.clone())?;
+        return Ok(files);
+    }
+
+    // 大きなファイルは先に少しずつ書き込み, 残りをまとめて memory に読む
+    let mut entries = Vec::new();
+    let mut contents = Vec::new();
+    for file in files.iter() {
+        if git.stream_size(file.clone())?.is_some() {
+            let hash = git.write_worktree_blob(file.clone())?;
+            entries.push(git.index_entry(&hash, file.clone())?);
+        } else {
+            contents.push((file.clone(), git.read_worktree_file(file.clone())?));
+        }
+    }
+    if !files.is_empty() {
+        entries.extend(write_blobs(git, contents)?);
+        update_entries(git, entries, &[])?;
+    }
+
+    Ok(files)
+}
+
+fn unmatched(pathspec: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("pathspec '{}' did not match any files", pathspec),
+    )
+}
+
+// 無視されているファイルのうち, まだ index に無いものを取り除く
+// 既に追加されているファイルは無視されていても変更を追う
+fn untracked_ignored<F: FileSystem>(git: &Git<F>, files: Vec<String>) -> io::Result<Vec<String>> {
+    let index = git.load_index()?;
+    let ignore = git.ignore()?;
+    Ok(files
+        .into_iter()
+        .filter(|x| index.get_entry(x).is_some() || !ignore.is_ignored(x, false))
+        .collect())
+}
+
+// git add -A
+// 新しいファイルと変更されたファイルを全て追加し, worktree から消えたファイルを index から取り除く
+pub fn add_all<F: FileSystem>(git: &mut Git<F>) -> io::Result<()> {
+    let index = git.load_index()?;
+    let files = untracked_ignored(git, git.worktree_files("")?)?;
+
+    let tracked = index
+        .entries
+        .iter()
+        .map(|x| (x.name.as_str(), &x.hash))
+        .collect::<HashMap<_, _>>();
+    // 大きなファイルは memory に読まずに hash を比べ, 変わっていれば少しずつ書き込む
+    let mut entries = Vec::new();
+    let mut changed = Vec::new();
+    for file in files.iter() {
+        if let Some(size) = git.stream_size(file.clone())? {
+            let reader = git.filesystem.open(file.clone())?;
+            if tracked.get(file.as_str()) != Some(&&git.hash_object_stream(reader, size)?) {
+                let hash = git.write_worktree_blob(file.clone())?;
+                entries.push(git.index_entry(&hash, file.clone())?);
+            }
+            continue;
+        }
+        let bytes = git.read_worktree_file(file.clone())?;
+        let blob = git.hash_object(&bytes).map(GitObject::Blob)?;
+        if tracked.get(file.as_str()) != Some(&&blob.calc_hash_with(git.hash_algo)) {
+            changed.push((file.clone(), bytes));
+        }
+    }
+    let removed = index
+        .entries
+        .iter()
+        .filter(|x| files.binary_search(&x.name).is_err())
+        .map(|x| x.name.clone())
+        .collect::<Vec<_>>();
+
+    entries.extend(write_blobs(git, changed)?);
+    update_entries(git, entries, &removed)
+}
+
+// blob を書き込んでから index の lock を取って読み, entry を置き換えて一度だけ書き出す
+fn stage<F: FileSystem>(
+    git: &mut Git<F>,
+    files: Vec<(String, Vec<u8>)>,
+    removed: &[String],
+) -> io::Result<()> {
+    let entries = write_blobs(git, files)?;
+    update_entries(git, entries, removed)
+}
+
+// index の lock を取って読み, entries を置き換えて removed を取り除く
+fn update_entries<F: FileSystem>(
+    git: &mut Git<F>,
+    entries: Vec<Entry>,
+    removed: &[String],
+) -> io::Result<()> {
+    git.modify_index(|_, index| {
+        for entry in entries {
+            index.upsert(entry);
+        }
+        for name in removed {
+            index.remove_entry(name);
+        }
+        Ok(())
+    })
+}
+
+// blob を書き込み, index に入れる entry を作る. index はまだ読まない
+fn write_blobs<F: FileSystem>(
+    git: &mut Git<F>,
+    files: Vec<(String, Vec<u8>)>,
+) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    // stream_threshold を超えるものは header を付けた複製を作らずに少しずつ書き込む
+    let (large, files) = files
+        .into_iter()
+        .partition::<Vec<_>, _>(|(_, bytes)| bytes.len() as u64 > git.stream_threshold);
+    for (filename, bytes) in large {
+        let hash = git.write_object_stream(bytes.as_slice(), bytes.len() as u64)?;
+        entries.push(git.index_entry(&hash, filename)?);
+    }
+
     // git hash-object -w path
-    let blob = git.hash_object(&bytes).map(GitObject::Blob)?;
-    git.write_object(&blob)?;
+    let blobs = files
+        .iter()
+        .map(|(_, bytes)| git.hash_object(bytes).map(GitObject::Blob))
+        .collect::<io::Result<Vec<_>>>()?;
+    git.write_objects(&blobs)?;
 
-    // git update-index --add --cacheinfo <mode> <hash> <name>
-    let index = git.read_index().and_then(|x| git.ls_files_stage(&x))?;
-    let index = git.update_index(&blob.calc_hash(), filename)?;
-    git.write_index(&index)?;
+    // git update-index --add --remove --cacheinfo <mode> <hash> <name>
+    for ((filename, _), blob) in files.into_iter().zip(blobs.iter()) {
+        entries.push(git.index_entry(&blob.calc_hash_with(git.hash_algo), filename)?);
+    }
 
-    Ok(())
+    Ok(entries)
 }
 
-pub fn commit<F: FileSystem>(git: &mut Git<F>, message: String) -> io::Result<()> {
-    let tree = git.write_tree().map(GitObject::Tree)?;
-    git.write_object(&tree)?;
+// git update-index [--add] [--remove] <file>...
+// worktree のファイルの内容で index を更新する. 新しいファイルは --add, 消えたファイルは --remove が必要
+pub fn update_index<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<()> {
+    let add = args.iter().any(|x| x == "--add");
+    let remove = args.iter().any(|x| x == "--remove");
 
-    let tree_hash = tree.calc_hash();
+    let mut files = Vec::new();
+    let mut removed = Vec::new();
+    for name in args.iter().filter(|x| !x.starts_with("--")) {
+        if !git.filesystem.exists(name.clone()) {
+            if !remove {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{}: does not exist and --remove not passed", name),
+                ));
+            }
+            removed.push(name.clone());
+        } else {
+            files.push((name.clone(), git.read_worktree_file(name.clone())?));
+        }
+    }
+
+    // 新しいファイルかどうかは lock を取ってから読んだ index で決める
+    let entries = write_blobs(git, files)?;
+    git.modify_index(|_, index| {
+        if let Some(entry) = entries
+            .iter()
+            .find(|x| !add && index.get_entry(&x.name).is_none())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{}: cannot add to the index - missing --add option?",
+                    entry.name
+                ),
+            ));
+        }
+        for entry in entries {
+            index.upsert(entry);
+        }
+        for name in removed.iter() {
+            index.remove_entry(name);
+        }
+        Ok(())
+    })
+}
+
+// git ls-files --others [--exclude-standard]
+// index に無いファイルを返す. exclude_standard なら無視されているファイルも除く
+pub fn ls_files_others<F: FileSystem>(
+    git: &Git<F>,
+    exclude_standard: bool,
+) -> io::Result<Vec<String>> {
+    let index = git.load_index()?;
+    let ignore = if exclude_standard {
+        git.ignore()?
+    } else {
+        IgnoreMatcher::new()
+    };
+    Ok(git
+        .worktree_files("")?
+        .into_iter()
+        .filter(|x| index.get_entry(x).is_none() && !ignore.is_ignored(x, false))
+        .collect())
+}
+
+// git check-ignore [-v] <path>...
+// 無視されるパスを返す. -v なら `<ファイル>:<行>:<パターン>\t<パス>` の形で決め手になったパターンも示す
+pub fn check_ignore<F: FileSystem>(git: &Git<F>, args: &[String]) -> io::Result<Vec<String>> {
+    let verbose = args.iter().any(|x| x == "-v" || x == "--verbose");
+    let ignore = git.ignore()?;
+
+    let mut lines = Vec::new();
+    for path in args.iter().filter(|x| !x.starts_with('-')) {
+        // 末尾の `/` か, worktree にあるディレクトリならディレクトリとして調べる
+        let is_dir = path.ends_with('/') || git.filesystem.is_dir(path.clone()).unwrap_or(false);
+        match ignore.find(path, is_dir) {
+            Some(found) if !found.pattern.negated => lines.push(if verbose {
+                format!(
+                    "{}:{}:{}\t{}",
+                    found.source, found.pattern.line, found.pattern.text, path
+                )
+            } else {
+                path.clone()
+            }),
+            _ => {}
+        }
+    }
+
+    Ok(lines)
+}
+
+// git clean [-n]
+// index に無く, 無視もされていないファイルを消す. dry_run なら消さずに一覧だけ返す
+pub fn clean<F: FileSystem>(git: &mut Git<F>, dry_run: bool) -> io::Result<Vec<String>> {
+    let untracked = ls_files_others(git, true)?;
+
+    let mut lines = Vec::new();
+    for path in untracked {
+        if dry_run {
+            lines.push(format!("Would remove {}", path));
+        } else {
+            git.filesystem.remove(path.clone())?;
+            lines.push(format!("Removing {}", path));
+        }
+    }
+
+    Ok(lines)
+}
+
+// allow_empty でなければ, 親と同じ tree や空の最初の commit は作らない
+pub fn commit<F: FileSystem>(
+    git: &mut Git<F>,
+    message: String,
+    allow_empty: bool,
+) -> io::Result<()> {
+    commit_as(git, None, message, allow_empty)
+}
+
+// author が None なら committer と同じく設定から決める
+fn commit_as<F: FileSystem>(
+    git: &mut Git<F>,
+    author: Option<User>,
+    message: String,
+    allow_empty: bool,
+) -> io::Result<()> {
+    let trees = git.write_tree()?;
+    // 一番上の tree は必ず最後にある
+    let (tree, root) = trees.last().unwrap();
+    let tree = tree.clone();
+    if !allow_empty {
+        let empty = match git.head_tree()? {
+            Some(parent) => parent == tree,
+            None => root.contents.is_empty(),
+        };
+        if empty {
+            return Err(io::Error::other("nothing to commit"));
+        }
+    }
+
+    let trees = trees
+        .into_iter()
+        .map(|(_, tree)| GitObject::Tree(tree))
+        .collect::<Vec<_>>();
+    git.write_objects(&trees)?;
+
+    let (default_author, committer) = ident::idents(git)?;
+    let author = author.unwrap_or(default_author);
     let commit = git
-        .commit_tree(
-            "yusei-wy".to_string(), // gitconfig からの読み取りが大変なので固定値
-            "yusei.kasa@gmail.com".to_string(),
-            hex::encode(tree_hash),
-            message,
-        )
+        .commit_tree(author, Some(committer), &tree, message)
         .map(GitObject::Commit)?;
     git.write_object(&commit)?;
 
-    git.update_ref(git.head_ref()?, &commit.calc_hash())?;
+    git.update_ref(git.head_ref()?, &commit.calc_hash_with(git.hash_algo))?;
 
     Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Write;
+// git commit [-a] [--allow-empty] [-m <message>]... [-F <file>]
+// 複数の -m は空行で区切って繋げる. `-F -` なら stdin から読む
+pub fn commit_args<F: FileSystem, R: Read>(
+    git: &mut Git<F>,
+    args: &[String],
+    mut stdin: R,
+) -> io::Result<()> {
+    let mut all = false;
+    let mut allow_empty = false;
+    let mut paragraphs = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || {
+            iter.next().cloned().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("option '{}' requires a value", arg),
+                )
+            })
+        };
+        match arg.as_str() {
+            "-a" | "--all" => all = true,
+            "--allow-empty" => allow_empty = true,
+            "-m" | "--message" => paragraphs.push(value()?),
+            "-am" => {
+                all = true;
+                paragraphs.push(value()?);
+            }
+            "-F" | "--file" => {
+                let path = value()?;
+                let bytes = if path == "-" {
+                    let mut buf = Vec::new();
+                    stdin.read_to_end(&mut buf)?;
+                    buf
+                } else {
+                    git.filesystem.read(path)?
+                };
+                paragraphs.push(String::from_utf8_lossy(&bytes).into_owned());
+            }
+            x if x.starts_with('-') => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown option '{}'", x),
+                ))
+            }
+            // 以前の `rgit commit <message>` の形
+            x => paragraphs.push(x.to_string()),
+        }
+    }
 
-    #[test]
-    #[should_panic(expected = "byte index 2 is out of bounds of ``")]
-    fn cmd_cat_file_p_panic() {
-        assert!(cat_file_p(String::from("")).is_err());
+    if paragraphs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no commit message given",
+        ));
     }
+    // 末尾の改行は Commit::new で1つに揃える
+    let message = paragraphs
+        .iter()
+        .map(|x| x.trim_end_matches('\n'))
+        .collect::<Vec<_>>()
+        .join("\n\n");
 
-    // #[test]
-    // fn cmd_cat_file_p() {
-    //     // file not found
-    //     assert!(cat_file_p(String::from("hoge123...;;;")).is_err());
+    if all {
+        commit_all(git, message, allow_empty)
+    } else {
+        commit(git, message, allow_empty)
+    }
+}
 
-    //     // first commit
-    //     let r = cat_file_p(String::from("01a0c85dd05755281466d29983dfcb15889e1a64"));
-    //     assert!(r.is_ok());
-    //     let r = r.ok().unwrap();
-    //     let expected = "tree 179\u{0}tree 38b38f11af50240a2ddf643619e065408211e9e9\nauthor yusei-wy <yusei.kasa@gmail.com> 1609642799 +0900\ncomitter yusei-wy <yusei.kasa@gmail.com> 1609642799 +0900\n\nadd: blob object\n";
-    //     assert_eq!(r.to_string(), expected);
-    // }
+// git commit -a
+// tracked なファイルの変更と削除を index に反映してから commit する. untracked なファイルは追加しない
+pub fn commit_all<F: FileSystem>(
+    git: &mut Git<F>,
+    message: String,
+    allow_empty: bool,
+) -> io::Result<()> {
+    let index = git.load_index()?;
+    let modified = git
+        .modified_files(&index)?
+        .into_iter()
+        .map(|x| git.read_worktree_file(x.clone()).map(|bytes| (x, bytes)))
+        .collect::<io::Result<Vec<_>>>()?;
+    let deleted = git.deleted_files(&index)?;
+    stage(git, modified, &deleted)?;
 
-    #[test]
-    fn cmd_hash_object() {
-        assert!(hash_object(String::from("")).is_err());
-        assert!(hash_object(String::from("hoge123...;;;")).is_err());
+    commit(git, message, allow_empty)
+}
 
-        let (testfile, hash) = create_test_file();
+// git checkout <branch>
+// git checkout -b <name> [<start point>]
+pub fn checkout<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<()> {
+    match args.first().map(String::as_str) {
+        Some("-b") => {
+            let name = args.get(1).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "switch 'b' requires a value")
+            })?;
+            let start = args.get(2).map(String::as_str).unwrap_or("HEAD");
+            switch_branch(git, name, Some(start))
+        }
+        Some(name) => switch_branch(git, name, None),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "branch name required",
+        )),
+    }
+}
 
-        let blob = hash_object(testfile).unwrap();
-        assert_eq!(hex::encode(blob.calc_hash()), hash,);
+// git switch <branch>
+// git switch -c <name> [<start point>]
+// checkout と違ってファイルの復元はせず branch の切り替えだけを行う
+pub fn switch<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<()> {
+    match args.first().map(String::as_str) {
+        Some("-c") | Some("--create") => {
+            let name = args.get(1).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "switch 'c' requires a value")
+            })?;
+            let start = args.get(2).map(String::as_str).unwrap_or("HEAD");
+            switch_branch(git, name, Some(start))
+        }
+        Some(name) => switch_branch(git, name, None),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "missing branch or commit argument",
+        )),
     }
+}
 
-    // #[test]
-    // fn cmd_add() {
-    //     let (testfile, _) = create_test_file();
-    //     let git = Git::new();
-    //     assert!(add(&git, testfile).is_ok());
+// git restore [--staged] [--source <rev>] <path>...
+// --staged なら source (既定は HEAD) の内容を index にだけ書き, そうでなければ source (既定は index) の内容を worktree にだけ書く
+pub fn restore<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<()> {
+    let mut staged = false;
+    let mut source = None;
+    let mut pathspecs = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-S" | "--staged" => staged = true,
+            "-s" | "--source" => {
+                source = Some(iter.next().cloned().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("option '{}' requires a value", arg),
+                    )
+                })?)
+            }
+            x if x.starts_with("--source=") => source = Some(x["--source=".len()..].to_string()),
+            x => pathspecs.push((Matcher::new(x), x.to_string())),
+        }
+    }
+    if pathspecs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "you must specify path(s) to restore",
+        ));
+    }
 
-    //     let index = git
-    //         .read_index()
-    //         .and_then(|x| git.ls_files_stage(&x))
-    //         .unwrap();
-    // }
+    let index = git.load_index()?;
+    let from_index = || {
+        index
+            .entries
+            .iter()
+            .map(|x| (x.name.clone(), x.mode as usize, x.hash.clone()))
+            .collect::<Vec<_>>()
+    };
+    let files = match source.as_deref() {
+        Some(rev) => commit_files(git, rev)?,
+        None if staged => match git.head_tree()? {
+            Some(tree) => git.read_tree_files(&tree)?,
+            None => Vec::new(),
+        },
+        None => from_index(),
+    };
 
-    // return (filename, hash)
-    fn create_test_file() -> (String, String) {
-        let testfile = String::from("hash_object_test.txt");
-        let mut file = File::create(testfile.clone()).unwrap();
-        let mut buf = "hello, git".as_bytes();
-        file.write_all(&mut buf).unwrap();
-        file.flush().unwrap();
+    // 書き込む前に全ての pathspec が何かに当てはまることを確かめる
+    let known = if staged {
+        let mut names = from_index().into_iter().map(|x| x.0).collect::<Vec<_>>();
+        names.extend(files.iter().map(|x| x.0.clone()));
+        names
+    } else {
+        files.iter().map(|x| x.0.clone()).collect()
+    };
+    for (matcher, arg) in pathspecs.iter() {
+        if !known.iter().any(|x| matcher.matches(x)) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("pathspec '{}' did not match any file(s) known to git", arg),
+            ));
+        }
+    }
+    let matched = |name: &str| pathspecs.iter().any(|(x, _)| x.matches(name));
 
-        (
-            testfile,
-            String::from("3edbc45b9a7f744c2345cd2cd073c3de091341ac"),
+    if staged {
+        git.modify_index(|git, index| {
+            let removed = index
+                .entries
+                .iter()
+                .filter(|x| matched(&x.name) && !files.iter().any(|(path, _, _)| *path == x.name))
+                .map(|x| x.name.clone())
+                .collect::<Vec<_>>();
+            for name in removed.iter() {
+                index.remove_entry(name);
+            }
+            for (path, mode, hash) in files.into_iter().filter(|x| matched(&x.0)) {
+                index.upsert(staged_entry(git, &hash, mode, path)?);
+            }
+            Ok(())
+        })
+    } else {
+        for (path, mode, hash) in files.into_iter().filter(|x| matched(&x.0)) {
+            write_worktree_file(git, &path, mode, &hash)?;
+        }
+        Ok(())
+    }
+}
+
+// git apply [--check] [--cached] <patch>
+// 全てのファイルに hunk が当てはまることを確かめてから書き込むので, 途中までだけ当たることは無い
+// --check なら確かめるだけ, --cached なら worktree ではなく index の blob に当てる
+pub fn apply<F: FileSystem>(
+    git: &mut Git<F>,
+    patch_path: String,
+    check: bool,
+    cached: bool,
+) -> io::Result<()> {
+    let text = String::from_utf8(git.filesystem.read(patch_path)?)
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+    apply_patches(git, &parse_patch(&text)?, check, cached)
+}
+
+fn parse_patch(text: &str) -> io::Result<Vec<FilePatch>> {
+    patch::parse(text).map_err(|line| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("corrupt patch at line {}", line),
+        )
+    })
+}
+
+// cached なら index, そうでなければ worktree に patches を当てる
+// index に書き込む時は lock を取ってから読んだ index に当てる
+fn apply_patches<F: FileSystem>(
+    git: &mut Git<F>,
+    patches: &[FilePatch],
+    check: bool,
+    cached: bool,
+) -> io::Result<()> {
+    if cached && !check {
+        return git.modify_index(|git, index| apply_to(git, index, patches, false, true));
+    }
+    let mut index = git.load_index()?;
+    apply_to(git, &mut index, patches, check, cached)
+}
+
+fn apply_to<F: FileSystem>(
+    git: &mut Git<F>,
+    index: &mut Index,
+    patches: &[FilePatch],
+    check: bool,
+    cached: bool,
+) -> io::Result<()> {
+    let location = if cached { "index" } else { "working directory" };
+
+    // path ごとの当てた後の中身. None は削除
+    let mut results: Vec<(String, Option<String>)> = Vec::new();
+    for file in patches.iter() {
+        if file.binary {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "cannot apply binary patch to '{}' without full index line",
+                    file.path()
+                ),
+            ));
+        }
+        let current = |path: &str| -> io::Result<Option<String>> {
+            // 同じパッチの中で先に変更したファイルはその結果に当てる
+            if let Some((_, content)) = results.iter().rev().find(|x| x.0 == path) {
+                return Ok(content.clone());
+            }
+            let bytes = if cached {
+                match index.get_entry(path) {
+                    Some(entry) => git.read_blob(&entry.hash.to_hex())?,
+                    None => return Ok(None),
+                }
+            } else {
+                match git.filesystem.read(path.to_string()) {
+                    Ok(bytes) => bytes,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                    Err(e) => return Err(e),
+                }
+            };
+            String::from_utf8(bytes)
+                .map(Some)
+                .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))
+        };
+
+        let old = match file.old_path.as_deref() {
+            Some(path) => Some(current(path)?.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{}: does not exist in {}", path, location),
+                )
+            })?),
+            None => None,
+        };
+        if let Some(path) = file.new_path.as_deref() {
+            if file.old_path.as_deref() != Some(path) && current(path)?.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{}: already exists in {}", path, location),
+                ));
+            }
+        }
+
+        let content =
+            patch::apply(old.as_deref().unwrap_or_default(), &file.hunks).map_err(|hunk| {
+                io::Error::other(format!("patch failed: {}: {}", file.path(), hunk.header))
+            })?;
+
+        if let Some(path) = file.old_path.as_deref() {
+            if file.new_path.as_deref() != Some(path) {
+                if file.new_path.is_none() && !content.is_empty() {
+                    return Err(io::Error::other(format!(
+                        "removal patch leaves file contents: {}",
+                        path
+                    )));
+                }
+                results.push((path.to_string(), None));
+            }
+        }
+        if let Some(path) = file.new_path.as_deref() {
+            results.push((path.to_string(), Some(content)));
+        }
+    }
+
+    if check {
+        return Ok(());
+    }
+
+    for (path, content) in results {
+        match (content, cached) {
+            (Some(content), true) => {
+                let blob = GitObject::Blob(git.hash_object(content.as_bytes())?);
+                git.write_object(&blob)?;
+                let mode = index
+                    .get_entry(&path)
+                    .map(|x| x.mode as usize)
+                    .unwrap_or(MODE_FILE);
+                let hash = blob.calc_hash_with(git.hash_algo);
+                index.upsert(staged_entry(git, &hash, mode, path)?);
+            }
+            (None, true) => {
+                index.remove_entry(&path);
+            }
+            (Some(content), false) => {
+                if let Some((dir, _)) = path.rsplit_once('/') {
+                    git.create_dir_all(dir)?;
+                }
+                git.filesystem.write(path, content.as_bytes())?;
+            }
+            (None, false) => git.filesystem.remove(path)?,
+        }
+    }
+
+    Ok(())
+}
+
+// git format-patch [--stdout] <since>[..<until>]
+// since から until (既定は HEAD) までの commit を古い順に 1 commit 1 ファイルのパッチにする
+// ファイルに書き出した場合はファイル名を, --stdout なら全てのパッチの行を返す
+pub fn format_patch<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<Vec<String>> {
+    let stdout = args.iter().any(|x| x == "--stdout");
+    let range = args
+        .iter()
+        .find(|x| !x.starts_with("--"))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "missing revision range"))?;
+    let (since, until) = range.split_once("..").unwrap_or((range, "HEAD"));
+    let until = if until.is_empty() { "HEAD" } else { until };
+
+    let mut walk = RevWalk::new(git);
+    walk.hide(git.rev_parse(since)?)?;
+    walk.push(git.rev_parse(until)?)?;
+    let mut commits = walk.collect::<io::Result<Vec<_>>>()?;
+    commits.reverse();
+
+    let total = commits.len();
+    let mut lines = Vec::new();
+    for (n, (hash, commit)) in commits.iter().enumerate() {
+        let message = String::from_utf8_lossy(&commit.message);
+        let (subject, body) = message.split_once("\n\n").unwrap_or((&message, ""));
+        let subject = subject.lines().collect::<Vec<_>>().join(" ");
+        let prefix = match total {
+            1 => "[PATCH]".to_string(),
+            _ => format!("[PATCH {}/{}]", n + 1, total),
+        };
+
+        let mut text = format!(
+            "From {} Mon Sep 17 00:00:00 2001\nFrom: {} <{}>\nDate: {}\nSubject: {} {}\n\n",
+            hash,
+            commit.author.name,
+            commit.author.email,
+            commit.author.ts.format("%a, %-d %b %Y %H:%M:%S %z"),
+            prefix,
+            subject
+        );
+        if !body.trim().is_empty() {
+            text.push_str(body.trim_end());
+            text.push('\n');
+        }
+        text.push_str("---\n");
+        for file in commit_diff(git, commit)? {
+            text.push_str(&file.to_string());
+        }
+        text.push_str(&format!("-- \nrgit {}\n\n", env!("CARGO_PKG_VERSION")));
+
+        if stdout {
+            lines.extend(text.lines().map(String::from));
+        } else {
+            let name = format!("{:04}-{}.patch", n + 1, sanitize_subject(&subject));
+            git.filesystem.write(name.clone(), text.as_bytes())?;
+            lines.push(name);
+        }
+    }
+
+    Ok(lines)
+}
+
+// git am <mbox> | --continue | --skip | --abort
+// mbox のメールを順に worktree と index に当て, メールの author で commit する
+// 途中で当てられなかったら .git/rebase-apply に残りを記録して止まる
+// 始める前の HEAD を ORIG_HEAD に残し, --abort でそこへ HEAD と index, worktree を戻す
+pub fn am<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<Vec<String>> {
+    let dir = git.git_path("rebase-apply");
+    let in_progress = git.filesystem.is_dir(dir.clone()).unwrap_or(false);
+    let arg = args
+        .first()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "missing mbox"))?;
+    match (arg.as_str(), in_progress) {
+        ("--abort", true) => {
+            if let Some(orig) = git.read_special_ref("ORIG_HEAD")? {
+                let orig: ObjectId = orig.parse()?;
+                let tree = git.read_commit(&orig.to_hex())?.tree;
+                reset_tree(git, &tree)?;
+                match git.head_ref() {
+                    Ok(refname) => git.update_ref(refname, &orig)?,
+                    Err(_) => git.write_special_ref("HEAD", &orig)?,
+                }
+            }
+            return git.filesystem.remove_dir_all(dir).map(|_| Vec::new());
+        }
+        ("--skip", true) => am_advance(git)?,
+        ("--continue", true) => {
+            // 利用者が直した index をそのまま commit する
+            let mail = am_current(git)?;
+            let author = User::new(mail.name, mail.email, mail.date);
+            commit_as(git, Some(author), mail.message, false)?;
+            am_advance(git)?;
+        }
+        ("--abort" | "--skip" | "--continue", false) => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no am session in progress",
+            ))
+        }
+        (_, true) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("previous rebase directory {} still exists", dir),
+            ))
+        }
+        (path, false) => {
+            let text = String::from_utf8(git.filesystem.read(path.to_string())?)
+                .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+            let mails = patch::split_mbox(&text);
+            if let Some(head) = git.read_special_ref("HEAD")? {
+                git.write_special_ref("ORIG_HEAD", &head.parse()?)?;
+            }
+            git.filesystem.create_dir(dir.clone())?;
+            for (i, mail) in mails.iter().enumerate() {
+                git.filesystem
+                    .write(format!("{}/{:04}", dir, i + 1), mail.as_bytes())?;
+            }
+            git.filesystem.write(format!("{}/next", dir), b"1\n")?;
+            git.filesystem.write(
+                format!("{}/last", dir),
+                format!("{}\n", mails.len()).as_bytes(),
+            )?;
+        }
+    }
+
+    let mut lines = Vec::new();
+    while am_number(git, "next")? <= am_number(git, "last")? {
+        let mail = am_current(git)?;
+        lines.push(format!("Applying: {}", mail.subject));
+
+        let result = parse_patch(&mail.diff).and_then(|patches| {
+            // worktree と index のどちらにも当てられることを確かめてから書き込む
+            apply_patches(git, &patches, true, false)?;
+            apply_patches(git, &patches, true, true)?;
+            apply_patches(git, &patches, false, false)?;
+            apply_patches(git, &patches, false, true)
+        });
+        if let Err(e) = result {
+            return Err(io::Error::new(
+                e.kind(),
+                format!(
+                    "{}\npatch failed at {:04} {}\nuse 'rgit am --continue', 'rgit am --skip' or 'rgit am --abort'",
+                    e,
+                    am_number(git, "next")?,
+                    mail.subject
+                ),
+            ));
+        }
+
+        let author = User::new(mail.name, mail.email, mail.date);
+        commit_as(git, Some(author), mail.message, false)?;
+        am_advance(git)?;
+    }
+    git.filesystem.remove_dir_all(dir)?;
+
+    Ok(lines)
+}
+
+// .git/rebase-apply/next や last に書いた番号
+fn am_number<F: FileSystem>(git: &Git<F>, name: &str) -> io::Result<usize> {
+    let path = git.git_path(&format!("rebase-apply/{}", name));
+    String::from_utf8_lossy(&git.filesystem.read(path)?)
+        .trim()
+        .parse()
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))
+}
+
+// .git/rebase-apply/next が指すメール
+fn am_current<F: FileSystem>(git: &Git<F>) -> io::Result<Mail> {
+    let next = am_number(git, "next")?;
+    let text = git
+        .filesystem
+        .read(git.git_path(&format!("rebase-apply/{:04}", next)))?;
+    Mail::parse(&String::from_utf8_lossy(&text)).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("patch {:04} is not a valid mail", next),
+        )
+    })
+}
+
+fn am_advance<F: FileSystem>(git: &mut Git<F>) -> io::Result<()> {
+    let next = am_number(git, "next")?;
+    git.filesystem.write(
+        git.git_path("rebase-apply/next"),
+        format!("{}\n", next + 1).as_bytes(),
+    )
+}
+
+// commit と最初の parent (無ければ空の tree) の差分
+fn commit_diff<F: FileSystem>(git: &Git<F>, commit: &Commit) -> io::Result<Vec<FilePatch>> {
+    let before = match commit.parents.first() {
+        Some(parent) => {
+            let tree = git.read_commit(&parent.to_hex())?.tree;
+            git.read_tree_files(&tree)?
+        }
+        None => Vec::new(),
+    };
+    let after = git.read_tree_files(&commit.tree)?;
+
+    let mut paths = before
+        .iter()
+        .chain(after.iter())
+        .map(|x| x.0.clone())
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths.dedup();
+
+    // (mode, 中身)
+    let content =
+        |files: &[(String, usize, ObjectId)], path: &str| -> io::Result<Option<(usize, Vec<u8>)>> {
+            match files.iter().find(|x| x.0 == path) {
+                Some((_, mode, hash)) => git.read_blob(&hash.to_hex()).map(|x| Some((*mode, x))),
+                None => Ok(None),
+            }
+        };
+    let mut patches = Vec::new();
+    for path in paths {
+        let old = content(&before, &path)?;
+        let new = content(&after, &path)?;
+        if old == new {
+            continue;
+        }
+        let binary = [&old, &new]
+            .iter()
+            .any(|x| x.as_ref().is_some_and(|(_, x)| patch::is_binary(x)));
+        // binary でなければどちらも文字列として読める
+        let text = |x: &Option<(usize, Vec<u8>)>| {
+            x.as_ref()
+                .and_then(|(_, x)| std::str::from_utf8(x).ok())
+                .unwrap_or_default()
+                .to_string()
+        };
+        patches.push(FilePatch {
+            old_path: old.as_ref().map(|_| path.clone()),
+            new_path: new.as_ref().map(|_| path.clone()),
+            old_mode: old.as_ref().map(|x| x.0),
+            new_mode: new.as_ref().map(|x| x.0),
+            binary,
+            hunks: match binary {
+                true => Vec::new(),
+                false => patch::diff(&text(&old), &text(&new)),
+            },
+        });
+    }
+
+    Ok(patches)
+}
+
+// パッチのファイル名に使う subject. 英数字と `.` `_` 以外は `-` にまとめ, 長さを切り詰める
+fn sanitize_subject(subject: &str) -> String {
+    const NAME_MAX: usize = 64;
+
+    let mut name = String::new();
+    for c in subject.chars() {
+        let c = if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+            c
+        } else {
+            '-'
+        };
+        // `-` と `.` は続けない
+        if (c == '-' || c == '.') && name.ends_with(c) {
+            continue;
+        }
+        name.push(c);
+    }
+    let name = name.trim_matches(['-', '.']);
+    let name = &name[..name.len().min(NAME_MAX)];
+
+    name.trim_end_matches(['-', '.']).to_string()
+}
+
+// name の branch に切り替える. start があれば start の位置に branch を作ってから切り替える
+fn switch_branch<F: FileSystem>(
+    git: &mut Git<F>,
+    name: &str,
+    start: Option<&str>,
+) -> io::Result<()> {
+    let refname = format!("refs/heads/{}", name);
+    let target = match start {
+        Some(start) => {
+            if git.read_ref(refname.clone()).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("a branch named '{}' already exists", name),
+                ));
+            }
+            git.rev_parse(start)?
+        }
+        None => git.read_ref(refname.clone()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("pathspec '{}' did not match any branch", name),
+            )
+        })?,
+    };
+
+    // tree が変わらなければ index と worktree はそのまま
+    let tree = git.read_commit(&target)?.tree;
+    if git.head_tree()? != Some(tree.clone()) {
+        checkout_tree(git, &tree)?;
+    }
+
+    if start.is_some() {
+        create_ref(git, &refname, &target)?;
+    }
+    git.set_head(&refname)
+}
+
+// refs/heads/topic/x のように途中のディレクトリが無くても ref を書き込む
+fn create_ref<F: FileSystem>(git: &mut Git<F>, refname: &str, hash: &str) -> io::Result<()> {
+    // .git の外を指す名前ならディレクトリを作る前に止める
+    let path = git.ref_path(refname)?;
+    if let Some((dir, _)) = path.rsplit_once('/') {
+        git.create_dir_all(dir)?;
+    }
+    git.update_ref(refname.to_string(), &hash.parse()?)
+}
+
+// worktree と index を tree の内容に置き換える
+// 変更されている tracked なファイルや, tree のファイルに上書きされる untracked なファイルがあれば
+// 失われるので何もしない
+fn checkout_tree<F: FileSystem>(git: &mut Git<F>, tree: &ObjectId) -> io::Result<()> {
+    let index = git.load_index()?;
+    if !git.modified_files(&index)?.is_empty() || !git.deleted_files(&index)?.is_empty() {
+        return Err(io::Error::other(
+            "your local changes would be overwritten by checkout",
+        ));
+    }
+
+    let files = git.read_tree_files(tree)?;
+    let overwritten = overwritten_untracked(git, &files)?;
+    if !overwritten.is_empty() {
+        return Err(io::Error::other(format!(
+            "the following untracked working tree files would be overwritten by checkout:\n\t{}",
+            overwritten.join("\n\t")
+        )));
+    }
+
+    reset_tree(git, tree)
+}
+
+// 変更を確かめずに worktree と index を tree の内容にする. index にあって tree に無いファイルは消す
+fn reset_tree<F: FileSystem>(git: &mut Git<F>, tree: &ObjectId) -> io::Result<()> {
+    let files = git.read_tree_files(tree)?;
+    git.modify_index(|git, index| {
+        for entry in index.entries.iter() {
+            if files.iter().any(|(path, _, _)| *path == entry.name) {
+                continue;
+            }
+            // worktree から既に消えていてもよい
+            match git.filesystem.remove(entry.name.clone()) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+
+        *index = Index::new(Vec::new());
+        for (path, mode, hash) in files {
+            if write_worktree_file(git, &path, mode, &hash)? {
+                index.upsert(git.index_entry(&hash, path)?);
+            }
+        }
+        Ok(())
+    })
+}
+
+// checkout で消えてしまう untracked なファイル
+// tree に同じ名前で違う中身のファイルがあるか, ディレクトリとファイルが入れ替わるもの. 無視されているファイルは上書きしてよい
+fn overwritten_untracked<F: FileSystem>(
+    git: &Git<F>,
+    files: &[(String, usize, ObjectId)],
+) -> io::Result<Vec<String>> {
+    let mut overwritten = Vec::new();
+    for path in ls_files_others(git, true)? {
+        let mut conflict = false;
+        for (name, _, hash) in files.iter() {
+            if *name == path {
+                let bytes = git.read_worktree_file(path.clone())?;
+                let blob = git.hash_object(&bytes).map(GitObject::Blob)?;
+                conflict = blob.calc_hash_with(git.hash_algo) != *hash;
+            } else {
+                conflict = name.starts_with(&format!("{}/", path))
+                    || path.starts_with(&format!("{}/", name));
+            }
+            if conflict {
+                break;
+            }
+        }
+        if conflict {
+            overwritten.push(path);
+        }
+    }
+
+    Ok(overwritten)
+}
+
+// rev の commit の tree にある全てのファイル
+fn commit_files<F: FileSystem>(
+    git: &Git<F>,
+    rev: &str,
+) -> io::Result<Vec<(String, usize, ObjectId)>> {
+    let tree = git.read_commit(&git.rev_parse(rev)?)?.tree;
+    git.read_tree_files(&tree)
+}
+
+// git ls-tree [-r] <tree-ish>
+// "<mode> <type> <hash>\t<name>" の行を返す. recursive ならサブディレクトリの中のファイルを並べる
+pub fn ls_tree<F: FileSystem>(git: &Git<F>, rev: &str, recursive: bool) -> io::Result<Vec<String>> {
+    let hash = git.rev_parse(rev)?;
+    let tree = match git.read_git_object(&hash.parse()?)? {
+        GitObject::Commit(commit) => commit.tree,
+        GitObject::Tree(_) => hash.parse()?,
+        GitObject::Blob(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a tree object", rev),
+            ))
+        }
+    };
+
+    let entries = if recursive {
+        git.read_tree_files(&tree)?
+    } else {
+        match git.read_git_object(&tree)? {
+            GitObject::Tree(tree) => tree
+                .contents
+                .into_iter()
+                .map(|x| (x.name, x.mode.to_octal(), x.hash))
+                .collect(),
+            _ => return Err(io::Error::from(io::ErrorKind::InvalidData)),
+        }
+    };
+
+    entries
+        .into_iter()
+        .map(|(name, mode, hash)| {
+            let mode = FileMode::from_octal(mode)
+                .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidData))?;
+            Ok(format!(
+                "{:06o} {} {}\t{}",
+                mode.to_octal(),
+                mode.object_type(),
+                hex::encode(hash),
+                name
+            ))
+        })
+        .collect()
+}
+
+// git mktree [-z] [--missing]
+// ls-tree の形式 `<mode> SP <type> SP <hash> TAB <name>` の行から tree を作って書き込み, hash を返す
+// --missing でなければ entry の object があって mode と同じ型であることを確かめる. submodule の commit は確かめない
+pub fn mktree<F: FileSystem>(
+    git: &mut Git<F>,
+    args: &[String],
+    input: &[u8],
+) -> io::Result<String> {
+    let separator = if args.iter().any(|x| x == "-z") {
+        b'\0'
+    } else {
+        b'\n'
+    };
+    let missing = args.iter().any(|x| x == "--missing");
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+
+    let mut files = Vec::new();
+    let mut names = HashSet::new();
+    for line in input.split(|&x| x == separator).filter(|x| !x.is_empty()) {
+        let line = String::from_utf8(line.to_vec())
+            .map_err(|_| invalid("input is not UTF-8".to_string()))?;
+        let (meta, name) = line
+            .split_once('\t')
+            .ok_or_else(|| invalid(format!("input format error: {}", line)))?;
+        let (mode, object_type, hash) = match meta.split(' ').collect::<Vec<_>>()[..] {
+            [mode, object_type, hash] => (mode, object_type, hash),
+            _ => return Err(invalid(format!("input format error: {}", line))),
+        };
+
+        let mode = usize::from_str_radix(mode, 8)
+            .ok()
+            .and_then(FileMode::from_octal)
+            .ok_or_else(|| invalid(format!("invalid mode {} for {}", mode, name)))?;
+        if object_type != mode.object_type().to_string() {
+            return Err(invalid(format!(
+                "entry '{}' object type ({}) doesn't match mode type ({})",
+                name,
+                object_type,
+                mode.object_type()
+            )));
+        }
+        let id = ObjectId::from_hex(hash)
+            .filter(|x| x.as_bytes().len() == git.hash_algo.width())
+            .ok_or_else(|| invalid(format!("invalid object id {} for {}", hash, name)))?;
+        if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            return Err(invalid(format!("invalid path '{}'", name)));
+        }
+        if !names.insert(name.to_string()) {
+            return Err(invalid(format!("duplicate entry '{}'", name)));
+        }
+
+        if !missing && mode != FileMode::Gitlink {
+            let actual = cat_file_t(git, hash.to_string()).map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => {
+                    invalid(format!("entry '{}' object {} is unavailable", name, hash))
+                }
+                _ => e,
+            })?;
+            if actual != mode.object_type() {
+                return Err(invalid(format!(
+                    "entry '{}' object {} is a {} but specified type was ({})",
+                    name, hash, actual, object_type
+                )));
+            }
+        }
+        files.push(tree::File::new(mode, name.to_string(), id));
+    }
+
+    // Tree::new が git と同じ順に並べる
+    let object = GitObject::Tree(tree::Tree::new(files));
+    git.write_object(&object)?;
+
+    Ok(object.calc_hash_with(git.hash_algo).to_hex())
+}
+
+// blob の中身を path に書き出す. mode は tree の mode でも stat の mode でも良い
+// submodule は別のリポジトリなので作らずに false を返す
+fn write_worktree_file<F: FileSystem>(
+    git: &mut Git<F>,
+    path: &str,
+    mode: usize,
+    hash: &ObjectId,
+) -> io::Result<bool> {
+    // tree や index から来たパスが worktree の外や .git の中を指していれば書き出さない
+    if !tree::verify_path(path) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid path '{}'", path),
+        ));
+    }
+    let kind = mode & tree::MODE_TYPE_MASK;
+    if kind == MODE_GITLINK {
+        return Ok(false);
+    }
+    if let Some((dir, _)) = path.rsplit_once('/') {
+        git.create_dir_all(dir)?;
+    }
+
+    let content = git.read_blob(&hash.to_hex())?;
+    // symlink を書き換える時と, ファイルから symlink に変わる時は先に消す
+    if git.filesystem.read_link(path.to_string()).is_ok()
+        || (kind == MODE_SYMLINK && git.filesystem.stat(path.to_string()).is_ok())
+    {
+        git.filesystem.remove(path.to_string())?;
+    }
+    if kind == MODE_SYMLINK {
+        let target =
+            String::from_utf8(content).map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+        git.filesystem.symlink(target, path.to_string())?;
+    } else {
+        git.filesystem.write(path.to_string(), &content)?;
+        let perm = if mode & 0o100 != 0 { 0o755 } else { 0o644 };
+        git.filesystem.set_mode(path.to_string(), perm)?;
+    }
+
+    Ok(true)
+}
+
+// worktree とは関係なく hash と mode を index に載せる entry
+// worktree にファイルがあれば stat を使い, 無ければ空の stat にする
+fn staged_entry<F: FileSystem>(
+    git: &Git<F>,
+    hash: &ObjectId,
+    mode: usize,
+    path: String,
+) -> io::Result<Entry> {
+    let mut entry = match git.index_entry(hash, path.clone()) {
+        Ok(entry) => entry,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let epoch = Utc.timestamp(0, 0);
+            Entry::new(epoch, epoch, 0, 0, 0, 0, 0, 0, hash.clone(), path)
+        }
+        Err(e) => return Err(e),
+    };
+    entry.mode = mode as u32;
+
+    Ok(entry)
+}
+
+// git log [--show-notes] [--abbrev-commit] [--abbrev=<n>] [<rev>]
+pub fn log<F: FileSystem>(git: &Git<F>, args: &[String]) -> io::Result<Vec<String>> {
+    let show_notes = args.iter().any(|x| x == "--show-notes");
+    let abbrev = match args.iter().find_map(|x| x.strip_prefix("--abbrev=")) {
+        Some(n) => Some(
+            n.parse::<usize>()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, n.to_string()))?,
+        ),
+        None if args.iter().any(|x| x == "--abbrev-commit") => Some(DEFAULT_ABBREV),
+        None => None,
+    };
+    let rev = args
+        .iter()
+        .find(|x| !x.starts_with("--"))
+        .map(String::as_str)
+        .unwrap_or("HEAD");
+
+    let mut walk = RevWalk::new(git);
+    walk.push(git.rev_parse(rev)?)?;
+
+    let mut lines = Vec::new();
+    for result in walk {
+        let (hash, commit) = result?;
+        if !lines.is_empty() {
+            lines.push(String::new());
+        }
+        match abbrev {
+            Some(len) => lines.push(format!(
+                "commit {}",
+                short_hash(&hex::decode(&hash).unwrap_or_default(), len)
+            )),
+            None => lines.push(format!("commit {}", hash)),
+        }
+        lines.push(format!(
+            "Author: {} <{}>",
+            commit.author.name, commit.author.email
+        ));
+        lines.push(format!(
+            "Date:   {}",
+            commit.author.ts.format("%a %b %-d %H:%M:%S %Y %z")
+        ));
+        lines.push(String::new());
+        lines.extend(commit.message_text().lines().map(|x| format!("    {}", x)));
+
+        if show_notes {
+            if let Some(note) = notes::read(git, &hash)? {
+                lines.push(String::new());
+                lines.push("Notes:".to_string());
+                lines.extend(note.lines().map(|x| format!("    {}", x)));
+            }
+        }
+    }
+
+    Ok(lines)
+}
+
+// .git/logs/<ref_name> を古い順に読む. reflog が無ければ空
+pub fn reflog<F: FileSystem>(git: &Git<F>, ref_name: String) -> io::Result<Vec<ReflogEntry>> {
+    let path = git.ref_path(&format!("logs/{}", ref_name))?;
+    let bytes = match git.filesystem.read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    String::from_utf8_lossy(&bytes)
+        .lines()
+        .filter(|x| !x.is_empty())
+        .map(|x| {
+            ReflogEntry::from(x).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid reflog entry in {}: {}", ref_name, x),
+                )
+            })
+        })
+        .collect()
+}
+
+// git notes add [-f] -m <msg> [<commit>] / git notes show [<commit>] / git notes remove [<commit>]
+// 出力する行を返す
+pub fn notes<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<Vec<String>> {
+    let usage = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "usage: notes add -m <msg> [<commit>] | show [<commit>] | remove [<commit>]",
         )
+    };
+    let (sub_cmd, rest) = args.split_first().ok_or_else(usage)?;
+    let mut message = None;
+    let mut rev = None;
+    let mut iter = rest.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-m" => message = Some(iter.next().ok_or_else(usage)?.clone()),
+            // 既存の note は常に置き換えるので -f は受け取るだけ
+            "-f" | "--force" => {}
+            _ => rev = Some(arg.as_str()),
+        }
+    }
+    let commit = git.rev_parse(rev.unwrap_or("HEAD"))?;
+
+    match sub_cmd.as_str() {
+        "add" => {
+            let message = message.ok_or_else(usage)?;
+            let (author, committer) = ident::idents(git)?;
+            notes::write(
+                git,
+                &commit,
+                Some(format!("{}\n", message)),
+                author,
+                committer,
+            )?;
+            Ok(Vec::new())
+        }
+        "show" => match notes::read(git, &commit)? {
+            Some(note) => Ok(note.lines().map(String::from).collect()),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no note found for object {}", commit),
+            )),
+        },
+        "remove" => {
+            let (author, committer) = ident::idents(git)?;
+            notes::write(git, &commit, None, author, committer)?;
+            Ok(Vec::new())
+        }
+        _ => Err(usage()),
+    }
+}
+
+// git rev-list [--count] [--max-count=<n>] <rev>... [^<rev>...] [<rev>..<rev>]
+pub fn rev_list<F: FileSystem>(git: &Git<F>, args: &[String]) -> io::Result<Vec<String>> {
+    let mut count = false;
+    let mut max_count = None;
+    let mut include = Vec::new();
+    let mut exclude = Vec::new();
+
+    for arg in args {
+        if arg == "--count" {
+            count = true;
+        } else if let Some(n) = arg.strip_prefix("--max-count=") {
+            let n = n
+                .parse::<usize>()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, arg.clone()))?;
+            max_count = Some(n);
+        } else if let Some(rev) = arg.strip_prefix('^') {
+            exclude.push(git.rev_parse(rev)?);
+        } else if let Some((from, to)) = arg.split_once("..") {
+            exclude.push(git.rev_parse(from)?);
+            include.push(git.rev_parse(to)?);
+        } else {
+            include.push(git.rev_parse(arg)?);
+        }
+    }
+
+    // 除外する側の祖先を先に全て記録してから辿る
+    let mut walk = RevWalk::new(git);
+    for hash in exclude {
+        walk.hide(hash)?;
+    }
+    for hash in include {
+        walk.push(hash)?;
+    }
+
+    let hashes = walk
+        .take(max_count.unwrap_or(usize::MAX))
+        .map(|x| x.map(|(hash, _)| hash))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    if count {
+        return Ok(vec![hashes.len().to_string()]);
+    }
+    Ok(hashes)
+}
+
+// git rev-list --count <rev>
+// rev から辿れる commit の数. 訪問済みの commit は数えないので merge の先の共通の祖先も一度だけ数える
+pub fn rev_list_count<F: FileSystem>(git: &Git<F>, rev: String) -> io::Result<usize> {
+    let mut count = 0;
+    for commit in CommitWalker::new(git, git.rev_parse(&rev)?) {
+        commit?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+// git name-rev <hash>...
+// 各 ref から first-parent を辿った距離で名前を付ける. tag は branch より優先する
+pub fn name_rev<F: FileSystem>(git: &Git<F>, hashes: &[String]) -> io::Result<Vec<String>> {
+    let targets = hashes
+        .iter()
+        .map(|x| git.rev_parse(x))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut refs = git
+        .list_refs()?
+        .into_iter()
+        .filter_map(|(name, hash)| {
+            if let Some(tag) = name.strip_prefix("refs/tags/") {
+                Some((0, format!("tags/{}", tag), hash))
+            } else {
+                name.strip_prefix("refs/heads/")
+                    .map(|x| (1, x.to_string(), hash))
+            }
+        })
+        .collect::<Vec<_>>();
+    refs.sort();
+
+    let mut names: HashMap<&str, (usize, usize, String)> = HashMap::new();
+    for (priority, name, tip) in refs {
+        let mut visited = HashSet::new();
+        let mut next = Some(tip);
+        let mut distance = 0;
+
+        while let Some(hash) = next {
+            if !visited.insert(hash.clone()) {
+                break;
+            }
+
+            if let Some(target) = targets.iter().find(|&x| x == &hash) {
+                let better = names
+                    .get(target.as_str())
+                    .map(|&(p, d, _)| (priority, distance) < (p, d))
+                    .unwrap_or(true);
+                if better {
+                    names.insert(target, (priority, distance, name.clone()));
+                }
+                // 1つだけ探しているなら見つかった時点でこの ref の探索をやめる
+                if targets.len() == 1 {
+                    break;
+                }
+            }
+
+            // commit を指していない ref は辿らない
+            next = match git.read_commit(&hash) {
+                Ok(commit) => commit.parents.first().map(ObjectId::to_hex),
+                Err(_) => None,
+            };
+            distance += 1;
+        }
+    }
+
+    let lines = targets
+        .iter()
+        .map(|hash| {
+            let name = match names.get(hash.as_str()) {
+                Some((_, 0, name)) => name.clone(),
+                Some((_, distance, name)) => format!("{}~{}", name, distance),
+                None => "undefined".to_string(),
+            };
+            format!("{} {}", hash, name)
+        })
+        .collect();
+
+    Ok(lines)
+}
+
+// git describe <commit>
+// commit から祖先を近い順に辿って最初に見つかった tag で `<tag>-<n>-g<short>` と名前を付ける
+// commit 自身に tag があれば tag の名前だけを返す. 同じ距離なら名前順で先の tag を使う
+pub fn describe<F: FileSystem>(git: &Git<F>, commit: String) -> io::Result<String> {
+    let target = git.rev_parse(&commit)?;
+
+    let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+    for (name, hash) in git.list_refs()? {
+        if let Some(tag) = name.strip_prefix("refs/tags/") {
+            tags.entry(hash).or_default().push(tag.to_string());
+        }
+    }
+
+    // merge commit の全ての parent を辿るので幅優先で距離を数える
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::from(vec![(target.clone(), 0)]);
+    while let Some((hash, distance)) = queue.pop_front() {
+        if !visited.insert(hash.clone()) {
+            continue;
+        }
+
+        if let Some(names) = tags.get(&hash) {
+            let name = names.iter().min().unwrap();
+            if distance == 0 {
+                return Ok(name.clone());
+            }
+            return Ok(format!(
+                "{}-{}-g{}",
+                name,
+                distance,
+                &target[..DEFAULT_ABBREV]
+            ));
+        }
+
+        let commit = git.read_commit(&hash)?;
+        queue.extend(commit.parents.iter().map(|x| (x.to_hex(), distance + 1)));
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no tag can describe {}", target),
+    ))
+}
+
+// git pack-objects <prefix>
+// 指定された object だけを含む <prefix>-<hash>.pack と .idx を書き出して pack の hash 値を返す
+pub fn pack_objects<F: FileSystem>(
+    git: &mut Git<F>,
+    prefix: String,
+    hashes: &[String],
+) -> io::Result<String> {
+    let mut writer = PackWriter::with_hash_algo(git.hash_algo);
+    for hash in hashes {
+        let object = git.read_git_object(&hash.parse()?)?;
+        writer.add(object)?;
+    }
+
+    let pack = writer.finish();
+    let hash = hex::encode(&pack.hash);
+    git.filesystem
+        .write(format!("{}-{}.pack", prefix, hash), &pack.pack)?;
+    git.filesystem
+        .write(format!("{}-{}.idx", prefix, hash), &pack.idx)?;
+    // .git/objects/pack に書いた時は読み込み済みの pack に加える
+    git.reload_packs();
+
+    Ok(hash)
+}
+
+// git upload-pack <dir>
+// ref を広告し, input から client の want/have を読んで output に pack を返す
+// multi_ack には対応しないので, 共通の commit は最初に見つけた1つだけ ACK する
+pub fn upload_pack<F: FileSystem, R: Read, W: Write>(
+    git: &Git<F>,
+    mut input: R,
+    mut output: W,
+) -> io::Result<()> {
+    let mut refs = git.list_refs()?;
+    let mut capabilities = upload_pack::CAPABILITIES
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>();
+    if let Ok(head) = git.head_ref() {
+        if let Ok(hash) = git.read_ref(head.clone()) {
+            refs.insert(0, ("HEAD".to_string(), hash));
+            capabilities.push(format!("symref=HEAD:{}", head));
+        }
+    }
+    let zero_hash = "0".repeat(git.hash_algo.width() * 2);
+    upload_pack::write_advertisement(&mut output, &refs, &capabilities, &zero_hash)?;
+    output.flush()?;
+
+    let wants = match upload_pack::Wants::read(&mut input)? {
+        Some(wants) => wants,
+        None => return Ok(()),
+    };
+    // 広告していない object は渡さない
+    if let Some(want) = wants
+        .wants
+        .iter()
+        .find(|x| !refs.iter().any(|r| r.1 == x.to_hex()))
+    {
+        let message = format!("upload-pack: not our ref {}", want);
+        pktline::write_pkt(&mut output, format!("ERR {}\n", message).as_bytes())?;
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    }
+
+    let mut common = Vec::new();
+    loop {
+        match upload_pack::read_have(&mut input)? {
+            Some(Have::Have(hash)) => {
+                if git.has_object(&hash)? {
+                    if common.is_empty() {
+                        pktline::write_pkt(&mut output, format!("ACK {}\n", hash).as_bytes())?;
+                    }
+                    common.push(hash.to_hex());
+                }
+            }
+            Some(Have::Flush) => {
+                if common.is_empty() {
+                    pktline::write_pkt(&mut output, b"NAK\n")?;
+                }
+                output.flush()?;
+            }
+            Some(Have::Done) => break,
+            // 共通の commit だけで足りた client は done を送らずに閉じる
+            None => return Ok(()),
+        }
+    }
+    if common.is_empty() {
+        pktline::write_pkt(&mut output, b"NAK\n")?;
+    }
+
+    // client が持っている commit から辿れる object は送らない
+    let wants_hex = wants.wants.iter().map(|x| x.to_hex()).collect();
+    let pack = build_pack(git, wants_hex, common)?;
+
+    if wants.has_capability("side-band-64k") {
+        upload_pack::write_side_band(&mut output, 1, &pack)?;
+        pktline::write_flush(&mut output)?;
+    } else {
+        output.write_all(&pack)?;
+    }
+    output.flush()
+}
+
+// roots から辿れて exclude から辿れない object の pack を作る
+// commit の中身は作り直すと変わることがあるので, 読んだバイト列をそのまま入れる
+fn build_pack<F: FileSystem>(
+    git: &Git<F>,
+    roots: Vec<String>,
+    exclude: Vec<String>,
+) -> io::Result<Vec<u8>> {
+    let exclude = reachable::walk(git, exclude)?;
+    let mut hashes = reachable::walk(git, roots)?
+        .into_iter()
+        .filter(|x| !exclude.contains(x))
+        .collect::<Vec<_>>();
+    hashes.sort();
+    let mut writer = PackWriter::with_hash_algo(git.hash_algo);
+    for hash in hashes.iter() {
+        let bytes = git.read_object(&hash.parse()?)?;
+        let mut raw = Vec::new();
+        Decoder::new(&bytes[..])?.read_to_end(&mut raw)?;
+        writer.add_raw(&raw)?;
+    }
+
+    Ok(writer.finish().pack)
+}
+
+// git receive-pack <dir>
+// client が送ってくる ref の更新と pack を受け取り, 結果を report-status で返す
+// 全ての更新を確かめてから ref を書き換えるので, 途中で失敗しても半端な状態にならない
+// protocol には force の印が無いので, git と同じく fast-forward でない更新も受け入れ,
+// receive.denyNonFastForwards = true の時だけ断る
+pub fn receive_pack<F: FileSystem, R: Read, W: Write>(
+    git: &mut Git<F>,
+    mut input: R,
+    mut output: W,
+) -> io::Result<()> {
+    let refs = git.list_refs()?;
+    let capabilities = receive_pack::CAPABILITIES
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>();
+    let zero_hash = "0".repeat(git.hash_algo.width() * 2);
+    upload_pack::write_advertisement(&mut output, &refs, &capabilities, &zero_hash)?;
+    output.flush()?;
+
+    let commands = match receive_pack::Commands::read(&mut input)? {
+        Some(commands) => commands,
+        None => return Ok(()),
+    };
+    // 削除だけなら pack は送られてこない
+    let unpack = if commands.commands.iter().any(|x| x.new != zero_hash) {
+        let pack = pack::read_pack(&mut input, git.hash_algo)?;
+        match pack::verify_pack(&pack, git.hash_algo)? {
+            0 => Ok(()),
+            _ => store_pack(git, &pack)
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+        }
+    } else {
+        Ok(())
+    };
+
+    let config = git.read_config()?;
+    let deny_non_ff = config
+        .get("receive", None, "denyNonFastForwards")
+        .map(|x| x.to_ascii_lowercase());
+    let allow_non_ff = !matches!(deny_non_ff.as_deref(), Some("true" | "yes" | "on" | "1"));
+    let mut statuses = Vec::new();
+    for command in commands.commands.iter() {
+        let status = match unpack {
+            Ok(()) => check_ref_update(git, command, &zero_hash, allow_non_ff)?,
+            Err(_) => Err("unpacker error".to_string()),
+        };
+        statuses.push((command.name.clone(), status));
+    }
+    if commands.has_capability("atomic") && statuses.iter().any(|x| x.1.is_err()) {
+        for (_, status) in statuses.iter_mut().filter(|x| x.1.is_ok()) {
+            *status = Err("atomic push failure".to_string());
+        }
+    }
+
+    for (command, (_, status)) in commands.commands.iter().zip(statuses.iter_mut()) {
+        if status.is_err() {
+            continue;
+        }
+        let result = if command.new == zero_hash {
+            git.delete_ref(command.name.clone())
+        } else {
+            create_ref(git, &command.name, &command.new)
+        };
+        if result.is_err() {
+            *status = Err("failed to update ref".to_string());
+        }
+    }
+
+    if commands.has_capability("report-status") {
+        receive_pack::write_report(&mut output, &unpack, &statuses)?;
+    }
+    output.flush()
+}
+
+// ref を command の通りに書き換えてよいか. 駄目なら report-status に載せる理由を返す
+fn check_ref_update<F: FileSystem>(
+    git: &Git<F>,
+    command: &RefCommand,
+    zero_hash: &str,
+    allow_non_ff: bool,
+) -> io::Result<Result<(), String>> {
+    let reject = |reason: &str| Ok(Err(reason.to_string()));
+    let is_hash = |x: &str| x.len() == zero_hash.len() && x.bytes().all(|b| b.is_ascii_hexdigit());
+    if !is_hash(&command.old) || !is_hash(&command.new) {
+        return reject("invalid object name");
+    }
+    if !command.name.starts_with("refs/")
+        || command
+            .name
+            .split('/')
+            .any(|x| x.is_empty() || x == "." || x == "..")
+    {
+        return reject("funny refname");
+    }
+    let current = match git.read_ref(command.name.clone()) {
+        Ok(hash) => Some(hash),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    // client が見た値から変わっていれば, 他の push と競合している
+    let expected = Some(command.old.clone()).filter(|x| x != zero_hash);
+    if current != expected {
+        return reject("failed to lock");
+    }
+    if command.new == zero_hash {
+        return Ok(Ok(()));
+    }
+    if !git.has_object(&command.new.parse()?)? {
+        return reject("missing necessary objects");
+    }
+    match current {
+        Some(old) if !allow_non_ff && !is_ancestor(git, &old, &command.new)? => {
+            reject("non-fast-forward")
+        }
+        _ => Ok(Ok(())),
+    }
+}
+
+// git unpack-objects < <pack>
+// pack の checksum を確認してから全ての object を loose object として書き込む
+pub fn unpack_objects<F: FileSystem>(git: &mut Git<F>, pack_path: String) -> io::Result<usize> {
+    let bytes = git.filesystem.read(pack_path)?;
+    let objects = pack::unpack(&bytes, git.hash_algo)?;
+    for object in objects.iter() {
+        git.write_raw_object(&object.as_bytes())?;
+    }
+
+    Ok(objects.len())
+}
+
+// git index-pack <file.pack>
+// pack と同じ場所に idx を書き出して pack の checksum を返す
+pub fn index_pack<F: FileSystem>(git: &mut Git<F>, pack_path: String) -> io::Result<String> {
+    let idx_path = pack_path
+        .strip_suffix(".pack")
+        .map(|x| format!("{}.idx", x))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "packfile name must end with .pack",
+            )
+        })?;
+
+    let bytes = git.filesystem.read(pack_path)?;
+    let idx = pack::index_pack(&bytes, git.hash_algo)?;
+    git.filesystem.write(idx_path, &idx)?;
+    git.reload_packs();
+
+    Ok(hex::encode(&bytes[(bytes.len() - git.hash_algo.width())..]))
+}
+
+// git prune [-n]
+// どこからも辿れない loose object を削除して, その hash を返す
+// dry_run なら削除せずに hash だけ返す
+pub fn prune<F: FileSystem>(git: &mut Git<F>, dry_run: bool) -> io::Result<Vec<String>> {
+    let hashes = reachable::unreachable_loose(git)?;
+    if !dry_run {
+        for hash in hashes.iter() {
+            git.remove_loose_object(hash)?;
+        }
+    }
+
+    Ok(hashes)
+}
+
+// git prune-packed [-n]
+// pack にも含まれている loose object を削除して, その hash を返す
+// 空になった fan-out ディレクトリも削除する
+pub fn prune_packed<F: FileSystem>(git: &mut Git<F>, dry_run: bool) -> io::Result<Vec<String>> {
+    let mut hashes = Vec::new();
+    for hash in git.loose_objects()? {
+        if git.is_packed(&hash.parse()?)? {
+            hashes.push(hash);
+        }
+    }
+    if dry_run {
+        return Ok(hashes);
+    }
+
+    for hash in hashes.iter() {
+        git.remove_loose_object(hash)?;
+
+        let dir = git.object_dir(&hash.parse()?);
+        if git.filesystem.read_dir(dir.clone())?.is_empty() {
+            git.filesystem.remove_dir(dir)?;
+        }
+    }
+
+    Ok(hashes)
+}
+
+// git remote [-v] / git remote add <name> <url> / git remote remove <name>
+// 出力する行を返す
+pub fn remote<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<Vec<String>> {
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    match args.as_slice() {
+        [] => Ok(git.remotes()?.into_iter().map(|x| x.name).collect()),
+        ["-v"] | ["--verbose"] => Ok(git
+            .remotes()?
+            .into_iter()
+            .flat_map(|x| {
+                vec![
+                    format!("{}\t{} (fetch)", x.name, x.url),
+                    format!("{}\t{} (push)", x.name, x.url),
+                ]
+            })
+            .collect()),
+        ["add", name, url] => {
+            git.add_remote(&Remote::new(name.to_string(), url.to_string()))?;
+            Ok(Vec::new())
+        }
+        ["remove", name] | ["rm", name] => {
+            git.remove_remote(name)?;
+            Ok(Vec::new())
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "usage: remote [-v] | add <name> <url> | remove <name>",
+        )),
+    }
+}
+
+// git bundle create <file> (--all | <ref>...) | verify <file> | list-heads <file> | unbundle <file>
+// create は ref から辿れる全ての object を 1 つの pack にして ref の一覧と一緒に書き出す
+// unbundle は pack を取り込んで bundle の ref を作る
+pub fn bundle<F: FileSystem>(git: &mut Git<F>, args: &[String]) -> io::Result<Vec<String>> {
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    match args.as_slice() {
+        ["create", file, names @ ..] if !names.is_empty() => {
+            let mut refs = Vec::new();
+            for &name in names {
+                if name == "--all" {
+                    if let Ok(hash) = git.rev_parse("HEAD") {
+                        refs.push((hash, "HEAD".to_string()));
+                    }
+                    refs.extend(git.list_refs()?);
+                    continue;
+                }
+                let refname = match name {
+                    "HEAD" => name.to_string(),
+                    x if x.starts_with("refs/") => x.to_string(),
+                    x => vec![format!("refs/heads/{}", x), format!("refs/tags/{}", x)]
+                        .into_iter()
+                        .find(|x| git.read_ref(x.clone()).is_ok())
+                        .ok_or_else(|| {
+                            io::Error::new(
+                                io::ErrorKind::NotFound,
+                                format!("unknown ref: {}", name),
+                            )
+                        })?,
+                };
+                refs.push((git.rev_parse(&refname)?, refname));
+            }
+            refs.dedup_by(|a, b| a.1 == b.1);
+
+            let mut hashes = reachable::walk(git, refs.iter().map(|x| x.0.clone()).collect())?
+                .into_iter()
+                .collect::<Vec<_>>();
+            hashes.sort();
+            let mut writer = PackWriter::with_hash_algo(git.hash_algo);
+            for hash in hashes {
+                let object = git
+                    .read_git_object(&hash.parse()?)?;
+                writer.add(object)?;
+            }
+
+            let bundle = Bundle::new(refs, writer.finish().pack);
+            git.filesystem.write(file.to_string(), &bundle.as_bytes())?;
+            Ok(Vec::new())
+        }
+        ["verify", file] => {
+            read_bundle(git, file)?;
+            Ok(vec![format!("{} is okay", file)])
+        }
+        ["list-heads", file] => Ok(read_bundle(git, file)?
+            .refs
+            .into_iter()
+            .map(|(hash, refname)| format!("{} {}", hash, refname))
+            .collect()),
+        ["unbundle", file] => {
+            let refs = unbundle(git, file)?;
+            for (hash, refname) in refs.iter().filter(|x| x.1 != "HEAD") {
+                create_ref(git, refname, hash)?;
+            }
+            Ok(refs
+                .into_iter()
+                .map(|(hash, refname)| format!("{} {}", hash, refname))
+                .collect())
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "usage: bundle (create <file> <ref>... | verify <file> | list-heads <file> | unbundle <file>)",
+        )),
+    }
+}
+
+// bundle を読み, 前提の commit がこのリポジトリにあることと pack の checksum を確かめる
+fn read_bundle<F: FileSystem>(git: &Git<F>, path: &str) -> io::Result<Bundle> {
+    let bundle = Bundle::from(&git.filesystem.read(path.to_string())?).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a v2 bundle file", path),
+        )
+    })?;
+    for hash in bundle.prerequisites.iter() {
+        if !git.has_object(&hash.parse()?)? {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("repository lacks the prerequisite commit {}", hash),
+            ));
+        }
+    }
+    pack::verify_pack(&bundle.pack, git.hash_algo)?;
+
+    Ok(bundle)
+}
+
+// bundle の pack を .git/objects/pack に置いて, bundle の ref の一覧を返す
+fn unbundle<F: FileSystem>(git: &mut Git<F>, path: &str) -> io::Result<Vec<(String, String)>> {
+    let bundle = read_bundle(git, path)?;
+    store_pack(git, &bundle.pack)?;
+
+    for (hash, refname) in bundle.refs.iter() {
+        if !git.has_object(&hash.parse()?)? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} points to {} which is not in the bundle", refname, hash),
+            ));
+        }
+    }
+
+    Ok(bundle.refs)
+}
+
+// pack を index して objects/pack に pack-<hash>.pack と .idx として置く
+fn store_pack<F: FileSystem>(git: &mut Git<F>, pack: &[u8]) -> io::Result<String> {
+    let idx = pack::index_pack(pack, git.hash_algo)?;
+    let hash = hex::encode(&pack[(pack.len() - git.hash_algo.width())..]);
+
+    git.create_dir_all(&git.git_path("objects/pack"))?;
+    git.filesystem.write(
+        git.git_path(&format!("objects/pack/pack-{}.pack", hash)),
+        pack,
+    )?;
+    git.filesystem.write(
+        git.git_path(&format!("objects/pack/pack-{}.idx", hash)),
+        &idx,
+    )?;
+    git.reload_packs();
+
+    Ok(hash)
+}
+
+// git clone <bundle>
+// 空のリポジトリに bundle を取り込み, branch を refs/remotes/origin 以下に置く
+// bundle の HEAD と同じ commit の branch (無ければ最初の branch) を作って checkout する
+pub fn clone_bundle<F: FileSystem>(git: &mut Git<F>, bundle_path: String) -> io::Result<()> {
+    let refs = unbundle(git, &bundle_path)?;
+    let remote = Remote::new("origin".to_string(), bundle_path);
+    git.add_remote(&remote)?;
+
+    for (hash, refname) in refs.iter() {
+        let local = match refname.as_str() {
+            "HEAD" => continue,
+            x if x.starts_with("refs/tags/") => Some(x.to_string()),
+            x => remote.fetch.iter().find_map(|spec| spec.map(x)),
+        };
+        if let Some(local) = local {
+            create_ref(git, &local, hash)?;
+        }
+    }
+
+    let head = refs.iter().find(|x| x.1 == "HEAD").map(|x| x.0.clone());
+    let branches = refs
+        .iter()
+        .filter(|x| x.1.starts_with("refs/heads/"))
+        .collect::<Vec<_>>();
+    let branch = branches
+        .iter()
+        .find(|x| Some(&x.0) == head.as_ref())
+        .or(branches.first());
+    if let Some((hash, refname)) = branch {
+        create_ref(git, refname, hash)?;
+        git.set_head(refname)?;
+        let tree = git.read_commit(hash)?.tree;
+        checkout_tree(git, &tree)?;
+    }
+
+    Ok(())
+}
+
+// git push [--force] <remote> <branch>
+// remote のリポジトリに足りない object を書き写し, remote のブランチと追跡ブランチを更新する
+pub fn push<F: FileSystem, R: FileSystem>(
+    git: &mut Git<F>,
+    remote: &mut Git<R>,
+    remote_name: &str,
+    branch: &str,
+    force: bool,
+) -> io::Result<Vec<String>> {
+    let url = find_remote(git, remote_name)?.url;
+    let refname = format!("refs/heads/{}", branch);
+    let new = push_source(git, branch)?;
+    let old = remote.read_ref(refname.clone()).ok();
+
+    let mut lines = vec![format!("To {}", url)];
+    let status = match push_status(git, old.as_deref(), &new, branch, force)? {
+        Some(status) => status,
+        None => return Ok(vec!["Everything up-to-date".to_string()]),
+    };
+
+    copy_objects(git, remote, vec![new.clone()])?;
+    create_ref(remote, &refname, &new)?;
+    create_ref(
+        git,
+        &format!("refs/remotes/{}/{}", remote_name, branch),
+        &new,
+    )?;
+    lines.push(status);
+
+    Ok(lines)
+}
+
+// git push [--force] <remote> <branch> を ssh などの transport で送る
+// remote の ref の広告から足りない object を決めて pack にし, report-status で結果を確かめる
+pub fn push_transport<F: FileSystem, T: PushTransport + ?Sized>(
+    git: &mut Git<F>,
+    transport: &mut T,
+    remote_name: &str,
+    branch: &str,
+    force: bool,
+) -> io::Result<Vec<String>> {
+    let url = find_remote(git, remote_name)?.url;
+    let refname = format!("refs/heads/{}", branch);
+    let new = push_source(git, branch)?;
+    let adv = transport.receive_advertisement()?;
+    let old = adv
+        .refs
+        .iter()
+        .find(|x| x.0 == refname)
+        .map(|x| x.1.clone());
+
+    let mut lines = vec![format!("To {}", url)];
+    let status = match push_status(git, old.as_deref(), &new, branch, force)? {
+        Some(status) => status,
+        None => return Ok(vec!["Everything up-to-date".to_string()]),
+    };
+
+    // remote にある ref のうち手元にもある commit の先は送らない
+    let mut haves = Vec::new();
+    for (_, hash) in adv.refs.iter() {
+        if git.has_object(&hash.parse()?)? {
+            haves.push(hash.clone());
+        }
+    }
+    let pack = build_pack(git, vec![new.clone()], haves)?;
+    let zero_hash = "0".repeat(git.hash_algo.width() * 2);
+    let command = RefCommand {
+        old: old.unwrap_or(zero_hash),
+        new: new.clone(),
+        name: refname.clone(),
+    };
+    let request = push::push_request(&adv, &[command], Some(&pack));
+    let report = transport.send_pack(&request)?;
+    if let Err(reason) = report.unpack {
+        return Err(io::Error::other(format!(
+            "remote unpack failed: {}",
+            reason
+        )));
+    }
+    if let Some((_, Err(reason))) = report.statuses.iter().find(|x| x.0 == refname) {
+        return Err(io::Error::other(format!(
+            "! [remote rejected] {} -> {} ({})",
+            branch, branch, reason
+        )));
+    }
+
+    create_ref(
+        git,
+        &format!("refs/remotes/{}/{}", remote_name, branch),
+        &new,
+    )?;
+    lines.push(status);
+
+    Ok(lines)
+}
+
+// push するブランチの commit
+fn push_source<F: FileSystem>(git: &Git<F>, branch: &str) -> io::Result<String> {
+    git.read_ref(format!("refs/heads/{}", branch)).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("src refspec {} does not match any", branch),
+        )
+    })
+}
+
+// git push が表示する1行. 更新が要らなければ None, fast-forward でなく force も無ければエラー
+fn push_status<F: FileSystem>(
+    git: &Git<F>,
+    old: Option<&str>,
+    new: &str,
+    branch: &str,
+    force: bool,
+) -> io::Result<Option<String>> {
+    let abbrev = |x: &str| x[..DEFAULT_ABBREV].to_string();
+    let status = match old {
+        Some(old) if old == new => return Ok(None),
+        Some(old) if is_ancestor(git, old, new)? => format!(
+            "   {}..{}  {} -> {}",
+            abbrev(old),
+            abbrev(new),
+            branch,
+            branch
+        ),
+        Some(old) if force => format!(
+            " + {}...{} {} -> {} (forced update)",
+            abbrev(old),
+            abbrev(new),
+            branch,
+            branch
+        ),
+        Some(_) => {
+            return Err(io::Error::other(format!(
+                "! [rejected] {} -> {} (non-fast-forward)",
+                branch, branch
+            )))
+        }
+        None => format!(" * [new branch]      {} -> {}", branch, branch),
+    };
+
+    Ok(Some(status))
+}
+
+// git fetch [--prune] <remote>
+// remote の ref を config の refspec で手元の ref に対応させ, 足りない object を書き写して更新する
+// prune なら remote で消えたブランチに対応する ref も消す
+pub fn fetch<F: FileSystem, R: FileSystem>(
+    git: &mut Git<F>,
+    remote: &Git<R>,
+    remote_name: &str,
+    prune: bool,
+) -> io::Result<Vec<String>> {
+    let config = find_remote(git, remote_name)?;
+    let updates = fetch_updates(&config, remote.list_refs()?);
+    copy_objects(
+        remote,
+        git,
+        updates.iter().map(|x| x.2.clone()).collect::<Vec<_>>(),
+    )?;
+
+    update_remote_refs(git, &config, &updates, prune)
+}
+
+// git fetch [--prune] <remote> を http や git:// などの transport 越しに行う
+// 手元に無い ref の先だけを want にし, 手元の ref を全て have にする
+pub fn fetch_transport<F: FileSystem, T: Transport + ?Sized>(
+    git: &mut Git<F>,
+    transport: &mut T,
+    remote_name: &str,
+    prune: bool,
+    progress: &mut dyn FnMut(&[u8]),
+) -> io::Result<Vec<String>> {
+    let config = find_remote(git, remote_name)?;
+    let adv = transport.advertisement()?;
+    let updates = fetch_updates(&config, adv.refs.clone());
+
+    let mut wants = Vec::new();
+    for (_, _, hash, _) in updates.iter() {
+        if !wants.contains(hash) && !git.has_object(&hash.parse()?)? {
+            wants.push(hash.clone());
+        }
+    }
+    if !wants.is_empty() {
+        let haves = git
+            .list_refs()?
+            .into_iter()
+            .map(|x| x.1)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let request = fetch::want_request(&adv, &wants, &haves);
+        let pack = transport.fetch_pack(&adv, &request, progress)?;
+        store_pack(git, &pack)?;
+    }
+
+    update_remote_refs(git, &config, &updates, prune)
+}
+
+// git ls-remote [--heads] [--tags] <repository>
+// 手元にある別のリポジトリの ref を `<hash>\t<ref>` の形で返す. HEAD が ref を指していれば最初に HEAD を置く
+pub fn ls_remote<F: FileSystem>(remote: &Git<F>, args: &[String]) -> io::Result<Vec<String>> {
+    let mut refs = remote.list_refs()?;
+    // packed-refs にしか無い branch も HEAD から辿れるように, 一覧の中から探す
+    let head = remote
+        .head_ref()
+        .ok()
+        .and_then(|head| refs.iter().find(|x| x.0 == head).map(|x| x.1.clone()));
+    if let Some(hash) = head {
+        refs.insert(0, ("HEAD".to_string(), hash));
+    }
+
+    Ok(ls_remote_lines(refs, args))
+}
+
+// upload-pack が広告した ref で ls-remote の結果を作る
+pub fn ls_remote_transport<T: Transport + ?Sized>(
+    transport: &mut T,
+    args: &[String],
+) -> io::Result<Vec<String>> {
+    let adv = transport.advertisement()?;
+    Ok(ls_remote_lines(adv.refs, args))
+}
+
+// HEAD を最初に, 残りを名前順に並べる. --heads と --tags はその種類の ref だけにし, HEAD も出さない
+fn ls_remote_lines(refs: Vec<(String, String)>, args: &[String]) -> Vec<String> {
+    let heads = args.iter().any(|x| x == "--heads");
+    let tags = args.iter().any(|x| x == "--tags");
+    let (head, mut refs): (Vec<_>, Vec<_>) = refs.into_iter().partition(|x| x.0 == "HEAD");
+    refs.sort();
+
+    head.into_iter()
+        .chain(refs)
+        .filter(|(name, _)| {
+            (!heads && !tags)
+                || (heads && name.starts_with("refs/heads/"))
+                || (tags && name.starts_with("refs/tags/"))
+        })
+        .map(|(name, hash)| format!("{}\t{}", hash, name))
+        .collect()
+}
+
+fn find_remote<F: FileSystem>(git: &Git<F>, name: &str) -> io::Result<Remote> {
+    git.remotes()?
+        .into_iter()
+        .find(|x| x.name == name)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("'{}' does not appear to be a git remote", name),
+            )
+        })
+}
+
+// remote の ref のうち refspec に当てはまるものを (remote の ref, 手元の ref, hash, 強制するか) にする
+fn fetch_updates(
+    config: &Remote,
+    refs: Vec<(String, String)>,
+) -> Vec<(String, String, String, bool)> {
+    let mut updates = Vec::new();
+    for (src, hash) in refs {
+        if let Some(spec) = config.fetch.iter().find(|x| x.map(&src).is_some()) {
+            let dst = spec.map(&src).unwrap_or_default();
+            updates.push((src, dst, hash, spec.force));
+        }
+    }
+    updates
+}
+
+// object が揃った後に追跡ブランチを更新して, git fetch と同じ形式の行を返す
+fn update_remote_refs<F: FileSystem>(
+    git: &mut Git<F>,
+    config: &Remote,
+    updates: &[(String, String, String, bool)],
+    prune: bool,
+) -> io::Result<Vec<String>> {
+    let short = |x: &str| {
+        ["refs/heads/", "refs/tags/", "refs/remotes/"]
+            .iter()
+            .find_map(|prefix| x.strip_prefix(prefix))
+            .unwrap_or(x)
+            .to_string()
+    };
+    let abbrev = |x: &str| x[..DEFAULT_ABBREV].to_string();
+    let mut lines = Vec::new();
+    for (src, dst, new, force) in updates.iter() {
+        let (src, dst_name) = (short(src), short(dst));
+        let status = match git.read_ref(dst.clone()).ok() {
+            Some(old) if &old == new => continue,
+            Some(old) if is_ancestor(git, &old, new)? => {
+                format!(
+                    "   {}..{}  {} -> {}",
+                    abbrev(&old),
+                    abbrev(new),
+                    src,
+                    dst_name
+                )
+            }
+            Some(old) if *force => format!(
+                " + {}...{} {} -> {}  (forced update)",
+                abbrev(&old),
+                abbrev(new),
+                src,
+                dst_name
+            ),
+            Some(_) => {
+                lines.push(format!(
+                    " ! [rejected]        {} -> {}  (non-fast-forward)",
+                    src, dst_name
+                ));
+                continue;
+            }
+            None => format!(" * [new branch]      {} -> {}", src, dst_name),
+        };
+        create_ref(git, dst, new)?;
+        lines.push(status);
+    }
+
+    if prune {
+        // refspec の dst に当てはまるのに, 今回どの remote の ref にも対応しなかった ref
+        let fetched = updates.iter().map(|x| x.1.as_str()).collect::<HashSet<_>>();
+        for (refname, _) in git.list_refs()? {
+            let stale = !fetched.contains(refname.as_str())
+                && config
+                    .fetch
+                    .iter()
+                    .any(|spec| match spec.dst.split_once('*') {
+                        Some((prefix, suffix)) => {
+                            refname.starts_with(prefix) && refname.ends_with(suffix)
+                        }
+                        None => refname == spec.dst,
+                    });
+            if stale {
+                git.delete_ref(refname.clone())?;
+                lines.push(format!(
+                    " - [deleted]         (none) -> {}",
+                    short(&refname)
+                ));
+            }
+        }
+    }
+
+    if !lines.is_empty() {
+        lines.insert(0, format!("From {}", config.url));
+    }
+
+    Ok(lines)
+}
+
+// ancestor が hash から parent を辿って着く commit か. 手元に無い commit なら false
+fn is_ancestor<F: FileSystem>(git: &Git<F>, ancestor: &str, hash: &str) -> io::Result<bool> {
+    let mut walk = RevWalk::new(git);
+    walk.push(hash.to_string())?;
+    for x in walk {
+        if x?.0 == ancestor {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+// roots から辿れて to に無い object を from から書き写し, 書き写した数を返す
+// to にある commit や tree の先は to にも揃っているものとして辿らない
+fn copy_objects<F: FileSystem, R: FileSystem>(
+    from: &Git<F>,
+    to: &mut Git<R>,
+    roots: Vec<String>,
+) -> io::Result<usize> {
+    let mut seen = HashSet::new();
+    let mut missing = Vec::new();
+    let mut stack = roots;
+    while let Some(hash) = stack.pop() {
+        if !seen.insert(hash.clone()) || to.has_object(&hash.parse()?)? {
+            continue;
+        }
+        let bytes = from.read_object(&hash.parse()?)?;
+        let mut raw = Vec::new();
+        Decoder::new(&bytes[..])?.read_to_end(&mut raw)?;
+        // blob は UTF-8 とは限らないので中身を読まない
+        if GitObject::peek_type(&raw) != Some(ObjectType::Blob) {
+            match from.cat_file_p(&bytes)? {
+                GitObject::Tree(tree) => stack.extend(
+                    tree.contents
+                        .iter()
+                        // gitlink は別のリポジトリの commit
+                        .filter(|x| x.mode != FileMode::Gitlink)
+                        .map(|x| x.hash.to_hex()),
+                ),
+                GitObject::Commit(commit) => {
+                    stack.push(commit.tree.to_hex());
+                    stack.extend(commit.parents.iter().map(ObjectId::to_hex));
+                }
+                GitObject::Blob(_) => {}
+            }
+        }
+        missing.push(raw);
+    }
+
+    // 途中で失敗しても辿れない object が残るだけになるように, 参照される側から書く
+    for raw in missing.iter().rev() {
+        to.write_raw_object(raw)?;
+    }
+
+    Ok(missing.len())
+}
+
+// git fast-export --all
+// 全ての ref から辿れる commit を親から順に fast-import のコマンド列として返す.
+// blob と commit には共通の番号で mark を振り, 複数の ref に現れても一度しか出力しない
+pub fn fast_export<F: FileSystem>(git: &Git<F>) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut marks = HashMap::new();
+    for (refname, hash) in git.list_refs()? {
+        // まだ出力していない commit を, 全ての parent が先に来る順に集める
+        let mut pending = Vec::new();
+        let mut visited = HashSet::new();
+        let mut stack = vec![(hash.clone(), None)];
+        while let Some((hash, commit)) = stack.pop() {
+            // parent を出し終えた commit
+            if let Some(commit) = commit {
+                pending.push((hash, commit));
+                continue;
+            }
+            if marks.contains_key(&hash) || !visited.insert(hash.clone()) {
+                continue;
+            }
+            let commit = git.read_commit(&hash)?;
+            let parents = commit.parents.clone();
+            stack.push((hash, Some(commit)));
+            // 最初の parent から出すように逆順に積む
+            stack.extend(parents.iter().rev().map(|x| (x.to_hex(), None)));
+        }
+        // 他の ref で出力済みの commit を指しているなら ref を向けるだけ
+        if pending.is_empty() {
+            out.extend(format!("reset {}\nfrom :{}\n\n", refname, marks[&hash]).into_bytes());
+            continue;
+        }
+        for (hash, commit) in pending {
+            fast_export_commit(git, &mut out, &mut marks, &refname, hash, &commit)?;
+        }
+    }
+
+    Ok(out)
+}
+
+fn fast_export_commit<F: FileSystem>(
+    git: &Git<F>,
+    out: &mut Vec<u8>,
+    marks: &mut HashMap<String, usize>,
+    refname: &str,
+    hash: String,
+    commit: &Commit,
+) -> io::Result<()> {
+    let before = match commit.parents.first() {
+        Some(parent) => {
+            let tree = git.read_commit(&parent.to_hex())?.tree;
+            git.read_tree_files(&tree)?
+        }
+        None => Vec::new(),
+    };
+    let after = git.read_tree_files(&commit.tree)?;
+
+    let mut changes = Vec::new();
+    for (path, mode, id) in after.iter() {
+        if before
+            .iter()
+            .any(|x| &x.0 == path && &x.1 == mode && &x.2 == id)
+        {
+            continue;
+        }
+        let id = hex::encode(id);
+        // submodule の commit はこのリポジトリに無いので hash のまま参照する
+        let dataref = if *mode == MODE_GITLINK {
+            id
+        } else {
+            if !marks.contains_key(&id) {
+                let bytes = git.read_blob(&id)?;
+                let mark = marks.len() + 1;
+                out.extend(format!("blob\nmark :{}\ndata {}\n", mark, bytes.len()).into_bytes());
+                out.extend(bytes);
+                out.push(b'\n');
+                marks.insert(id.clone(), mark);
+            }
+            format!(":{}", marks[&id])
+        };
+        changes.push(format!(
+            "M {:o} {} {}\n",
+            mode,
+            dataref,
+            fast_export_path(path)
+        ));
+    }
+    for (path, _, _) in before.iter() {
+        if !after.iter().any(|x| &x.0 == path) {
+            changes.push(format!("D {}\n", fast_export_path(path)));
+        }
+    }
+
+    let mark = marks.len() + 1;
+    marks.insert(hash, mark);
+    // 親の無い commit は ref の今の位置から続けないように一度リセットする
+    if commit.parents.is_empty() {
+        out.extend(format!("reset {}\n", refname).into_bytes());
+    }
+    let mut header = format!(
+        "commit {}\nmark :{}\nauthor {}\ncommitter {}\n",
+        refname, mark, commit.author, commit.committer
+    );
+    if let Some(encoding) = commit.encoding.as_ref() {
+        header.push_str(&format!("encoding {}\n", encoding));
+    }
+    header.push_str(&format!("data {}\n", commit.message.len()));
+    out.extend(header.into_bytes());
+    out.extend(commit.message.iter());
+    out.push(b'\n');
+    // 最初の parent は from, 残りは merge
+    for (i, parent) in commit.parents.iter().enumerate() {
+        let command = if i == 0 { "from" } else { "merge" };
+        out.extend(format!("{} :{}\n", command, marks[&parent.to_hex()]).into_bytes());
+    }
+    for change in changes {
+        out.extend(change.into_bytes());
+    }
+    out.push(b'\n');
+
+    Ok(())
+}
+
+// fast-import では `"` で始まるか改行を含む path だけ C 言語風に quote する
+fn fast_export_path(path: &str) -> String {
+    if !path.starts_with('"') && !path.contains('\n') {
+        return path.to_string();
+    }
+    let quoted = path
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", quoted)
+}
+
+// git fast-import
+// blob と commit を作り, 最後に commit や reset で使われた ref を更新する. 出力は作った数のまとめ
+pub fn fast_import<F: FileSystem>(git: &mut Git<F>, stream: &[u8]) -> io::Result<Vec<String>> {
+    let commands = fast_import::parse(stream).map_err(|line| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid fast-import stream at line {}", line),
+        )
+    })?;
+
+    let mut marks = HashMap::new();
+    // ref ごとの今の commit. None はまだ commit の無い ref
+    let mut branches: BTreeMap<String, Option<String>> = BTreeMap::new();
+    let (mut blobs, mut commits) = (0, 0);
+    for command in commands {
+        match command {
+            Command::Blob { mark, data } => {
+                // 中身が UTF-8 とは限らないのでそのまま書き込む
+                let header = format!("{} {}\0", ObjectType::Blob, data.len());
+                let hash = git.write_raw_object(&[header.as_bytes(), &data].concat())?;
+                if let Some(mark) = mark {
+                    marks.insert(mark, hex::encode(hash));
+                }
+                blobs += 1;
+            }
+            Command::Reset { refname, from } => {
+                let from = match from {
+                    Some(x) => Some(fast_import_resolve(git, &marks, &branches, &x)?),
+                    None => None,
+                };
+                branches.insert(refname, from);
+            }
+            Command::Commit(commit) => {
+                let CommitCommand {
+                    refname,
+                    mark,
+                    author,
+                    committer,
+                    encoding,
+                    message,
+                    from,
+                    merges,
+                    changes,
+                } = *commit;
+                let parent = match (from, branches.get(&refname)) {
+                    (Some(x), _) => Some(fast_import_resolve(git, &marks, &branches, &x)?),
+                    (None, Some(tip)) => tip.clone(),
+                    (None, None) => git.read_ref(refname.clone()).ok(),
+                };
+
+                let mut files = match parent.as_deref() {
+                    Some(parent) => {
+                        let tree = git.read_commit(parent)?.tree;
+                        git.read_tree_files(&tree)?
+                    }
+                    None => Vec::new(),
+                };
+                for change in changes {
+                    match change {
+                        Change::Modify {
+                            mode,
+                            dataref,
+                            path,
+                        } => {
+                            let hash = match mode {
+                                // submodule の commit はこのリポジトリに無くてよい
+                                MODE_GITLINK => dataref,
+                                MODE_FILE | MODE_EXECUTABLE | MODE_SYMLINK => {
+                                    fast_import_resolve(git, &marks, &branches, &dataref)?
+                                }
+                                _ => {
+                                    return Err(io::Error::new(
+                                        io::ErrorKind::Unsupported,
+                                        format!("{}: unsupported mode {:o}", path, mode),
+                                    ))
+                                }
+                            };
+                            let hash = ObjectId::from_hex(&hash).ok_or_else(|| {
+                                io::Error::new(io::ErrorKind::InvalidData, hash.clone())
+                            })?;
+                            // ファイルとディレクトリが入れ替わる場合も古い方を消す
+                            files.retain(|x| !is_same_or_nested(&x.0, &path));
+                            files.push((path, mode, hash));
+                        }
+                        Change::Delete(path) => files.retain(|x| !is_same_or_nested(&x.0, &path)),
+                        Change::DeleteAll => files.clear(),
+                    }
+                }
+                files.sort();
+                let tree = git.write_tree_files(&files)?;
+
+                // merge で指定された commit は from の後ろに並べる
+                let mut parents = parent.into_iter().collect::<Vec<_>>();
+                for merge in merges {
+                    parents.push(fast_import_resolve(git, &marks, &branches, &merge)?);
+                }
+                let parents = parents
+                    .iter()
+                    .map(|x| x.parse())
+                    .collect::<io::Result<Vec<ObjectId>>>()?;
+
+                let author = author.unwrap_or_else(|| committer.clone());
+                let mut commit = Commit::new(tree, parents, author, committer, String::new());
+                // message は data のバイト列をそのまま使う
+                commit.message = message;
+                commit.encoding = encoding;
+                let commit = GitObject::Commit(commit);
+                git.write_object(&commit)?;
+                let hash = commit.calc_hash_with(git.hash_algo).to_hex();
+                if let Some(mark) = mark {
+                    marks.insert(mark, hash.clone());
+                }
+                branches.insert(refname, Some(hash));
+                commits += 1;
+            }
+        }
+    }
+
+    let mut refs = 0;
+    for (refname, hash) in branches.iter() {
+        if let Some(hash) = hash {
+            create_ref(git, refname, hash)?;
+            refs += 1;
+        }
+    }
+
+    Ok(vec![
+        format!("blobs: {}", blobs),
+        format!("commits: {}", commits),
+        format!("refs: {}", refs),
+    ])
+}
+
+// `:<mark>`, hash, ref のどれかを hash にする
+fn fast_import_resolve<F: FileSystem>(
+    git: &Git<F>,
+    marks: &HashMap<usize, String>,
+    branches: &BTreeMap<String, Option<String>>,
+    name: &str,
+) -> io::Result<String> {
+    if let Some(mark) = name.strip_prefix(':') {
+        return mark
+            .parse::<usize>()
+            .ok()
+            .and_then(|x| marks.get(&x))
+            .cloned()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("mark {} not declared", name),
+                )
+            });
+    }
+    match branches.get(name) {
+        Some(Some(hash)) => Ok(hash.clone()),
+        _ => git.rev_parse(name),
+    }
+}
+
+// path が prefix そのものか, prefix というディレクトリの中にあるか. prefix がその中にある場合も含む
+fn is_same_or_nested(path: &str, prefix: &str) -> bool {
+    let nested = |a: &str, b: &str| a.strip_prefix(b).is_some_and(|x| x.starts_with('/'));
+    path == prefix || nested(path, prefix) || nested(prefix, path)
+}
+
+// loose object の型ごとの数と, 圧縮した大きさと展開した大きさを `<type> <count> <compressed> <inflated>` で返す
+pub fn count_objects<F: FileSystem>(git: &Git<F>) -> io::Result<Vec<String>> {
+    let stats = git.object_store_size()?;
+    let line = |name: &str, x: &ObjectSizes| {
+        format!("{} {} {} {}", name, x.count, x.compressed, x.inflated)
+    };
+
+    let mut lines = [ObjectType::Blob, ObjectType::Tree, ObjectType::Commit]
+        .iter()
+        .map(|&x| line(&x.to_string(), stats.get(x)))
+        .collect::<Vec<_>>();
+    lines.push(line("total", &stats.total()));
+    Ok(lines)
+}
+
+// git fsck [--unreachable]
+// 壊れた loose object と, unreachable なら到達できない loose object を出力する行で返す
+pub fn fsck<F: FileSystem>(git: &Git<F>, unreachable: bool) -> io::Result<Vec<String>> {
+    let mut lines = git
+        .fsck_loose()?
+        .into_iter()
+        .map(|x| match x.actual {
+            Some(actual) => format!("hash mismatch {} ({})", x.path, actual),
+            None => format!("corrupt object {}", x.path),
+        })
+        .collect::<Vec<_>>();
+
+    // 古い rgit が `comitter` と綴った commit は git が committer を読めない
+    for hash in git.loose_objects()? {
+        let object = git.read_git_object(&hash.parse()?);
+        if let Ok(GitObject::Commit(commit)) = object {
+            if commit.legacy_committer {
+                lines.push(format!("misspelled committer header in {}", hash));
+            }
+        }
+    }
+
+    if unreachable {
+        lines.extend(
+            reachable::unreachable_loose(git)?
+                .into_iter()
+                .map(|x| format!("unreachable {}", x)),
+        );
+    }
+
+    Ok(lines)
+}
+
+// git verify <rev>
+// rev から辿れる object が全て揃っていて壊れていないかを確かめる
+// 壊れていれば最初に見つかった壊れたつながりをエラーにする
+pub fn verify<F: FileSystem>(git: &Git<F>, rev: &str) -> io::Result<Vec<String>> {
+    let hash = git.rev_parse(rev)?;
+    match reachable::verify(git, hash.clone())? {
+        Ok(count) => Ok(vec![format!("{}: {} objects ok", hash, count)]),
+        Err(broken) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            broken.to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::hash::{HashAlgo, Hasher, ObjectId};
+    use crate::object::commit::{Commit, User};
+    use crate::object::tree::Tree;
+    use crate::remote::RefSpec;
+    use crate::GitBuilder;
+    use chrono::{FixedOffset, TimeZone};
+    use std::io::Write;
+
+    #[test]
+    fn cmd_cat_file_p_invalid() {
+        for hash in ["", "../../config", "x"] {
+            let e = cat_file_p(hash.to_string()).err().unwrap();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    // #[test]
+    // fn cmd_cat_file_p() {
+    //     // file not found
+    //     assert!(cat_file_p(String::from("hoge123...;;;")).is_err());
+
+    //     // first commit
+    //     let r = cat_file_p(String::from("01a0c85dd05755281466d29983dfcb15889e1a64"));
+    //     assert!(r.is_ok());
+    //     let r = r.ok().unwrap();
+    //     let expected = "tree 179\u{0}tree 38b38f11af50240a2ddf643619e065408211e9e9\nauthor yusei-wy <yusei.kasa@gmail.com> 1609642799 +0900\ncomitter yusei-wy <yusei.kasa@gmail.com> 1609642799 +0900\n\nadd: blob object\n";
+    //     assert_eq!(r.to_string(), expected);
+    // }
+
+    #[test]
+    fn cmd_cat_file_check() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let commit = test_commit(&mut git, None, 1);
+        assert!(cat_file_check(&git, commit.clone(), ObjectType::Commit).unwrap());
+        assert!(!cat_file_check(&git, commit, ObjectType::Blob).unwrap());
+
+        let blob = GitObject::Blob(Blob::new("hello".to_string()));
+        git.write_object(&blob).unwrap();
+        let hash = hex::encode(blob.calc_hash());
+        assert!(cat_file_check(&git, hash.clone(), ObjectType::Blob).unwrap());
+        assert!(!cat_file_check(&git, hash, ObjectType::Tree).unwrap());
+
+        assert!(cat_file_check(&git, "0".repeat(40), ObjectType::Blob).is_err());
+    }
+
+    #[test]
+    fn cmd_cat_file_t() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let commit = test_commit(&mut git, None, 1);
+        assert_eq!(cat_file_t(&git, commit).unwrap(), ObjectType::Commit);
+
+        // 中身が tree として読めなくても型は分かる
+        let hash = git.write_raw_object(b"tree 5\0junk!").unwrap();
+        assert_eq!(
+            cat_file_t(&git, hex::encode(hash)).unwrap(),
+            ObjectType::Tree
+        );
+
+        let hash = git.write_raw_object(b"tag 5\0junk!").unwrap();
+        assert_eq!(
+            cat_file_t(&git, hex::encode(hash)).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn cmd_mktree() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let args = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        let blob = git.hash_object_write(b"hello\n").unwrap();
+        let sub = mktree(
+            &mut git,
+            &[],
+            format!("100644 blob {}\ta.txt\n", blob).as_bytes(),
+        )
+        .unwrap();
+        let commit = "e83c5163316f89bfbde7d9ab23ca2e25604af290";
+
+        // ディレクトリは名前の末尾に `/` があるものとして並べる
+        let input = format!(
+            "100644 blob {blob}\tfile.txt\n040000 tree {sub}\tfile\n100755 blob {blob}\tfile-a\n160000 commit {commit}\tsub\n120000 blob {blob}\tlink\n",
+            blob = blob,
+            sub = sub,
+            commit = commit
+        );
+        let hash = mktree(&mut git, &[], input.as_bytes()).unwrap();
+        assert_eq!(
+            ls_tree(&git, &hash, false).unwrap(),
+            vec![
+                format!("100755 blob {}\tfile-a", blob),
+                format!("100644 blob {}\tfile.txt", blob),
+                format!("040000 tree {}\tfile", sub),
+                format!("120000 blob {}\tlink", blob),
+                format!("160000 commit {}\tsub", commit),
+            ]
+        );
+
+        // -z なら名前に改行を含められる
+        let odd = mktree(
+            &mut git,
+            &args(&["-z"]),
+            format!("100644 blob {}\todd\nname\0", blob).as_bytes(),
+        )
+        .unwrap();
+        let expected = tree::Tree::new(vec![tree::File::new(
+            FileMode::Regular,
+            "odd\nname".to_string(),
+            blob.parse().unwrap(),
+        )]);
+        assert_eq!(odd, hex::encode(expected.calc_hash()));
+
+        let missing = "0123456789012345678901234567890123456789";
+        for input in [
+            format!("100644 blob {}\ta\n100755 blob {}\ta\n", blob, blob),
+            format!("040000 tree {}\ta\n100644 blob {}\ta\n", sub, blob),
+            format!("100644 tree {}\ta\n", sub),
+            format!("040000 tree {}\ta\n", blob),
+            format!("100644 blob {}\ta\n", missing),
+            format!("100644 blob {}\ta\n", &blob[..10]),
+            format!("100600 blob {}\ta\n", blob),
+            format!("100644 blob {}\ta/b\n", blob),
+            format!("100644 blob {} a\n", blob),
+        ] {
+            assert!(
+                mktree(&mut git, &[], input.as_bytes()).is_err(),
+                "{}",
+                input
+            );
+        }
+        // --missing なら無い object も指せる
+        let with_missing = format!("100644 blob {}\ta\n", missing);
+        let hash_missing =
+            mktree(&mut git, &args(&["--missing"]), with_missing.as_bytes()).unwrap();
+
+        // git が使えるなら git mktree と同じ hash になる
+        let dir = std::env::temp_dir().join(format!("rgit-mktree-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let git_mktree = |args: &[&str], input: &str| {
+            let mut child = std::process::Command::new("git")
+                .arg("-C")
+                .arg(&dir)
+                .arg("mktree")
+                .arg("--missing")
+                .args(args)
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::piped())
+                .spawn()?;
+            child.stdin.take().unwrap().write_all(input.as_bytes())?;
+            let out = child.wait_with_output()?;
+            Ok::<_, io::Error>(String::from_utf8_lossy(&out.stdout).trim().to_string())
+        };
+        let init = std::process::Command::new("git")
+            .arg("-C")
+            .arg(&dir)
+            .args(["init", "-q"])
+            .output();
+        let expected = init.and_then(|_| {
+            Ok((
+                git_mktree(&[], &input)?,
+                git_mktree(&["-z"], &format!("100644 blob {}\todd\nname\0", blob))?,
+                git_mktree(&[], &with_missing)?,
+            ))
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+        if let Ok(expected) = expected {
+            assert_eq!((hash, odd, hash_missing), expected);
+        }
+    }
+
+    #[test]
+    fn cmd_ls_tree_gitlink() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let submodule = test_commit(&mut git, None, 1);
+        let blob = git.hash_object_write(b"hello\n").unwrap();
+        let blob = blob.parse().unwrap();
+
+        let mut index = Index::new(Vec::new());
+        index.upsert(staged_entry(&git, &blob, MODE_FILE, "a.txt".to_string()).unwrap());
+        let hash = submodule.parse().unwrap();
+        index.upsert(staged_entry(&git, &hash, MODE_GITLINK, "sub".to_string()).unwrap());
+        git.write_index(&index).unwrap();
+
+        let (_, tree) = git.write_tree().unwrap().pop().unwrap();
+        let tree = GitObject::Tree(tree);
+        git.write_object(&tree).unwrap();
+        let tree = hex::encode(tree.calc_hash());
+        assert_eq!(
+            ls_tree(&git, &tree, false).unwrap(),
+            vec![
+                format!("100644 blob {}\ta.txt", hex::encode(&blob)),
+                format!("160000 commit {}\tsub", submodule),
+            ]
+        );
+        // 中身はこのリポジトリに無いので再帰しても同じ
+        assert_eq!(
+            ls_tree(&git, &tree, true).unwrap(),
+            ls_tree(&git, &tree, false).unwrap()
+        );
+        assert!(ls_tree(&git, &hex::encode(&blob), false).is_err());
+    }
+
+    #[test]
+    fn cmd_hash_object() {
+        assert!(hash_object(String::from("")).is_err());
+        assert!(hash_object(String::from("hoge123...;;;")).is_err());
+
+        let dir = std::env::temp_dir().join(format!("rgit-hash-object-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (testfile, hash) = create_test_file(&dir);
+
+        let blob = hash_object(testfile).unwrap();
+        assert_eq!(hex::encode(blob.calc_hash()), hash,);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cmd_rev_list() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let first = test_commit(&mut git, None, 1);
+        let second = test_commit(&mut git, Some(first.clone()), 2);
+        let third = test_commit(&mut git, Some(second.clone()), 3);
+        git.update_ref("refs/heads/master".to_string(), &third.parse().unwrap())
+            .unwrap();
+
+        let args = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+
+        let r = rev_list(&git, &args(&["HEAD"])).unwrap();
+        assert_eq!(r, vec![third.clone(), second.clone(), first.clone()]);
+
+        let r = rev_list(&git, &args(&["--count", "master"])).unwrap();
+        assert_eq!(r, vec!["3"]);
+
+        let r = rev_list(&git, &args(&["--max-count=1", "master"])).unwrap();
+        assert_eq!(r, vec![third.clone()]);
+
+        let r = rev_list(&git, &args(&["master", &format!("^{}", first)])).unwrap();
+        assert_eq!(r, vec![third.clone(), second.clone()]);
+
+        let r = rev_list(&git, &args(&[&format!("{}..master", second)])).unwrap();
+        assert_eq!(r, vec![third]);
+    }
+
+    #[test]
+    fn cmd_rev_list_count() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let first = test_commit(&mut git, None, 1);
+        let second = test_commit(&mut git, Some(first.clone()), 2);
+        let third = test_commit(&mut git, Some(second.clone()), 3);
+        // second から分岐した branch
+        let side = test_commit(&mut git, Some(second), 4);
+        git.update_ref("refs/heads/master".to_string(), &third.parse().unwrap())
+            .unwrap();
+
+        assert_eq!(rev_list_count(&git, "HEAD".to_string()).unwrap(), 3);
+        assert_eq!(rev_list_count(&git, side.clone()).unwrap(), 3);
+        assert_eq!(rev_list_count(&git, first).unwrap(), 1);
+        assert!(rev_list_count(&git, "unknown".to_string()).is_err());
+
+        // 両方の parent から辿れる first と second は一度だけ数える
+        let merge = test_merge(&mut git, &[third, side.clone()], 5);
+        assert_eq!(rev_list_count(&git, merge.clone()).unwrap(), 5);
+        // merge の上にさらに merge を重ねても同じ
+        let again = test_merge(&mut git, &[merge, side], 6);
+        assert_eq!(rev_list_count(&git, again).unwrap(), 6);
+    }
+
+    #[test]
+    fn cmd_name_rev() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let first = test_commit(&mut git, None, 1);
+        let second = test_commit(&mut git, Some(first.clone()), 2);
+        let third = test_commit(&mut git, Some(second.clone()), 3);
+        let orphan = test_commit(&mut git, None, 4);
+        git.update_ref("refs/heads/master".to_string(), &third.parse().unwrap())
+            .unwrap();
+        git.filesystem
+            .create_dir(".git/refs/tags".to_string())
+            .unwrap();
+        git.update_ref("refs/tags/v1".to_string(), &second.parse().unwrap())
+            .unwrap();
+
+        let r = name_rev(&git, std::slice::from_ref(&third)).unwrap();
+        assert_eq!(r, vec![format!("{} master", third)]);
+
+        // tag の方が優先される
+        let r = name_rev(&git, std::slice::from_ref(&first)).unwrap();
+        assert_eq!(r, vec![format!("{} tags/v1~1", first)]);
+
+        let r = name_rev(&git, &[second.clone(), orphan.clone()]).unwrap();
+        assert_eq!(
+            r,
+            vec![
+                format!("{} tags/v1", second),
+                format!("{} undefined", orphan)
+            ]
+        );
+    }
+
+    #[test]
+    fn cmd_describe() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let first = test_commit(&mut git, None, 1);
+        let second = test_commit(&mut git, Some(first.clone()), 2);
+        let third = test_commit(&mut git, Some(second.clone()), 3);
+        let fourth = test_commit(&mut git, Some(third.clone()), 4);
+        git.update_ref("refs/heads/master".to_string(), &fourth.parse().unwrap())
+            .unwrap();
+
+        // tag が無ければ名前を付けられない
+        assert_eq!(
+            describe(&git, "HEAD".to_string()).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        git.filesystem
+            .create_dir(".git/refs/tags".to_string())
+            .unwrap();
+        git.update_ref("refs/tags/v0".to_string(), &first.parse().unwrap())
+            .unwrap();
+        git.update_ref("refs/tags/v1".to_string(), &second.parse().unwrap())
+            .unwrap();
+
+        // 2つ前の commit にある近い方の tag を使う
+        assert_eq!(
+            describe(&git, "HEAD".to_string()).unwrap(),
+            format!("v1-2-g{}", &fourth[..7])
+        );
+        assert_eq!(
+            describe(&git, third.clone()).unwrap(),
+            format!("v1-1-g{}", &third[..7])
+        );
+        assert_eq!(describe(&git, second).unwrap(), "v1");
+        assert_eq!(describe(&git, first).unwrap(), "v0");
+    }
+
+    #[test]
+    fn cmd_pack_objects() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let blob = GitObject::Blob(Blob::new("hello, git".to_string()));
+        git.write_object(&blob).unwrap();
+        let commit = test_commit(&mut git, None, 1);
+
+        let hashes = vec![hex::encode(blob.calc_hash()), commit];
+        let hash = pack_objects(&mut git, ".git/objects/pack-test".to_string(), &hashes).unwrap();
+
+        let pack = git
+            .filesystem
+            .read(format!(".git/objects/pack-test-{}.pack", hash))
+            .unwrap();
+        assert_eq!(&pack[8..12], &[0, 0, 0, 2]);
+        assert!(git
+            .filesystem
+            .read(format!(".git/objects/pack-test-{}.idx", hash))
+            .is_ok());
+
+        assert!(pack_objects(&mut git, "pack".to_string(), &["00".repeat(20)]).is_err());
+
+        // 読み込み済みの pack の一覧も新しい pack を含むようになる
+        let id = blob.calc_hash();
+        git.filesystem
+            .create_dir(".git/objects/pack".to_string())
+            .unwrap();
+        assert!(!git.is_packed(&id).unwrap());
+        pack_objects(&mut git, ".git/objects/pack/pack".to_string(), &hashes).unwrap();
+        assert!(git.is_packed(&id).unwrap());
+    }
+
+    // git:// の最初の要求を読み飛ばして upload_pack に繋ぐ
+    fn serve_upload_pack(git: &Git<InMemFileSystem>, listener: &std::net::TcpListener) {
+        let (stream, _) = listener.accept().unwrap();
+        let request = crate::protocol::pktline::PktReader::new(&stream).next();
+        assert!(matches!(
+            request,
+            Some(Ok(crate::protocol::pktline::Pkt::Data(_)))
+        ));
+        upload_pack(git, &stream, &stream).unwrap();
+    }
+
+    #[test]
+    fn cmd_checkout_rejects_bad_path() {
+        let mut git = test_git_with_user();
+        let hash = git.hash_object_write(b"evil").unwrap().parse().unwrap();
+        for path in [
+            "../evil",
+            ".git/hooks/post-checkout",
+            "a/./b",
+            "a/.GIT/config",
+        ] {
+            let e = write_worktree_file(&mut git, path, tree::MODE_FILE, &hash).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{}", path);
+        }
+        assert!(!git
+            .filesystem
+            .exists(".git/hooks/post-checkout".to_string()));
+
+        // 名前を確かめずに作った tree は書き込めても checkout できない
+        let tree = GitObject::Tree(Tree::new(vec![tree::File::new(
+            FileMode::Regular,
+            "..".to_string(),
+            hash,
+        )]));
+        git.write_object(&tree).unwrap();
+        assert!(git.read_tree_files(&tree.calc_hash()).is_err());
+    }
+
+    #[test]
+    fn cmd_upload_pack_invalid_have() {
+        let mut git = test_git_with_user();
+        git.filesystem.write("a.txt".to_string(), b"a\n").unwrap();
+        add(&mut git, "a.txt".to_string(), b"a\n").unwrap();
+        commit(&mut git, "first".to_string(), false).unwrap();
+        let head = git.rev_parse("HEAD").unwrap();
+
+        // 短い hash や objects の外を指すパスで panic したり stat したりしない
+        for have in ["x", "../../config"] {
+            let mut input = Vec::new();
+            pktline::write_pkt(&mut input, format!("want {}\n", head).as_bytes()).unwrap();
+            pktline::write_flush(&mut input).unwrap();
+            pktline::write_pkt(&mut input, format!("have {}\n", have).as_bytes()).unwrap();
+            let e = upload_pack(&git, input.as_slice(), Vec::new()).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn cmd_upload_pack() {
+        let dir = std::env::temp_dir().join(format!("rgit-upload-pack-{}", std::process::id()));
+        let git_cmd = |args: &[&str]| {
+            std::process::Command::new("git")
+                .arg("-C")
+                .arg(&dir)
+                .args(args)
+                .output()
+        };
+        std::fs::create_dir_all(&dir).unwrap();
+        // git が無ければ確認できない
+        if git_cmd(&["--version"]).is_err() {
+            std::fs::remove_dir_all(&dir).unwrap();
+            return;
+        }
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let server = std::thread::spawn(move || {
+            let mut git = test_git_with_user();
+            for (name, message) in [("a.txt", "first"), ("b.txt", "second")] {
+                git.filesystem.write(name.to_string(), b"x\n").unwrap();
+                add(&mut git, name.to_string(), b"x\n").unwrap();
+                commit(&mut git, message.to_string(), false).unwrap();
+            }
+            tx.send(git.rev_parse("HEAD").unwrap()).unwrap();
+            serve_upload_pack(&git, &listener);
+
+            // 2回目は client が持っている commit を除いた分だけを送る
+            git.filesystem.write("c.txt".to_string(), b"c\n").unwrap();
+            add(&mut git, "c.txt".to_string(), b"c\n").unwrap();
+            commit(&mut git, "third".to_string(), false).unwrap();
+            tx.send(git.rev_parse("HEAD").unwrap()).unwrap();
+            serve_upload_pack(&git, &listener);
+        });
+
+        let url = format!("git://127.0.0.1:{}/repo", port);
+        let second = rx.recv().unwrap();
+        let out = git_cmd(&["clone", "-q", &url, "clone"]).unwrap();
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        let out = git_cmd(&["-C", "clone", "log", "--format=%H %s"]).unwrap();
+        assert_eq!(
+            String::from_utf8(out.stdout).unwrap().lines().next(),
+            Some(format!("{} second", second).as_str())
+        );
+        assert_eq!(std::fs::read(dir.join("clone/b.txt")).unwrap(), b"x\n");
+
+        let third = rx.recv().unwrap();
+        let out = git_cmd(&["-C", "clone", "fetch", "-q", "origin"]).unwrap();
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        let out = git_cmd(&["-C", "clone", "rev-parse", "origin/master"]).unwrap();
+        assert_eq!(String::from_utf8(out.stdout).unwrap().trim(), third);
+
+        server.join().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // git:// の request を読んでから receive-pack を動かす
+    fn serve_receive_pack(git: &mut Git<InMemFileSystem>, listener: &std::net::TcpListener) {
+        let (stream, _) = listener.accept().unwrap();
+        let request = crate::protocol::pktline::PktReader::new(&stream).next();
+        match request {
+            Some(Ok(crate::protocol::pktline::Pkt::Data(data))) => {
+                assert!(data.starts_with(b"git-receive-pack "))
+            }
+            x => panic!("unexpected request {:?}", x),
+        }
+        receive_pack(git, &stream, &stream).unwrap();
+    }
+
+    #[test]
+    fn cmd_receive_pack() {
+        let dir = std::env::temp_dir().join(format!("rgit-receive-pack-{}", std::process::id()));
+        let git_cmd = |args: &[&str]| {
+            std::process::Command::new("git")
+                .arg("-C")
+                .arg(&dir)
+                .args(["-c", "user.name=rgit", "-c", "user.email=rgit@example.com"])
+                .args(args)
+                .output()
+        };
+        std::fs::create_dir_all(&dir).unwrap();
+        // git が無ければ確認できない
+        if git_cmd(&["--version"]).is_err() {
+            std::fs::remove_dir_all(&dir).unwrap();
+            return;
+        }
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (log_tx, log_rx) = std::sync::mpsc::channel();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let server = std::thread::spawn(move || {
+            let mut git = test_git_with_user();
+            git.filesystem.write("a.txt".to_string(), b"a\n").unwrap();
+            add(&mut git, "a.txt".to_string(), b"a\n").unwrap();
+            commit(&mut git, "first".to_string(), false).unwrap();
+            serve_upload_pack(&git, &listener);
+
+            // fast-forward と新しい branch
+            serve_receive_pack(&mut git, &listener);
+            log_tx.send(log(&git, &[]).unwrap()).unwrap();
+            serve_receive_pack(&mut git, &listener);
+            tx.send(git.list_refs().unwrap()).unwrap();
+
+            // 強制 push は receive.denyNonFastForwards = true の時だけ断る
+            let mut config = git.read_config().unwrap();
+            config.set("receive", None, "denyNonFastForwards", "true");
+            git.write_config(&config).unwrap();
+            serve_receive_pack(&mut git, &listener);
+            tx.send(git.list_refs().unwrap()).unwrap();
+            // 設定が無ければ git と同じく受け入れる
+            config.remove_section("receive", None);
+            git.write_config(&config).unwrap();
+            serve_receive_pack(&mut git, &listener);
+            tx.send(git.list_refs().unwrap()).unwrap();
+        });
+
+        let url = format!("git://127.0.0.1:{}/repo", port);
+        let out = git_cmd(&["clone", "-q", &url, "clone"]).unwrap();
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        std::fs::write(dir.join("clone/b.txt"), "b\n").unwrap();
+        git_cmd(&["-C", "clone", "add", "b.txt"]).unwrap();
+        git_cmd(&["-C", "clone", "commit", "-q", "-m", "second"]).unwrap();
+        let second = git_cmd(&["-C", "clone", "rev-parse", "HEAD"])
+            .unwrap()
+            .stdout;
+        let second = String::from_utf8(second).unwrap().trim().to_string();
+        let first = git_cmd(&["-C", "clone", "rev-parse", "HEAD~"])
+            .unwrap()
+            .stdout;
+        let first = String::from_utf8(first).unwrap().trim().to_string();
+
+        let out = git_cmd(&["-C", "clone", "push", "-q", "origin", "master"]).unwrap();
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        let lines = log_rx.recv().unwrap();
+        assert_eq!(lines[0], format!("commit {}", second));
+        assert!(lines.contains(&"    second".to_string()));
+        assert!(lines.contains(&format!("commit {}", first)));
+
+        // object は全て届いているので空の pack で branch を作る
+        let out = git_cmd(&[
+            "-C",
+            "clone",
+            "push",
+            "-q",
+            "origin",
+            "HEAD~:refs/heads/topic",
+        ])
+        .unwrap();
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        assert_eq!(
+            rx.recv().unwrap(),
+            vec![
+                ("refs/heads/master".to_string(), second.clone()),
+                ("refs/heads/topic".to_string(), first.clone()),
+            ]
+        );
+
+        git_cmd(&["-C", "clone", "reset", "-q", "--hard", "HEAD~"]).unwrap();
+        std::fs::write(dir.join("clone/c.txt"), "c\n").unwrap();
+        git_cmd(&["-C", "clone", "add", "c.txt"]).unwrap();
+        git_cmd(&["-C", "clone", "commit", "-q", "-m", "other"]).unwrap();
+        let other = git_cmd(&["-C", "clone", "rev-parse", "HEAD"])
+            .unwrap()
+            .stdout;
+        let other = String::from_utf8(other).unwrap().trim().to_string();
+
+        let out = git_cmd(&["-C", "clone", "push", "-f", "origin", "master", ":topic"]).unwrap();
+        assert!(!out.status.success());
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(stderr.contains("(non-fast-forward)"), "{}", stderr);
+        // atomic でなければ削除の方は通る
+        assert_eq!(
+            rx.recv().unwrap(),
+            vec![("refs/heads/master".to_string(), second.clone())]
+        );
+
+        let out = git_cmd(&["-C", "clone", "push", "-q", "-f", "origin", "master"]).unwrap();
+        assert!(
+            out.status.success(),
+            "{}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        assert_eq!(
+            rx.recv().unwrap(),
+            vec![("refs/heads/master".to_string(), other)]
+        );
+
+        server.join().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cmd_unpack_objects() {
+        let expected = include_str!("../tests/fixtures/objects.txt")
+            .lines()
+            .map(|x| x.split(' ').next().unwrap().to_string())
+            .collect::<Vec<_>>();
+
+        for pack in [
+            include_bytes!("../tests/fixtures/ofs-delta.pack").as_ref(),
+            include_bytes!("../tests/fixtures/ref-delta.pack").as_ref(),
+        ] {
+            let mut git = Git::new(InMemFileSystem::init());
+            git.filesystem
+                .write(".git/test.pack".to_string(), pack)
+                .unwrap();
+            assert_eq!(
+                unpack_objects(&mut git, ".git/test.pack".to_string()).unwrap(),
+                expected.len()
+            );
+
+            let mut hashes = git.loose_objects().unwrap();
+            hashes.sort();
+            let mut expected = expected.clone();
+            expected.sort();
+            assert_eq!(hashes, expected);
+            assert!(git.fsck_loose().unwrap().is_empty());
+        }
+
+        // checksum が壊れていたら何も書き込まない
+        let mut pack = include_bytes!("../tests/fixtures/ofs-delta.pack").to_vec();
+        let last = pack.len() - 1;
+        pack[last] ^= 0xff;
+        let mut git = Git::new(InMemFileSystem::init());
+        git.filesystem
+            .write(".git/test.pack".to_string(), &pack)
+            .unwrap();
+        assert!(unpack_objects(&mut git, ".git/test.pack".to_string()).is_err());
+        assert!(git.loose_objects().unwrap().is_empty());
+    }
+
+    // commit の author と committer に使う user を設定したリポジトリ
+    fn test_git_with_user() -> Git<InMemFileSystem> {
+        test_git_with_hash_algo(HashAlgo::default())
+    }
+
+    fn test_git_with_hash_algo(algo: HashAlgo) -> Git<InMemFileSystem> {
+        let mut git = GitBuilder::new(InMemFileSystem::init())
+            .hash_algo(algo)
+            .build();
+        git.init().unwrap();
+        let mut config = git.read_config().unwrap();
+        config.set("user", None, "name", "rgit");
+        config.set("user", None, "email", "rgit@example.com");
+        git.write_config(&config).unwrap();
+        git
+    }
+
+    fn test_commit(git: &mut Git<InMemFileSystem>, parent: Option<String>, ts: i64) -> String {
+        test_merge(git, &parent.into_iter().collect::<Vec<_>>(), ts)
+    }
+
+    fn test_merge(git: &mut Git<InMemFileSystem>, parents: &[String], ts: i64) -> String {
+        let user = User::new(
+            String::from("rgit"),
+            String::from("rgit@example.com"),
+            FixedOffset::east(0).timestamp(ts, 0),
+        );
+        let commit = GitObject::Commit(Commit::new(
+            "4b825dc642cb6eb9a060e54bf8d69288fbee4904".parse().unwrap(),
+            parents.iter().map(|x| x.parse().unwrap()).collect(),
+            user.clone(),
+            user,
+            format!("commit at {}", ts),
+        ));
+        git.write_object(&commit).unwrap();
+        hex::encode(commit.calc_hash())
+    }
+
+    // #[test]
+    // fn cmd_add() {
+    //     let (testfile, _) = create_test_file(&dir);
+    //     let git = Git::new();
+    //     assert!(add(&git, testfile).is_ok());
+
+    //     let index = git
+    //         .read_index()
+    //         .and_then(|x| git.ls_files_stage(&x))
+    //         .unwrap();
+    // }
+
+    // dir の下に作り, (filename, hash) を返す
+    fn create_test_file(dir: &std::path::Path) -> (String, String) {
+        let testfile = dir
+            .join("hash_object_test.txt")
+            .to_string_lossy()
+            .into_owned();
+        let mut file = File::create(testfile.clone()).unwrap();
+        let buf = "hello, git".as_bytes();
+        file.write_all(buf).unwrap();
+        file.flush().unwrap();
+
+        (
+            testfile,
+            String::from("3edbc45b9a7f744c2345cd2cd073c3de091341ac"),
+        )
+    }
+
+    #[test]
+    fn cmd_index_pack() {
+        let mut git = Git::new(InMemFileSystem::init());
+        git.filesystem
+            .create_dir(".git/objects/pack".to_string())
+            .unwrap();
+        git.filesystem
+            .write(
+                ".git/objects/pack/pack-test.pack".to_string(),
+                include_bytes!("../tests/fixtures/ofs-delta.pack"),
+            )
+            .unwrap();
+
+        let hash = index_pack(&mut git, ".git/objects/pack/pack-test.pack".to_string()).unwrap();
+        let pack = include_bytes!("../tests/fixtures/ofs-delta.pack");
+        assert_eq!(hash, hex::encode(&pack[(pack.len() - 20)..]));
+
+        // git が作った idx と同じになる
+        let idx = git
+            .filesystem
+            .read(".git/objects/pack/pack-test.idx".to_string())
+            .unwrap();
+        assert_eq!(
+            idx,
+            include_bytes!("../tests/fixtures/ofs-delta.idx").to_vec()
+        );
+
+        // read_object から読める
+        for line in include_str!("../tests/fixtures/objects.txt").lines() {
+            let hash = line.split(' ').next().unwrap();
+            let bytes = git.read_packed_object(hash).unwrap().unwrap();
+            assert_eq!(hex::encode(git.hash_algo.digest(&bytes)), hash);
+        }
+
+        assert!(index_pack(&mut git, ".git/objects/pack/pack-test.idx".to_string()).is_err());
+    }
+
+    #[test]
+    fn cmd_fsck_legacy_committer() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let head = test_commit(&mut git, None, 1);
+        assert_eq!(fsck(&git, false).unwrap(), Vec::<String>::new());
+
+        let mut commit = git.read_commit(&head).unwrap();
+        commit.legacy_committer = true;
+        let commit = GitObject::Commit(commit);
+        git.write_object(&commit).unwrap();
+        assert_eq!(
+            fsck(&git, false).unwrap(),
+            vec![format!(
+                "misspelled committer header in {}",
+                hex::encode(commit.calc_hash())
+            )]
+        );
+    }
+
+    #[test]
+    fn cmd_prune() {
+        let mut git = Git::new(InMemFileSystem::init());
+        git.write_object(&GitObject::Tree(Tree::new(Vec::new())))
+            .unwrap();
+        let head = test_commit(&mut git, None, 1);
+        git.update_ref("refs/heads/master".to_string(), &head.parse().unwrap())
+            .unwrap();
+        let orphan = GitObject::Blob(Blob::new("orphan".to_string()));
+        git.write_object(&orphan).unwrap();
+        let orphan = hex::encode(orphan.calc_hash());
+
+        assert_eq!(
+            fsck(&git, true).unwrap(),
+            vec![format!("unreachable {}", orphan)]
+        );
+
+        // -n では削除しない
+        assert_eq!(prune(&mut git, true).unwrap(), vec![orphan.clone()]);
+        assert!(git.loose_objects().unwrap().contains(&orphan));
+
+        assert_eq!(prune(&mut git, false).unwrap(), vec![orphan.clone()]);
+        assert!(!git.loose_objects().unwrap().contains(&orphan));
+        assert!(git.loose_objects().unwrap().contains(&head));
+        assert!(prune(&mut git, false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn cmd_prune_packed() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let packed = GitObject::Blob(Blob::new("packed".to_string()));
+        let loose = GitObject::Blob(Blob::new("loose".to_string()));
+        git.write_object(&packed).unwrap();
+        git.write_object(&loose).unwrap();
+        let packed = hex::encode(packed.calc_hash());
+        let loose = hex::encode(loose.calc_hash());
+
+        git.filesystem
+            .create_dir(".git/objects/pack".to_string())
+            .unwrap();
+        pack_objects(
+            &mut git,
+            ".git/objects/pack/pack".to_string(),
+            std::slice::from_ref(&packed),
+        )
+        .unwrap();
+
+        assert_eq!(prune_packed(&mut git, true).unwrap(), vec![packed.clone()]);
+        assert!(git.loose_objects().unwrap().contains(&packed));
+
+        assert_eq!(prune_packed(&mut git, false).unwrap(), vec![packed.clone()]);
+        assert_eq!(git.loose_objects().unwrap(), vec![loose]);
+        assert!(git
+            .filesystem
+            .read_dir(format!(".git/objects/{}", &packed[..2]))
+            .is_err());
+
+        // pack から読める
+        let object = git
+            .read_object(&packed.parse().unwrap())
+            .and_then(|x| git.cat_file_p(&x))
+            .unwrap();
+        assert_eq!(hex::encode(object.calc_hash()), packed);
+    }
+
+    #[test]
+    fn cmd_remote() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let args = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+
+        remote(&mut git, &args(&["add", "origin", "/srv/repo name?a=b"])).unwrap();
+        remote(
+            &mut git,
+            &args(&["add", "upstream", "https://example.com/repo"]),
+        )
+        .unwrap();
+        assert!(remote(&mut git, &args(&["add", "origin", "/tmp"])).is_err());
+
+        assert_eq!(
+            remote(&mut git, &args(&[])).unwrap(),
+            vec!["origin", "upstream"]
+        );
+        assert_eq!(
+            remote(&mut git, &args(&["-v"])).unwrap()[..2],
+            [
+                "origin\t/srv/repo name?a=b (fetch)",
+                "origin\t/srv/repo name?a=b (push)"
+            ]
+        );
+
+        let remotes = git.remotes().unwrap();
+        assert_eq!(remotes[0].url, "/srv/repo name?a=b");
+        assert_eq!(
+            remotes[0].fetch[0].to_string(),
+            "+refs/heads/*:refs/remotes/origin/*"
+        );
+
+        // refs/remotes/origin も消える
+        git.filesystem
+            .create_dir(".git/refs/remotes".to_string())
+            .unwrap();
+        git.filesystem
+            .create_dir(".git/refs/remotes/origin".to_string())
+            .unwrap();
+        git.update_ref(
+            "refs/remotes/origin/main".to_string(),
+            &ObjectId::from_bytes(&[0xab; 20]).unwrap(),
+        )
+        .unwrap();
+        remote(&mut git, &args(&["remove", "origin"])).unwrap();
+        assert_eq!(remote(&mut git, &args(&[])).unwrap(), vec!["upstream"]);
+        assert!(git
+            .filesystem
+            .read_dir(".git/refs/remotes/origin".to_string())
+            .is_err());
+        assert!(remote(&mut git, &args(&["remove", "origin"])).is_err());
+    }
+
+    #[test]
+    fn cmd_reflog() {
+        let mut git = Git::new(InMemFileSystem::init());
+        git.init().unwrap();
+        assert!(reflog(&git, "HEAD".to_string()).unwrap().is_empty());
+
+        let log = "\
+0000000000000000000000000000000000000000 0f6a66d5e5e4a1cc5b3d22e49e3ca71a5acb2ab5 alice <alice@example.com> 1609642799 +0900\tcommit (initial): first
+0f6a66d5e5e4a1cc5b3d22e49e3ca71a5acb2ab5 9f1a8a1bc5bce39a7e7e0ac2d1da3a4e6bd57c2a bob <bob@example.com> 1609646400 +0000\tcommit: second
+";
+        git.filesystem.create_dir(".git/logs".to_string()).unwrap();
+        git.filesystem
+            .write(".git/logs/HEAD".to_string(), log.as_bytes())
+            .unwrap();
+
+        let entries = reflog(&git, "HEAD".to_string()).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].old, "0".repeat(40));
+        assert_eq!(entries[0].new, "0f6a66d5e5e4a1cc5b3d22e49e3ca71a5acb2ab5");
+        assert_eq!(entries[0].user.name, "alice");
+        assert_eq!(entries[0].user.email, "alice@example.com");
+        assert_eq!(entries[0].user.ts.timestamp(), 1609642799);
+        assert_eq!(entries[0].user.ts.offset().local_minus_utc(), 9 * 60 * 60);
+        assert_eq!(entries[0].message, "commit (initial): first");
+        assert_eq!(entries[1].old, "0f6a66d5e5e4a1cc5b3d22e49e3ca71a5acb2ab5");
+        assert_eq!(entries[1].new, "9f1a8a1bc5bce39a7e7e0ac2d1da3a4e6bd57c2a");
+        assert_eq!(entries[1].user.name, "bob");
+        assert_eq!(entries[1].user.email, "bob@example.com");
+        assert_eq!(entries[1].user.ts.timestamp(), 1609646400);
+        assert_eq!(entries[1].message, "commit: second");
+
+        git.filesystem
+            .write(".git/logs/HEAD".to_string(), b"broken\n")
+            .unwrap();
+        let e = reflog(&git, "HEAD".to_string()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(reflog(&git, "../config".to_string()).is_err());
+    }
+
+    #[test]
+    fn cmd_notes() {
+        let mut git = GitBuilder::new(InMemFileSystem::init())
+            .user("rgit", "rgit@example.com")
+            .clock(FixedClock(
+                FixedOffset::east(9 * 60 * 60).timestamp(1609642799, 0),
+            ))
+            .build();
+        let head = test_commit(&mut git, None, 1);
+        git.update_ref("refs/heads/master".to_string(), &head.parse().unwrap())
+            .unwrap();
+        let args = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+
+        assert!(notes(&mut git, &args(&["show"])).is_err());
+        notes(&mut git, &args(&["add", "-m", "reviewed"])).unwrap();
+        // note の commit も他の commit と同じく設定された作者と時刻を使う
+        let note = git
+            .read_ref("refs/notes/commits".to_string())
+            .and_then(|x| git.read_commit(&x))
+            .unwrap();
+        assert_eq!(note.author.name, "rgit");
+        assert_eq!(note.committer.email, "rgit@example.com");
+        assert_eq!(note.committer.ts.timestamp(), 1609642799);
+        assert_eq!(
+            notes(&mut git, &args(&["show", &head])).unwrap(),
+            vec!["reviewed"]
+        );
+
+        notes(&mut git, &args(&["add", "-f", "-m", "edited", "HEAD"])).unwrap();
+        assert_eq!(notes(&mut git, &args(&["show"])).unwrap(), vec!["edited"]);
+
+        let lines = log(&git, &args(&["--show-notes"])).unwrap();
+        assert_eq!(lines[0], format!("commit {}", head));
+        assert_eq!(lines[2], "Date:   Thu Jan 1 00:00:01 1970 +0000");
+        assert_eq!(lines[4..], ["    commit at 1", "", "Notes:", "    edited"]);
+        assert_eq!(log(&git, &args(&[])).unwrap().len(), 5);
+        assert_eq!(
+            log(&git, &args(&["--abbrev-commit"])).unwrap()[0],
+            format!("commit {}", &head[..7])
+        );
+        assert_eq!(
+            log(&git, &args(&["--abbrev=12", "HEAD"])).unwrap()[0],
+            format!("commit {}", &head[..12])
+        );
+
+        notes(&mut git, &args(&["remove"])).unwrap();
+        assert!(notes(&mut git, &args(&["show"])).is_err());
+        assert!(notes(&mut git, &args(&["remove"])).is_err());
+    }
+
+    #[test]
+    fn cmd_update_index() {
+        let mut git = test_git_with_user();
+        let args = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        for name in ["a.txt", "b.txt"] {
+            git.filesystem.write(name.to_string(), b"x\n").unwrap();
+        }
+        // 新しいファイルは --add が無いと追加しない
+        assert!(update_index(&mut git, &args(&["a.txt"])).is_err());
+        update_index(&mut git, &args(&["--add", "a.txt", "b.txt"])).unwrap();
+        commit(&mut git, "first".to_string(), false).unwrap();
+
+        git.filesystem.remove("a.txt".to_string()).unwrap();
+        let e = update_index(&mut git, &args(&["a.txt"])).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "a.txt: does not exist and --remove not passed"
+        );
+        update_index(&mut git, &args(&["--remove", "a.txt"])).unwrap();
+        let index = git.load_index().unwrap();
+        assert_eq!(index.entries.len(), 1);
+        assert_eq!(index.entries[0].name, "b.txt");
+
+        // 削除も commit に反映される
+        commit(&mut git, "remove a.txt".to_string(), false).unwrap();
+        let tree = git
+            .read_commit(&git.rev_parse("HEAD").unwrap())
+            .unwrap()
+            .tree;
+        let files = git.read_tree_files(&tree).unwrap();
+        assert_eq!(
+            files.iter().map(|x| x.0.as_str()).collect::<Vec<_>>(),
+            vec!["b.txt"]
+        );
+    }
+
+    #[test]
+    fn cmd_add_all() {
+        let mut git = Git::new(InMemFileSystem::init());
+        git.filesystem.create_dir("src".to_string()).unwrap();
+        git.filesystem
+            .write("README.md".to_string(), b"readme")
+            .unwrap();
+        git.filesystem
+            .write("src/lib.rs".to_string(), b"lib")
+            .unwrap();
+        git.filesystem
+            .write("src/main.rs".to_string(), b"main")
+            .unwrap();
+
+        add_all(&mut git).unwrap();
+        let names = |git: &Git<InMemFileSystem>| {
+            git.load_index()
+                .unwrap()
+                .entries
+                .iter()
+                .map(|x| x.name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&git), vec!["README.md", "src/lib.rs", "src/main.rs"]);
+
+        git.filesystem
+            .write("README.md".to_string(), b"changed")
+            .unwrap();
+        git.filesystem.remove("src/lib.rs".to_string()).unwrap();
+        git.filesystem.write("new.txt".to_string(), b"new").unwrap();
+        add_all(&mut git).unwrap();
+        assert_eq!(names(&git), vec!["README.md", "new.txt", "src/main.rs"]);
+
+        let index = git.load_index().unwrap();
+        let changed = GitObject::Blob(Blob::new("changed".to_string()));
+        assert_eq!(index.entries[0].hash, changed.calc_hash());
+        assert!(git.has_object(&changed.calc_hash()).unwrap());
+
+        // 1ファイルの add も同じ経路で index を更新する
+        git.filesystem
+            .write("src/main.rs".to_string(), b"main2")
+            .unwrap();
+        add(&mut git, "src/main.rs".to_string(), b"main2").unwrap();
+        let index = git.load_index().unwrap();
+        assert_eq!(index.entries.len(), 3);
+        assert_eq!(
+            index.entries[2].hash,
+            GitObject::Blob(Blob::new("main2".to_string())).calc_hash()
+        );
+    }
+
+    #[test]
+    fn cmd_commit_all() {
+        let mut git = test_git_with_user();
+        for (name, bytes) in [("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c")] {
+            git.filesystem.write(name.to_string(), bytes).unwrap();
+            add(&mut git, name.to_string(), bytes).unwrap();
+        }
+        commit(&mut git, "first".to_string(), false).unwrap();
+
+        git.filesystem.write("a.txt".to_string(), b"a2").unwrap();
+        git.filesystem.remove("b.txt".to_string()).unwrap();
+        git.filesystem
+            .write("untracked.txt".to_string(), b"u")
+            .unwrap();
+        let index = git.load_index().unwrap();
+        assert_eq!(git.modified_files(&index).unwrap(), vec!["a.txt"]);
+        assert_eq!(git.deleted_files(&index).unwrap(), vec!["b.txt"]);
+
+        commit_all(&mut git, "second".to_string(), false).unwrap();
+        let head = git.read_commit(&git.rev_parse("HEAD").unwrap()).unwrap();
+        assert_eq!(head.message, b"second\n");
+        let tree = match git.read_git_object(&head.tree).unwrap() {
+            GitObject::Tree(tree) => tree,
+            _ => panic!("not a tree"),
+        };
+        let files = tree
+            .contents
+            .iter()
+            .map(|x| (x.name.as_str(), x.hash.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            vec![
+                (
+                    "a.txt",
+                    GitObject::Blob(Blob::new("a2".to_string())).calc_hash()
+                ),
+                (
+                    "c.txt",
+                    GitObject::Blob(Blob::new("c".to_string())).calc_hash()
+                ),
+            ]
+        );
+
+        // 変更が無ければ何も検出しない
+        let index = git.load_index().unwrap();
+        assert!(git.modified_files(&index).unwrap().is_empty());
+        assert!(git.deleted_files(&index).unwrap().is_empty());
+    }
+
+    #[test]
+    fn cmd_commit_nested() {
+        let mut git = test_git_with_user();
+        for dir in ["src", "src/object", "tests"] {
+            git.filesystem.create_dir(dir.to_string()).unwrap();
+        }
+        // "src.rs" は "src/" より前に並ぶ
+        for name in [
+            "README",
+            "src/main.rs",
+            "src/object/mod.rs",
+            "src/object/tree.rs",
+            "tests/a.rs",
+            "src.rs",
+        ] {
+            git.filesystem.write(name.to_string(), b"hello\n").unwrap();
+            add(&mut git, name.to_string(), b"hello\n").unwrap();
+        }
+        commit(&mut git, "nested".to_string(), false).unwrap();
+
+        // 同じファイルを git add して git write-tree した時の hash
+        let head = git.read_commit(&git.rev_parse("HEAD").unwrap()).unwrap();
+        assert_eq!(
+            head.tree.to_hex(),
+            "afddfb1d403ddd2438b3f76d79a654b5af08d229"
+        );
+        for (path, hash) in [
+            ("", "afddfb1d403ddd2438b3f76d79a654b5af08d229"),
+            ("src", "88c4c18d085b3e345805c4691333b64b7d9ac66a"),
+            ("src/object", "e2cea4bfdaccb4493c703af76296bbbbffdf81ba"),
+            ("tests", "6d8125b25060bd4c96d3f10d673da42da77a2cd3"),
+        ] {
+            assert!(git.has_object(&hash.parse().unwrap()).unwrap(), "{}", path);
+        }
+        assert_eq!(
+            ls_tree(&git, "HEAD", true).unwrap(),
+            vec![
+                "100644 blob ce013625030ba8dba906f756967f9e9ca394464a\tREADME",
+                "100644 blob ce013625030ba8dba906f756967f9e9ca394464a\tsrc.rs",
+                "100644 blob ce013625030ba8dba906f756967f9e9ca394464a\tsrc/main.rs",
+                "100644 blob ce013625030ba8dba906f756967f9e9ca394464a\tsrc/object/mod.rs",
+                "100644 blob ce013625030ba8dba906f756967f9e9ca394464a\tsrc/object/tree.rs",
+                "100644 blob ce013625030ba8dba906f756967f9e9ca394464a\ttests/a.rs",
+            ]
+        );
+    }
+
+    #[test]
+    fn cmd_count_objects() {
+        let mut git = Git::new(InMemFileSystem::init());
+        git.init().unwrap();
+        let hash = git.hash_object_write(b"hello\n").unwrap();
+        let compressed = git.read_object(&hash.parse().unwrap()).unwrap().len();
+
+        assert_eq!(
+            count_objects(&git).unwrap(),
+            vec![
+                format!("blob 1 {} 13", compressed),
+                "tree 0 0 0".to_string(),
+                "commit 0 0 0".to_string(),
+                format!("total 1 {} 13", compressed),
+            ]
+        );
+    }
+
+    #[test]
+    fn cmd_commit_fixed_clock() {
+        let mut git = GitBuilder::new(InMemFileSystem::init())
+            .user("rgit", "rgit@example.com")
+            .clock(FixedClock(
+                FixedOffset::east(9 * 60 * 60).timestamp(1609642799, 0),
+            ))
+            .build();
+        git.filesystem.write("a.txt".to_string(), b"a").unwrap();
+        add(&mut git, "a.txt".to_string(), b"a").unwrap();
+        commit(&mut git, "first".to_string(), false).unwrap();
+
+        let hash = git.rev_parse("HEAD").unwrap();
+        let object = git
+            .read_object(&hash.parse().unwrap())
+            .and_then(|x| git.cat_file_p(&x))
+            .unwrap();
+        // 環境変数や config に関係なく, 何度実行しても同じ commit になる
+        let expected = [
+            "tree 1a602d9bd07ce5272ddaa64e21da12dbca2b8c9f",
+            "author rgit <rgit@example.com> 1609642799 +0900",
+            "committer rgit <rgit@example.com> 1609642799 +0900",
+            "",
+            "first",
+            "",
+        ]
+        .join("\n");
+        assert_eq!(object.content(), expected.as_bytes());
+        // git hash-object -t commit で同じ中身から求めた hash
+        assert_eq!(hash, "dfe5693f297184c93aee3037fa5ac396949d7635");
+    }
+
+    #[test]
+    fn cmd_commit_empty() {
+        let mut git = test_git_with_user();
+        let nothing = |r: io::Result<()>| r.unwrap_err().to_string() == "nothing to commit";
+
+        // 空の index の最初の commit
+        git.write_index(&Index::new(Vec::new())).unwrap();
+        assert!(nothing(commit(&mut git, "initial".to_string(), false)));
+        assert!(git.rev_parse("HEAD").is_err());
+
+        git.filesystem.write("a.txt".to_string(), b"a").unwrap();
+        add(&mut git, "a.txt".to_string(), b"a").unwrap();
+        commit(&mut git, "first".to_string(), false).unwrap();
+        let first = git.rev_parse("HEAD").unwrap();
+
+        // 親と同じ tree
+        assert!(nothing(commit(&mut git, "again".to_string(), false)));
+        assert!(nothing(commit_all(&mut git, "again".to_string(), false)));
+        assert_eq!(git.rev_parse("HEAD").unwrap(), first);
+
+        commit(&mut git, "empty".to_string(), true).unwrap();
+        let head = git.read_commit(&git.rev_parse("HEAD").unwrap()).unwrap();
+        assert_eq!(head.parents, vec![first.parse().unwrap()]);
+        assert_eq!(Some(head.tree), git.head_tree().unwrap());
+
+        git.filesystem.write("a.txt".to_string(), b"a2").unwrap();
+        commit_all(&mut git, "changed".to_string(), false).unwrap();
+        assert_ne!(git.rev_parse("HEAD").unwrap(), first);
+    }
+
+    #[test]
+    fn cmd_commit_args() {
+        let mut git = test_git_with_user();
+        git.filesystem.write("a.txt".to_string(), b"a").unwrap();
+        add(&mut git, "a.txt".to_string(), b"a").unwrap();
+        let args = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        let head = |git: &Git<InMemFileSystem>| {
+            git.read_commit(&git.rev_parse("HEAD").unwrap())
+                .unwrap()
+                .message
+        };
+
+        commit_args(
+            &mut git,
+            &args(&["-m", "subject", "-m", "body\n"]),
+            io::empty(),
+        )
+        .unwrap();
+        assert_eq!(head(&git), b"subject\n\nbody\n");
+
+        git.filesystem
+            .write("MSG".to_string(), b"from file\n\n\nkeeps blank lines\n\n")
+            .unwrap();
+        commit_args(
+            &mut git,
+            &args(&["--allow-empty", "-F", "MSG"]),
+            io::empty(),
+        )
+        .unwrap();
+        assert_eq!(head(&git), b"from file\n\n\nkeeps blank lines\n");
+
+        commit_args(
+            &mut git,
+            &args(&["-F", "-", "--allow-empty"]),
+            &b"from stdin"[..],
+        )
+        .unwrap();
+        assert_eq!(head(&git), b"from stdin\n");
+
+        git.filesystem.write("a.txt".to_string(), b"a2").unwrap();
+        commit_args(&mut git, &args(&["-am", "all"]), io::empty()).unwrap();
+        assert_eq!(head(&git), b"all\n");
+        assert!(git
+            .modified_files(&git.load_index().unwrap())
+            .unwrap()
+            .is_empty());
+
+        assert!(commit_args(&mut git, &args(&["-m"]), io::empty()).is_err());
+        assert!(commit_args(&mut git, &args(&[]), io::empty()).is_err());
+        assert!(commit_args(&mut git, &args(&["-x", "msg"]), io::empty()).is_err());
+    }
+
+    #[test]
+    fn cmd_checkout() {
+        // sha256 のリポジトリでも同じように動く
+        for algo in [HashAlgo::Sha1, HashAlgo::Sha256] {
+            let mut git = test_git_with_hash_algo(algo);
+            let args = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            git.filesystem.write("a.txt".to_string(), b"a").unwrap();
+            add(&mut git, "a.txt".to_string(), b"a").unwrap();
+            commit(&mut git, "first".to_string(), false).unwrap();
+            let first = git.rev_parse("HEAD").unwrap();
+
+            // 新しい branch に切り替えるだけで worktree はそのまま
+            git.filesystem.write("a.txt".to_string(), b"dirty").unwrap();
+            checkout(&mut git, &args(&["-b", "feature/x"])).unwrap();
+            assert_eq!(git.head_ref().unwrap(), "refs/heads/feature/x");
+            assert_eq!(git.rev_parse("HEAD").unwrap(), first);
+            assert_eq!(git.filesystem.read("a.txt".to_string()).unwrap(), b"dirty");
+            assert_eq!(
+                checkout(&mut git, &args(&["-b", "feature/x"]))
+                    .unwrap_err()
+                    .kind(),
+                io::ErrorKind::AlreadyExists
+            );
+
+            git.filesystem
+                .write("b.sh".to_string(), b"#!/bin/sh\n")
+                .unwrap();
+            add(&mut git, "a.txt".to_string(), b"dirty").unwrap();
+            add(&mut git, "b.sh".to_string(), b"#!/bin/sh\n").unwrap();
+            commit(&mut git, "second".to_string(), false).unwrap();
+            let second = git.rev_parse("HEAD").unwrap();
+
+            // 変更があれば tree を切り替えない
+            git.filesystem.write("a.txt".to_string(), b"again").unwrap();
+            assert!(checkout(&mut git, &args(&["-b", "old", "master"])).is_err());
+            assert!(git.read_ref("refs/heads/old".to_string()).is_err());
+            git.filesystem.write("a.txt".to_string(), b"dirty").unwrap();
+
+            // 起点を指定すると tree も切り替える
+            checkout(&mut git, &args(&["-b", "old", "master"])).unwrap();
+            assert_eq!(git.head_ref().unwrap(), "refs/heads/old");
+            assert_eq!(git.rev_parse("HEAD").unwrap(), first);
+            assert_eq!(git.filesystem.read("a.txt".to_string()).unwrap(), b"a");
+            assert!(!git.filesystem.exists("b.sh".to_string()));
+            let index = git.load_index().unwrap();
+            assert_eq!(index.entries.len(), 1);
+            assert!(git.modified_files(&index).unwrap().is_empty());
+
+            checkout(&mut git, &args(&["feature/x"])).unwrap();
+            assert_eq!(git.rev_parse("HEAD").unwrap(), second);
+            assert_eq!(
+                git.filesystem.read("b.sh".to_string()).unwrap(),
+                b"#!/bin/sh\n"
+            );
+            assert!(checkout(&mut git, &args(&["unknown"])).is_err());
+        }
+    }
+
+    #[test]
+    fn cmd_checkout_untracked_overwritten() {
+        let mut git = test_git_with_user();
+        let args = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        git.filesystem.write("a.txt".to_string(), b"a").unwrap();
+        add(&mut git, "a.txt".to_string(), b"a").unwrap();
+        commit(&mut git, "first".to_string(), false).unwrap();
+
+        checkout(&mut git, &args(&["-b", "feature"])).unwrap();
+        git.filesystem.create_dir("dir".to_string()).unwrap();
+        for path in ["b.txt", "dir/c.txt"] {
+            git.filesystem.write(path.to_string(), b"theirs").unwrap();
+            add(&mut git, path.to_string(), b"theirs").unwrap();
+        }
+        commit(&mut git, "second".to_string(), false).unwrap();
+        let second = git.rev_parse("HEAD").unwrap();
+        checkout(&mut git, &args(&["master"])).unwrap();
+        assert!(!git.filesystem.exists("b.txt".to_string()));
+        git.filesystem.remove_dir("dir".to_string()).unwrap();
+
+        // tree に同じ名前のファイルがあれば untracked なファイルを上書きしない
+        git.filesystem.write("b.txt".to_string(), b"mine").unwrap();
+        let err = checkout(&mut git, &args(&["feature"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the following untracked working tree files would be overwritten by checkout:\n\tb.txt"
+        );
+        assert_eq!(git.filesystem.read("b.txt".to_string()).unwrap(), b"mine");
+        assert_eq!(git.head_ref().unwrap(), "refs/heads/master");
+        git.filesystem.remove("b.txt".to_string()).unwrap();
+
+        // ディレクトリが入る場所にあるファイルも失われる
+        git.filesystem.write("dir".to_string(), b"mine").unwrap();
+        let err = checkout(&mut git, &args(&["feature"])).unwrap_err();
+        assert!(err.to_string().ends_with("\n\tdir"));
+        git.filesystem.remove("dir".to_string()).unwrap();
+
+        // 中身が同じか, 無視されているファイルなら上書きしてよい
+        git.filesystem
+            .write("b.txt".to_string(), b"theirs")
+            .unwrap();
+        checkout(&mut git, &args(&["feature"])).unwrap();
+        assert_eq!(git.rev_parse("HEAD").unwrap(), second);
+        checkout(&mut git, &args(&["master"])).unwrap();
+
+        git.filesystem
+            .write(".gitignore".to_string(), b"b.txt\n")
+            .unwrap();
+        git.filesystem
+            .write("b.txt".to_string(), b"ignored")
+            .unwrap();
+        checkout(&mut git, &args(&["feature"])).unwrap();
+        assert_eq!(git.filesystem.read("b.txt".to_string()).unwrap(), b"theirs");
+    }
+
+    #[test]
+    fn cmd_sha256_git_interop() {
+        use crate::fs::linux::LinuxFileSystem;
+
+        let dir = std::env::temp_dir().join(format!("rgit-sha256-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut git = GitBuilder::new(LinuxFileSystem::with_root(dir.clone()))
+            .hash_algo(HashAlgo::Sha256)
+            .user("rgit", "rgit@example.com")
+            .build();
+        git.init().unwrap();
+        git.filesystem.create_dir("src".to_string()).unwrap();
+        for (path, content) in [("a.txt", "a\n"), ("src/lib.rs", "lib\n")] {
+            git.filesystem
+                .write(path.to_string(), content.as_bytes())
+                .unwrap();
+            add(&mut git, path.to_string(), content.as_bytes()).unwrap();
+        }
+        commit(&mut git, "first".to_string(), false).unwrap();
+        git.filesystem.write("a.txt".to_string(), b"A\n").unwrap();
+        commit_all(&mut git, "second".to_string(), false).unwrap();
+        let head = git.rev_parse("HEAD").unwrap();
+        assert_eq!(head.len(), 64);
+        let expected = git
+            .load_index()
+            .unwrap()
+            .entries
+            .iter()
+            .map(|x| format!("{:o} {} 0\t{}\n", x.mode, x.hash, x.name))
+            .collect::<String>();
+
+        // git が使えるなら同じリポジトリとして読めて壊れていない
+        let run = |args: &[&str]| {
+            std::process::Command::new("git")
+                .arg("-C")
+                .arg(&dir)
+                .args(args)
+                .output()
+        };
+        let log = run(&["log", "--format=%H %s"]);
+        let fsck = run(&["fsck", "--strict"]);
+        let ls_files = run(&["ls-files", "-s"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let (log, fsck, ls_files) = match (log, fsck, ls_files) {
+            (Ok(log), Ok(fsck), Ok(ls_files)) => (log, fsck, ls_files),
+            _ => return,
+        };
+        assert_eq!(
+            String::from_utf8(log.stdout).unwrap().lines().next(),
+            Some(format!("{} second", head).as_str())
+        );
+        assert!(
+            fsck.status.success(),
+            "{}",
+            String::from_utf8_lossy(&fsck.stderr)
+        );
+        assert_eq!(String::from_utf8(ls_files.stdout).unwrap(), expected);
+    }
+
+    #[test]
+    fn cmd_commit_executable() {
+        let mut git = test_git_with_user();
+        let args = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        git.filesystem
+            .write("run.sh".to_string(), b"#!/bin/sh\n")
+            .unwrap();
+        git.filesystem
+            .set_mode("run.sh".to_string(), 0o755)
+            .unwrap();
+        git.filesystem.write("a.txt".to_string(), b"a").unwrap();
+        add(&mut git, "run.sh".to_string(), b"#!/bin/sh\n").unwrap();
+        add(&mut git, "a.txt".to_string(), b"a").unwrap();
+        commit(&mut git, "first".to_string(), false).unwrap();
+        let first = git.rev_parse("HEAD").unwrap();
+
+        let lines = ls_tree(&git, "HEAD", false).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("100644 blob "));
+        assert!(lines[0].ends_with("\ta.txt"));
+        assert!(lines[1].starts_with("100755 blob "));
+        assert!(lines[1].ends_with("\trun.sh"));
+
+        // 実行権限を外した commit から戻すと checkout で権限も戻る
+        git.filesystem
+            .set_mode("run.sh".to_string(), 0o644)
+            .unwrap();
+        add(&mut git, "run.sh".to_string(), b"#!/bin/sh\n").unwrap();
+        commit(&mut git, "second".to_string(), false).unwrap();
+        assert!(ls_tree(&git, "HEAD", false).unwrap()[1].starts_with("100644 blob "));
+
+        checkout(&mut git, &args(&["-b", "old", &first])).unwrap();
+        assert_eq!(
+            git.filesystem.stat("run.sh".to_string()).unwrap().mode,
+            0o100755
+        );
+        assert!(ls_tree(&git, "HEAD", false).unwrap()[1].starts_with("100755 blob "));
+    }
+
+    #[test]
+    fn cmd_commit_symlink() {
+        let mut git = test_git_with_user();
+        let args = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        // 指す先の無い symlink
+        git.filesystem
+            .symlink("v2/".to_string(), "latest".to_string())
+            .unwrap();
+        git.filesystem.write("a.txt".to_string(), b"a").unwrap();
+        assert_eq!(
+            add_path(&mut git, "latest".to_string()).unwrap(),
+            vec!["latest"]
+        );
+        add_all(&mut git).unwrap();
+        commit(&mut git, "first".to_string(), false).unwrap();
+        let first = git.rev_parse("HEAD").unwrap();
+
+        // blob は指している先のパス. git hash-object で "v2/" を書いた hash と同じ
+        assert_eq!(
+            ls_tree(&git, "HEAD", false).unwrap(),
+            vec![
+                "100644 blob 2e65efe2a145dda7ee51d1741299f848e5bf752e\ta.txt",
+                "120000 blob 523c98efad9352a25849f1a3e24b864813559930\tlatest",
+            ]
+        );
+        let index = git.load_index().unwrap();
+        assert!(git.modified_files(&index).unwrap().is_empty());
+
+        // 指す先を変えると変更になる. 中身ではなくパスを比べる
+        git.filesystem.remove("latest".to_string()).unwrap();
+        git.filesystem
+            .symlink("v3/".to_string(), "latest".to_string())
+            .unwrap();
+        assert_eq!(git.modified_files(&index).unwrap(), vec!["latest"]);
+        commit_all(&mut git, "second".to_string(), false).unwrap();
+
+        // ファイルに置き換えた commit から戻すと symlink を作り直す
+        git.filesystem.remove("latest".to_string()).unwrap();
+        git.filesystem.write("latest".to_string(), b"file").unwrap();
+        add_all(&mut git).unwrap();
+        commit(&mut git, "third".to_string(), false).unwrap();
+        assert!(ls_tree(&git, "HEAD", false).unwrap()[1].starts_with("100644 blob "));
+
+        checkout(&mut git, &args(&["-b", "old", &first])).unwrap();
+        assert_eq!(
+            git.filesystem.read_link("latest".to_string()).unwrap(),
+            "v2/"
+        );
+        let index = git.load_index().unwrap();
+        assert!(git.modified_files(&index).unwrap().is_empty());
+    }
+
+    #[test]
+    fn cmd_switch() {
+        let mut git = test_git_with_user();
+        let args = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        git.filesystem.write("a.txt".to_string(), b"a").unwrap();
+        add(&mut git, "a.txt".to_string(), b"a").unwrap();
+        commit(&mut git, "first".to_string(), false).unwrap();
+
+        switch(&mut git, &args(&["-c", "topic"])).unwrap();
+        assert_eq!(git.head_ref().unwrap(), "refs/heads/topic");
+        git.filesystem.write("a.txt".to_string(), b"topic").unwrap();
+        commit_all(&mut git, "topic".to_string(), false).unwrap();
+
+        // 変更されたファイルがあれば切り替えない
+        git.filesystem.write("a.txt".to_string(), b"dirty").unwrap();
+        assert!(switch(&mut git, &args(&["master"])).is_err());
+        assert_eq!(git.head_ref().unwrap(), "refs/heads/topic");
+        assert_eq!(git.filesystem.read("a.txt".to_string()).unwrap(), b"dirty");
+
+        git.filesystem.write("a.txt".to_string(), b"topic").unwrap();
+        switch(&mut git, &args(&["master"])).unwrap();
+        assert_eq!(git.head_ref().unwrap(), "refs/heads/master");
+        assert_eq!(git.filesystem.read("a.txt".to_string()).unwrap(), b"a");
+        assert!(switch(&mut git, &args(&["-c", "topic"])).is_err());
+        assert!(switch(&mut git, &args(&["unknown"])).is_err());
+    }
+
+    #[test]
+    fn cmd_index_writes_locked() {
+        let mut git = test_git_with_user();
+        let args = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        git.filesystem.write("a.txt".to_string(), b"a\n").unwrap();
+        add(&mut git, "a.txt".to_string(), b"a\n").unwrap();
+        commit(&mut git, "first".to_string(), false).unwrap();
+        let tree = git.head_tree().unwrap().unwrap();
+        git.filesystem.write("a.txt".to_string(), b"b\n").unwrap();
+        add(&mut git, "a.txt".to_string(), b"b\n").unwrap();
+        git.filesystem
+            .write(
+                "a.patch".to_string(),
+                b"--- a/a.txt\n+++ b/a.txt\n@@ -1 +1 @@\n-b\n+c\n",
+            )
+            .unwrap();
+        let before = git.read_index().unwrap();
+
+        // 他の rgit が index の lock を持っている間は index を読み書きしない
+        git.filesystem
+            .write(".git/index.lock".to_string(), b"")
+            .unwrap();
+        let locked = |e: io::Error| e.kind() == io::ErrorKind::AlreadyExists;
+        assert!(locked(
+            restore(&mut git, &args(&["--staged", "a.txt"])).unwrap_err()
+        ));
+        assert!(locked(
+            apply(&mut git, "a.patch".to_string(), false, true).unwrap_err()
+        ));
+        assert!(locked(reset_tree(&mut git, &tree).unwrap_err()));
+        assert_eq!(git.read_index().unwrap(), before);
+        assert_eq!(git.filesystem.read("a.txt".to_string()).unwrap(), b"b\n");
+        assert_eq!(
+            git.filesystem.read(".git/index.lock".to_string()).unwrap(),
+            b""
+        );
+
+        git.filesystem
+            .remove(".git/index.lock".to_string())
+            .unwrap();
+        apply(&mut git, "a.patch".to_string(), false, true).unwrap();
+        let index = git.load_index().unwrap();
+        let hash = index.get_entry("a.txt").unwrap().hash.to_hex();
+        assert_eq!(git.read_blob(&hash).unwrap(), b"c\n");
+        reset_tree(&mut git, &tree).unwrap();
+        assert_eq!(git.filesystem.read("a.txt".to_string()).unwrap(), b"a\n");
+        assert!(!git.filesystem.exists(".git/index.lock".to_string()));
+    }
+
+    #[test]
+    fn cmd_restore() {
+        let mut git = test_git_with_user();
+        let args = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        let read =
+            |git: &Git<InMemFileSystem>, path: &str| git.filesystem.read(path.to_string()).unwrap();
+        let staged = |git: &Git<InMemFileSystem>, path: &str| {
+            let index = git.load_index().unwrap();
+            let hash = index.get_entry(path).map(|x| hex::encode(&x.hash));
+            hash.map(|x| git.read_blob(&x).unwrap())
+        };
+        git.filesystem.write("a.txt".to_string(), b"a").unwrap();
+        git.filesystem.write("b.txt".to_string(), b"b").unwrap();
+        add(&mut git, "a.txt".to_string(), b"a").unwrap();
+        add(&mut git, "b.txt".to_string(), b"b").unwrap();
+        commit(&mut git, "first".to_string(), false).unwrap();
+
+        // index から worktree に戻す
+        git.filesystem.write("a.txt".to_string(), b"a2").unwrap();
+        add(&mut git, "a.txt".to_string(), b"a2").unwrap();
+        git.filesystem.write("a.txt".to_string(), b"a3").unwrap();
+        git.filesystem.remove("b.txt".to_string()).unwrap();
+        restore(&mut git, &args(&["a.txt", "b.txt"])).unwrap();
+        assert_eq!(read(&git, "a.txt"), b"a2");
+        assert_eq!(read(&git, "b.txt"), b"b");
+
+        // --staged は HEAD の内容を index にだけ戻す
+        restore(&mut git, &args(&["--staged", "*.txt"])).unwrap();
+        assert_eq!(staged(&git, "a.txt").unwrap(), b"a");
+        assert_eq!(read(&git, "a.txt"), b"a2");
+
+        // 新しく追加したファイルは index から外れる
+        git.filesystem.write("c.txt".to_string(), b"c").unwrap();
+        add(&mut git, "c.txt".to_string(), b"c").unwrap();
+        restore(&mut git, &args(&["--staged", "c.txt"])).unwrap();
+        assert_eq!(staged(&git, "c.txt"), None);
+        assert_eq!(read(&git, "c.txt"), b"c");
+
+        // --source で取り出す commit を選ぶ
+        add(&mut git, "a.txt".to_string(), b"a2").unwrap();
+        commit(&mut git, "second".to_string(), false).unwrap();
+        restore(&mut git, &args(&["--source", "HEAD", "a.txt"])).unwrap();
+        assert_eq!(read(&git, "a.txt"), b"a2");
+        let first = git
+            .read_commit(&git.rev_parse("HEAD").unwrap())
+            .unwrap()
+            .parents
+            .remove(0);
+        restore(&mut git, &args(&[&format!("--source={}", first), "a.txt"])).unwrap();
+        assert_eq!(read(&git, "a.txt"), b"a");
+        assert_eq!(staged(&git, "a.txt").unwrap(), b"a2");
+
+        assert!(restore(&mut git, &args(&["missing.txt"])).is_err());
+        assert!(restore(&mut git, &args(&["--staged"])).is_err());
+    }
+
+    #[test]
+    fn cmd_format_patch() {
+        let mut git = test_git_with_user();
+        let args = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        git.filesystem
+            .write("a.txt".to_string(), b"one\ntwo\n")
+            .unwrap();
+        add(&mut git, "a.txt".to_string(), b"one\ntwo\n").unwrap();
+        commit(&mut git, "first".to_string(), false).unwrap();
+        let first = git.rev_parse("HEAD").unwrap();
+
+        git.filesystem
+            .write("a.txt".to_string(), b"one\nTWO\n")
+            .unwrap();
+        git.filesystem.write("b.txt".to_string(), b"b\n").unwrap();
+        add(&mut git, "a.txt".to_string(), b"one\nTWO\n").unwrap();
+        add(&mut git, "b.txt".to_string(), b"b\n").unwrap();
+        commit(
+            &mut git,
+            "Fix: a.txt & add b.txt\n\nlonger description".to_string(),
+            false,
+        )
+        .unwrap();
+        git.filesystem.remove("b.txt".to_string()).unwrap();
+        commit_all(&mut git, "remove b".to_string(), false).unwrap();
+
+        let names = format_patch(&mut git, &args(&[&first])).unwrap();
+        assert_eq!(
+            names,
+            vec!["0001-Fix-a.txt-add-b.txt.patch", "0002-remove-b.patch"]
+        );
+        let text = String::from_utf8(git.filesystem.read(names[0].clone()).unwrap()).unwrap();
+        let head = text.lines().take(4).collect::<Vec<_>>();
+        assert!(head[0].starts_with("From ") && head[0].ends_with(" Mon Sep 17 00:00:00 2001"));
+        assert_eq!(head[1], "From: rgit <rgit@example.com>");
+        assert!(head[2].starts_with("Date: "));
+        assert_eq!(head[3], "Subject: [PATCH 1/2] Fix: a.txt & add b.txt");
+        assert!(text.contains("\n\nlonger description\n---\ndiff --git a/a.txt b/a.txt\n"));
+        assert!(text.contains("new file mode 100644\n--- /dev/null\n+++ b/b.txt\n"));
+
+        // --stdout は全てのパッチを続けて返し, ファイルは作らない
+        let lines =
+            format_patch(&mut git, &args(&["--stdout", &format!("{}..HEAD", first)])).unwrap();
+        assert_eq!(lines.iter().filter(|x| x.starts_with("From ")).count(), 2);
+        assert!(lines.contains(&"Subject: [PATCH 2/2] remove b".to_string()));
+        assert!(lines.contains(&"deleted file mode 100644".to_string()));
+
+        // 書き出したパッチを最初の状態に当てると同じ内容になる
+        git.filesystem
+            .write("a.txt".to_string(), b"one\ntwo\n")
+            .unwrap();
+        apply(&mut git, names[0].clone(), false, false).unwrap();
+        assert_eq!(
+            git.filesystem.read("a.txt".to_string()).unwrap(),
+            b"one\nTWO\n"
+        );
+        assert_eq!(git.filesystem.read("b.txt".to_string()).unwrap(), b"b\n");
+        apply(&mut git, names[1].clone(), false, false).unwrap();
+        assert!(!git.filesystem.exists("b.txt".to_string()));
+    }
+
+    #[test]
+    fn cmd_format_patch_mode_and_binary() {
+        let mut git = test_git_with_user();
+        let args = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        let commit_files = |git: &mut Git<InMemFileSystem>, files: &[(&str, &str, &[u8])]| {
+            let mut input = String::new();
+            for (mode, name, content) in files {
+                let hash = git.hash_object_write(content).unwrap();
+                input.push_str(&format!("{} blob {}\t{}\n", mode, hash, name));
+            }
+            let tree = mktree(git, &[], input.as_bytes()).unwrap().parse().unwrap();
+            let user = User::new(
+                "rgit".to_string(),
+                "rgit@example.com".to_string(),
+                FixedOffset::east(0).timestamp(0, 0),
+            );
+            let commit = git
+                .commit_tree(user, None, &tree, "change\n".to_string())
+                .unwrap();
+            let commit = GitObject::Commit(commit);
+            git.write_object(&commit).unwrap();
+            let hash = commit.calc_hash_with(git.hash_algo);
+            git.update_ref("refs/heads/master".to_string(), &hash)
+                .unwrap();
+            hash.to_hex()
+        };
+        let first = commit_files(
+            &mut git,
+            &[
+                ("100644", "image.bin", b"\0\x01"),
+                ("100644", "run.sh", b"echo\n"),
+            ],
+        );
+        commit_files(
+            &mut git,
+            &[
+                ("100644", "image.bin", b"\0\x02"),
+                ("100755", "new.sh", b"x\n"),
+                ("100755", "run.sh", b"echo\n"),
+            ],
+        );
+
+        let lines = format_patch(&mut git, &args(&["--stdout", &first])).unwrap();
+        let text = lines.join("\n");
+        assert!(text.contains(
+            "diff --git a/image.bin b/image.bin\nBinary files a/image.bin and b/image.bin differ\n"
+        ));
+        assert!(
+            text.contains("diff --git a/new.sh b/new.sh\nnew file mode 100755\n--- /dev/null\n")
+        );
+        assert!(text.contains("diff --git a/run.sh b/run.sh\nold mode 100644\nnew mode 100755\n"));
+
+        // binary のパッチは当てられない
+        git.filesystem
+            .write("0001.patch".to_string(), text.as_bytes())
+            .unwrap();
+        let e = apply(&mut git, "0001.patch".to_string(), true, false).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn cmd_am() {
+        let mut git = test_git_with_user();
+        let args = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        git.filesystem
+            .write("a.txt".to_string(), b"one\ntwo\n")
+            .unwrap();
+        add(&mut git, "a.txt".to_string(), b"one\ntwo\n").unwrap();
+        commit(&mut git, "first".to_string(), false).unwrap();
+        let first = git.rev_parse("HEAD").unwrap();
+        git.filesystem
+            .write("a.txt".to_string(), b"one\nTWO\n")
+            .unwrap();
+        git.filesystem.write("b.txt".to_string(), b"b\n").unwrap();
+        add(&mut git, "a.txt".to_string(), b"one\nTWO\n").unwrap();
+        add(&mut git, "b.txt".to_string(), b"b\n").unwrap();
+        commit(&mut git, "second\n\nbody".to_string(), false).unwrap();
+        git.filesystem.remove("b.txt".to_string()).unwrap();
+        commit_all(&mut git, "third".to_string(), false).unwrap();
+        let master_tree = git.head_tree().unwrap();
+
+        // 別の author のパッチとして受け取る
+        let mbox = format_patch(&mut git, &args(&["--stdout", &first]))
+            .unwrap()
+            .join("\n")
+            .replace(
+                "From: rgit <rgit@example.com>",
+                "From: Other <other@example.com>",
+            )
+            .lines()
+            .map(|x| match x.starts_with("Date: ") {
+                true => "Date: Sun, 3 Jan 2021 11:59:59 +0900\n".to_string(),
+                false => format!("{}\n", x),
+            })
+            .collect::<String>();
+        git.filesystem
+            .write("series.mbox".to_string(), mbox.as_bytes())
+            .unwrap();
+        checkout(&mut git, &args(&["-b", "other", &first])).unwrap();
+
+        let lines = am(&mut git, &args(&["series.mbox"])).unwrap();
+        assert_eq!(lines, vec!["Applying: second", "Applying: third"]);
+        assert_eq!(git.head_tree().unwrap(), master_tree);
+        assert!(!git.filesystem.exists(".git/rebase-apply".to_string()));
+        assert!(!git.filesystem.exists("b.txt".to_string()));
+        assert!(git
+            .modified_files(&git.load_index().unwrap())
+            .unwrap()
+            .is_empty());
+
+        let third = git.read_commit(&git.rev_parse("HEAD").unwrap()).unwrap();
+        assert_eq!(third.author.name, "Other");
+        assert_eq!(third.author.email, "other@example.com");
+        assert_eq!(third.author.ts.timestamp(), 1609642799);
+        assert_eq!(third.committer.name, "rgit");
+        let second = git.read_commit(&third.parents[0].to_hex()).unwrap();
+        assert_eq!(second.message, b"second\n\nbody\n");
+        assert_eq!(second.parents, vec![first.parse().unwrap()]);
+
+        // 2 通目で止まると状態が残り, --skip で残りを続ける
+        checkout(&mut git, &args(&["-b", "retry", &first])).unwrap();
+        git.filesystem
+            .write("a.txt".to_string(), b"one\ntwo\nthree\n")
+            .unwrap();
+        add(&mut git, "a.txt".to_string(), b"one\ntwo\nthree\n").unwrap();
+        commit(&mut git, "diverge".to_string(), false).unwrap();
+        let diverge = git.rev_parse("HEAD").unwrap();
+        let broken = mbox.replace("-b\n", "-x\n");
+        git.filesystem
+            .write("series.mbox".to_string(), broken.as_bytes())
+            .unwrap();
+        let e = am(&mut git, &args(&["series.mbox"])).unwrap_err();
+        assert!(e.to_string().contains("patch failed at 0002 third"));
+        assert!(git.filesystem.exists(".git/rebase-apply/0002".to_string()));
+        assert_eq!(
+            git.read_commit(&git.rev_parse("HEAD").unwrap())
+                .unwrap()
+                .message,
+            b"second\n\nbody\n"
+        );
+        assert_eq!(
+            am(&mut git, &args(&["series.mbox"])).unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        assert!(am(&mut git, &args(&["--skip"])).unwrap().is_empty());
+        assert!(!git.filesystem.exists(".git/rebase-apply".to_string()));
+        assert!(git.filesystem.exists("b.txt".to_string()));
+
+        // --abort は状態を消し, HEAD と index, worktree を始める前に戻す
+        checkout(&mut git, &args(&["-b", "abort", &diverge])).unwrap();
+        let before = git.rev_parse("HEAD").unwrap();
+        let before_tree = git.head_tree().unwrap();
+        git.filesystem
+            .write("series.mbox".to_string(), broken.as_bytes())
+            .unwrap();
+        assert!(am(&mut git, &args(&["series.mbox"])).is_err());
+        assert_ne!(git.rev_parse("HEAD").unwrap(), before);
+        assert_eq!(
+            git.read_special_ref("ORIG_HEAD").unwrap(),
+            Some(before.clone())
+        );
+        git.filesystem.write("c.txt".to_string(), b"c\n").unwrap();
+        add(&mut git, "c.txt".to_string(), b"c\n").unwrap();
+        am(&mut git, &args(&["--abort"])).unwrap();
+        assert!(!git.filesystem.exists(".git/rebase-apply".to_string()));
+        assert_eq!(git.head_ref().unwrap(), "refs/heads/abort");
+        assert_eq!(git.rev_parse("HEAD").unwrap(), before);
+        let index = git.load_index().unwrap();
+        assert!(index.get_entry("c.txt").is_none());
+        assert!(!git.filesystem.exists("c.txt".to_string()));
+        assert!(git.modified_files(&index).unwrap().is_empty());
+        assert_eq!(
+            git.write_tree().unwrap().last().map(|x| x.0.clone()),
+            before_tree
+        );
+        assert!(am(&mut git, &args(&["--abort"])).is_err());
+    }
+
+    #[test]
+    fn cmd_bundle() {
+        let mut git = test_git_with_user();
+        let args = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        git.filesystem.write("a.txt".to_string(), b"a\n").unwrap();
+        add(&mut git, "a.txt".to_string(), b"a\n").unwrap();
+        commit(&mut git, "first".to_string(), false).unwrap();
+        git.filesystem.create_dir("src".to_string()).unwrap();
+        git.filesystem
+            .write("src/lib.rs".to_string(), b"lib\n")
+            .unwrap();
+        add(&mut git, "src/lib.rs".to_string(), b"lib\n").unwrap();
+        commit(&mut git, "second".to_string(), false).unwrap();
+        switch(&mut git, &args(&["-c", "topic"])).unwrap();
+        git.filesystem.write("a.txt".to_string(), b"A\n").unwrap();
+        commit_all(&mut git, "topic".to_string(), false).unwrap();
+        switch(&mut git, &args(&["master"])).unwrap();
+
+        bundle(
+            &mut git,
+            &args(&["create", "repo.bundle", "HEAD", "master", "topic"]),
+        )
+        .unwrap();
+        let bytes = git.filesystem.read("repo.bundle".to_string()).unwrap();
+        let heads = bundle(&mut git, &args(&["list-heads", "repo.bundle"])).unwrap();
+        let master = git.rev_parse("master").unwrap();
+        let topic = git.rev_parse("topic").unwrap();
+        assert_eq!(
+            heads,
+            vec![
+                format!("{} HEAD", master),
+                format!("{} refs/heads/master", master),
+                format!("{} refs/heads/topic", topic),
+            ]
+        );
+        assert_eq!(
+            bundle(&mut git, &args(&["verify", "repo.bundle"])).unwrap(),
+            vec!["repo.bundle is okay"]
+        );
+
+        // 別のリポジトリに clone すると同じ commit になる
+        let mut other = Git::new(InMemFileSystem::init());
+        other
+            .filesystem
+            .write("repo.bundle".to_string(), &bytes)
+            .unwrap();
+        clone_bundle(&mut other, "repo.bundle".to_string()).unwrap();
+        assert_eq!(other.head_ref().unwrap(), "refs/heads/master");
+        assert_eq!(other.rev_parse("HEAD").unwrap(), master);
+        assert_eq!(other.rev_parse("refs/remotes/origin/topic").unwrap(), topic);
+        assert_eq!(
+            rev_list(&other, &args(&["refs/remotes/origin/topic"])).unwrap(),
+            rev_list(&git, &args(&["topic"])).unwrap()
+        );
+        assert_eq!(
+            other.filesystem.read("src/lib.rs".to_string()).unwrap(),
+            b"lib\n"
+        );
+        assert_eq!(other.remotes().unwrap()[0].url, "repo.bundle");
+
+        // unbundle は bundle の ref をそのまま作る
+        let mut other = Git::new(InMemFileSystem::init());
+        other
+            .filesystem
+            .write("repo.bundle".to_string(), &bytes)
+            .unwrap();
+        assert_eq!(
+            bundle(&mut other, &args(&["unbundle", "repo.bundle"])).unwrap(),
+            heads
+        );
+        assert_eq!(other.rev_parse("topic").unwrap(), topic);
+
+        // 前提の commit が無い bundle は使えない
+        let mut broken = Bundle::from(&bytes).unwrap();
+        broken.prerequisites.push("ab".repeat(20));
+        other
+            .filesystem
+            .write("broken.bundle".to_string(), &broken.as_bytes())
+            .unwrap();
+        assert_eq!(
+            bundle(&mut other, &args(&["verify", "broken.bundle"]))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+        assert!(bundle(&mut other, &args(&["create", "x.bundle"])).is_err());
+    }
+
+    #[test]
+    fn cmd_push() {
+        let mut git = test_git_with_user();
+        let mut remote = test_git_with_user();
+        git.add_remote(&Remote::new("origin".to_string(), "../remote".to_string()))
+            .unwrap();
+        git.filesystem.write("a.txt".to_string(), b"a\n").unwrap();
+        add(&mut git, "a.txt".to_string(), b"a\n").unwrap();
+        commit(&mut git, "first".to_string(), false).unwrap();
+        let first = git.rev_parse("master").unwrap();
+
+        assert_eq!(
+            push(&mut git, &mut remote, "origin", "master", false).unwrap(),
+            vec!["To ../remote", " * [new branch]      master -> master"]
+        );
+        assert_eq!(remote.rev_parse("master").unwrap(), first);
+        assert_eq!(
+            git.read_ref("refs/remotes/origin/master".to_string())
+                .unwrap(),
+            first
+        );
+        assert_eq!(
+            reachable::walk(&remote, vec![first.clone()]).unwrap().len(),
+            3
+        );
+        assert_eq!(
+            push(&mut git, &mut remote, "origin", "master", false).unwrap(),
+            vec!["Everything up-to-date"]
+        );
+
+        // fast-forward なら足りない object だけ書き写す
+        git.filesystem.write("b.txt".to_string(), b"b\n").unwrap();
+        add(&mut git, "b.txt".to_string(), b"b\n").unwrap();
+        commit(&mut git, "second".to_string(), false).unwrap();
+        let second = git.rev_parse("master").unwrap();
+        assert_eq!(
+            push(&mut git, &mut remote, "origin", "master", false).unwrap()[1],
+            format!("   {}..{}  master -> master", &first[..7], &second[..7])
+        );
+        assert_eq!(remote.rev_parse("master").unwrap(), second);
+        assert_eq!(
+            copy_objects(&git, &mut remote, vec![second.clone()]).unwrap(),
+            0
+        );
+
+        // remote にしか無い commit があれば --force が要る
+        remote
+            .filesystem
+            .write("c.txt".to_string(), b"c\n")
+            .unwrap();
+        add(&mut remote, "c.txt".to_string(), b"c\n").unwrap();
+        commit(&mut remote, "remote".to_string(), false).unwrap();
+        let err = push(&mut git, &mut remote, "origin", "master", false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "! [rejected] master -> master (non-fast-forward)"
+        );
+        assert_ne!(remote.rev_parse("master").unwrap(), second);
+        assert!(
+            push(&mut git, &mut remote, "origin", "master", true).unwrap()[1]
+                .ends_with("master -> master (forced update)")
+        );
+        assert_eq!(remote.rev_parse("master").unwrap(), second);
+
+        assert_eq!(
+            push(&mut git, &mut remote, "upstream", "master", false)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+        assert!(push(&mut git, &mut remote, "origin", "topic", false).is_err());
+    }
+
+    #[test]
+    fn cmd_ls_remote() {
+        let mut remote = Git::new(InMemFileSystem::init());
+        let args = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        let first = test_commit(&mut remote, None, 1);
+        let second = test_commit(&mut remote, Some(first.clone()), 2);
+        remote
+            .update_ref("refs/heads/master".to_string(), &second.parse().unwrap())
+            .unwrap();
+        remote
+            .filesystem
+            .create_dir(".git/refs/tags".to_string())
+            .unwrap();
+        remote
+            .update_ref("refs/tags/v1".to_string(), &first.parse().unwrap())
+            .unwrap();
+        // packed-refs の ref はファイルの ref と混ぜて並べ, 同じ名前ならファイルを使う
+        remote
+            .filesystem
+            .write(
+                ".git/packed-refs".to_string(),
+                format!(
+                    "# pack-refs with: peeled fully-peeled sorted\n{first} refs/heads/master\n{first} refs/heads/old\n{first} refs/tags/v0\n^{second}\n",
+                    first = first,
+                    second = second
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+
+        let all = ls_remote(&remote, &[]).unwrap();
+        assert_eq!(
+            all,
+            vec![
+                format!("{}\tHEAD", second),
+                format!("{}\trefs/heads/master", second),
+                format!("{}\trefs/heads/old", first),
+                format!("{}\trefs/tags/v0", first),
+                format!("{}\trefs/tags/v1", first),
+            ]
+        );
+        assert_eq!(
+            ls_remote(&remote, &args(&["--heads"])).unwrap(),
+            all[1..3].to_vec()
+        );
+        assert_eq!(
+            ls_remote(&remote, &args(&["--tags"])).unwrap(),
+            all[3..].to_vec()
+        );
+        assert_eq!(
+            ls_remote(&remote, &args(&["--heads", "--tags"])).unwrap(),
+            all[1..].to_vec()
+        );
+
+        // upload-pack の広告からも同じ結果になる
+        struct Advertised(Vec<u8>);
+        impl Transport for Advertised {
+            fn advertisement(&mut self) -> io::Result<fetch::Advertisement> {
+                fetch::Advertisement::read(self.0.as_slice())
+            }
+            fn fetch_pack(
+                &mut self,
+                _: &fetch::Advertisement,
+                _: &[u8],
+                _: &mut dyn FnMut(&[u8]),
+            ) -> io::Result<Vec<u8>> {
+                unreachable!()
+            }
+        }
+        let mut adv = Vec::new();
+        upload_pack(&remote, b"0000".as_ref(), &mut adv).unwrap();
+        assert_eq!(ls_remote_transport(&mut Advertised(adv), &[]).unwrap(), all);
+
+        // HEAD の指す branch がまだ無ければ HEAD は出さない
+        let empty = Git::new(InMemFileSystem::init());
+        assert!(ls_remote(&empty, &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn cmd_fetch() {
+        let mut git = test_git_with_user();
+        let mut remote = test_git_with_user();
+        let args = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        git.add_remote(&Remote::new("origin".to_string(), "../remote".to_string()))
+            .unwrap();
+        remote
+            .filesystem
+            .write("a.txt".to_string(), b"a\n")
+            .unwrap();
+        add(&mut remote, "a.txt".to_string(), b"a\n").unwrap();
+        commit(&mut remote, "first".to_string(), false).unwrap();
+        let first = remote.rev_parse("master").unwrap();
+        switch(&mut remote, &args(&["-c", "topic"])).unwrap();
+        remote
+            .filesystem
+            .write("b.txt".to_string(), b"b\n")
+            .unwrap();
+        add(&mut remote, "b.txt".to_string(), b"b\n").unwrap();
+        commit(&mut remote, "topic".to_string(), false).unwrap();
+        let topic = remote.rev_parse("topic").unwrap();
+
+        assert_eq!(
+            fetch(&mut git, &remote, "origin", false).unwrap(),
+            vec![
+                "From ../remote",
+                " * [new branch]      master -> origin/master",
+                " * [new branch]      topic -> origin/topic",
+            ]
+        );
+        assert_eq!(git.rev_parse("refs/remotes/origin/topic").unwrap(), topic);
+        assert!(git.read_commit(&topic).is_ok());
+        assert!(fetch(&mut git, &remote, "origin", false)
+            .unwrap()
+            .is_empty());
+
+        // `+` の付いた refspec は fast-forward でなくても更新する
+        create_ref(&mut remote, "refs/heads/topic", &first).unwrap();
+        assert_eq!(
+            fetch(&mut git, &remote, "origin", false).unwrap()[1],
+            format!(
+                " + {}...{} topic -> origin/topic  (forced update)",
+                &topic[..7],
+                &first[..7]
+            )
+        );
+
+        git.remove_remote("origin").unwrap();
+        let mut config = Remote::new("origin".to_string(), "../remote".to_string());
+        config.fetch = vec![RefSpec::from("refs/heads/*:refs/remotes/origin/*").unwrap()];
+        git.add_remote(&config).unwrap();
+        // remote を消すと追跡ブランチも消える
+        assert_eq!(fetch(&mut git, &remote, "origin", false).unwrap().len(), 3);
+        create_ref(&mut remote, "refs/heads/topic", &topic).unwrap();
+        assert_eq!(
+            fetch(&mut git, &remote, "origin", false).unwrap()[1],
+            format!("   {}..{}  topic -> origin/topic", &first[..7], &topic[..7])
+        );
+        create_ref(&mut remote, "refs/heads/topic", &first).unwrap();
+        assert_eq!(
+            fetch(&mut git, &remote, "origin", false).unwrap()[1],
+            " ! [rejected]        topic -> origin/topic  (non-fast-forward)"
+        );
+        assert_eq!(git.rev_parse("refs/remotes/origin/topic").unwrap(), topic);
+
+        // remote で消えたブランチは --prune の時だけ消す
+        remote.delete_ref("refs/heads/topic".to_string()).unwrap();
+        assert!(fetch(&mut git, &remote, "origin", false)
+            .unwrap()
+            .is_empty());
+        assert!(git
+            .read_ref("refs/remotes/origin/topic".to_string())
+            .is_ok());
+        assert_eq!(
+            fetch(&mut git, &remote, "origin", true).unwrap(),
+            vec![
+                "From ../remote",
+                " - [deleted]         (none) -> origin/topic"
+            ]
+        );
+        assert!(git
+            .read_ref("refs/remotes/origin/topic".to_string())
+            .is_err());
+        assert!(fetch(&mut git, &remote, "upstream", false).is_err());
+    }
+
+    #[test]
+    fn cmd_fast_import() {
+        let stream = b"blob\nmark :1\ndata 3\n\xff\x01\n\n\
+            blob\nmark :2\ndata 6\nstart\n\n\
+            commit refs/heads/master\nmark :3\n\
+            author A U Thor <author@example.com> 1600000000 +0000\n\
+            committer C O Mitter <committer@example.com> 1600000001 +0000\n\
+            data 6\nfirst\n\
+            M 100644 :1 assets/logo.bin\nM 100755 :2 src/bin/main.sh\nM 644 :2 README\n\n\
+            commit refs/heads/topic\nmark :4\n\
+            committer C O Mitter <committer@example.com> 1600000002 +0000\n\
+            data 7\nsecond\n\
+            from :3\nD src/bin\n\n\
+            reset refs/tags/v1\nfrom :3\n\n";
+        let mut git = Git::new(InMemFileSystem::init());
+        assert_eq!(
+            fast_import(&mut git, stream).unwrap(),
+            vec!["blobs: 2", "commits: 2", "refs: 3"]
+        );
+
+        let master = git.rev_parse("master").unwrap();
+        assert_eq!(git.rev_parse("v1").unwrap(), master);
+        let first = git.read_commit(&master).unwrap();
+        assert_eq!(first.author.name, "A U Thor");
+        assert_eq!(first.committer.name, "C O Mitter");
+        assert_eq!(first.message, b"first\n");
+        let files = git.read_tree_files(&first.tree).unwrap();
+        let names = files
+            .iter()
+            .map(|x| (x.0.as_str(), x.1))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                ("README", MODE_FILE),
+                ("assets/logo.bin", MODE_FILE),
+                ("src/bin/main.sh", MODE_EXECUTABLE)
+            ]
+        );
+        assert_eq!(
+            git.read_blob(&hex::encode(&files[1].2)).unwrap(),
+            b"\xff\x01\n"
+        );
+
+        let topic = git.read_commit(&git.rev_parse("topic").unwrap()).unwrap();
+        assert_eq!(topic.parents, vec![master.parse().unwrap()]);
+        assert_eq!(topic.author.name, "C O Mitter");
+        let files = git.read_tree_files(&topic.tree).unwrap();
+        assert_eq!(files.len(), 2);
+
+        // 自分の fast-export を読ませると同じ hash になる
+        let mut other = Git::new(InMemFileSystem::init());
+        fast_import(&mut other, &fast_export(&git).unwrap()).unwrap();
+        assert_eq!(other.list_refs().unwrap(), git.list_refs().unwrap());
+
+        let mut git = test_git_with_user();
+        git.filesystem.write("a.txt".to_string(), b"a\n").unwrap();
+        add(&mut git, "a.txt".to_string(), b"a\n").unwrap();
+        commit(&mut git, "first".to_string(), false).unwrap();
+        git.filesystem.write("b.txt".to_string(), b"b\n").unwrap();
+        add(&mut git, "b.txt".to_string(), b"b\n").unwrap();
+        commit(&mut git, "second".to_string(), false).unwrap();
+        let mut other = Git::new(InMemFileSystem::init());
+        fast_import(&mut other, &fast_export(&git).unwrap()).unwrap();
+        assert_eq!(other.list_refs().unwrap(), git.list_refs().unwrap());
+
+        let mut other = Git::new(InMemFileSystem::init());
+        assert_eq!(
+            fast_import(&mut other, b"commit refs/heads/x\ndata 0\n")
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+        let stream = b"commit refs/heads/x\n\
+            committer C <c@example.com> 0 +0000\ndata 0\nM 100644 :9 a\n";
+        assert_eq!(
+            fast_import(&mut other, stream).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        assert!(!is_same_or_nested("src/binary", "src/bin"));
+        assert!(is_same_or_nested("src", "src/bin"));
+    }
+
+    #[test]
+    fn cmd_fast_import_merge() {
+        let stream = b"blob\nmark :1\ndata 2\na\n\n\
+            commit refs/heads/master\nmark :2\n\
+            committer C O Mitter <committer@example.com> 1600000000 +0000\n\
+            data 6\nfirst\nM 100644 :1 a.txt\n\n\
+            commit refs/heads/topic\nmark :3\n\
+            committer C O Mitter <committer@example.com> 1600000001 +0000\n\
+            data 6\ntopic\nfrom :2\nM 100644 :1 b.txt\n\n\
+            commit refs/heads/side\nmark :4\n\
+            committer C O Mitter <committer@example.com> 1600000002 +0000\n\
+            data 5\nside\nfrom :2\nM 100644 :1 c.txt\n\n\
+            commit refs/heads/master\nmark :5\n\
+            committer C O Mitter <committer@example.com> 1600000003 +0000\n\
+            data 6\nmerge\nfrom :2\nmerge :3\nmerge :4\n\
+            M 100644 :1 b.txt\nM 100644 :1 c.txt\n\n";
+        let mut git = Git::new(InMemFileSystem::init());
+        fast_import(&mut git, stream).unwrap();
+        let master = git.rev_parse("master").unwrap();
+        let merge = git.read_commit(&master).unwrap();
+        // from, merge の順に parent が並ぶ
+        let topic = git.read_commit(&git.rev_parse("topic").unwrap()).unwrap();
+        let parents = vec![
+            topic.parents[0].clone(),
+            git.rev_parse("topic").unwrap().parse().unwrap(),
+            git.rev_parse("side").unwrap().parse().unwrap(),
+        ];
+        assert_eq!(merge.parents, parents);
+        assert_eq!(merge.message, b"merge\n");
+
+        // export した stream を読み込み直しても同じ commit になる
+        let stream = fast_export(&git).unwrap();
+        let text = String::from_utf8(stream.clone()).unwrap();
+        assert!(text.contains("from :"));
+        assert_eq!(text.matches("\nmerge :").count(), 2);
+        let mut other = Git::new(InMemFileSystem::init());
+        fast_import(&mut other, &stream).unwrap();
+        assert_eq!(other.rev_parse("master").unwrap(), master);
+        assert_eq!(other.rev_parse("topic").unwrap(), parents[1].to_hex());
+        assert_eq!(other.rev_parse("side").unwrap(), parents[2].to_hex());
+    }
+
+    #[test]
+    fn cmd_clean() {
+        let mut git = Git::new(InMemFileSystem::init());
+        git.filesystem.create_dir("src".to_string()).unwrap();
+        git.filesystem.create_dir("target".to_string()).unwrap();
+        git.filesystem.write("a.txt".to_string(), b"a").unwrap();
+        add(&mut git, "a.txt".to_string(), b"a").unwrap();
+        git.filesystem
+            .write(".gitignore".to_string(), b"target/\n*.log\n")
+            .unwrap();
+        git.filesystem.write("b.txt".to_string(), b"b").unwrap();
+        git.filesystem
+            .write("src/new.rs".to_string(), b"new")
+            .unwrap();
+        git.filesystem
+            .write("target/rgit".to_string(), b"bin")
+            .unwrap();
+        git.filesystem
+            .write("debug.log".to_string(), b"log")
+            .unwrap();
+        git.filesystem
+            .write(".git/info.txt".to_string(), b"git")
+            .unwrap();
+
+        // dry run は一覧を返すだけ
+        assert_eq!(
+            clean(&mut git, true).unwrap(),
+            vec![
+                "Would remove .gitignore",
+                "Would remove b.txt",
+                "Would remove src/new.rs"
+            ]
+        );
+        assert!(git.filesystem.exists("b.txt".to_string()));
+
+        add(&mut git, ".gitignore".to_string(), b"target/\n*.log\n").unwrap();
+        assert_eq!(
+            clean(&mut git, false).unwrap(),
+            vec!["Removing b.txt", "Removing src/new.rs"]
+        );
+        let fs = &git.filesystem;
+        assert!(!fs.exists("b.txt".to_string()));
+        assert!(!fs.exists("src/new.rs".to_string()));
+        // tracked, 無視されたファイルと .git の中は残る
+        for path in [
+            "a.txt",
+            ".gitignore",
+            "target/rgit",
+            "debug.log",
+            ".git/info.txt",
+        ] {
+            assert!(fs.exists(path.to_string()), "{}", path);
+        }
+        assert!(clean(&mut git, true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn cmd_add_binary() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\xff\xfe";
+        git.filesystem.write("logo.png".to_string(), png).unwrap();
+        add_all(&mut git).unwrap();
+
+        // git hash-object logo.png と同じ
+        let index = git.load_index().unwrap();
+        let hash = hex::encode(&index.get_entry("logo.png").unwrap().hash);
+        assert_eq!(hash, "ac46b867e6e844c117dd10ed279bb55d6c934c28");
+        let object = git
+            .read_object(&hash.parse().unwrap())
+            .and_then(|x| git.cat_file_p(&x))
+            .unwrap();
+        assert_eq!(object.content(), png);
+    }
+
+    #[test]
+    fn cmd_check_ignore() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let args = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        let fs = &mut git.filesystem;
+        for dir in ["src", "src/gen", "out", ".git/info"] {
+            fs.create_dir(dir.to_string()).unwrap();
+        }
+        for (path, bytes) in [
+            (".git/info/exclude", "*.swp\n"),
+            (".gitignore", "*.log\nout/\n"),
+            ("src/.gitignore", "!keep.log\ngen/\n"),
+            ("out/.gitignore", "!*\n"),
+            ("a.txt", "a"),
+            ("a.log", "log"),
+            ("a.swp", "swp"),
+            ("src/keep.log", "keep"),
+            ("src/debug.log", "debug"),
+            ("src/gen/x.rs", "gen"),
+            ("out/bin", "bin"),
+        ] {
+            fs.write(path.to_string(), bytes.as_bytes()).unwrap();
+        }
+
+        assert_eq!(
+            check_ignore(
+                &git,
+                &args(&[
+                    "a.txt",
+                    "a.log",
+                    "a.swp",
+                    "src/keep.log",
+                    "src/debug.log",
+                    "src/gen",
+                    "out/bin"
+                ])
+            )
+            .unwrap(),
+            vec!["a.log", "a.swp", "src/debug.log", "src/gen", "out/bin"]
+        );
+        assert_eq!(
+            check_ignore(&git, &args(&["-v", "a.swp", "src/gen/x.rs", "out/bin"])).unwrap(),
+            vec![
+                ".git/info/exclude:1:*.swp\ta.swp",
+                "src/.gitignore:2:gen/\tsrc/gen/x.rs",
+                ".gitignore:2:out/\tout/bin",
+            ]
+        );
+
+        assert_eq!(
+            ls_files_others(&git, true).unwrap(),
+            vec![".gitignore", "a.txt", "src/.gitignore", "src/keep.log"]
+        );
+        assert_eq!(ls_files_others(&git, false).unwrap().len(), 10);
+
+        // 無視されたファイルは追加しないが, 既に追加されたファイルの変更は追う
+        add(&mut git, "a.log".to_string(), b"old").unwrap();
+        add_all(&mut git).unwrap();
+        let index = git.load_index().unwrap();
+        let names = index
+            .entries
+            .iter()
+            .map(|x| x.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                ".gitignore",
+                "a.log",
+                "a.txt",
+                "src/.gitignore",
+                "src/keep.log"
+            ]
+        );
+        assert_eq!(
+            index.get_entry("a.log").unwrap().hash,
+            git.hash_object(b"log")
+                .map(GitObject::Blob)
+                .unwrap()
+                .calc_hash_with(git.hash_algo)
+        );
+        assert_eq!(
+            add_path(&mut git, "src".to_string()).unwrap(),
+            vec!["src/.gitignore", "src/keep.log"]
+        );
+    }
+
+    #[test]
+    fn cmd_fast_export() {
+        let mut git = test_git_with_user();
+        let args = |xs: &[&str]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        git.filesystem.write("a.txt".to_string(), b"a\n").unwrap();
+        add(&mut git, "a.txt".to_string(), b"a\n").unwrap();
+        commit(&mut git, "first".to_string(), false).unwrap();
+        git.filesystem.create_dir("src".to_string()).unwrap();
+        git.filesystem
+            .write("src/lib.rs".to_string(), b"a\n")
+            .unwrap();
+        add(&mut git, "src/lib.rs".to_string(), b"a\n").unwrap();
+        commit(&mut git, "second".to_string(), false).unwrap();
+        switch(&mut git, &args(&["-c", "topic"])).unwrap();
+        git.filesystem.remove("a.txt".to_string()).unwrap();
+        commit_all(&mut git, "remove a".to_string(), false).unwrap();
+        switch(&mut git, &args(&["master"])).unwrap();
+        let master = git.rev_parse("master").unwrap();
+        create_ref(&mut git, "refs/tags/v1", &master).unwrap();
+
+        let stream = String::from_utf8(fast_export(&git).unwrap()).unwrap();
+        let first = git.read_commit(&master).unwrap();
+        // 同じ中身の blob は一度だけ出力され, mark を使い回す
+        assert_eq!(stream.matches("blob\n").count(), 1);
+        assert!(stream.starts_with("blob\nmark :1\ndata 2\na\n\nreset refs/heads/master\n"));
+        assert!(stream.contains(&format!(
+            "commit refs/heads/master\nmark :2\nauthor {}\ncommitter {}\ndata 6\nfirst\n\nM 100644 :1 a.txt\n\n",
+            first.author, first.committer
+        )));
+        assert!(stream.contains("data 7\nsecond\n\nfrom :2\nM 100644 :1 src/lib.rs\n\n"));
+        assert!(stream.contains("commit refs/heads/topic\nmark :4\n"));
+        assert!(stream.contains("from :3\nD a.txt\n\n"));
+        assert!(stream.ends_with("reset refs/tags/v1\nfrom :3\n\n"));
+        assert_eq!(fast_export_path("\"x\ny"), "\"\\\"x\\ny\"");
+
+        // git が使えるなら本物の fast-import に読み込ませる
+        let dir = std::env::temp_dir().join(format!("rgit-fast-export-{}", std::process::id()));
+        let git_cmd = |args: &[&str]| {
+            std::process::Command::new("git")
+                .arg("-C")
+                .arg(&dir)
+                .args(args)
+                .output()
+        };
+        std::fs::create_dir_all(&dir).unwrap();
+        if git_cmd(&["init", "-q"]).is_err() {
+            std::fs::remove_dir_all(&dir).unwrap();
+            return;
+        }
+        let mut child = std::process::Command::new("git")
+            .arg("-C")
+            .arg(&dir)
+            .args(["fast-import", "--quiet"])
+            .stdin(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stream.as_bytes())
+            .unwrap();
+        let status = child.wait().unwrap();
+        let log = git_cmd(&["log", "--format=%s", "topic"]).unwrap().stdout;
+        let files = git_cmd(&["ls-tree", "-r", "--name-only", "topic"])
+            .unwrap()
+            .stdout;
+        let tag = git_cmd(&["log", "--format=%s", "-1", "v1"]).unwrap().stdout;
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(status.success());
+        assert_eq!(String::from_utf8(log).unwrap(), "remove a\nsecond\nfirst\n");
+        assert_eq!(String::from_utf8(files).unwrap(), "src/lib.rs\n");
+        assert_eq!(String::from_utf8(tag).unwrap(), "second\n");
+    }
+
+    #[test]
+    fn cmd_sanitize_subject() {
+        assert_eq!(sanitize_subject("Add `foo` to bar!"), "Add-foo-to-bar");
+        assert_eq!(sanitize_subject("...hidden..file..."), "hidden.file");
+        assert_eq!(sanitize_subject("日本語のみ"), "");
+        assert_eq!(sanitize_subject(&"x".repeat(100)).len(), 64);
+    }
+
+    #[test]
+    fn cmd_apply() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let read =
+            |git: &Git<InMemFileSystem>, path: &str| git.filesystem.read(path.to_string()).unwrap();
+        let patch = "diff --git a/a.txt b/a.txt
+--- a/a.txt
++++ b/a.txt
+@@ -1,2 +1,2 @@
+ one
+-two
++TWO
+diff --git a/b.txt b/b.txt
+deleted file mode 100644
+--- a/b.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-b
+diff --git a/dir/c.txt b/dir/c.txt
+new file mode 100644
+--- /dev/null
++++ b/dir/c.txt
+@@ -0,0 +1 @@
++c
+";
+        git.filesystem
+            .write("fix.patch".to_string(), patch.as_bytes())
+            .unwrap();
+        git.filesystem
+            .write("a.txt".to_string(), b"one\ntwo\n")
+            .unwrap();
+        git.filesystem.write("b.txt".to_string(), b"b\n").unwrap();
+        add(&mut git, "a.txt".to_string(), b"one\ntwo\n").unwrap();
+        add(&mut git, "b.txt".to_string(), b"b\n").unwrap();
+
+        // --check は何も書き換えない
+        apply(&mut git, "fix.patch".to_string(), true, false).unwrap();
+        assert_eq!(read(&git, "a.txt"), b"one\ntwo\n");
+
+        // --cached は index だけを書き換える
+        apply(&mut git, "fix.patch".to_string(), false, true).unwrap();
+        let index = git.load_index().unwrap();
+        let names = index
+            .entries
+            .iter()
+            .map(|x| x.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["a.txt", "dir/c.txt"]);
+        assert_eq!(
+            git.read_blob(&hex::encode(&index.entries[0].hash)).unwrap(),
+            b"one\nTWO\n"
+        );
+        assert_eq!(read(&git, "a.txt"), b"one\ntwo\n");
+        assert!(git.filesystem.exists("b.txt".to_string()));
+
+        apply(&mut git, "fix.patch".to_string(), false, false).unwrap();
+        assert_eq!(read(&git, "a.txt"), b"one\nTWO\n");
+        assert!(!git.filesystem.exists("b.txt".to_string()));
+        assert_eq!(read(&git, "dir/c.txt"), b"c\n");
+
+        // 当てはまらない hunk があればどのファイルも書き換えない
+        git.filesystem
+            .write("a.txt".to_string(), b"one\ntwo\n")
+            .unwrap();
+        git.filesystem
+            .write("b.txt".to_string(), b"changed\n")
+            .unwrap();
+        git.filesystem.remove("dir/c.txt".to_string()).unwrap();
+        let e = apply(&mut git, "fix.patch".to_string(), false, false).unwrap_err();
+        assert_eq!(e.to_string(), "patch failed: b.txt: @@ -1 +0,0 @@");
+        assert_eq!(read(&git, "a.txt"), b"one\ntwo\n");
+        assert!(!git.filesystem.exists("dir/c.txt".to_string()));
+
+        // 新しいファイルが既にあれば失敗する
+        git.filesystem.write("b.txt".to_string(), b"b\n").unwrap();
+        git.filesystem
+            .write("dir/c.txt".to_string(), b"c\n")
+            .unwrap();
+        let e = apply(&mut git, "fix.patch".to_string(), true, false).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn cmd_add_stream() {
+        // 閾値を超えるファイルも小さいファイルも同じ blob と entry になる
+        let large = "hello, git\n".repeat(100);
+        let mut expected = test_git_with_user();
+        let mut git = test_git_with_user();
+        git.stream_threshold = 64;
+        for git in [&mut expected, &mut git] {
+            git.filesystem.create_dir("src".to_string()).unwrap();
+            git.filesystem
+                .write("src/large.txt".to_string(), large.as_bytes())
+                .unwrap();
+            git.filesystem
+                .write("src/small.txt".to_string(), b"small")
+                .unwrap();
+            git.filesystem
+                .write("large.txt".to_string(), large.as_bytes())
+                .unwrap();
+            add(git, "large.txt".to_string(), large.as_bytes()).unwrap();
+            add_path(git, "src".to_string()).unwrap();
+        }
+
+        assert_eq!(git.read_index().unwrap(), expected.read_index().unwrap());
+        let mut objects = git.loose_objects().unwrap();
+        objects.sort();
+        let mut expected_objects = expected.loose_objects().unwrap();
+        expected_objects.sort();
+        assert_eq!(objects, expected_objects);
+        for hash in objects {
+            assert_eq!(
+                git.read_blob(&hash).unwrap(),
+                expected.read_blob(&hash).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn cmd_add_path_from_subdirectory() {
+        let mut fs = InMemFileSystem::init();
+        for dir in ["src", "src/object"] {
+            fs.create_dir(dir.to_string()).unwrap();
+        }
+        fs.write("src/object/mod.rs".to_string(), b"mod").unwrap();
+        fs.write("README.md".to_string(), b"readme").unwrap();
+        let mut git = Git::discover(fs, "src/object").unwrap();
+
+        // cwd からのパスを worktree からのパスにして渡す
+        let path = git.prefix_path("mod.rs").unwrap();
+        assert_eq!(add_path(&mut git, path).unwrap(), vec!["src/object/mod.rs"]);
+        let path = git.prefix_path("../../README.md").unwrap();
+        assert_eq!(add_path(&mut git, path).unwrap(), vec!["README.md"]);
+
+        git.filesystem
+            .write("src/object/mod.rs".to_string(), b"changed")
+            .unwrap();
+        let path = git.prefix_path("mod.rs").unwrap();
+        restore(&mut git, &[path]).unwrap();
+        assert_eq!(
+            git.filesystem
+                .read("src/object/mod.rs".to_string())
+                .unwrap(),
+            b"mod"
+        );
+
+        assert!(git.prefix_path("../../../README.md").is_err());
+    }
+
+    #[test]
+    fn cmd_add_path() {
+        let mut git = Git::new(InMemFileSystem::init());
+        for dir in ["src", "src/object", "empty"] {
+            git.filesystem.create_dir(dir.to_string()).unwrap();
+        }
+        git.filesystem
+            .write("src/main.rs".to_string(), b"main")
+            .unwrap();
+        git.filesystem
+            .write("src/.hidden".to_string(), b"hidden")
+            .unwrap();
+        git.filesystem
+            .write("src/object/mod.rs".to_string(), b"mod")
+            .unwrap();
+        git.filesystem
+            .write("README.md".to_string(), b"readme")
+            .unwrap();
+        git.filesystem
+            .symlink("../empty".to_string(), "src/link".to_string())
+            .unwrap();
+
+        assert_eq!(
+            add_path(&mut git, "src".to_string()).unwrap(),
+            vec![
+                "src/.hidden",
+                "src/link",
+                "src/main.rs",
+                "src/object/mod.rs"
+            ]
+        );
+        assert!(add_path(&mut git, "empty".to_string()).unwrap().is_empty());
+        assert_eq!(
+            add_path(&mut git, "README.md".to_string()).unwrap(),
+            vec!["README.md"]
+        );
+        assert_eq!(
+            add_path(&mut git, "hoge".to_string())
+                .unwrap_err()
+                .to_string(),
+            "pathspec 'hoge' did not match any files"
+        );
+        assert_eq!(
+            add_path(&mut git, "*.rs".to_string()).unwrap(),
+            vec!["src/main.rs", "src/object/mod.rs"]
+        );
+        assert_eq!(
+            add_path(&mut git, "src/*".to_string()).unwrap(),
+            vec![
+                "src/.hidden",
+                "src/link",
+                "src/main.rs",
+                "src/object/mod.rs"
+            ]
+        );
+        assert!(add_path(&mut git, "*.toml".to_string()).is_err());
+
+        let names = git
+            .load_index()
+            .unwrap()
+            .entries
+            .iter()
+            .map(|x| x.name.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "README.md",
+                "src/.hidden",
+                "src/link",
+                "src/main.rs",
+                "src/object/mod.rs"
+            ]
+        );
     }
 }
@@ -0,0 +1,421 @@
+use std::fmt;
+
+// .git/config の中身
+// section と key は大文字小文字を区別しないので小文字にして持つ
+// 書き戻すときにコメントや空行, 変更していない行の書き方が消えないように元の行も持つ
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    pub sections: Vec<Section>,
+    // 最初の section より前のコメントや空行
+    head: Vec<String>,
+}
+
+// `[name "subsection"]` と, それに続く `key = value` の並び
+#[derive(Clone, Debug, PartialEq)]
+pub struct Section {
+    pub name: String,
+    pub subsection: Option<String>,
+    // 元の header 行. 新しく作った section は None
+    header: Option<String>,
+    lines: Vec<Line>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Line {
+    // key と値, 元の行. set や add で書き換えた entry は元の行を持たない
+    Entry(String, String, Option<String>),
+    // コメントや空行
+    Other(String),
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from(bytes: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(bytes).ok()?;
+
+        let mut config = Self::new();
+        for raw in text.lines() {
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                match config.sections.last_mut() {
+                    Some(section) => section.lines.push(Line::Other(raw.to_string())),
+                    None => config.head.push(raw.to_string()),
+                }
+                continue;
+            }
+
+            if let Some(rest) = line.strip_prefix('[') {
+                let end = rest.rfind(']')?;
+                let mut section = parse_section_header(&rest[..end])?;
+                section.header = Some(raw.to_string());
+                config.sections.push(section);
+                continue;
+            }
+
+            // section の前に key があるのは壊れている
+            let section = config.sections.last_mut()?;
+            let (key, value) = match line.find('=') {
+                Some(pos) => (line[..pos].trim(), parse_value(&line[(pos + 1)..])?),
+                // 値が無い key は true
+                None => (line, "true".to_string()),
+            };
+            section.lines.push(Line::Entry(
+                key.to_lowercase(),
+                value,
+                Some(raw.to_string()),
+            ));
+        }
+
+        Some(config)
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.to_string().into_bytes()
+    }
+
+    // 最後に設定された値を返す
+    pub fn get(&self, name: &str, subsection: Option<&str>, key: &str) -> Option<&str> {
+        self.get_all(name, subsection, key).pop()
+    }
+
+    pub fn get_all(&self, name: &str, subsection: Option<&str>, key: &str) -> Vec<&str> {
+        let key = key.to_lowercase();
+        self.sections
+            .iter()
+            .filter(|x| x.is(name, subsection))
+            .flat_map(|x| x.lines.iter())
+            .filter_map(|x| match x {
+                Line::Entry(k, v, _) if *k == key => Some(v.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // 既存の値を全て置き換える. 最後の値の行を書き換え, それより前の値の行は消す
+    pub fn set(&mut self, name: &str, subsection: Option<&str>, key: &str, value: &str) {
+        let lower = key.to_lowercase();
+        let mut found = false;
+        for section in self.sections.iter_mut().rev() {
+            if !section.is(name, subsection) {
+                continue;
+            }
+            for i in (0..section.lines.len()).rev() {
+                if !matches!(&section.lines[i], Line::Entry(k, _, _) if *k == lower) {
+                    continue;
+                }
+                if found {
+                    section.lines.remove(i);
+                } else {
+                    section.lines[i] = Line::Entry(lower.clone(), value.to_string(), None);
+                    found = true;
+                }
+            }
+        }
+        if !found {
+            self.add(name, subsection, key, value);
+        }
+    }
+
+    // 複数の値を持てる key (remote の fetch など) に値を追加する
+    // section の最後の entry の後に入れ, その後に続くコメントや空行はそのまま後ろに残す
+    pub fn add(&mut self, name: &str, subsection: Option<&str>, key: &str, value: &str) {
+        let entry = Line::Entry(key.to_lowercase(), value.to_string(), None);
+        match self
+            .sections
+            .iter_mut()
+            .rev()
+            .find(|x| x.is(name, subsection))
+        {
+            Some(section) => {
+                let pos = section
+                    .lines
+                    .iter()
+                    .rposition(|x| matches!(x, Line::Entry(..)))
+                    .map_or(0, |x| x + 1);
+                section.lines.insert(pos, entry);
+            }
+            None => self.sections.push(Section {
+                name: name.to_lowercase(),
+                subsection: subsection.map(String::from),
+                header: None,
+                lines: vec![entry],
+            }),
+        }
+    }
+
+    // section を削除して, 削除したかどうかを返す. section の中のコメントや空行も消える
+    pub fn remove_section(&mut self, name: &str, subsection: Option<&str>) -> bool {
+        let len = self.sections.len();
+        self.sections.retain(|x| !x.is(name, subsection));
+        self.sections.len() != len
+    }
+
+    // name の section に付いている subsection の名前を出てきた順に返す
+    pub fn subsections(&self, name: &str) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for section in self.sections.iter() {
+            if section.name != name.to_lowercase() {
+                continue;
+            }
+            if let Some(subsection) = section.subsection.as_deref() {
+                if !names.contains(&subsection) {
+                    names.push(subsection);
+                }
+            }
+        }
+        names
+    }
+}
+
+// 同じ内容を書き出すものを等しいとする
+impl PartialEq for Config {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_string() == other.to_string()
+    }
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in self.head.iter() {
+            writeln!(f, "{}", line)?;
+        }
+        for section in self.sections.iter() {
+            match (&section.header, &section.subsection) {
+                (Some(header), _) => writeln!(f, "{}", header)?,
+                (None, Some(subsection)) => writeln!(
+                    f,
+                    "[{} \"{}\"]",
+                    section.name,
+                    subsection.replace('\\', "\\\\").replace('"', "\\\"")
+                )?,
+                (None, None) => writeln!(f, "[{}]", section.name)?,
+            }
+            for line in section.lines.iter() {
+                match line {
+                    Line::Entry(_, _, Some(raw)) | Line::Other(raw) => writeln!(f, "{}", raw)?,
+                    Line::Entry(key, value, None) => {
+                        writeln!(f, "\t{} = {}", key, format_value(value))?
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Section {
+    fn is(&self, name: &str, subsection: Option<&str>) -> bool {
+        self.name == name.to_lowercase() && self.subsection.as_deref() == subsection
+    }
+}
+
+// `core` や `remote "origin"` を分ける
+fn parse_section_header(header: &str) -> Option<Section> {
+    let header = header.trim();
+    let (name, subsection) = match header.find(|x: char| x.is_whitespace()) {
+        Some(pos) => {
+            let quoted = header[pos..].trim();
+            let inner = quoted.strip_prefix('"')?.strip_suffix('"')?;
+            let mut subsection = String::new();
+            let mut chars = inner.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => subsection.push(chars.next()?),
+                    _ => subsection.push(c),
+                }
+            }
+            (&header[..pos], Some(subsection))
+        }
+        None => (header, None),
+    };
+
+    Some(Section {
+        name: name.to_lowercase(),
+        subsection,
+        header: None,
+        lines: Vec::new(),
+    })
+}
+
+// 値の引用符とエスケープを外す. 引用符の外の `#` `;` 以降はコメント
+fn parse_value(raw: &str) -> Option<String> {
+    let mut value = String::new();
+    // 引用符の外の末尾の空白は取り除くので, 残す長さを覚えておく
+    let mut keep = 0;
+    let mut quoted = false;
+    let mut chars = raw.trim_start().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' => {
+                match chars.next()? {
+                    'n' => value.push('\n'),
+                    't' => value.push('\t'),
+                    'b' => {
+                        value.pop();
+                    }
+                    c => value.push(c),
+                }
+                keep = value.len();
+            }
+            '#' | ';' if !quoted => break,
+            _ => {
+                value.push(c);
+                if quoted || !c.is_whitespace() {
+                    keep = value.len();
+                }
+            }
+        }
+    }
+    if quoted {
+        return None;
+    }
+    value.truncate(keep);
+
+    Some(value)
+}
+
+// parse_value で元に戻せるように必要なら引用符で囲む
+fn format_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    let needs_quote = value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains(['#', ';']);
+    if needs_quote {
+        format!("\"{}\"", escaped)
+    } else {
+        escaped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_from() {
+        let text = [
+            "# comment",
+            "[core]",
+            "\trepositoryformatversion = 0",
+            "\tbare = false ; comment",
+            "\tlogAllRefUpdates",
+            "[remote \"origin\"]",
+            "\turl = https://example.com/a b?x=1",
+            "\tfetch = +refs/heads/*:refs/remotes/origin/*",
+            "\tfetch = +refs/tags/*:refs/tags/*",
+            "\tmessage = \" padded # not comment \"",
+        ]
+        .join("\n");
+        let config = Config::from(text.as_bytes()).unwrap();
+
+        assert_eq!(config.get("core", None, "bare"), Some("false"));
+        assert_eq!(config.get("CORE", None, "logallrefupdates"), Some("true"));
+        assert_eq!(
+            config.get("remote", Some("origin"), "url"),
+            Some("https://example.com/a b?x=1")
+        );
+        assert_eq!(config.get_all("remote", Some("origin"), "fetch").len(), 2);
+        assert_eq!(
+            config.get("remote", Some("origin"), "message"),
+            Some(" padded # not comment ")
+        );
+        assert_eq!(config.subsections("remote"), vec!["origin"]);
+
+        assert!(Config::from(b"key = value").is_none());
+        assert!(Config::from(b"[core]\nkey = \"unterminated").is_none());
+    }
+
+    #[test]
+    fn config_round_trip() {
+        let mut config = Config::new();
+        config.set("core", None, "bare", "false");
+        config.set("remote", Some("origin"), "url", "a=b c");
+        config.add("remote", Some("origin"), "fetch", "+refs/heads/*:refs/x/*");
+        config.set("user", None, "name", " spaced \"name\" ;");
+        config.set("core", None, "bare", "true");
+
+        let bytes = config.as_bytes();
+        let parsed = Config::from(&bytes).unwrap();
+        assert_eq!(parsed, config);
+        assert_eq!(parsed.get("core", None, "bare"), Some("true"));
+        assert_eq!(parsed.get("remote", Some("origin"), "url"), Some("a=b c"));
+        assert_eq!(parsed.get("user", None, "name"), Some(" spaced \"name\" ;"));
+
+        assert!(config.remove_section("remote", Some("origin")));
+        assert!(!config.remove_section("remote", Some("origin")));
+        assert!(config.subsections("remote").is_empty());
+    }
+
+    #[test]
+    fn config_keeps_comments() {
+        let text = [
+            "# global comment",
+            "",
+            "[core]",
+            "\tbare = false ; keep me",
+            "\tBare = true",
+            "  filemode=true",
+            "",
+            "; about origin",
+            "[remote \"origin\"]",
+            "\turl = a",
+            "\tfetch = +refs/heads/*:refs/remotes/origin/*",
+            "",
+            "[user]",
+            "\tname = me",
+            "",
+        ]
+        .join("\n");
+        let mut config = Config::from(text.as_bytes()).unwrap();
+        // 変更しなければそのまま書き戻す
+        assert_eq!(config.to_string(), text);
+
+        config.set("core", None, "bare", "yes");
+        config.add(
+            "remote",
+            Some("origin"),
+            "fetch",
+            "+refs/tags/*:refs/tags/*",
+        );
+        config.set("user", None, "email", "me@example.com");
+        assert!(config.remove_section("remote", Some("origin")));
+        config.set("remote", Some("up"), "url", "b");
+
+        let expected = [
+            "# global comment",
+            "",
+            "[core]",
+            "\tbare = yes",
+            "  filemode=true",
+            "",
+            "; about origin",
+            "[user]",
+            "\tname = me",
+            "\temail = me@example.com",
+            "[remote \"up\"]",
+            "\turl = b",
+            "",
+        ]
+        .join("\n");
+        assert_eq!(config.to_string(), expected);
+
+        let mut config = Config::from(text.as_bytes()).unwrap();
+        config.add(
+            "remote",
+            Some("origin"),
+            "fetch",
+            "+refs/tags/*:refs/tags/*",
+        );
+        assert!(config.to_string().contains(
+            "\tfetch = +refs/heads/*:refs/remotes/origin/*\n\tfetch = +refs/tags/*:refs/tags/*\n\n[user]"
+        ));
+    }
+}
@@ -0,0 +1,54 @@
+use std::fmt;
+use std::io;
+
+// object や index を bytes から読む時に失敗した理由
+#[derive(Debug, PartialEq)]
+pub enum RgitError {
+    // 読み終わる前に bytes が尽きた. 何を読んでいたか
+    Truncated(&'static str),
+    // header が壊れている. 何がおかしいか
+    BadHeader(String),
+    // UTF-8 でなければならない所が UTF-8 でない
+    InvalidUtf8(&'static str),
+    // 対応していない version
+    UnsupportedVersion(u32),
+    // object の hash 値として読めない. 長さが違うか16進数でない
+    InvalidObjectId(String),
+}
+
+impl fmt::Display for RgitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Truncated(what) => write!(f, "truncated {}", what),
+            Self::BadHeader(reason) => write!(f, "bad header: {}", reason),
+            Self::InvalidUtf8(what) => write!(f, "invalid UTF-8 in {}", what),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported version {}", version),
+            Self::InvalidObjectId(reason) => write!(f, "invalid object id: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for RgitError {}
+
+// 他の API と同じく io::Result で返せるようにする
+impl From<RgitError> for io::Error {
+    fn from(err: RgitError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgit_error_to_io_error() {
+        let err = io::Error::from(RgitError::Truncated("tree entry"));
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "truncated tree entry");
+        assert_eq!(
+            RgitError::BadHeader(String::from("missing tree")).to_string(),
+            "bad header: missing tree"
+        );
+    }
+}
@@ -0,0 +1,353 @@
+use crate::object::commit::User;
+
+// git fast-import が読むコマンド列
+// https://git-scm.com/docs/git-fast-import
+#[derive(Debug)]
+pub enum Command {
+    Blob {
+        mark: Option<usize>,
+        data: Vec<u8>,
+    },
+    Commit(Box<CommitCommand>),
+    Reset {
+        refname: String,
+        from: Option<String>,
+    },
+}
+
+#[derive(Debug)]
+pub struct CommitCommand {
+    pub refname: String,
+    pub mark: Option<usize>,
+    // None なら committer と同じ
+    pub author: Option<User>,
+    pub committer: User,
+    pub encoding: Option<String>,
+    pub message: Vec<u8>,
+    // `:<mark>`, hash, ref のどれか. None なら ref の今の commit に続ける
+    pub from: Option<String>,
+    pub merges: Vec<String>,
+    pub changes: Vec<Change>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    // M <mode> <dataref> <path>
+    Modify {
+        mode: usize,
+        dataref: String,
+        path: String,
+    },
+    // D <path>
+    Delete(String),
+    // deleteall
+    DeleteAll,
+}
+
+// 行単位で読みつつ, data の中身だけはバイト数で読む
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    // 今読んでいる行の番号. エラーの位置に使う
+    line: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn peek_line(&self) -> Option<&'a str> {
+        let rest = self.bytes.get(self.pos..).filter(|x| !x.is_empty())?;
+        let end = rest.iter().position(|&x| x == b'\n').unwrap_or(rest.len());
+        std::str::from_utf8(&rest[..end]).ok()
+    }
+
+    fn next_line(&mut self) -> Option<&'a str> {
+        let line = self.peek_line()?;
+        self.pos += line.len() + 1;
+        self.line += 1;
+        Some(line)
+    }
+
+    // `data <n>` か `data <<<delim>` の行の後の中身
+    fn data(&mut self) -> Option<Vec<u8>> {
+        let arg = self.next_line()?.strip_prefix("data ")?;
+        let data = match arg.strip_prefix("<<") {
+            Some(delim) => {
+                let mut data = Vec::new();
+                loop {
+                    let line = self.next_line()?;
+                    if line == delim {
+                        break;
+                    }
+                    data.extend_from_slice(line.as_bytes());
+                    data.push(b'\n');
+                }
+                data
+            }
+            None => {
+                let len = arg.parse::<usize>().ok()?;
+                // 長さは stream に書かれた値なので, 足して溢れれば壊れた stream として扱う
+                let end = self.pos.checked_add(len)?;
+                let data = self.bytes.get(self.pos..end)?.to_vec();
+                self.pos += len;
+                self.line += data.iter().filter(|&&x| x == b'\n').count();
+                // 中身の後の改行は省略できる
+                if self.bytes.get(self.pos) == Some(&b'\n') {
+                    self.pos += 1;
+                    self.line += 1;
+                }
+                data
+            }
+        };
+        Some(data)
+    }
+
+    // `<key> <value>` の形の行が次にあれば読んで value を返す
+    fn optional(&mut self, key: &str) -> Option<&'a str> {
+        let value = self.peek_line()?.strip_prefix(key)?.strip_prefix(' ')?;
+        self.next_line();
+        Some(value)
+    }
+
+    fn mark(&mut self) -> Result<Option<usize>, usize> {
+        match self.optional("mark") {
+            Some(x) => parse_mark(x).map(Some).ok_or(self.line),
+            None => Ok(None),
+        }
+    }
+}
+
+// 読めなかった場合は行番号を返す
+pub fn parse(bytes: &[u8]) -> Result<Vec<Command>, usize> {
+    let mut reader = Reader {
+        bytes,
+        pos: 0,
+        line: 0,
+    };
+
+    let mut commands = Vec::new();
+    while reader.pos < bytes.len() {
+        let line = reader.next_line().ok_or(reader.line + 1)?;
+        let command = if line == "blob" {
+            let mark = reader.mark()?;
+            let data = reader.data().ok_or(reader.line)?;
+            Command::Blob { mark, data }
+        } else if let Some(refname) = line.strip_prefix("commit ") {
+            parse_commit(&mut reader, refname)?
+        } else if let Some(refname) = line.strip_prefix("reset ") {
+            Command::Reset {
+                refname: refname.to_string(),
+                from: reader.optional("from").map(String::from),
+            }
+        } else if line == "done" {
+            break;
+        } else if line.is_empty()
+            || line.starts_with('#')
+            || line == "checkpoint"
+            || ["progress ", "feature ", "option "]
+                .iter()
+                .any(|x| line.starts_with(x))
+        {
+            continue;
+        } else {
+            return Err(reader.line);
+        };
+        commands.push(command);
+    }
+
+    Ok(commands)
+}
+
+fn parse_commit(reader: &mut Reader, refname: &str) -> Result<Command, usize> {
+    let mark = reader.mark()?;
+    reader.optional("original-oid");
+    let author = match reader.optional("author") {
+        Some(x) => Some(User::from(x.as_bytes()).ok_or(reader.line)?),
+        None => None,
+    };
+    let committer = reader
+        .optional("committer")
+        .and_then(|x| User::from(x.as_bytes()))
+        .ok_or(reader.line + 1)?;
+    let encoding = reader.optional("encoding").map(String::from);
+    let message = reader.data().ok_or(reader.line)?;
+    let from = reader.optional("from").map(String::from);
+
+    let mut merges = Vec::new();
+    while let Some(merge) = reader.optional("merge") {
+        merges.push(merge.to_string());
+    }
+
+    // 空行は読み飛ばし, 変更の行でなくなったら commit の終わり
+    let mut changes = Vec::new();
+    while let Some(line) = reader.peek_line() {
+        let change = if line.is_empty() {
+            None
+        } else if line == "deleteall" {
+            Some(Change::DeleteAll)
+        } else if let Some(path) = line.strip_prefix("D ") {
+            Some(Change::Delete(unquote(path).ok_or(reader.line + 1)?))
+        } else if let Some(rest) = line.strip_prefix("M ") {
+            Some(parse_modify(rest).ok_or(reader.line + 1)?)
+        } else {
+            break;
+        };
+        reader.next_line();
+        changes.extend(change);
+    }
+
+    Ok(Command::Commit(Box::new(CommitCommand {
+        refname: refname.to_string(),
+        mark,
+        author,
+        committer,
+        encoding,
+        message,
+        from,
+        merges,
+        changes,
+    })))
+}
+
+// `<mode> <dataref> <path>`
+fn parse_modify(rest: &str) -> Option<Change> {
+    let mut iter = rest.splitn(3, ' ');
+    let mode = usize::from_str_radix(iter.next()?, 8).ok()?;
+    // 省略形の mode
+    let mode = match mode {
+        0o644 => 0o100644,
+        0o755 => 0o100755,
+        x => x,
+    };
+    let dataref = iter.next()?.to_string();
+    let path = unquote(iter.next()?)?;
+
+    Some(Change::Modify {
+        mode,
+        dataref,
+        path,
+    })
+}
+
+fn parse_mark(s: &str) -> Option<usize> {
+    s.strip_prefix(':')?.parse().ok()
+}
+
+// `"` で囲まれた path の C 言語風のエスケープを戻す
+fn unquote(path: &str) -> Option<String> {
+    let quoted = match path.strip_prefix('"') {
+        Some(x) => x.strip_suffix('"')?,
+        None => return Some(path.to_string()),
+    };
+
+    let mut bytes = Vec::new();
+    let mut iter = quoted.bytes();
+    while let Some(b) = iter.next() {
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        let b = match iter.next()? {
+            b'n' => b'\n',
+            b't' => b'\t',
+            b'"' => b'"',
+            b'\\' => b'\\',
+            // 3桁の8進数
+            x @ b'0'..=b'3' => {
+                let digits = [x, iter.next()?, iter.next()?];
+                u8::from_str_radix(std::str::from_utf8(&digits).ok()?, 8).ok()?
+            }
+            _ => return None,
+        };
+        bytes.push(b);
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fast_import_parse() {
+        let stream = b"feature done\n\
+            blob\nmark :1\ndata 4\n\0\x01\n\n\n\
+            reset refs/heads/master\n\
+            commit refs/heads/master\nmark :2\n\
+            author A U Thor <author@example.com> 1600000000 +0000\n\
+            committer C O Mitter <committer@example.com> 1600000001 +0000\n\
+            data <<EOF\nfirst\nEOF\n\
+            M 644 :1 bin/data\nM 100755 :1 \"a \\\"b\\\"\\n\\303\\251\"\n\n\
+            commit refs/heads/topic\n\
+            committer C O Mitter <committer@example.com> 1600000002 +0000\n\
+            data 7\nsecond\n\
+            from :2\nD bin/data\ndeleteall\n\
+            reset refs/tags/v1\nfrom :2\n\n\
+            done\n";
+        let commands = parse(stream).unwrap();
+        assert_eq!(commands.len(), 5);
+
+        match &commands[0] {
+            Command::Blob { mark, data } => {
+                assert_eq!(*mark, Some(1));
+                assert_eq!(data, b"\0\x01\n\n");
+            }
+            x => panic!("{:?}", x),
+        }
+        match &commands[1] {
+            Command::Reset { refname, from } => {
+                assert_eq!(refname, "refs/heads/master");
+                assert_eq!(*from, None);
+            }
+            x => panic!("{:?}", x),
+        }
+        match &commands[2] {
+            Command::Commit(commit) => {
+                assert_eq!(commit.refname, "refs/heads/master");
+                assert_eq!(commit.mark, Some(2));
+                assert_eq!(commit.author.as_ref().unwrap().email, "author@example.com");
+                assert_eq!(commit.committer.name, "C O Mitter");
+                assert_eq!(commit.message, b"first\n");
+                assert_eq!(commit.from, None);
+                assert_eq!(
+                    commit.changes,
+                    vec![
+                        Change::Modify {
+                            mode: 0o100644,
+                            dataref: ":1".to_string(),
+                            path: "bin/data".to_string(),
+                        },
+                        Change::Modify {
+                            mode: 0o100755,
+                            dataref: ":1".to_string(),
+                            path: "a \"b\"\né".to_string(),
+                        },
+                    ]
+                );
+            }
+            x => panic!("{:?}", x),
+        }
+        match &commands[3] {
+            Command::Commit(commit) => {
+                assert!(commit.author.is_none());
+                assert_eq!(commit.message, b"second\n");
+                assert_eq!(commit.from.as_deref(), Some(":2"));
+                assert_eq!(
+                    commit.changes,
+                    vec![Change::Delete("bin/data".to_string()), Change::DeleteAll]
+                );
+            }
+            x => panic!("{:?}", x),
+        }
+
+        // 5行目に committer が無い
+        assert_eq!(
+            parse(b"blob\ndata 0\n\ncommit refs/heads/x\ndata 0\n").unwrap_err(),
+            5
+        );
+        assert!(parse(b"tag v1\n").is_err());
+        assert!(parse(b"blob\ndata 10\nshort\n").is_err());
+        // pos に足すと溢れる長さ
+        let huge = format!("blob\ndata {}\nshort\n", usize::MAX);
+        assert!(parse(huge.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+use super::{FileSystem, Metadata};
+use std::cell::Cell;
+use std::io::{self, Read};
+
+// 別の FileSystem に処理を任せ, read/write/stat/sync を呼ばれた回数を数える
+// cache やまとめ書きで filesystem への操作が減ったことを確かめるのに使う
+pub struct CountingFileSystem<F> {
+    pub inner: F,
+    reads: Cell<usize>,
+    writes: Cell<usize>,
+    stats: Cell<usize>,
+    syncs: Cell<usize>,
+}
+
+impl<F: FileSystem> CountingFileSystem<F> {
+    pub fn new(inner: F) -> Self {
+        Self {
+            inner,
+            reads: Cell::new(0),
+            writes: Cell::new(0),
+            stats: Cell::new(0),
+            syncs: Cell::new(0),
+        }
+    }
+
+    pub fn reads(&self) -> usize {
+        self.reads.get()
+    }
+
+    pub fn writes(&self) -> usize {
+        self.writes.get()
+    }
+
+    pub fn stats(&self) -> usize {
+        self.stats.get()
+    }
+
+    pub fn syncs(&self) -> usize {
+        self.syncs.get()
+    }
+
+    // 数えた回数を 0 に戻す
+    pub fn reset(&self) {
+        self.reads.set(0);
+        self.writes.set(0);
+        self.stats.set(0);
+        self.syncs.set(0);
+    }
+}
+
+fn increment(count: &Cell<usize>) {
+    count.set(count.get() + 1);
+}
+
+impl<F: FileSystem> FileSystem for CountingFileSystem<F> {
+    fn read(&self, path: String) -> io::Result<Vec<u8>> {
+        increment(&self.reads);
+        self.inner.read(path)
+    }
+
+    fn write(&mut self, path: String, data: &[u8]) -> io::Result<()> {
+        increment(&self.writes);
+        self.inner.write(path, data)
+    }
+
+    fn append(&mut self, path: String, data: &[u8]) -> io::Result<()> {
+        increment(&self.writes);
+        self.inner.append(path, data)
+    }
+
+    fn open(&self, path: String) -> io::Result<Box<dyn Read>> {
+        increment(&self.reads);
+        self.inner.open(path)
+    }
+
+    fn create_new(&mut self, path: String) -> io::Result<()> {
+        increment(&self.writes);
+        self.inner.create_new(path)
+    }
+
+    fn sync(&self, path: String) -> io::Result<()> {
+        increment(&self.syncs);
+        self.inner.sync(path)
+    }
+
+    fn stat(&self, path: String) -> io::Result<Metadata> {
+        increment(&self.stats);
+        self.inner.stat(path)
+    }
+
+    fn exists(&self, path: String) -> bool {
+        self.inner.exists(path)
+    }
+
+    fn create_dir(&mut self, path: String) -> io::Result<()> {
+        self.inner.create_dir(path)
+    }
+
+    fn rename(&mut self, from: String, to: String) -> io::Result<()> {
+        self.inner.rename(from, to)
+    }
+
+    fn remove(&mut self, path: String) -> io::Result<()> {
+        self.inner.remove(path)
+    }
+
+    fn remove_dir(&mut self, path: String) -> io::Result<()> {
+        self.inner.remove_dir(path)
+    }
+
+    fn remove_dir_all(&mut self, path: String) -> io::Result<()> {
+        self.inner.remove_dir_all(path)
+    }
+
+    fn read_dir(&self, path: String) -> io::Result<Vec<String>> {
+        self.inner.read_dir(path)
+    }
+
+    fn is_dir(&self, path: String) -> io::Result<bool> {
+        self.inner.is_dir(path)
+    }
+
+    fn symlink(&mut self, target: String, link: String) -> io::Result<()> {
+        self.inner.symlink(target, link)
+    }
+
+    fn read_link(&self, path: String) -> io::Result<String> {
+        self.inner.read_link(path)
+    }
+
+    fn set_mode(&mut self, path: String, mode: u32) -> io::Result<()> {
+        self.inner.set_mode(path, mode)
+    }
+
+    fn change_root(&mut self, path: String) -> io::Result<()> {
+        self.inner.change_root(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::inmem::InMemFileSystem;
+
+    #[test]
+    fn counting_fs() {
+        let mut fs = CountingFileSystem::new(InMemFileSystem::init());
+        fs.write("a.txt".to_string(), b"a").unwrap();
+        fs.write("b.txt".to_string(), b"b").unwrap();
+        assert_eq!(fs.read("a.txt".to_string()).unwrap(), b"a");
+        assert!(fs.read("c.txt".to_string()).is_err());
+        fs.stat("b.txt".to_string()).unwrap();
+        // 数えない操作
+        assert!(fs.exists("a.txt".to_string()));
+        fs.read_dir("".to_string()).unwrap();
+
+        assert_eq!((fs.reads(), fs.writes(), fs.stats()), (2, 2, 1));
+        assert_eq!(fs.inner.read("b.txt".to_string()).unwrap(), b"b");
+        assert_eq!(fs.reads(), 2);
+
+        fs.reset();
+        assert_eq!((fs.reads(), fs.writes(), fs.stats()), (0, 0, 0));
+    }
+}
@@ -6,30 +6,48 @@ use serde::{Serialize, Serializer};
 use std::collections::HashMap;
 use std::io;
 
+// 通常のファイルの mode (0o100644)
+const MODE_FILE: u32 = 33188;
+const MODE_SYMLINK: u32 = 0o120000;
+// ディレクトリの mode. Linux の mkdir と同じく 0o755
+const MODE_DIR: u32 = 0o40755;
+const MODE_TYPE_MASK: u32 = 0o170000;
+
+#[derive(Clone)]
 enum Entity {
     Dir(HashMap<String, Entity>),
-    File(Vec<u8>),
+    // 中身と mode
+    File(Vec<u8>, u32),
+    // link 先のパス
+    Symlink(String),
 }
 
 impl Entity {
     pub fn change_dir(&self, path: String) -> io::Result<&Entity> {
+        // 空のパスは自分自身
+        if path.is_empty() {
+            return Ok(self);
+        }
         path.split("/").try_fold(self, |st, x| match st {
             Self::Dir(dir) => dir.get(x).ok_or(io::Error::from(io::ErrorKind::NotFound)),
-            Self::File(_) => Err(io::Error::from(io::ErrorKind::NotFound)),
+            _ => Err(io::Error::from(io::ErrorKind::NotFound)),
         })
     }
 
     pub fn change_dir_mut(&mut self, path: String) -> io::Result<&mut Entity> {
+        if path.is_empty() {
+            return Ok(self);
+        }
         path.split("/").try_fold(self, |st, x| match st {
             Self::Dir(dir) => dir
                 .get_mut(x)
                 .ok_or(io::Error::from(io::ErrorKind::NotFound)),
-            Self::File(_) => Err(io::Error::from(io::ErrorKind::NotFound)),
+            _ => Err(io::Error::from(io::ErrorKind::NotFound)),
         })
     }
 
     pub fn read(&self) -> io::Result<Vec<u8>> {
-        if let Self::File(data) = self {
+        if let Self::File(data, _) = self {
             return Ok(data.clone());
         }
         Err(io::Error::from(io::ErrorKind::NotFound))
@@ -37,20 +55,62 @@ impl Entity {
 
     pub fn write(&mut self, name: String, data: &[u8]) -> io::Result<()> {
         if let Self::Dir(dir) = self {
-            dir.insert(name, Self::File(data.to_vec()));
+            // 上書きする場合は mode を引き継ぐ
+            let mode = match dir.get(&name) {
+                Some(Self::File(_, mode)) => *mode,
+                _ => MODE_FILE,
+            };
+            dir.insert(name, Self::File(data.to_vec(), mode));
             return Ok(());
         }
         Err(io::Error::from(io::ErrorKind::NotFound))
     }
 
+    pub fn append(&mut self, name: String, data: &[u8]) -> io::Result<()> {
+        if let Self::Dir(dir) = self {
+            return match dir.get_mut(&name) {
+                Some(Self::File(content, _)) => {
+                    content.extend_from_slice(data);
+                    Ok(())
+                }
+                Some(_) => Err(io::Error::from(io::ErrorKind::InvalidInput)),
+                None => {
+                    dir.insert(name, Self::File(data.to_vec(), MODE_FILE));
+                    Ok(())
+                }
+            };
+        }
+        Err(io::Error::from(io::ErrorKind::NotFound))
+    }
+
     pub fn make_dir(&mut self, name: String) -> io::Result<()> {
         if let Self::Dir(dir) = self {
-            dir.insert(name, Self::Dir(HashMap::new()));
+            dir.entry(name).or_insert_with(|| Self::Dir(HashMap::new()));
             return Ok(());
         }
         Err(io::Error::from(io::ErrorKind::NotFound))
     }
 
+    pub fn make_symlink(&mut self, name: String, target: String) -> io::Result<()> {
+        if let Self::Dir(dir) = self {
+            if dir.contains_key(&name) {
+                return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+            }
+            dir.insert(name, Self::Symlink(target));
+            return Ok(());
+        }
+        Err(io::Error::from(io::ErrorKind::NotFound))
+    }
+
+    pub fn list(&self) -> io::Result<Vec<String>> {
+        if let Self::Dir(dir) = self {
+            let mut names = dir.keys().cloned().collect::<Vec<_>>();
+            names.sort();
+            return Ok(names);
+        }
+        Err(io::Error::from(io::ErrorKind::NotFound))
+    }
+
     pub fn remove(&mut self, name: String) -> io::Result<()> {
         let (path, name) = path_split(name);
         match path.len() {
@@ -84,7 +144,8 @@ impl Serialize for Entity {
                 }
                 s.end()
             }
-            Self::File(file) => serializer.serialize_bytes(file),
+            Self::File(file, _) => serializer.serialize_bytes(file),
+            Self::Symlink(target) => serializer.serialize_str(target),
         }
     }
 }
@@ -92,6 +153,14 @@ impl Serialize for Entity {
 #[cfg_attr(feature = "json", derive(Serialize))]
 pub struct InMemFileSystem {
     root: Entity,
+    // change_root で移った先. パスはここからの相対パスとして扱う
+    base: String,
+}
+
+// ある時点の InMemFileSystem の中身
+#[derive(Clone)]
+pub struct Snapshot {
+    root: Entity,
 }
 
 impl InMemFileSystem {
@@ -112,7 +181,7 @@ impl InMemFileSystem {
                         ),
                         (
                             "HEAD".to_owned(),
-                            Entity::File(b"ref: refs/heads/master".to_vec()),
+                            Entity::File(b"ref: refs/heads/master".to_vec(), MODE_FILE),
                         ),
                     ]
                     .into_iter()
@@ -123,19 +192,43 @@ impl InMemFileSystem {
             .collect::<HashMap<_, _>>(),
         );
 
-        Self { root }
+        Self {
+            root,
+            base: String::new(),
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            root: self.root.clone(),
+        }
+    }
+
+    // snapshot を取った時点の状態に戻す
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.root = snapshot.root;
+    }
+
+    fn resolve(&self, path: String) -> String {
+        match (self.base.is_empty(), path.is_empty()) {
+            (true, _) => path,
+            (false, true) => self.base.clone(),
+            (false, false) => format!("{}/{}", self.base, path),
+        }
     }
 }
 
 impl FileSystem for InMemFileSystem {
     fn read(&self, path: String) -> io::Result<Vec<u8>> {
+        let path = self.resolve(path);
         self.root.change_dir(path).and_then(|x| x.read())
     }
 
     fn write(&mut self, path: String, data: &[u8]) -> io::Result<()> {
+        let path = self.resolve(path);
         let (dir_name, file) = path_split(path);
 
-        if dir_name.len() > 0 {
+        if !dir_name.is_empty() {
             self.root.change_dir_mut(dir_name.join("/"))
         } else {
             Ok(&mut self.root)
@@ -143,32 +236,65 @@ impl FileSystem for InMemFileSystem {
         .and_then(|x| x.write(file, data))
     }
 
-    fn stat(&self, path: String) -> io::Result<Metadata> {
-        let entity = self.root.change_dir(path)?;
+    fn append(&mut self, path: String, data: &[u8]) -> io::Result<()> {
+        let path = self.resolve(path);
+        let (dir_name, file) = path_split(path);
 
-        if let Entity::File(_) = entity {
-            Ok(Metadata {
-                dev: 0,
-                ino: 0,
-                mode: 33188,
-                uid: 0,
-                gid: 0,
-                size: 0,
-                mtime: 0,
-                mtime_nsec: 0,
-                ctime: 0,
-                ctime_nsec: 0,
-            })
+        if !dir_name.is_empty() {
+            self.root.change_dir_mut(dir_name.join("/"))
         } else {
-            Err(io::Error::from(io::ErrorKind::InvalidData))
+            Ok(&mut self.root)
         }
+        .and_then(|x| x.append(file, data))
+    }
+
+    fn create_new(&mut self, path: String) -> io::Result<()> {
+        if self.exists(path.clone()) {
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+        }
+        self.write(path, b"")
+    }
+
+    fn stat(&self, path: String) -> io::Result<Metadata> {
+        let path = self.resolve(path);
+        let entity = self.root.change_dir(path)?;
+
+        // symlink は辿らずに symlink 自体の mode を返す
+        let mode = match entity {
+            Entity::File(_, mode) => *mode,
+            Entity::Symlink(_) => MODE_SYMLINK,
+            Entity::Dir(_) => MODE_DIR,
+        };
+
+        Ok(Metadata {
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            mtime: 0,
+            mtime_nsec: 0,
+            ctime: 0,
+            ctime_nsec: 0,
+        })
+    }
+
+    fn exists(&self, path: String) -> bool {
+        let path = self.resolve(path);
+        self.root.change_dir(path).is_ok()
     }
 
     fn create_dir(&mut self, path: String) -> io::Result<()> {
+        let path = self.resolve(path);
         let (dir_name, dir) = path_split(path);
-        self.root
-            .change_dir_mut(dir_name.join("/"))
-            .and_then(|x| x.make_dir(dir))
+
+        if !dir_name.is_empty() {
+            self.root.change_dir_mut(dir_name.join("/"))
+        } else {
+            Ok(&mut self.root)
+        }
+        .and_then(|x| x.make_dir(dir))
     }
 
     fn rename(&mut self, from: String, to: String) -> io::Result<()> {
@@ -178,8 +304,79 @@ impl FileSystem for InMemFileSystem {
     }
 
     fn remove(&mut self, path: String) -> io::Result<()> {
+        let path = self.resolve(path);
+        self.root.remove(path)
+    }
+
+    fn remove_dir(&mut self, path: String) -> io::Result<()> {
+        let path = self.resolve(path);
+        if !self.root.change_dir(path.clone())?.list()?.is_empty() {
+            return Err(io::Error::other("directory not empty"));
+        }
         self.root.remove(path)
     }
+
+    fn remove_dir_all(&mut self, path: String) -> io::Result<()> {
+        let path = self.resolve(path);
+        match self.root.change_dir(path.clone())? {
+            Entity::Dir(_) => self.root.remove(path),
+            _ => Err(io::Error::from(io::ErrorKind::InvalidInput)),
+        }
+    }
+
+    fn read_dir(&self, path: String) -> io::Result<Vec<String>> {
+        let path = self.resolve(path);
+        self.root.change_dir(path).and_then(|x| x.list())
+    }
+
+    fn is_dir(&self, path: String) -> io::Result<bool> {
+        let path = self.resolve(path);
+        self.root
+            .change_dir(path)
+            .map(|x| matches!(x, Entity::Dir(_)))
+    }
+
+    fn symlink(&mut self, target: String, link: String) -> io::Result<()> {
+        let link = self.resolve(link);
+        let (dir_name, name) = path_split(link);
+
+        if !dir_name.is_empty() {
+            self.root.change_dir_mut(dir_name.join("/"))
+        } else {
+            Ok(&mut self.root)
+        }
+        .and_then(|x| x.make_symlink(name, target))
+    }
+
+    fn read_link(&self, path: String) -> io::Result<String> {
+        let path = self.resolve(path);
+        match self.root.change_dir(path)? {
+            Entity::Symlink(target) => Ok(target.clone()),
+            _ => Err(io::Error::from(io::ErrorKind::InvalidInput)),
+        }
+    }
+
+    fn set_mode(&mut self, path: String, mode: u32) -> io::Result<()> {
+        let path = self.resolve(path);
+        match self.root.change_dir_mut(path)? {
+            Entity::File(_, current) => {
+                *current = (*current & MODE_TYPE_MASK) | (mode & !MODE_TYPE_MASK);
+                Ok(())
+            }
+            _ => Err(io::Error::from(io::ErrorKind::InvalidInput)),
+        }
+    }
+
+    fn change_root(&mut self, path: String) -> io::Result<()> {
+        let path = self.resolve(path);
+        match self.root.change_dir(path.clone())? {
+            Entity::Dir(_) => {
+                self.base = path;
+                Ok(())
+            }
+            _ => Err(io::Error::from(io::ErrorKind::InvalidInput)),
+        }
+    }
 }
 
 fn path_split(path: String) -> (Vec<String>, String) {
@@ -206,15 +403,12 @@ mod tests {
         assert_eq!(name, "".to_string());
 
         let (path, name) = path_split(".git/objects".to_string());
-        let mut v: Vec<String> = Vec::new();
-        v.push(".git".to_string());
+        let v: Vec<String> = vec![".git".to_string()];
         assert_eq!(path, v);
         assert_eq!(name, "objects".to_string());
 
         let (path, name) = path_split(".git/hoge/objects".to_string());
-        let mut v: Vec<String> = Vec::new();
-        v.push(".git".to_string());
-        v.push("hoge".to_string());
+        let v: Vec<String> = vec![".git".to_string(), "hoge".to_string()];
         assert_eq!(path, v);
         assert_eq!(name, "objects".to_string());
     }
@@ -232,4 +426,150 @@ mod tests {
         let result = fs.write(".git/objects/hoge".to_string(), b"hello");
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_fs_read_dir() {
+        let fs = InMemFileSystem::init();
+        let names = fs.read_dir(".git".to_string()).unwrap();
+        assert_eq!(names, vec!["HEAD", "objects", "refs"]);
+        assert!(fs.read_dir(".git/HEAD".to_string()).is_err());
+        assert!(fs.read_dir(".git/hoge".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_fs_stat() {
+        let fs = InMemFileSystem::init();
+        let dir = fs.stat(".git/objects".to_string()).unwrap();
+        let file = fs.stat(".git/HEAD".to_string()).unwrap();
+        assert_eq!(dir.mode & MODE_TYPE_MASK, 0o040000);
+        assert_eq!(file.mode & MODE_TYPE_MASK, 0o100000);
+        assert_ne!(dir.mode, file.mode);
+        // worktree の一番上もディレクトリ
+        assert_eq!(fs.stat("".to_string()).unwrap().mode, MODE_DIR);
+        assert_eq!(
+            fs.stat(".git/hoge".to_string()).err().map(|x| x.kind()),
+            Some(io::ErrorKind::NotFound)
+        );
+    }
+
+    #[test]
+    fn test_fs_symlink() {
+        let mut fs = InMemFileSystem::init();
+        fs.symlink("refs/heads/master".to_string(), ".git/link".to_string())
+            .unwrap();
+        assert_eq!(
+            fs.read_link(".git/link".to_string()).unwrap(),
+            "refs/heads/master"
+        );
+        assert_eq!(fs.stat(".git/link".to_string()).unwrap().mode, MODE_SYMLINK);
+        assert!(fs
+            .symlink("hoge".to_string(), ".git/link".to_string())
+            .is_err());
+        assert!(fs.read_link(".git/HEAD".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_fs_set_mode() {
+        let mut fs = InMemFileSystem::init();
+        fs.write("run.sh".to_string(), b"echo hello").unwrap();
+        fs.set_mode("run.sh".to_string(), 0o755).unwrap();
+        assert_eq!(fs.stat("run.sh".to_string()).unwrap().mode, 0o100755);
+
+        // 上書きしても mode はそのまま
+        fs.write("run.sh".to_string(), b"echo hi").unwrap();
+        assert_eq!(fs.stat("run.sh".to_string()).unwrap().mode, 0o100755);
+        assert!(fs.set_mode(".git".to_string(), 0o755).is_err());
+    }
+
+    #[test]
+    fn test_fs_remove_dir() {
+        let mut fs = InMemFileSystem::init();
+        fs.create_dir(".git/objects/ab".to_string()).unwrap();
+        fs.write(".git/objects/ab/cd".to_string(), b"hello")
+            .unwrap();
+        assert!(fs.remove_dir(".git/objects/ab".to_string()).is_err());
+        assert!(fs.remove_dir(".git/objects/ab/cd".to_string()).is_err());
+
+        fs.remove(".git/objects/ab/cd".to_string()).unwrap();
+        fs.remove_dir(".git/objects/ab".to_string()).unwrap();
+        assert!(fs.read_dir(".git/objects/ab".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_fs_snapshot() {
+        let mut fs = InMemFileSystem::init();
+        fs.write("hello.txt".to_string(), b"hello").unwrap();
+        let snapshot = fs.snapshot();
+
+        fs.write("hello.txt".to_string(), b"bye").unwrap();
+        fs.write("new.txt".to_string(), b"new").unwrap();
+        assert_eq!(fs.read("hello.txt".to_string()).unwrap(), b"bye");
+
+        fs.restore(snapshot.clone());
+        assert_eq!(fs.read("hello.txt".to_string()).unwrap(), b"hello");
+        assert!(fs.read("new.txt".to_string()).is_err());
+
+        // 同じ snapshot に何度でも戻せる
+        fs.remove("hello.txt".to_string()).unwrap();
+        fs.restore(snapshot);
+        assert_eq!(fs.read("hello.txt".to_string()).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_fs_remove_dir_all() {
+        let mut fs = InMemFileSystem::init();
+        fs.create_dir("src".to_string()).unwrap();
+        fs.create_dir("src/object".to_string()).unwrap();
+        fs.write("src/lib.rs".to_string(), b"lib").unwrap();
+        fs.write("src/object/mod.rs".to_string(), b"mod").unwrap();
+        assert!(fs.remove_dir_all("src/lib.rs".to_string()).is_err());
+
+        fs.remove_dir_all("src".to_string()).unwrap();
+        assert!(fs.read_dir("src".to_string()).is_err());
+        assert!(fs.read("src/object/mod.rs".to_string()).is_err());
+        assert!(fs.remove_dir_all("src".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_fs_is_dir() {
+        let mut fs = InMemFileSystem::init();
+        fs.symlink(".git".to_string(), "link".to_string()).unwrap();
+        assert!(fs.is_dir(".git".to_string()).unwrap());
+        assert!(fs.is_dir("".to_string()).unwrap());
+        assert!(!fs.is_dir(".git/HEAD".to_string()).unwrap());
+        assert!(!fs.is_dir("link".to_string()).unwrap());
+        assert!(fs.is_dir("hoge".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_fs_change_root() {
+        let mut fs = InMemFileSystem::init();
+        fs.create_dir("sub".to_string()).unwrap();
+        fs.create_dir("sub/dir".to_string()).unwrap();
+        fs.write("sub/dir/a.txt".to_string(), b"a").unwrap();
+        assert!(fs.change_root("sub/dir/a.txt".to_string()).is_err());
+
+        fs.change_root("sub".to_string()).unwrap();
+        assert_eq!(fs.read("dir/a.txt".to_string()).unwrap(), b"a");
+        assert_eq!(fs.read_dir("".to_string()).unwrap(), vec!["dir"]);
+        fs.change_root("dir".to_string()).unwrap();
+        fs.write("b.txt".to_string(), b"b").unwrap();
+        fs.symlink("b.txt".to_string(), "c.txt".to_string())
+            .unwrap();
+        assert_eq!(fs.read_link("c.txt".to_string()).unwrap(), "b.txt");
+        assert!(!fs.exists(".git".to_string()));
+
+        fs.change_root("".to_string()).unwrap();
+        assert!(fs.exists("a.txt".to_string()));
+    }
+
+    #[test]
+    fn inmem_exists() {
+        let mut fs = InMemFileSystem::init();
+        fs.write("a.txt".to_string(), b"a").unwrap();
+        assert!(fs.exists("a.txt".to_string()));
+        assert!(fs.exists(".git/objects".to_string()));
+        assert!(!fs.exists("b.txt".to_string()));
+        assert!(!fs.exists(".git/objects/ab".to_string()));
+    }
 }
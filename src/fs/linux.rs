@@ -1,9 +1,10 @@
 use super::{FileSystem, Metadata};
 use std::env;
-use std::fs::{self, File};
+use std::fs::{self, File, OpenOptions};
 use std::io::{self, Read, Write};
 #[cfg(target_os = "linux")]
 use std::os::linux::fs::MetadataExt;
+use std::os::unix::fs::PermissionsExt;
 use std::path::PathBuf;
 
 #[cfg(target_os = "linux")]
@@ -18,6 +19,11 @@ impl LinuxFileSystem {
             root: env::current_dir()?,
         })
     }
+
+    // カレントディレクトリ以外を起点にする
+    pub fn with_root(root: PathBuf) -> Self {
+        LinuxFileSystem { root }
+    }
 }
 
 #[cfg(target_os = "linux")]
@@ -29,6 +35,10 @@ impl FileSystem for LinuxFileSystem {
         Ok(buf)
     }
 
+    fn open(&self, path: String) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(File::open(self.root.join(path))?))
+    }
+
     fn write(&mut self, path: String, data: &[u8]) -> io::Result<()> {
         let mut file = File::create(self.root.join(path))?;
         file.write_all(data)?;
@@ -36,9 +46,30 @@ impl FileSystem for LinuxFileSystem {
         Ok(())
     }
 
+    fn append(&mut self, path: String, data: &[u8]) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(self.root.join(path))?;
+        file.write_all(data)
+    }
+
+    fn create_new(&mut self, path: String) -> io::Result<()> {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(self.root.join(path))?;
+        Ok(())
+    }
+
+    fn sync(&self, path: String) -> io::Result<()> {
+        File::open(self.root.join(path))?.sync_all()
+    }
+
     fn stat(&self, path: String) -> io::Result<Metadata> {
+        // git と同じく symlink は辿らない (lstat)
         let path = self.root.join(path);
-        let metadata = path.metadata()?;
+        let metadata = path.symlink_metadata()?;
 
         Ok(Metadata {
             dev: metadata.st_dev() as u32,
@@ -54,6 +85,11 @@ impl FileSystem for LinuxFileSystem {
         })
     }
 
+    fn exists(&self, path: String) -> bool {
+        // 指す先が無い symlink もあるものとする
+        self.root.join(path).symlink_metadata().is_ok()
+    }
+
     fn create_dir(&mut self, path: String) -> io::Result<()> {
         let path = self.root.join(path);
         fs::create_dir_all(path)
@@ -69,4 +105,100 @@ impl FileSystem for LinuxFileSystem {
         let path = self.root.join(path);
         fs::remove_file(path)
     }
+
+    fn remove_dir(&mut self, path: String) -> io::Result<()> {
+        let path = self.root.join(path);
+        fs::remove_dir(path)
+    }
+
+    fn remove_dir_all(&mut self, path: String) -> io::Result<()> {
+        let path = self.root.join(path);
+        fs::remove_dir_all(path)
+    }
+
+    fn read_dir(&self, path: String) -> io::Result<Vec<String>> {
+        let path = self.root.join(path);
+        let mut names = fs::read_dir(path)?
+            .map(|x| x.map(|x| x.file_name().to_string_lossy().into_owned()))
+            .collect::<io::Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    }
+
+    fn is_dir(&self, path: String) -> io::Result<bool> {
+        let path = self.root.join(path);
+        fs::symlink_metadata(path).map(|x| x.is_dir())
+    }
+
+    fn symlink(&mut self, target: String, link: String) -> io::Result<()> {
+        let link = self.root.join(link);
+        std::os::unix::fs::symlink(target, link)
+    }
+
+    fn read_link(&self, path: String) -> io::Result<String> {
+        let path = self.root.join(path);
+        fs::read_link(path).map(|x| x.to_string_lossy().into_owned())
+    }
+
+    fn set_mode(&mut self, path: String, mode: u32) -> io::Result<()> {
+        let path = self.root.join(path);
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+    }
+
+    fn change_root(&mut self, path: String) -> io::Result<()> {
+        let root = self.root.join(path);
+        if !root.is_dir() {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+        self.root = root;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linux_exists() {
+        let fs = LinuxFileSystem::init().unwrap();
+        assert!(fs.exists("Cargo.toml".to_string()));
+        assert!(fs.exists("src".to_string()));
+        assert!(!fs.exists("no-such-file".to_string()));
+    }
+
+    #[test]
+    fn linux_sync() {
+        let root = env::temp_dir().join(format!("rgit-sync-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let mut fs = LinuxFileSystem::with_root(root.clone());
+        fs.write("index.lock".to_string(), b"new").unwrap();
+
+        let synced = fs.sync("index.lock".to_string());
+        let missing = fs.sync("missing".to_string());
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert!(synced.is_ok());
+        assert_eq!(missing.unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn linux_symlink() {
+        let root = env::temp_dir().join(format!("rgit-symlink-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let mut fs = LinuxFileSystem::with_root(root.clone());
+        fs.symlink("v2/".to_string(), "latest".to_string()).unwrap();
+
+        // 指す先が無くても symlink 自体を見る
+        let exists = fs.exists("latest".to_string());
+        let mode = fs.stat("latest".to_string()).map(|x| x.mode & 0o170000);
+        let target = fs.read_link("latest".to_string());
+        let is_dir = fs.is_dir("latest".to_string());
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert!(exists);
+        assert_eq!(mode.unwrap(), 0o120000);
+        assert_eq!(target.unwrap(), "v2/");
+        assert!(!is_dir.unwrap());
+    }
 }
@@ -0,0 +1,152 @@
+use super::FileSystem;
+use std::io;
+
+// `<path>.lock` を排他的に作って書き, commit で path に rename する
+// 他の rgit が同じ path を書いている間は acquire が失敗する
+// commit せずに drop したら lock を消し, path は前の中身のまま残す
+pub struct LockFile<'a, F: FileSystem> {
+    filesystem: &'a mut F,
+    path: String,
+    committed: bool,
+}
+
+impl<'a, F: FileSystem> LockFile<'a, F> {
+    pub fn acquire(filesystem: &'a mut F, path: String) -> io::Result<Self> {
+        acquire(filesystem, &path)?;
+
+        Ok(Self {
+            filesystem,
+            path,
+            committed: false,
+        })
+    }
+
+    // lock を取った時点の path の中身. lock を外すまで他の rgit は書き換えない
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        self.filesystem.read(self.path.clone())
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.filesystem.write(lock_path(&self.path), data)
+    }
+
+    // 書いた中身で path を置き換えて lock を外す
+    pub fn commit(mut self) -> io::Result<()> {
+        commit(self.filesystem, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl<F: FileSystem> Drop for LockFile<'_, F> {
+    fn drop(&mut self) {
+        if !self.committed {
+            // 消せなくても呼び出し元に返す方法が無い
+            let _ = rollback(self.filesystem, &self.path);
+        }
+    }
+}
+
+// lock を持ったまま filesystem を他の処理にも使う時は LockFile の代わりにこれらを順に呼ぶ
+// acquire の後は必ず commit か rollback で lock を外す
+pub fn acquire<F: FileSystem>(filesystem: &mut F, path: &str) -> io::Result<()> {
+    filesystem
+        .create_new(lock_path(path))
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::AlreadyExists => io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("unable to create '{}': File exists", lock_path(path)),
+            ),
+            _ => err,
+        })
+}
+
+// 途中で止まっても path が壊れた中身にならないように, ディスクに書き出してから置き換える
+pub fn commit<F: FileSystem>(filesystem: &mut F, path: &str) -> io::Result<()> {
+    filesystem.sync(lock_path(path))?;
+    filesystem.rename(lock_path(path), path.to_string())
+}
+
+pub fn rollback<F: FileSystem>(filesystem: &mut F, path: &str) -> io::Result<()> {
+    filesystem.remove(lock_path(path))
+}
+
+pub fn lock_path(path: &str) -> String {
+    format!("{}.lock", path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::counting::CountingFileSystem;
+    use crate::fs::inmem::InMemFileSystem;
+
+    #[test]
+    fn lock_file_commit() {
+        let mut fs = InMemFileSystem::init();
+        fs.write(".git/index".to_string(), b"old").unwrap();
+
+        let mut lock = LockFile::acquire(&mut fs, ".git/index".to_string()).unwrap();
+        lock.write(b"new").unwrap();
+        lock.commit().unwrap();
+
+        assert_eq!(fs.read(".git/index".to_string()).unwrap(), b"new");
+        assert!(!fs.exists(".git/index.lock".to_string()));
+    }
+
+    #[test]
+    fn lock_file_sync() {
+        let mut fs = CountingFileSystem::new(InMemFileSystem::init());
+        let mut lock = LockFile::acquire(&mut fs, ".git/index".to_string()).unwrap();
+        lock.write(b"new").unwrap();
+        drop(lock);
+        assert_eq!(fs.syncs(), 0);
+
+        // rename する前に1度だけ書き出す
+        let mut lock = LockFile::acquire(&mut fs, ".git/index".to_string()).unwrap();
+        lock.write(b"new").unwrap();
+        lock.commit().unwrap();
+        assert_eq!(fs.syncs(), 1);
+        assert_eq!(fs.read(".git/index".to_string()).unwrap(), b"new");
+    }
+
+    #[test]
+    fn lock_file_drop() {
+        let mut fs = InMemFileSystem::init();
+        fs.write(".git/index".to_string(), b"old").unwrap();
+
+        let mut lock = LockFile::acquire(&mut fs, ".git/index".to_string()).unwrap();
+        lock.write(b"new").unwrap();
+        drop(lock);
+
+        assert_eq!(fs.read(".git/index".to_string()).unwrap(), b"old");
+        assert!(!fs.exists(".git/index.lock".to_string()));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn lock_file_held() {
+        use crate::fs::linux::LinuxFileSystem;
+
+        // 同じディレクトリを別々のプロセスから触るのと同じ
+        let root = std::env::temp_dir().join(format!("rgit-lock-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let mut first = LinuxFileSystem::with_root(root.clone());
+        let mut second = LinuxFileSystem::with_root(root.clone());
+
+        let lock = LockFile::acquire(&mut first, "index".to_string()).unwrap();
+        let held = LockFile::acquire(&mut second, "index".to_string()).map(|_| ());
+        drop(lock);
+        // 外した後なら取れる
+        let released = LockFile::acquire(&mut second, "index".to_string()).map(|_| ());
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let err = held.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(
+            err.to_string(),
+            "unable to create 'index.lock': File exists"
+        );
+        assert!(released.is_ok());
+    }
+}
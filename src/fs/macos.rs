@@ -1,9 +1,16 @@
+#[cfg(target_os = "macos")]
 use super::{FileSystem, Metadata};
+#[cfg(target_os = "macos")]
 use std::env;
-use std::fs::{self, File};
+#[cfg(target_os = "macos")]
+use std::fs::{self, File, OpenOptions};
+#[cfg(target_os = "macos")]
 use std::io::{self, Read, Write};
 #[cfg(target_os = "macos")]
 use std::os::macos::fs::MetadataExt;
+#[cfg(target_os = "macos")]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(target_os = "macos")]
 use std::path::PathBuf;
 
 #[cfg(target_os = "macos")]
@@ -18,6 +25,11 @@ impl MacOSFileSystem {
             root: env::current_dir()?,
         })
     }
+
+    // カレントディレクトリ以外を起点にする
+    pub fn with_root(root: PathBuf) -> Self {
+        MacOSFileSystem { root }
+    }
 }
 
 #[cfg(target_os = "macos")]
@@ -29,6 +41,10 @@ impl FileSystem for MacOSFileSystem {
         Ok(buf)
     }
 
+    fn open(&self, path: String) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(File::open(self.root.join(path))?))
+    }
+
     fn write(&mut self, path: String, data: &[u8]) -> io::Result<()> {
         let mut file = File::create(self.root.join(path))?;
         file.write_all(data)?;
@@ -36,9 +52,30 @@ impl FileSystem for MacOSFileSystem {
         Ok(())
     }
 
+    fn append(&mut self, path: String, data: &[u8]) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(self.root.join(path))?;
+        file.write_all(data)
+    }
+
+    fn create_new(&mut self, path: String) -> io::Result<()> {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(self.root.join(path))?;
+        Ok(())
+    }
+
+    fn sync(&self, path: String) -> io::Result<()> {
+        File::open(self.root.join(path))?.sync_all()
+    }
+
     fn stat(&self, path: String) -> io::Result<Metadata> {
+        // git と同じく symlink は辿らない (lstat)
         let path = self.root.join(path);
-        let metadata = path.metadata()?;
+        let metadata = path.symlink_metadata()?;
 
         Ok(Metadata {
             dev: metadata.st_dev() as u32,
@@ -54,6 +91,11 @@ impl FileSystem for MacOSFileSystem {
         })
     }
 
+    fn exists(&self, path: String) -> bool {
+        // 指す先が無い symlink もあるものとする
+        self.root.join(path).symlink_metadata().is_ok()
+    }
+
     fn create_dir(&mut self, path: String) -> io::Result<()> {
         let path = self.root.join(path);
         fs::create_dir_all(path)
@@ -69,4 +111,52 @@ impl FileSystem for MacOSFileSystem {
         let path = self.root.join(path);
         fs::remove_file(path)
     }
+
+    fn remove_dir(&mut self, path: String) -> io::Result<()> {
+        let path = self.root.join(path);
+        fs::remove_dir(path)
+    }
+
+    fn remove_dir_all(&mut self, path: String) -> io::Result<()> {
+        let path = self.root.join(path);
+        fs::remove_dir_all(path)
+    }
+
+    fn read_dir(&self, path: String) -> io::Result<Vec<String>> {
+        let path = self.root.join(path);
+        let mut names = fs::read_dir(path)?
+            .map(|x| x.map(|x| x.file_name().to_string_lossy().into_owned()))
+            .collect::<io::Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    }
+
+    fn is_dir(&self, path: String) -> io::Result<bool> {
+        let path = self.root.join(path);
+        fs::symlink_metadata(path).map(|x| x.is_dir())
+    }
+
+    fn symlink(&mut self, target: String, link: String) -> io::Result<()> {
+        let link = self.root.join(link);
+        std::os::unix::fs::symlink(target, link)
+    }
+
+    fn read_link(&self, path: String) -> io::Result<String> {
+        let path = self.root.join(path);
+        fs::read_link(path).map(|x| x.to_string_lossy().into_owned())
+    }
+
+    fn set_mode(&mut self, path: String, mode: u32) -> io::Result<()> {
+        let path = self.root.join(path);
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+    }
+
+    fn change_root(&mut self, path: String) -> io::Result<()> {
+        let root = self.root.join(path);
+        if !root.is_dir() {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+        self.root = root;
+        Ok(())
+    }
 }
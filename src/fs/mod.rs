@@ -1,16 +1,47 @@
+pub mod counting;
 pub mod inmem;
 pub mod linux;
+pub mod lock;
 pub mod macos;
 
-use std::io;
+use std::io::{self, Read};
 
 pub trait FileSystem {
     fn read(&self, path: String) -> io::Result<Vec<u8>>;
     fn write(&mut self, path: String, data: &[u8]) -> io::Result<()>;
+    // ファイルの後ろに data を書き足す. 無ければ作る
+    fn append(&mut self, path: String, data: &[u8]) -> io::Result<()>;
+    // 大きなファイルを少しずつ読む. 既定では全体を読んでから返すので, 実際のファイルでは上書きする
+    fn open(&self, path: String) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(io::Cursor::new(self.read(path)?)))
+    }
+    // 空のファイルを作る. 既にあれば AlreadyExists で失敗する
+    fn create_new(&mut self, path: String) -> io::Result<()>;
+    // 書いた中身をディスクまで書き出す. rename で置き換える前に呼ぶ
+    // memory の中だけにあるものは何もしなくてよい
+    fn sync(&self, _path: String) -> io::Result<()> {
+        Ok(())
+    }
     fn stat(&self, path: String) -> io::Result<Metadata>;
+    // ファイルかディレクトリがあるか. 読めるかどうかは問わない
+    fn exists(&self, path: String) -> bool;
     fn create_dir(&mut self, path: String) -> io::Result<()>;
     fn rename(&mut self, from: String, to: String) -> io::Result<()>;
     fn remove(&mut self, path: String) -> io::Result<()>;
+    // 空のディレクトリを削除する
+    fn remove_dir(&mut self, path: String) -> io::Result<()>;
+    // ディレクトリを中身ごと削除する
+    fn remove_dir_all(&mut self, path: String) -> io::Result<()>;
+    fn read_dir(&self, path: String) -> io::Result<Vec<String>>;
+    // symlink は辿らずにディレクトリかどうかを返す
+    fn is_dir(&self, path: String) -> io::Result<bool>;
+    // link に target を指す symlink を作る
+    fn symlink(&mut self, target: String, link: String) -> io::Result<()>;
+    fn read_link(&self, path: String) -> io::Result<String>;
+    // パーミッション (0o755 など) を設定する
+    fn set_mode(&mut self, path: String, mode: u32) -> io::Result<()>;
+    // 以降のパスを path のディレクトリからの相対パスとして扱う
+    fn change_root(&mut self, path: String) -> io::Result<()>;
 }
 
 pub struct Metadata {
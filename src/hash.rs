@@ -0,0 +1,316 @@
+use crate::error::RgitError;
+#[cfg(feature = "json")]
+use crate::json::{ToJson, Value};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+// 省略表示する時のデフォルトの桁数
+pub const DEFAULT_ABBREV: usize = 7;
+
+pub trait Hasher {
+    fn digest(&self, bytes: &[u8]) -> Vec<u8>;
+}
+
+// リポジトリの object format
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum HashAlgo {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+impl HashAlgo {
+    // hash 値のバイト数
+    pub fn width(self) -> usize {
+        match self {
+            HashAlgo::Sha1 => 20,
+            HashAlgo::Sha256 => 32,
+        }
+    }
+
+    pub fn from(s: &str) -> Option<Self> {
+        match s {
+            "sha1" => Some(HashAlgo::Sha1),
+            "sha256" => Some(HashAlgo::Sha256),
+            _ => None,
+        }
+    }
+}
+
+impl Hasher for HashAlgo {
+    fn digest(&self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            HashAlgo::Sha1 => Vec::from(Sha1::digest(bytes).as_slice()),
+            HashAlgo::Sha256 => Vec::from(Sha256::digest(bytes).as_slice()),
+        }
+    }
+}
+
+// 少しずつ bytes を渡して hash を計算する. 大きな blob を memory に載せずに hash する時に使う
+pub enum StreamHasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl StreamHasher {
+    pub fn new(algo: HashAlgo) -> Self {
+        match algo {
+            HashAlgo::Sha1 => Self::Sha1(Sha1::new()),
+            HashAlgo::Sha256 => Self::Sha256(Sha256::new()),
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Self::Sha1(hasher) => hasher.update(bytes),
+            Self::Sha256(hasher) => hasher.update(bytes),
+        }
+    }
+
+    pub fn finish(self) -> ObjectId {
+        match self {
+            Self::Sha1(hasher) => ObjectId(hasher.finalize().to_vec()),
+            Self::Sha256(hasher) => ObjectId(hasher.finalize().to_vec()),
+        }
+    }
+}
+
+// hash を16進数にして先頭 len 文字だけ返す. len が hash の長さを超えたら全体を返す
+pub fn short_hash(full: &[u8], len: usize) -> String {
+    let mut hex = hex::encode(full);
+    hex.truncate(len);
+    hex
+}
+
+// object の hash 値. SHA-1 なら20バイト, SHA-256 なら32バイト
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObjectId(Vec<u8>);
+
+impl ObjectId {
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Self::try_from_bytes(bytes).ok()
+    }
+
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        Self::try_from_hex(hex).ok()
+    }
+
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, RgitError> {
+        match bytes.len() {
+            20 | 32 => Ok(Self(bytes.to_vec())),
+            n => Err(RgitError::InvalidObjectId(format!("{} bytes", n))),
+        }
+    }
+
+    pub fn try_from_hex(hex: &str) -> Result<Self, RgitError> {
+        if !hex.bytes().all(|x| x.is_ascii_hexdigit()) {
+            return Err(RgitError::InvalidObjectId(format!("not hex {:?}", hex)));
+        }
+        match hex.len() {
+            40 | 64 => Ok(Self(hex::decode(hex).unwrap())),
+            n => Err(RgitError::InvalidObjectId(format!(
+                "{} hex digits in {:?}",
+                n, hex
+            ))),
+        }
+    }
+
+    // bytes の hash 値
+    pub fn digest(algo: HashAlgo, bytes: &[u8]) -> Self {
+        Self(algo.digest(bytes))
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(&self.0)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    // .git/objects/<先頭2文字>/<残り> のディレクトリ名とファイル名
+    pub fn split_at(&self) -> (String, String) {
+        let hex = self.to_hex();
+        let (dir, file) = hex.split_at(2);
+        (dir.to_string(), file.to_string())
+    }
+}
+
+impl FromStr for ObjectId {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::try_from_hex(s).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+    }
+}
+
+// hex::encode などに bytes としてそのまま渡せる
+impl AsRef<[u8]> for ObjectId {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+// JSON では16進数の文字列にする
+#[cfg(feature = "json")]
+impl ToJson for ObjectId {
+    fn to_json(&self) -> Value {
+        Value::String(self.to_hex())
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+impl fmt::Display for HashAlgo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HashAlgo::Sha1 => write!(f, "sha1"),
+            HashAlgo::Sha256 => write!(f, "sha256"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_algo_from() {
+        assert_eq!(HashAlgo::from("sha1"), Some(HashAlgo::Sha1));
+        assert_eq!(HashAlgo::from("sha256"), Some(HashAlgo::Sha256));
+        assert_eq!(HashAlgo::from("md5"), None);
+        assert_eq!(HashAlgo::default(), HashAlgo::Sha1);
+    }
+
+    #[test]
+    fn stream_hasher() {
+        for algo in [HashAlgo::Sha1, HashAlgo::Sha256] {
+            let mut hasher = StreamHasher::new(algo);
+            for chunk in [b"blob 10\0".as_ref(), b"hello", b", git"] {
+                hasher.update(chunk);
+            }
+            assert_eq!(
+                hasher.finish(),
+                ObjectId::digest(algo, b"blob 10\0hello, git")
+            );
+        }
+    }
+
+    #[test]
+    fn hash_algo_digest() {
+        let bytes = b"blob 10\0hello, git";
+
+        let hash = HashAlgo::Sha1.digest(bytes);
+        assert_eq!(hash.len(), HashAlgo::Sha1.width());
+        assert_eq!(
+            hex::encode(hash),
+            "3edbc45b9a7f744c2345cd2cd073c3de091341ac"
+        );
+
+        let hash = HashAlgo::Sha256.digest(bytes);
+        assert_eq!(hash.len(), HashAlgo::Sha256.width());
+        assert_eq!(
+            hex::encode(hash),
+            "ec3c2c93acca398bbbc8e91467c9fa219c8ceb03ea8f858c0a2dd351c9505ba0"
+        );
+    }
+
+    #[test]
+    fn short_hash_len() {
+        let hash = hex::decode("3edbc45b9a7f744c2345cd2cd073c3de091341ac").unwrap();
+        assert_eq!(short_hash(&hash, DEFAULT_ABBREV), "3edbc45");
+        assert_eq!(
+            short_hash(&hash, 40),
+            "3edbc45b9a7f744c2345cd2cd073c3de091341ac"
+        );
+        assert_eq!(short_hash(&hash, 100).len(), 40);
+        assert_eq!(short_hash(&hash, 0), "");
+    }
+
+    #[test]
+    fn object_id_hex() {
+        let hex = "3edbc45b9a7f744c2345cd2cd073c3de091341ac";
+        let id = ObjectId::from_hex(hex).unwrap();
+        assert_eq!(id.to_hex(), hex);
+        assert_eq!(id.to_string(), hex);
+        assert_eq!(id.as_bytes().len(), HashAlgo::Sha1.width());
+        assert_eq!(hex.parse::<ObjectId>().unwrap(), id);
+
+        let sha256 = "a".repeat(64);
+        assert_eq!(ObjectId::from_hex(&sha256).unwrap().to_hex(), sha256);
+    }
+
+    #[test]
+    fn object_id_invalid() {
+        assert!(ObjectId::from_hex("3edbc45b").is_none());
+        assert!(ObjectId::from_hex(&"a".repeat(41)).is_none());
+        assert!(ObjectId::from_hex(&"z".repeat(40)).is_none());
+        assert!(ObjectId::from_bytes(&[0; 21]).is_none());
+        assert_eq!(
+            "hoge".parse::<ObjectId>().unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn object_id_try_from() {
+        assert_eq!(
+            ObjectId::try_from_hex("3edbc45b"),
+            Err(RgitError::InvalidObjectId(String::from(
+                "8 hex digits in \"3edbc45b\""
+            )))
+        );
+        assert_eq!(
+            ObjectId::try_from_hex(&"z".repeat(40)),
+            Err(RgitError::InvalidObjectId(format!(
+                "not hex {:?}",
+                "z".repeat(40)
+            )))
+        );
+        assert_eq!(
+            ObjectId::try_from_bytes(&[0; 21]),
+            Err(RgitError::InvalidObjectId(String::from("21 bytes")))
+        );
+        assert_eq!(
+            "hoge".parse::<ObjectId>().unwrap_err().to_string(),
+            "invalid object id: not hex \"hoge\""
+        );
+
+        let id = ObjectId::try_from_bytes(&[0xab; 20]).unwrap();
+        assert_eq!(ObjectId::try_from_hex(&id.to_hex()), Ok(id.clone()));
+        assert_eq!(hex::encode(&id), "ab".repeat(20));
+        assert_eq!(
+            ObjectId::digest(HashAlgo::Sha1, b"blob 10\0hello, git").to_hex(),
+            "3edbc45b9a7f744c2345cd2cd073c3de091341ac"
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn object_id_json() {
+        let id = ObjectId::from_hex("3edbc45b9a7f744c2345cd2cd073c3de091341ac").unwrap();
+        assert_eq!(
+            crate::json::to_string_pretty(&id),
+            "\"3edbc45b9a7f744c2345cd2cd073c3de091341ac\""
+        );
+    }
+
+    #[test]
+    fn object_id_split_at() {
+        let id = ObjectId::from_hex("3edbc45b9a7f744c2345cd2cd073c3de091341ac").unwrap();
+        assert_eq!(
+            id.split_at(),
+            (
+                "3e".to_string(),
+                "dbc45b9a7f744c2345cd2cd073c3de091341ac".to_string()
+            )
+        );
+    }
+}
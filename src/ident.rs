@@ -0,0 +1,197 @@
+use crate::config::Config;
+use crate::fs::FileSystem;
+use crate::object::commit::User;
+use crate::Git;
+use chrono::{DateTime, FixedOffset, TimeZone};
+use std::env;
+use std::io;
+
+// commit の author と committer を git と同じ優先順位で決める
+// 環境変数 (GIT_AUTHOR_NAME など) -> .git/config の user.name, user.email -> エラー
+// Git に user が指定されていればそれと git.clock の時刻だけを使う
+pub fn idents<F: FileSystem>(git: &Git<F>) -> io::Result<(User, User)> {
+    idents_with(git, |key: &str| env::var(key).ok())
+}
+
+// 環境変数の代わりに lookup で値を引く
+fn idents_with<F: FileSystem, L: Fn(&str) -> Option<String>>(
+    git: &Git<F>,
+    lookup: L,
+) -> io::Result<(User, User)> {
+    let now = git.clock.now();
+    if let Some((name, email)) = &git.user {
+        let user = User::new(name.clone(), email.clone(), now);
+        return Ok((user.clone(), user));
+    }
+
+    let config = git.read_config()?;
+    Ok((
+        resolve(&config, "AUTHOR", &lookup, now)?,
+        resolve(&config, "COMMITTER", &lookup, now)?,
+    ))
+}
+
+// role は "AUTHOR" か "COMMITTER". 日時が無ければ now にする
+pub fn resolve<L: Fn(&str) -> Option<String>>(
+    config: &Config,
+    role: &str,
+    lookup: L,
+    now: DateTime<FixedOffset>,
+) -> io::Result<User> {
+    let value = |field: &str, key: &str| {
+        lookup(&format!("GIT_{}_{}", role, field))
+            .or_else(|| config.get("user", None, key).map(String::from))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "{} identity unknown: set user.{} or GIT_{}_{}",
+                        role.to_lowercase(),
+                        key,
+                        role,
+                        field
+                    ),
+                )
+            })
+    };
+    let name = value("NAME", "name")?;
+    let email = value("EMAIL", "email")?;
+
+    let ts = match lookup(&format!("GIT_{}_DATE", role)) {
+        Some(date) => parse_date(&date).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid date format: {}", date),
+            )
+        })?,
+        None => now,
+    };
+
+    Ok(User::new(name, email, ts))
+}
+
+// `<unix time> <+hhmm>` (先頭の `@` は省略可) か RFC 2822, RFC 3339 の日時
+pub fn parse_date(s: &str) -> Option<DateTime<FixedOffset>> {
+    let s = s.trim();
+    if let Some((ts, offset)) = s.trim_start_matches('@').split_once(' ') {
+        if let (Ok(ts), Some(offset)) = (ts.parse::<i64>(), parse_offset(offset)) {
+            return offset.timestamp_opt(ts, 0).single();
+        }
+    }
+
+    DateTime::parse_from_rfc2822(s)
+        .or_else(|_| DateTime::parse_from_rfc3339(s))
+        .ok()
+}
+
+// `+0900` や `-0530`
+fn parse_offset(s: &str) -> Option<FixedOffset> {
+    let (sign, digits) = match s.as_bytes().first()? {
+        b'+' => (1, &s[1..]),
+        b'-' => (-1, &s[1..]),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    let hours = digits[..2].parse::<i32>().ok()?;
+    let minutes = digits[2..].parse::<i32>().ok()?;
+    FixedOffset::east_opt(sign * (hours * 60 * 60 + minutes * 60))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SystemClock};
+    use crate::fs::inmem::InMemFileSystem;
+    use std::collections::HashMap;
+
+    #[test]
+    fn parse_date_formats() {
+        let date = parse_date("1609642799 +0900").unwrap();
+        assert_eq!(date.timestamp(), 1609642799);
+        assert_eq!(date.offset().local_minus_utc(), 9 * 60 * 60);
+        assert_eq!(date.to_string(), "2021-01-03 11:59:59 +09:00");
+
+        let date = parse_date("@1609642799 -0530").unwrap();
+        assert_eq!(date.offset().local_minus_utc(), -(5 * 60 + 30) * 60);
+
+        let date = parse_date("Sun, 3 Jan 2021 11:59:59 +0900").unwrap();
+        assert_eq!(date.timestamp(), 1609642799);
+        let date = parse_date("2021-01-03T11:59:59+09:00").unwrap();
+        assert_eq!(date.timestamp(), 1609642799);
+
+        assert!(parse_date("yesterday").is_none());
+        assert!(parse_date("1609642799 0900").is_none());
+        assert!(parse_date("1609642799 +09").is_none());
+    }
+
+    #[test]
+    fn resolve_priority() {
+        let mut config = Config::new();
+        config.set("user", None, "name", "config name");
+        config.set("user", None, "email", "config@example.com");
+        let vars = [
+            ("GIT_AUTHOR_NAME", "env author"),
+            ("GIT_AUTHOR_DATE", "1609642799 +0900"),
+            ("GIT_COMMITTER_EMAIL", "committer@example.com"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect::<HashMap<_, _>>();
+        let lookup = |key: &str| vars.get(key).cloned();
+        let now = SystemClock.now();
+
+        let author = resolve(&config, "AUTHOR", lookup, now).unwrap();
+        assert_eq!(author.name, "env author");
+        assert_eq!(author.email, "config@example.com");
+        assert_eq!(author.ts.timestamp(), 1609642799);
+
+        let committer = resolve(&config, "COMMITTER", lookup, now).unwrap();
+        assert_eq!(committer.name, "config name");
+        assert_eq!(committer.email, "committer@example.com");
+        assert_eq!(committer.ts, now);
+
+        // config にも環境変数にも無ければエラー
+        let e = resolve(&Config::new(), "AUTHOR", lookup, now).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        assert!(resolve(
+            &config,
+            "AUTHOR",
+            |key: &str| { (key == "GIT_AUTHOR_DATE").then(|| "soon".to_string()) },
+            now
+        )
+        .is_err());
+    }
+
+    // 環境変数はプロセス全体で共有されて他のテストと競合するので, lookup に渡して確かめる
+    #[test]
+    fn idents_from_env() {
+        let vars = [
+            ("GIT_AUTHOR_NAME", "author"),
+            ("GIT_AUTHOR_EMAIL", "author@example.com"),
+            ("GIT_AUTHOR_DATE", "1609642799 +0900"),
+            ("GIT_COMMITTER_NAME", "committer"),
+            ("GIT_COMMITTER_EMAIL", "committer@example.com"),
+            ("GIT_COMMITTER_DATE", "1609643000 +0900"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect::<HashMap<_, _>>();
+        let lookup = |key: &str| vars.get(key).cloned();
+
+        let commit = |git: &Git<InMemFileSystem>| {
+            let (author, committer) = idents_with(git, lookup).unwrap();
+            let tree = "4b825dc642cb6eb9a060e54bf8d69288fbee4904".parse().unwrap();
+            git.commit_tree(author, Some(committer), &tree, "message".to_string())
+                .unwrap()
+        };
+        let first = commit(&Git::new(InMemFileSystem::init()));
+        let second = commit(&Git::new(InMemFileSystem::init()));
+
+        assert_eq!(first.author.name, "author");
+        assert_eq!(first.committer.name, "committer");
+        assert_eq!(first.committer.ts.timestamp(), 1609643000);
+        assert_eq!(first.calc_hash(), second.calc_hash());
+    }
+}
@@ -0,0 +1,243 @@
+use crate::pathspec::Matcher;
+
+// .gitignore の1行
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pattern {
+    matcher: Matcher,
+    // `!` で始まり, 除外を取り消すパターン
+    pub negated: bool,
+    // 書かれていたままのパターンと行番号. check-ignore -v で表示する
+    pub text: String,
+    pub line: usize,
+}
+
+// 1つのファイルから読んだパターン
+#[derive(Clone, Debug, PartialEq)]
+struct PatternList {
+    // パターンを読んだファイル
+    source: String,
+    // .gitignore のあるディレクトリ. .git/info/exclude なら None
+    base: Option<String>,
+    patterns: Vec<Pattern>,
+}
+
+impl PatternList {
+    // worktree の直下からの深さ. 深いディレクトリの .gitignore ほど優先する
+    fn priority(&self) -> usize {
+        match self.base.as_deref() {
+            None => 0,
+            Some("") => 1,
+            Some(base) => base.split('/').count() + 1,
+        }
+    }
+
+    // path をこのファイルのディレクトリからの相対パスにする. 下に無ければ None
+    fn relative<'a>(&self, path: &'a str) -> Option<&'a str> {
+        match self.base.as_deref() {
+            None | Some("") => Some(path),
+            Some(base) => path.strip_prefix(base).and_then(|x| x.strip_prefix('/')),
+        }
+    }
+}
+
+// 無視するかを決めたパターンとそのファイル
+#[derive(Debug, PartialEq)]
+pub struct IgnoreMatch<'a> {
+    pub source: &'a str,
+    pub pattern: &'a Pattern,
+}
+
+// .git/info/exclude と各ディレクトリの .gitignore のパターンの集まり
+// 同じファイルの中では後に書かれたパターンが, ファイルの間では深いディレクトリのものが優先される
+// 親ディレクトリが無視されていれば, その中のファイルを `!` で取り戻すことはできない
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IgnoreMatcher {
+    lists: Vec<PatternList>,
+}
+
+impl IgnoreMatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // .git/info/exclude のように worktree 全体に当てはまるパターンを追加する
+    pub fn add_exclude(&mut self, source: &str, text: &str) {
+        self.add_list(source, None, text);
+    }
+
+    // dir にある .gitignore のパターンを追加する. dir は worktree の直下なら空文字列
+    pub fn add_gitignore(&mut self, source: &str, dir: &str, text: &str) {
+        self.add_list(source, Some(dir.trim_matches('/').to_string()), text);
+    }
+
+    // 1行1パターンとして読む. 空行と `#` で始まる行は読み飛ばす
+    fn add_list(&mut self, source: &str, base: Option<String>, text: &str) {
+        let mut patterns = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let text = trim_trailing_spaces(line);
+            if text.is_empty() || text.starts_with('#') {
+                continue;
+            }
+            let (pattern, negated) = match text.strip_prefix('!') {
+                Some(x) => (x, true),
+                None => (text, false),
+            };
+            // 先頭の `/` は .gitignore のあるディレクトリの直下だけに当てはまる
+            let matcher = match pattern.strip_prefix('/') {
+                Some(x) => Matcher::anchored(x),
+                None => Matcher::new(pattern),
+            };
+            patterns.push(Pattern {
+                matcher,
+                negated,
+                text: text.to_string(),
+                line: i + 1,
+            });
+        }
+
+        self.lists.push(PatternList {
+            source: source.to_string(),
+            base,
+            patterns,
+        });
+    }
+
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        matches!(self.find(path, is_dir), Some(x) if !x.pattern.negated)
+    }
+
+    // path を無視するかどうかを決めたパターン. どれにも当てはまらなければ None
+    // 無視されている親ディレクトリがあれば, そのディレクトリに当てはまったパターンを返す
+    pub fn find(&self, path: &str, is_dir: bool) -> Option<IgnoreMatch<'_>> {
+        let path = path.trim_matches('/');
+        let components = path.split('/').collect::<Vec<_>>();
+        for n in 1..components.len() {
+            match self.find_entry(&components[..n].join("/"), true) {
+                Some(found) if !found.pattern.negated => return Some(found),
+                _ => {}
+            }
+        }
+
+        self.find_entry(path, is_dir)
+    }
+
+    // 親ディレクトリは見ずに path そのものに当てはまるパターンを探す
+    fn find_entry(&self, path: &str, is_dir: bool) -> Option<IgnoreMatch<'_>> {
+        let mut lists = self
+            .lists
+            .iter()
+            .filter_map(|list| list.relative(path).map(|x| (list, x)))
+            .collect::<Vec<_>>();
+        // 同じ優先度なら後から追加したもの
+        lists.sort_by_key(|(list, _)| list.priority());
+
+        lists.iter().rev().find_map(|(list, relative)| {
+            list.patterns
+                .iter()
+                .rev()
+                .find(|x| x.matcher.matches_entry(relative, is_dir))
+                .map(|pattern| IgnoreMatch {
+                    source: &list.source,
+                    pattern,
+                })
+        })
+    }
+}
+
+// 末尾の空白は `\` でエスケープされていなければ取り除く
+fn trim_trailing_spaces(line: &str) -> &str {
+    let trimmed = line.trim_end_matches(' ');
+    if trimmed.ends_with('\\') && trimmed.len() < line.len() {
+        &line[..(trimmed.len() + 1)]
+    } else {
+        trimmed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignore_patterns() {
+        let mut ignore = IgnoreMatcher::new();
+        ignore.add_gitignore(
+            ".gitignore",
+            "",
+            "# comment\n\n*.log\n!keep.log\ntarget/\n/build\nspace\\ \nlib/**/gen\n",
+        );
+        assert!(ignore.is_ignored("debug.log", false));
+        assert!(ignore.is_ignored("src/debug.log", false));
+        assert!(!ignore.is_ignored("keep.log", false));
+        assert!(!ignore.is_ignored("# comment", false));
+        assert!(ignore.is_ignored("target/debug/rgit", false));
+        assert!(ignore.is_ignored("target", true));
+        assert!(!ignore.is_ignored("target", false));
+        assert!(ignore.is_ignored("build/out.o", false));
+        assert!(!ignore.is_ignored("src/build/out.o", false));
+        assert!(ignore.is_ignored("space ", false));
+        assert!(!ignore.is_ignored("src/main.rs", false));
+        assert!(ignore.is_ignored("lib/gen", true));
+        assert!(ignore.is_ignored("lib/a/b/gen/x.rs", false));
+        assert!(!ignore.is_ignored("src/gen", false));
+
+        // 後から追加したパターンが優先される
+        ignore.add_gitignore(".gitignore", "", "!*.log");
+        assert!(!ignore.is_ignored("debug.log", false));
+    }
+
+    #[test]
+    fn ignore_nested() {
+        let mut ignore = IgnoreMatcher::new();
+        ignore.add_exclude(".git/info/exclude", "*.tmp\n*.bak\n");
+        ignore.add_gitignore(".gitignore", "", "*.log\n!*.bak\nout/\n");
+        ignore.add_gitignore("src/.gitignore", "src", "!debug.log\n/gen\n*.tmp\n");
+        ignore.add_gitignore("src/a/.gitignore", "src/a", "*.log\n");
+
+        // 深い .gitignore の `!` は浅い方のパターンより優先される
+        assert!(ignore.is_ignored("debug.log", false));
+        assert!(!ignore.is_ignored("src/debug.log", false));
+        assert!(ignore.is_ignored("src/other.log", false));
+        // さらに深い .gitignore がもう一度無視する
+        assert!(ignore.is_ignored("src/a/debug.log", false));
+
+        // .gitignore は .git/info/exclude より優先される
+        assert!(!ignore.is_ignored("x.bak", false));
+        assert!(ignore.is_ignored("x.tmp", false));
+        assert!(ignore.is_ignored("src/x.tmp", false));
+
+        // `/` で始まるパターンは .gitignore のあるディレクトリからのパス
+        assert!(ignore.is_ignored("src/gen/x.rs", false));
+        assert!(!ignore.is_ignored("gen/x.rs", false));
+        assert!(!ignore.is_ignored("src/a/gen/x.rs", false));
+    }
+
+    #[test]
+    fn ignore_parent_dir() {
+        let mut ignore = IgnoreMatcher::new();
+        ignore.add_gitignore(
+            ".gitignore",
+            "",
+            "out/\n!out/keep.txt\nlogs/*\n!logs/keep.txt\n",
+        );
+        ignore.add_gitignore("out/.gitignore", "out", "!*.txt\n");
+
+        // 無視されたディレクトリの中は取り戻せない
+        assert!(ignore.is_ignored("out/keep.txt", false));
+        let found = ignore.find("out/keep.txt", false).unwrap();
+        assert_eq!(found.source, ".gitignore");
+        assert_eq!(
+            (found.pattern.line, found.pattern.text.as_str()),
+            (1, "out/")
+        );
+
+        // ディレクトリ自体ではなく中身を無視していれば取り戻せる
+        assert!(ignore.is_ignored("logs/a.txt", false));
+        assert!(!ignore.is_ignored("logs/keep.txt", false));
+        let found = ignore.find("logs/keep.txt", false).unwrap();
+        assert!(found.pattern.negated);
+        assert_eq!(found.pattern.line, 4);
+
+        assert_eq!(ignore.find("src/main.rs", false), None);
+    }
+}
@@ -1,7 +1,13 @@
+use crate::error::RgitError;
+use crate::hash::{HashAlgo, Hasher, ObjectId};
+#[cfg(feature = "json")]
+use crate::json::{ToJson, Value};
 use chrono::{DateTime, TimeZone, Utc};
-use sha1::{Digest, Sha1};
 use std::fmt;
 
+// flags に格納できる名前の長さの上限
+const NAME_MASK: usize = 0xFFF;
+
 pub struct Index {
     pub entries: Vec<Entry>,
 }
@@ -12,30 +18,85 @@ impl Index {
     }
 
     pub fn from(bytes: &[u8]) -> Option<Self> {
+        Self::from_with(bytes, HashAlgo::default())
+    }
+
+    pub fn from_with(bytes: &[u8], algo: HashAlgo) -> Option<Self> {
+        Self::try_from_bytes_with(bytes, algo).ok()
+    }
+
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, RgitError> {
+        Self::try_from_bytes_with(bytes, HashAlgo::default())
+    }
+
+    pub fn try_from_bytes_with(bytes: &[u8], algo: HashAlgo) -> Result<Self, RgitError> {
+        if bytes.len() < 12 {
+            return Err(RgitError::Truncated("index header"));
+        }
+
         // インデックスファイルじゃない
         if &bytes[0..4] != b"DIRC" {
-            return None;
+            return Err(RgitError::BadHeader(format!(
+                "bad signature {:?}",
+                String::from_utf8_lossy(&bytes[0..4])
+            )));
         }
 
         // version 2 にだけ対応
-        if hex_to_num(&bytes[4..8]) != 2 {
-            return None;
+        let version = hex_to_num(&bytes[4..8]);
+        if version != 2 {
+            return Err(RgitError::UnsupportedVersion(version));
         }
 
         let entry_num = hex_to_num(&bytes[8..12]);
         let entries = (0..entry_num)
             .try_fold((0, Vec::new()), |(offs, mut vec), _| {
-                let entry = Entry::from(&bytes[(12 + offs)..])?;
+                let rest = bytes
+                    .get((12 + offs)..)
+                    .ok_or(RgitError::Truncated("index entry"))?;
+                let entry = Entry::try_from_bytes_with(rest, algo)?;
                 let size = entry.size();
                 vec.push(entry);
-                Some((offs + size, vec))
+                Ok((offs + size, vec))
             })
             .map(|(_, entries)| entries)?;
 
-        Some(Self::new(entries))
+        Ok(Self::new(entries))
+    }
+
+    // entries は名前順なので二分探索で探す
+    pub fn get_entry(&self, name: &str) -> Option<&Entry> {
+        self.position(name).ok().map(|i| &self.entries[i])
+    }
+
+    // 同じ名前の entry を置き換えて名前順を保ったまま追加する
+    pub fn upsert(&mut self, entry: Entry) {
+        match self.position(&entry.name) {
+            Ok(i) => self.entries[i] = entry,
+            Err(i) => self.entries.insert(i, entry),
+        }
+    }
+
+    // 削除した entry があれば true
+    pub fn remove_entry(&mut self, name: &str) -> bool {
+        match self.position(name) {
+            Ok(i) => {
+                self.entries.remove(i);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn position(&self, name: &str) -> Result<usize, usize> {
+        self.entries.binary_search_by(|x| x.name.as_str().cmp(name))
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
+        self.as_bytes_with(HashAlgo::default())
+    }
+
+    pub fn as_bytes_with(&self, algo: HashAlgo) -> Vec<u8> {
         let header = [
             *b"DIRC",
             [0x00, 0x00, 0x00, 0x02],
@@ -50,7 +111,7 @@ impl Index {
             .collect::<Vec<_>>();
 
         let content = [header, entries].concat();
-        let hash = Vec::from(Sha1::digest(&content).as_slice());
+        let hash = algo.digest(&content);
 
         [content, hash].concat()
     }
@@ -58,7 +119,7 @@ impl Index {
 
 impl fmt::Display for Index {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.entries.iter().try_for_each(|e| write!(f, "{}\n", e))
+        self.entries.iter().try_for_each(|e| writeln!(f, "{}", e))
     }
 }
 
@@ -71,11 +132,12 @@ pub struct Entry {
     pub uid: u32,
     pub gid: u32,
     pub size: u32,
-    pub hash: Vec<u8>,
+    pub hash: ObjectId,
     pub name: String,
 }
 
 impl Entry {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         c_time: DateTime<Utc>,
         m_time: DateTime<Utc>,
@@ -85,7 +147,7 @@ impl Entry {
         uid: u32,
         gid: u32,
         size: u32,
-        hash: Vec<u8>,
+        hash: ObjectId,
         name: String,
     ) -> Self {
         Self {
@@ -103,6 +165,25 @@ impl Entry {
     }
 
     pub fn from(bytes: &[u8]) -> Option<Self> {
+        Self::from_with(bytes, HashAlgo::default())
+    }
+
+    pub fn from_with(bytes: &[u8], algo: HashAlgo) -> Option<Self> {
+        Self::try_from_bytes_with(bytes, algo).ok()
+    }
+
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, RgitError> {
+        Self::try_from_bytes_with(bytes, HashAlgo::default())
+    }
+
+    pub fn try_from_bytes_with(bytes: &[u8], algo: HashAlgo) -> Result<Self, RgitError> {
+        let flags = 40 + algo.width();
+        let name_start = flags + 2;
+        // 名前の前までの固定長の部分
+        if bytes.len() < name_start {
+            return Err(RgitError::Truncated("index entry"));
+        }
+
         let c_time = hex_to_num(&bytes[0..4]);
         let c_time_nano = hex_to_num(&bytes[4..8]);
         let m_time = hex_to_num(&bytes[8..12]);
@@ -113,11 +194,22 @@ impl Entry {
         let uid = hex_to_num(&bytes[28..32]);
         let gid = hex_to_num(&bytes[32..36]);
         let size = hex_to_num(&bytes[36..40]);
-        let hash = Vec::from(&bytes[40..60]);
-        let name_size = hex_to_num(&bytes[60..62]);
-        let name = String::from_utf8(Vec::from(&bytes[62..(62 + name_size as usize)])).ok()?;
+        let hash = ObjectId::try_from_bytes(&bytes[40..flags])?;
+        // flags の下位 12bit が名前の長さ. 0xFFF 以上の場合は NUL 終端まで読む
+        let name_size = hex_to_num(&bytes[flags..name_start]) as usize & NAME_MASK;
+        let name_end = if name_size == NAME_MASK {
+            bytes[name_start..]
+                .iter()
+                .position(|&x| x == b'\0')
+                .map(|x| name_start + x)
+        } else {
+            Some(name_start + name_size).filter(|&x| x <= bytes.len())
+        }
+        .ok_or(RgitError::Truncated("index entry name"))?;
+        let name = String::from_utf8(Vec::from(&bytes[name_start..name_end]))
+            .map_err(|_| RgitError::InvalidUtf8("index entry name"))?;
 
-        Some(Self {
+        Ok(Self {
             c_time: Utc.timestamp(c_time.into(), c_time_nano),
             m_time: Utc.timestamp(m_time.into(), m_time_nano),
             dev,
@@ -132,7 +224,7 @@ impl Entry {
     }
 
     pub fn size(&self) -> usize {
-        let size = 62 + self.name.len();
+        let size = 42 + self.hash.as_bytes().len() + self.name.len();
         size + (8 - size % 8)
     }
 
@@ -150,16 +242,16 @@ impl Entry {
         .flat_map(|&x| Vec::from(x.to_be_bytes()))
         .collect::<Vec<_>>();
 
-        let name_size = self.name.len() as u16;
+        let name_size = self.name.len().min(NAME_MASK) as u16;
         let name = self.name.as_bytes();
 
-        let len = 62 + name_size as usize;
+        let len = 42 + self.hash.as_bytes().len() + name.len();
 
         let padding = (0..(8 - len % 8)).map(|_| b'\0').collect::<Vec<_>>();
 
         [
             meta,
-            self.hash.clone(),
+            self.hash.as_bytes().to_vec(),
             Vec::from(name_size.to_be_bytes()),
             name.to_vec(),
             padding,
@@ -168,6 +260,38 @@ impl Entry {
     }
 }
 
+#[cfg(feature = "json")]
+impl ToJson for Index {
+    fn to_json(&self) -> Value {
+        Value::object(vec![
+            ("version", 2u32.into()),
+            (
+                "entries",
+                Value::Array(self.entries.iter().map(ToJson::to_json).collect()),
+            ),
+        ])
+    }
+}
+
+// 時刻は RFC 3339 の文字列, mode は8進数の文字列にする
+#[cfg(feature = "json")]
+impl ToJson for Entry {
+    fn to_json(&self) -> Value {
+        Value::object(vec![
+            ("name", self.name.as_str().into()),
+            ("hash", self.hash.to_json()),
+            ("mode", format!("{:06o}", self.mode).into()),
+            ("size", self.size.into()),
+            ("ctime", self.c_time.to_rfc3339().into()),
+            ("mtime", self.m_time.to_rfc3339().into()),
+            ("dev", self.dev.into()),
+            ("inode", self.inode.into()),
+            ("uid", self.uid.into()),
+            ("gid", self.gid.into()),
+        ])
+    }
+}
+
 impl fmt::Display for Entry {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -223,14 +347,38 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "range end index 4 out of range for slice of length 0")]
-    fn entry_from_panic() {
-        Entry::from(b"");
-        Entry::from(b"1");
-        Entry::from(b"12");
-        Entry::from(b"123");
-        Entry::from(b"1234");
-        Entry::from(b"12345");
+    fn entry_from_truncated() {
+        // 壊れた index の途中で切れた entry でも panic しない
+        assert!(Entry::from(&[0xff; 10]).is_none());
+        for len in 0..62 {
+            assert!(Entry::from(&vec![0; len]).is_none());
+        }
+        assert_eq!(
+            Entry::try_from_bytes(b"12345").err(),
+            Some(RgitError::Truncated("index entry"))
+        );
+
+        // 名前の長さが 3 なのに 1 byte しかない
+        let mut bytes = vec![0; 60];
+        bytes.extend([0x00, 0x03, b'a']);
+        assert_eq!(
+            Entry::try_from_bytes(&bytes).err(),
+            Some(RgitError::Truncated("index entry name"))
+        );
+        // NUL で終わらない長い名前
+        let mut bytes = vec![0; 60];
+        bytes.extend([0x0f, 0xff, b'a']);
+        assert_eq!(
+            Entry::try_from_bytes(&bytes).err(),
+            Some(RgitError::Truncated("index entry name"))
+        );
+
+        let mut bytes = vec![0; 60];
+        bytes.extend([0x00, 0x01, 0xff, 0x00]);
+        assert_eq!(
+            Entry::try_from_bytes(&bytes).err(),
+            Some(RgitError::InvalidUtf8("index entry name"))
+        );
     }
 
     #[test]
@@ -264,6 +412,171 @@ mod tests {
         assert_eq!(entry.as_bytes(), Vec::from(&bytes[..]));
     }
 
+    #[test]
+    fn entry_long_name() {
+        let name = "a".repeat(5000);
+        let entry = Entry::new(
+            Utc.timestamp(0, 0),
+            Utc.timestamp(0, 0),
+            0,
+            0,
+            33188,
+            0,
+            0,
+            0,
+            ObjectId::from_bytes(&[0; 20]).unwrap(),
+            name.clone(),
+        );
+        let bytes = entry.as_bytes();
+        assert_eq!(hex_to_num(&bytes[60..62]), 0xFFF);
+        assert_eq!(bytes.len(), entry.size());
+
+        let e = Entry::from(&bytes).unwrap();
+        assert_eq!(e.name, name);
+        assert_eq!(e.size(), bytes.len());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn index_json() {
+        let entry = Entry::new(
+            Utc.timestamp(1609642799, 0),
+            Utc.timestamp(1609642799, 0),
+            0,
+            0,
+            33188,
+            0,
+            0,
+            3,
+            ObjectId::from_bytes(&[0xab; 20]).unwrap(),
+            "a.txt".to_string(),
+        );
+        let value = Index::new(vec![entry]).to_json();
+        assert_eq!(value.keys(), vec!["version", "entries"]);
+        let entry = match value.get("entries") {
+            Some(crate::json::Value::Array(entries)) => &entries[0],
+            x => panic!("unexpected entries {:?}", x),
+        };
+        assert_eq!(
+            entry.get("mode"),
+            Some(&crate::json::Value::String("100644".to_string()))
+        );
+        assert_eq!(
+            entry.get("mtime"),
+            Some(&crate::json::Value::String(
+                "2021-01-03T02:59:59+00:00".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn entry_sha256() {
+        let entry = Entry::new(
+            Utc.timestamp(0, 0),
+            Utc.timestamp(0, 0),
+            0,
+            0,
+            33188,
+            0,
+            0,
+            0,
+            ObjectId::from_bytes(&[0xab; 32]).unwrap(),
+            "src/main.rs".to_string(),
+        );
+        let bytes = entry.as_bytes();
+        assert_eq!(bytes.len() % 8, 0);
+
+        let e = Entry::from_with(&bytes, HashAlgo::Sha256).unwrap();
+        assert_eq!(e.hash.as_bytes(), [0xab; 32]);
+        assert_eq!(e.name, "src/main.rs");
+
+        let index = Index::new(vec![e]);
+        let bytes = index.as_bytes_with(HashAlgo::Sha256);
+        let index = Index::from_with(&bytes, HashAlgo::Sha256).unwrap();
+        assert_eq!(index.entries.len(), 1);
+        assert_eq!(index.entries[0].name, "src/main.rs");
+    }
+
+    #[test]
+    fn index_upsert() {
+        let entry = |name: &str, hash: u8| {
+            Entry::new(
+                Utc.timestamp(0, 0),
+                Utc.timestamp(0, 0),
+                0,
+                0,
+                33188,
+                0,
+                0,
+                0,
+                ObjectId::from_bytes(&[hash; 20]).unwrap(),
+                name.to_string(),
+            )
+        };
+
+        let mut index = Index::new(Vec::new());
+        index.upsert(entry("src/main.rs", 1));
+        index.upsert(entry("README.md", 2));
+        index.upsert(entry("src/lib.rs", 3));
+        index.upsert(entry("src/main.rs", 4));
+
+        let names = index
+            .entries
+            .iter()
+            .map(|x| x.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["README.md", "src/lib.rs", "src/main.rs"]);
+        assert_eq!(index.entries[2].hash.as_bytes(), [4; 20]);
+
+        assert_eq!(
+            index.get_entry("src/main.rs").unwrap().hash.as_bytes(),
+            [4; 20]
+        );
+        assert_eq!(
+            index.get_entry("README.md").unwrap().hash.as_bytes(),
+            [2; 20]
+        );
+        assert!(index.get_entry("src").is_none());
+
+        assert!(index.remove_entry("src/lib.rs"));
+        assert!(!index.remove_entry("src/lib.rs"));
+        assert!(index.get_entry("src/lib.rs").is_none());
+        let names = index
+            .entries
+            .iter()
+            .map(|x| x.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["README.md", "src/main.rs"]);
+    }
+
+    #[test]
+    fn index_try_from_bytes() {
+        assert_eq!(
+            Index::try_from_bytes(b"DIRC").err(),
+            Some(RgitError::Truncated("index header"))
+        );
+        assert_eq!(
+            Index::try_from_bytes(b"HOGE\0\0\0\x02\0\0\0\0").err(),
+            Some(RgitError::BadHeader(String::from("bad signature \"HOGE\"")))
+        );
+        assert_eq!(
+            Index::try_from_bytes(b"DIRC\0\0\0\x03\0\0\0\0").err(),
+            Some(RgitError::UnsupportedVersion(3))
+        );
+        // entry が 1 つあるはずなのに無い
+        assert_eq!(
+            Index::try_from_bytes(b"DIRC\0\0\0\x02\0\0\0\x01").err(),
+            Some(RgitError::Truncated("index entry"))
+        );
+        // entry の途中で切れている
+        let bytes = [b"DIRC\0\0\0\x02\0\0\0\x01".as_ref(), &[0xff; 10]].concat();
+        assert_eq!(
+            Index::try_from_bytes(&bytes).err(),
+            Some(RgitError::Truncated("index entry"))
+        );
+        assert!(Index::try_from_bytes(b"DIRC\0\0\0\x02\0\0\0\0").is_ok());
+    }
+
     #[test]
     fn index_from() {
         assert!(Index::from(b"HOGE").is_none());
@@ -0,0 +1,254 @@
+// object や index を字下げした JSON にする
+// 各型が ToJson で Value を組み立て, それを書き出す. serde の Serialize も Value を通す
+use crate::hash::ObjectId;
+use crate::index::{Entry, Index};
+use crate::object::blob::Blob;
+use crate::object::commit::{Commit, User};
+use crate::object::tree::{File, Tree};
+use crate::object::GitObject;
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use std::fmt::{self, Write};
+
+#[derive(Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    // 整数も小数も書き出す形のまま持つ
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    // 書き出す時に並びが変わらないように Vec で持つ
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    // 並びを保ったまま object にする
+    pub fn object(fields: Vec<(&str, Value)>) -> Self {
+        Self::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Self::Object(fields) => fields.iter().find(|x| x.0 == key).map(|x| &x.1),
+            _ => None,
+        }
+    }
+
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Self::Object(fields) => fields.iter().map(|x| x.0.as_str()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn write_pretty(&self, out: &mut String, depth: usize) {
+        let indent = |out: &mut String, depth: usize| out.push_str(&"  ".repeat(depth));
+        match self {
+            Self::Null => out.push_str("null"),
+            Self::Bool(x) => out.push_str(if *x { "true" } else { "false" }),
+            Self::Number(x) => out.push_str(x),
+            Self::String(x) => write_string(out, x),
+            Self::Array(items) if items.is_empty() => out.push_str("[]"),
+            Self::Array(items) => {
+                out.push_str("[\n");
+                for (i, item) in items.iter().enumerate() {
+                    indent(out, depth + 1);
+                    item.write_pretty(out, depth + 1);
+                    out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+                }
+                indent(out, depth);
+                out.push(']');
+            }
+            Self::Object(fields) if fields.is_empty() => out.push_str("{}"),
+            Self::Object(fields) => {
+                out.push_str("{\n");
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    indent(out, depth + 1);
+                    write_string(out, key);
+                    out.push_str(": ");
+                    value.write_pretty(out, depth + 1);
+                    out.push_str(if i + 1 < fields.len() { ",\n" } else { "\n" });
+                }
+                indent(out, depth);
+                out.push('}');
+            }
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = String::new();
+        self.write_pretty(&mut out, 0);
+        f.write_str(&out)
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// JSON にできる型
+pub trait ToJson {
+    fn to_json(&self) -> Value;
+}
+
+pub fn to_string_pretty<T: ToJson + ?Sized>(value: &T) -> String {
+    value.to_json().to_string()
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Self::Null, Into::into)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(value: Vec<T>) -> Self {
+        Self::Array(value.into_iter().map(Into::into).collect())
+    }
+}
+
+macro_rules! number {
+    ($($t:ty),*) => {
+        $(impl From<$t> for Value {
+            fn from(value: $t) -> Self {
+                Self::Number(value.to_string())
+            }
+        })*
+    };
+}
+number!(u32, u64, usize, i64);
+
+// ToJson と同じ形で serde に渡す
+macro_rules! serialize_as_json {
+    ($($t:ty),*) => {
+        $(impl Serialize for $t {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                self.to_json().serialize(serializer)
+            }
+        })*
+    };
+}
+serialize_as_json!(ObjectId, GitObject, Blob, Tree, File, Commit, User, Index, Entry);
+
+impl Serialize for Value {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Self::Null => serializer.serialize_none(),
+            Self::Bool(x) => serializer.serialize_bool(*x),
+            Self::Number(x) => match (x.parse::<i64>(), x.parse::<u64>()) {
+                (Ok(x), _) => serializer.serialize_i64(x),
+                (_, Ok(x)) => serializer.serialize_u64(x),
+                _ => serializer.serialize_f64(x.parse().unwrap_or(f64::NAN)),
+            },
+            Self::String(x) => serializer.serialize_str(x),
+            Self::Array(items) => {
+                let mut s = serializer.serialize_seq(Some(items.len()))?;
+                for item in items.iter() {
+                    s.serialize_element(item)?;
+                }
+                s.end()
+            }
+            Self::Object(fields) => {
+                let mut s = serializer.serialize_map(Some(fields.len()))?;
+                for (key, value) in fields.iter() {
+                    s.serialize_entry(key, value)?;
+                }
+                s.end()
+            }
+        }
+    }
+}
+
+// 文字列にできないバイト列を JSON に入れるための標準の base64 (`=` で埋める)
+pub fn base64(bytes: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, &x)| acc | (x as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(TABLE[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_pretty() {
+        let value = Value::object(vec![
+            ("a\"b", vec![Value::from(1u32), Value::Null].into()),
+            ("empty", Value::Array(Vec::new())),
+            ("none", Value::from(None::<String>)),
+            ("object", Value::object(vec![])),
+        ]);
+        assert_eq!(
+            value.to_string(),
+            "{\n  \"a\\\"b\": [\n    1,\n    null\n  ],\n  \"empty\": [],\n  \"none\": null,\n  \"object\": {}\n}"
+        );
+        assert_eq!(value.keys(), vec!["a\"b", "empty", "none", "object"]);
+        assert_eq!(Value::from("x\ny\u{1}").to_string(), "\"x\\ny\\u0001\"");
+        assert_eq!(
+            Value::Array(vec![Value::Bool(true), Value::from(-1i64)]).to_string(),
+            "[\n  true,\n  -1\n]"
+        );
+    }
+
+    #[test]
+    fn json_base64() {
+        // base64 コマンドと同じ
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"\xff\xfe\xfd\0"), "//79AA==");
+    }
+}
@@ -1,86 +1,941 @@
+pub mod bundle;
+pub mod cache;
+pub mod clock;
 pub mod cmd;
+pub mod config;
+pub mod error;
+pub mod fast_import;
 pub mod fs;
+pub mod hash;
+pub mod ident;
+pub mod ignore;
 pub mod index;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod notes;
 pub mod object;
+pub mod pack;
+pub mod patch;
+pub mod pathspec;
+pub mod protocol;
+pub mod reachable;
+pub mod reflog;
+pub mod remote;
+pub mod revwalk;
 
+use crate::cache::LruCache;
+use crate::clock::{Clock, SystemClock};
+use crate::config::Config;
+use crate::ignore::IgnoreMatcher;
 use crate::index::{Entry, Index};
-use chrono::{Local, TimeZone, Utc};
+use chrono::{TimeZone, Utc};
+use fs::lock::{self, LockFile};
 use fs::FileSystem;
-use libflate::zlib::{Decoder, Encoder};
+use hash::{HashAlgo, Hasher, ObjectId, StreamHasher};
+use libflate::zlib::{self, Decoder, Encoder};
 use object::blob::Blob;
 use object::commit::{self, Commit};
-use object::tree::{self, Tree};
-use object::GitObject;
-use std::io::{self, Read, Write};
+use object::tree::{self, FileMode, Tree};
+use object::{GitObject, ObjectType};
+use pack::{DeltaBase, Pack};
+use remote::Remote;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, BufWriter, Read, Write};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// 展開済みの delta の base をいくつまで覚えておくか
+const DELTA_CACHE_SIZE: usize = 256;
+
+// 大きな blob を少しずつ読み書きする時の buffer の大きさ
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+
+// これより大きなファイルは memory に載せずに blob にする
+const DEFAULT_STREAM_THRESHOLD: u64 = 4 * 1024 * 1024;
+
+// write_object_stream の一時ファイルの名前に付ける番号
+static TMP_OBJECT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// delta を辿る深さの上限. git と同じ値で, 壊れた pack の循環もここで止まる
+const MAX_DELTA_DEPTH: usize = 10000;
+
+// .git の直下に置かれる refs/ 以外の ref
+const SPECIAL_REFS: [&str; 4] = ["HEAD", "ORIG_HEAD", "MERGE_HEAD", "FETCH_HEAD"];
+
+// pack の type と展開した中身
+type Resolved = (u8, Rc<Vec<u8>>);
 
 pub struct Git<F: FileSystem> {
     pub filesystem: F,
+    pub hash_algo: HashAlgo,
+    // .git/objects/pack 以下の pack. 初めて必要になった時に読み込む
+    packs: RefCell<Option<Rc<Vec<Pack>>>>,
+    // (pack の番号, offset) ごとの展開済み object
+    delta_cache: RefCell<HashMap<(usize, u64), Resolved>>,
+    // read_decoded が返した object. 既定では何も覚えない
+    object_cache: RefCell<LruCache<ObjectId, Resolved>>,
+    // ".git" など. refs や objects はこのディレクトリの下にある
+    git_dir: String,
+    // (name, email). 指定されていれば環境変数や config より優先して commit に使う
+    pub user: Option<(String, String)>,
+    pub clock: Box<dyn Clock>,
+    // commit_tree で tree が object として揃っていることを確かめる. 既定では確かめる
+    pub check_commit_tree: bool,
+    // git add でこのバイト数を超えるファイルは少しずつ hash して書き込む
+    pub stream_threshold: u64,
+    // cwd の worktree からの相対パス. discover で見つけた時だけ設定される
+    pub prefix: String,
+}
+
+// 作者や時刻を固定した Git を作る
+pub struct GitBuilder<F: FileSystem> {
+    git: Git<F>,
+}
+
+impl<F: FileSystem> GitBuilder<F> {
+    pub fn new(filesystem: F) -> Self {
+        Self {
+            git: Git::new(filesystem),
+        }
+    }
+
+    pub fn hash_algo(mut self, hash_algo: HashAlgo) -> Self {
+        self.git.hash_algo = hash_algo;
+        self
+    }
+
+    pub fn git_dir(mut self, git_dir: &str) -> Self {
+        self.git.git_dir = git_dir.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn user(mut self, name: &str, email: &str) -> Self {
+        self.git.user = Some((name.to_string(), email.to_string()));
+        self
+    }
+
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.git.clock = Box::new(clock);
+        self
+    }
+
+    pub fn cache_capacity(self, capacity: usize) -> Self {
+        self.git.set_cache_capacity(capacity);
+        self
+    }
+
+    pub fn check_commit_tree(mut self, check: bool) -> Self {
+        self.git.check_commit_tree = check;
+        self
+    }
+
+    pub fn stream_threshold(mut self, threshold: u64) -> Self {
+        self.git.stream_threshold = threshold;
+        self
+    }
+
+    pub fn build(self) -> Git<F> {
+        self.git
+    }
+}
+
+// hash 値がパスと一致しない loose object
+#[derive(Debug, PartialEq)]
+pub struct FsckProblem {
+    pub path: String,
+    pub expected: String,
+    // 展開できなかった場合は None
+    pub actual: Option<String>,
+}
+
+// loose object の数と大きさ. compressed はファイルの大きさ, inflated は展開した header 込みの大きさ
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ObjectSizes {
+    pub count: usize,
+    pub compressed: u64,
+    pub inflated: u64,
+}
+
+impl ObjectSizes {
+    fn add(&mut self, other: &Self) {
+        self.count += other.count;
+        self.compressed += other.compressed;
+        self.inflated += other.inflated;
+    }
+}
+
+// 型ごとの loose object の大きさ
+#[derive(Debug, Default, PartialEq)]
+pub struct StoreStats {
+    pub blob: ObjectSizes,
+    pub tree: ObjectSizes,
+    pub commit: ObjectSizes,
+}
+
+impl StoreStats {
+    pub fn get(&self, object_type: ObjectType) -> &ObjectSizes {
+        match object_type {
+            ObjectType::Blob => &self.blob,
+            ObjectType::Tree => &self.tree,
+            ObjectType::Commit => &self.commit,
+        }
+    }
+
+    fn get_mut(&mut self, object_type: ObjectType) -> &mut ObjectSizes {
+        match object_type {
+            ObjectType::Blob => &mut self.blob,
+            ObjectType::Tree => &mut self.tree,
+            ObjectType::Commit => &mut self.commit,
+        }
+    }
+
+    pub fn total(&self) -> ObjectSizes {
+        let mut total = ObjectSizes::default();
+        for sizes in [&self.blob, &self.tree, &self.commit] {
+            total.add(sizes);
+        }
+        total
+    }
 }
 
 impl<F: FileSystem> Git<F> {
     pub fn new(filesystem: F) -> Self {
-        Self { filesystem }
+        Self::with_hash_algo(filesystem, HashAlgo::default())
+    }
+
+    pub fn with_hash_algo(filesystem: F, hash_algo: HashAlgo) -> Self {
+        Self {
+            filesystem,
+            hash_algo,
+            packs: RefCell::new(None),
+            delta_cache: RefCell::new(HashMap::new()),
+            object_cache: RefCell::new(LruCache::new(0)),
+            git_dir: ".git".to_string(),
+            user: None,
+            clock: Box::new(SystemClock),
+            check_commit_tree: true,
+            stream_threshold: DEFAULT_STREAM_THRESHOLD,
+            prefix: String::new(),
+        }
+    }
+
+    // `git --git-dir=<dir>` のように .git 以外のディレクトリを使う
+    pub fn with_git_dir(filesystem: F, git_dir: String) -> Self {
+        Self {
+            git_dir: git_dir.trim_end_matches('/').to_string(),
+            ..Self::new(filesystem)
+        }
+    }
+
+    // cwd (filesystem の起点からの相対パス) から親へ .git ディレクトリを探し,
+    // 見つかったディレクトリを filesystem の起点にする
+    pub fn discover(mut filesystem: F, cwd: &str) -> io::Result<Self> {
+        let cwd = cwd.trim_matches('/');
+        let mut dir = cwd.to_string();
+        loop {
+            let git_dir = if dir.is_empty() {
+                ".git".to_string()
+            } else {
+                format!("{}/.git", dir)
+            };
+            if filesystem.is_dir(git_dir).unwrap_or(false) {
+                let prefix = cwd[dir.len()..].trim_start_matches('/').to_string();
+                filesystem.change_root(dir)?;
+                let mut git = Self::new(filesystem).detect_hash_algo()?;
+                git.prefix = prefix;
+                return Ok(git);
+            }
+            if dir.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "not a git repository (or any of the parent directories): .git",
+                ));
+            }
+            dir = dir
+                .rsplit_once('/')
+                .map(|(parent, _)| parent.to_string())
+                .unwrap_or_default();
+        }
+    }
+
+    // cwd からの相対パスを worktree からのパスにする. `.` と `..` は辿り, worktree の外は受け付けない
+    // 末尾の `/` は残し, worktree そのものは "." にする
+    pub fn prefix_path(&self, path: &str) -> io::Result<String> {
+        let mut names = Vec::new();
+        for name in self.prefix.split('/').chain(path.split('/')) {
+            match name {
+                "" | "." => {}
+                ".." => {
+                    names.pop().ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("{}: '{}' is outside repository", path, path),
+                        )
+                    })?;
+                }
+                name => names.push(name),
+            }
+        }
+
+        Ok(match names.join("/") {
+            x if x.is_empty() => ".".to_string(),
+            x if path.ends_with('/') => format!("{}/", x),
+            x => x,
+        })
+    }
+
+    // path (filesystem の起点からの相対パス) のリポジトリを開く
+    // path/.git があればそこを起点にし, 無ければ path 自体を bare リポジトリの git_dir とする
+    pub fn open(mut filesystem: F, path: &str) -> io::Result<Self> {
+        let path = path.trim_matches('/').to_string();
+        let join = |name: &str| {
+            if path.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}/{}", path, name)
+            }
+        };
+        if filesystem.is_dir(join(".git")).unwrap_or(false) {
+            filesystem.change_root(path)?;
+            return Self::new(filesystem).detect_hash_algo();
+        }
+        if !path.is_empty() && filesystem.exists(join("HEAD")) {
+            return Self::with_git_dir(filesystem, path).detect_hash_algo();
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("'{}' does not appear to be a git repository", path),
+        ))
+    }
+
+    // .git/config の extensions.objectformat から hash の種類を決める. 無ければ sha1
+    fn detect_hash_algo(mut self) -> io::Result<Self> {
+        let config = self.read_config()?;
+        if let Some(format) = config.get("extensions", None, "objectformat") {
+            self.hash_algo = HashAlgo::from(&format.to_lowercase()).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("unknown object format '{}'", format),
+                )
+            })?;
+        }
+        Ok(self)
+    }
+
+    // git init. git_dir に objects, refs と master を指す HEAD を作る
+    // sha1 以外の hash を使うなら git と同じく format version 1 と extensions.objectformat を書く
+    pub fn init(&mut self) -> io::Result<()> {
+        for dir in ["", "objects", "refs", "refs/heads", "refs/tags"] {
+            let path = self.git_path(dir);
+            if !self.filesystem.exists(path.clone()) {
+                self.filesystem
+                    .create_dir(path.trim_end_matches('/').to_string())?;
+            }
+        }
+        if self.hash_algo != HashAlgo::Sha1 {
+            let mut config = self.read_config()?;
+            config.set("core", None, "repositoryformatversion", "1");
+            config.set(
+                "extensions",
+                None,
+                "objectformat",
+                &self.hash_algo.to_string(),
+            );
+            self.write_config(&config)?;
+        }
+        self.set_head("refs/heads/master")
+    }
+
+    pub fn git_dir(&self) -> &str {
+        &self.git_dir
+    }
+
+    // git_dir の下のパス. git_path("HEAD") なら ".git/HEAD"
+    pub fn git_path(&self, path: &str) -> String {
+        format!("{}/{}", self.git_dir, path)
     }
 
     pub fn cat_file_p(&self, bytes: &[u8]) -> io::Result<GitObject> {
-        let mut d = Decoder::new(&bytes[..])?;
-        let mut buf = Vec::new();
-        d.read_to_end(&mut buf)?;
+        let buf = inflate(bytes)?;
+
+        GitObject::new_with(&buf, self.hash_algo).ok_or(io::Error::from(io::ErrorKind::InvalidData))
+    }
+
+    // 展開した中身の hash が id と一致することを確かめてから object にする
+    pub fn read_object_checked(&self, id: &ObjectId) -> io::Result<GitObject> {
+        let buf = inflate(&self.read_object(id)?)?;
+        let actual = self.hash_algo.digest(&buf);
+        if actual != id.as_bytes() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("hash mismatch (actual {})", hex::encode(actual)),
+            ));
+        }
+
+        GitObject::new_with(&buf, self.hash_algo)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "object is corrupt"))
+    }
+
+    // 展開した object. 中身は read_decoded の cache から作る
+    pub fn read_git_object(&self, id: &ObjectId) -> io::Result<GitObject> {
+        let (code, content) = self.read_decoded(id)?;
+        GitObject::new_with(&pack::object_bytes(code, &content), self.hash_algo)
+            .ok_or(io::Error::from(io::ErrorKind::InvalidData))
+    }
+
+    pub fn read_commit(&self, hash: &str) -> io::Result<Commit> {
+        match self.read_git_object(&hash.parse()?)? {
+            GitObject::Commit(commit) => Ok(commit),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a commit", hash),
+            )),
+        }
+    }
+
+    // blob の中身を header を除いて返す
+    pub fn read_blob(&self, hash: &str) -> io::Result<Vec<u8>> {
+        let (code, content) = self.read_decoded(&hash.parse()?)?;
+        if code != pack::OBJ_BLOB {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} is a {}, not a blob",
+                    hash,
+                    pack::type_name(code).unwrap_or_default()
+                ),
+            ));
+        }
+
+        Ok(content.to_vec())
+    }
+
+    // HEAD, ブランチ名, タグ名, refs/ から始まるパス, hash 値を hash 値に解決する
+    pub fn rev_parse(&self, rev: &str) -> io::Result<String> {
+        if rev == "HEAD" {
+            return self.resolve_head();
+        }
+        if rev.len() == self.hash_algo.width() * 2 && rev.bytes().all(|x| x.is_ascii_hexdigit()) {
+            return Ok(rev.to_lowercase());
+        }
 
-        GitObject::new(&buf).ok_or(io::Error::from(io::ErrorKind::InvalidData))
+        let candidates = [
+            rev.to_string(),
+            format!("refs/heads/{}", rev),
+            format!("refs/tags/{}", rev),
+        ];
+        candidates
+            .iter()
+            .filter(|x| x.starts_with("refs/"))
+            .find_map(|x| self.read_ref(x.clone()).ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("unknown revision: {}", rev),
+                )
+            })
     }
 
     pub fn read_index(&self) -> io::Result<Vec<u8>> {
-        self.filesystem.read(".git/index".to_string())
+        self.filesystem.read(self.git_path("index"))
     }
 
     pub fn write_index(&mut self, index: &Index) -> io::Result<()> {
-        self.filesystem
-            .write(".git/index".to_string(), &index.as_bytes())
+        let path = self.git_path("index");
+        self.write_locked(path, &index.as_bytes_with(self.hash_algo))
     }
 
-    pub fn read_object(&self, hash: String) -> io::Result<Vec<u8>> {
-        let (sub_dir, file) = hash.split_at(2);
-        self.filesystem
-            .read(format!(".git/objects/{}/{}", sub_dir, file))
+    // index の `.lock` を取ってから読み, f で書き換えて書き戻す
+    // 読んでから書くまでの間に他の rgit が書いた index を上書きして消すことがない
+    // f には lock を持ったままの self も渡すので, index の中身を見ながら object を読み書きできる
+    pub fn modify_index<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self, &mut Index) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let path = self.git_path("index");
+        lock::acquire(&mut self.filesystem, &path)?;
+        let result = (|| {
+            let mut index = self.load_index()?;
+            let result = f(self, &mut index)?;
+            self.filesystem
+                .write(lock::lock_path(&path), &index.as_bytes_with(self.hash_algo))?;
+            lock::commit(&mut self.filesystem, &path)?;
+            Ok(result)
+        })();
+        if result.is_err() {
+            // 消せなくても index を書けなかった理由の方を返す
+            let _ = lock::rollback(&mut self.filesystem, &path);
+        }
+        result
+    }
+
+    // loose object と同じく圧縮したバイト列を返す
+    pub fn read_object(&self, id: &ObjectId) -> io::Result<Vec<u8>> {
+        match self.filesystem.read(self.object_path(id)) {
+            // loose object が無ければ pack から探して loose object と同じ形式で返す
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let bytes = self.read_packed_object(&id.to_hex())?.ok_or(e)?;
+                deflate(&bytes)
+            }
+            r => r,
+        }
+    }
+
+    // object の type と展開した中身を返す
+    // tree を辿る時などは同じ object を何度も読むので, cache があればそこから返す
+    pub fn read_decoded(&self, id: &ObjectId) -> io::Result<Resolved> {
+        if let Some(resolved) = self.object_cache.borrow_mut().get(id) {
+            return Ok(resolved);
+        }
+
+        let resolved = match self.filesystem.read(self.object_path(id)) {
+            Ok(bytes) => decode_loose(id, &inflate(&bytes)?)?,
+            // loose object が無ければ pack から探す
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let raw_hash = id.as_bytes();
+                let packs = self.packs()?;
+                let found = packs
+                    .iter()
+                    .enumerate()
+                    .find_map(|(i, pack)| pack.index.find(raw_hash).map(|offset| (i, offset)));
+                let (i, offset) = found.ok_or(e)?;
+                self.resolve_packed(&packs, i, offset)?
+            }
+            Err(e) => return Err(e),
+        };
+        let size = resolved.1.len();
+        self.object_cache
+            .borrow_mut()
+            .insert(id.clone(), resolved.clone(), size);
+
+        Ok(resolved)
+    }
+
+    // read_decoded の cache に覚えておく中身の合計バイト数. 0 なら cache しない
+    pub fn set_cache_capacity(&self, capacity: usize) {
+        self.object_cache.borrow_mut().set_capacity(capacity);
+    }
+
+    pub fn cache_capacity(&self) -> usize {
+        self.object_cache.borrow().capacity()
+    }
+
+    // pack に格納されている object を展開して "<type> <size>\0<content>" の形式で返す
+    pub fn read_packed_object(&self, hash: &str) -> io::Result<Option<Vec<u8>>> {
+        let raw_hash =
+            hex::decode(hash).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let packs = self.packs()?;
+        for (i, pack) in packs.iter().enumerate() {
+            if let Some(offset) = pack.index.find(&raw_hash) {
+                let (code, content) = self.resolve_packed(&packs, i, offset)?;
+                return Ok(Some(pack::object_bytes(code, &content)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    // pack を読み込み直す. 新しい pack を書き込んだ後に呼ぶ
+    pub fn reload_packs(&self) {
+        *self.packs.borrow_mut() = None;
+        self.delta_cache.borrow_mut().clear();
+        self.object_cache.borrow_mut().clear();
+    }
+
+    // .git/objects/pack 以下の全ての pack. 一度読み込んだものを使い回す
+    pub fn packs(&self) -> io::Result<Rc<Vec<Pack>>> {
+        if let Some(packs) = self.packs.borrow().as_ref() {
+            return Ok(packs.clone());
+        }
+
+        let names = match self.filesystem.read_dir(self.git_path("objects/pack")) {
+            Ok(names) => names,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        let mut packs = Vec::new();
+        for name in names.iter().filter_map(|x| x.strip_suffix(".idx")) {
+            let bytes = self
+                .filesystem
+                .read(self.git_path(&format!("objects/pack/{}.idx", name)))?;
+            let index = pack::idx::PackIndex::from(&bytes, self.hash_algo)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, name.to_string()))?;
+            let data = self
+                .filesystem
+                .read(self.git_path(&format!("objects/pack/{}.pack", name)))?;
+            packs.push(Pack {
+                name: name.to_string(),
+                index,
+                data,
+            });
+        }
+
+        let packs = Rc::new(packs);
+        *self.packs.borrow_mut() = Some(packs.clone());
+        Ok(packs)
+    }
+
+    // delta を辿って pack 内の object を展開する
+    // 再帰せずに base まで delta を辿り, base から順に適用する
+    fn resolve_packed(&self, packs: &[Pack], i: usize, offset: u64) -> io::Result<Resolved> {
+        let invalid =
+            |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        let (mut i, mut offset) = (i, offset);
+        // (pack の番号, offset, delta) を辿った順に積む
+        let mut chain = Vec::new();
+        let mut visited = HashSet::new();
+        let mut resolved = loop {
+            if let Some(resolved) = self.delta_cache.borrow().get(&(i, offset)) {
+                break resolved.clone();
+            }
+            if !visited.insert((i, offset)) {
+                return Err(invalid("delta chain has a cycle"));
+            }
+            if chain.len() >= MAX_DELTA_DEPTH {
+                return Err(invalid("delta chain is too long"));
+            }
+
+            let entry = pack::read_entry(&packs[i].data, offset, self.hash_algo)?;
+            match entry.base {
+                None => {
+                    let resolved = (entry.code, Rc::new(entry.data));
+                    self.cache_resolved(i, offset, &resolved);
+                    break resolved;
+                }
+                Some(DeltaBase::Offset(base)) => {
+                    chain.push((i, offset, entry.data));
+                    offset = base;
+                }
+                Some(DeltaBase::Hash(hash)) => {
+                    chain.push((i, offset, entry.data));
+                    let id =
+                        ObjectId::from_bytes(&hash).ok_or_else(|| invalid("bad delta base"))?;
+                    match packs
+                        .iter()
+                        .enumerate()
+                        .find_map(|(j, x)| x.index.find(id.as_bytes()).map(|x| (j, x)))
+                    {
+                        Some((j, base)) => {
+                            i = j;
+                            offset = base;
+                        }
+                        // pack に無い base は loose object から読む
+                        None => break self.read_decoded(&id)?,
+                    }
+                }
+            }
+        };
+
+        while let Some((i, offset, delta)) = chain.pop() {
+            resolved = (resolved.0, Rc::new(pack::apply_delta(&resolved.1, &delta)?));
+            self.cache_resolved(i, offset, &resolved);
+        }
+
+        Ok(resolved)
+    }
+
+    fn cache_resolved(&self, i: usize, offset: u64, resolved: &Resolved) {
+        let mut cache = self.delta_cache.borrow_mut();
+        if cache.len() >= DELTA_CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert((i, offset), resolved.clone());
     }
 
     pub fn write_object(&mut self, object: &GitObject) -> io::Result<()> {
-        let hash = hex::encode(object.calc_hash());
-        let (sub_dir, file) = hash.split_at(2);
+        self.write_raw_object(&object.as_bytes()).map(|_| ())
+    }
+
+    // まとめて書き込み, 実際に書き込んだ object の hash を返す
+    // 既にある object と同じ batch 内の重複は書き込まない
+    pub fn write_objects(&mut self, objects: &[GitObject]) -> io::Result<Vec<String>> {
+        let mut seen = HashSet::new();
+        let mut pending = Vec::new();
+        for object in objects {
+            let bytes = object.as_bytes();
+            let id = ObjectId::digest(self.hash_algo, &bytes);
+            if !seen.insert(id.clone()) || self.has_object(&id)? {
+                continue;
+            }
+            pending.push((id, bytes));
+        }
+
+        let dirs = pending
+            .iter()
+            .map(|(id, _)| self.object_dir(id))
+            .collect::<HashSet<_>>();
+        for dir in dirs {
+            if !self.filesystem.exists(dir.clone()) {
+                self.filesystem.create_dir(dir)?;
+            }
+        }
 
-        let path = format!(".git/objects/{}", sub_dir);
+        for (id, bytes) in pending.iter() {
+            self.filesystem
+                .write(self.object_path(id), &deflate(bytes)?)?;
+            self.object_cache.borrow_mut().remove(id);
+        }
+
+        Ok(pending.into_iter().map(|(id, _)| id.to_hex()).collect())
+    }
+
+    // loose object か pack のどちらかにあるか
+    pub fn has_object(&self, id: &ObjectId) -> io::Result<bool> {
+        match self.filesystem.stat(self.object_path(id)) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => self.is_packed(id),
+            Err(e) => Err(e),
+        }
+    }
+
+    // "<type> <size>\0<content>" の形式のバイト列をそのまま書き込んで hash 値を返す
+    pub fn write_raw_object(&mut self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let id = ObjectId::digest(self.hash_algo, bytes);
+
+        let dir = self.object_dir(&id);
         // ディレクトがなければ
-        if let Err(_) = self.filesystem.stat(path.clone()) {
-            self.filesystem.create_dir(path.clone())?;
+        if !self.filesystem.exists(dir.clone()) {
+            self.filesystem.create_dir(dir)?;
+        }
+
+        self.filesystem
+            .write(self.object_path(&id), &deflate(bytes)?)?;
+        self.object_cache.borrow_mut().remove(&id);
+        Ok(id.as_bytes().to_vec())
+    }
+
+    // loose object のファイルを消す. cache からも忘れる
+    pub fn remove_loose_object(&mut self, hash: &str) -> io::Result<()> {
+        let id = hash.parse::<ObjectId>()?;
+        self.object_cache.borrow_mut().remove(&id);
+        self.filesystem.remove(self.object_path(&id))
+    }
+
+    // loose object のパス. ObjectId は16進数の文字しか持たないので, .git/objects の外を指すことは無い
+    // hash からパスを作る時は必ずここを通す
+    pub fn object_path(&self, id: &ObjectId) -> String {
+        let (sub_dir, file) = id.split_at();
+        self.git_path(&format!("objects/{}/{}", sub_dir, file))
+    }
+
+    // loose object を置く .git/objects/<先頭2文字> のディレクトリ
+    pub fn object_dir(&self, id: &ObjectId) -> String {
+        let (sub_dir, _) = id.split_at();
+        self.git_path(&format!("objects/{}", sub_dir))
+    }
+
+    // .git/objects/??/ 以下の loose object の hash 値を全て返す
+    pub fn loose_objects(&self) -> io::Result<Vec<String>> {
+        let mut hashes = Vec::new();
+        for dir in self.filesystem.read_dir(self.git_path("objects"))? {
+            if dir.len() != 2 || !dir.bytes().all(|x| x.is_ascii_hexdigit()) {
+                continue;
+            }
+            for file in self
+                .filesystem
+                .read_dir(self.git_path(&format!("objects/{}", dir)))?
+            {
+                // 書きかけの一時ファイルなど, object id にならない名前は飛ばす
+                let hash = format!("{}{}", dir, file);
+                if ObjectId::from_hex(&hash).is_some() {
+                    hashes.push(hash);
+                }
+            }
+        }
+
+        Ok(hashes)
+    }
+
+    // いずれかの pack に含まれている object か
+    pub fn is_packed(&self, id: &ObjectId) -> io::Result<bool> {
+        Ok(self
+            .packs()?
+            .iter()
+            .any(|x| x.index.find(id.as_bytes()).is_some()))
+    }
+
+    // .git/objects/??/ の loose object を全て展開して, 型ごとに数と大きさをまとめる
+    pub fn object_store_size(&self) -> io::Result<StoreStats> {
+        let mut stats = StoreStats::default();
+        for hash in self.loose_objects()? {
+            let path = self.object_path(&hash.parse()?);
+            // cache を通さずにファイルの大きさを測る
+            let bytes = self.filesystem.read(path.clone())?;
+            let inflated = inflate(&bytes)?;
+            let object_type = GitObject::peek_type(&inflated).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown object type in {}", path),
+                )
+            })?;
+
+            stats.get_mut(object_type).add(&ObjectSizes {
+                count: 1,
+                compressed: bytes.len() as u64,
+                inflated: inflated.len() as u64,
+            });
         }
 
-        let path = format!("{}/{}", path, file);
+        Ok(stats)
+    }
+
+    // 全ての loose object を展開して hash 値を計算し直し, パスと一致しないものを返す
+    pub fn fsck_loose(&self) -> io::Result<Vec<FsckProblem>> {
+        let mut problems = Vec::new();
+        for expected in self.loose_objects()? {
+            let id = expected.parse()?;
+            let bytes = self.read_object(&id)?;
+            let actual = inflate(&bytes)
+                .ok()
+                .map(|x| hex::encode(self.hash_algo.digest(&x)));
 
-        let mut encoder = Encoder::new(Vec::new())?;
-        encoder.write_all(&object.as_bytes())?;
-        let bytes = encoder.finish().into_result()?;
+            if actual.as_ref() != Some(&expected) {
+                problems.push(FsckProblem {
+                    path: self.object_path(&id),
+                    expected,
+                    actual,
+                });
+            }
+        }
 
-        self.filesystem.write(path, &bytes)
+        Ok(problems)
     }
 
     pub fn ls_files_stage(&self, bytes: &[u8]) -> io::Result<Index> {
-        Index::from(&bytes).ok_or(io::Error::from(io::ErrorKind::InvalidData))
+        // 壊れている理由は InvalidData の error に入れて返す
+        Ok(Index::try_from_bytes_with(bytes, self.hash_algo)?)
     }
 
     pub fn hash_object(&self, bytes: &[u8]) -> io::Result<Blob> {
-        let blob = Blob::from(&bytes).ok_or(io::Error::from(io::ErrorKind::InvalidInput))?;
-        Ok(blob)
+        Ok(Blob::new(bytes))
     }
 
-    pub fn update_index(&self, hash: &[u8], filename: String) -> io::Result<Index> {
-        let bytes = self
-            .read_index()
-            .unwrap_or([*b"DIRC", 0x0002u32.to_be_bytes(), 0x0000u32.to_be_bytes()].concat());
-        let index = self.ls_files_stage(&bytes)?;
+    // git hash-object -w. blob を書き込んで hash 値を返す
+    pub fn hash_object_write(&mut self, bytes: &[u8]) -> io::Result<String> {
+        let blob = GitObject::Blob(self.hash_object(bytes)?);
+        self.write_object(&blob)?;
+        Ok(blob.calc_hash_with(self.hash_algo).to_hex())
+    }
+
+    // reader から len バイトの blob の hash 値を計算する. 中身は固定の大きさの buffer ずつしか読まない
+    pub fn hash_object_stream<R: Read>(&self, mut reader: R, len: u64) -> io::Result<ObjectId> {
+        let mut hasher = StreamHasher::new(self.hash_algo);
+        hasher.update(format!("blob {}\0", len).as_bytes());
+        copy_exact(&mut reader, len, |chunk| {
+            hasher.update(chunk);
+            Ok(())
+        })?;
+        Ok(hasher.finish())
+    }
+
+    // reader から len バイトの blob を書き込む. 一時ファイルに圧縮しながら書き, 最後に object のパスへ移す
+    pub fn write_object_stream<R: Read>(
+        &mut self,
+        mut reader: R,
+        len: u64,
+    ) -> io::Result<ObjectId> {
+        // 同じ process の中で同時に書いても重ならないように呼ぶ度に名前を変える
+        let n = TMP_OBJECT_COUNT.fetch_add(1, Ordering::Relaxed);
+        let tmp = self.git_path(&format!("objects/tmp_obj_{}_{}", std::process::id(), n));
+        self.filesystem.write(tmp.clone(), &[])?;
+
+        let header = format!("blob {}\0", len);
+        let mut hasher = StreamHasher::new(self.hash_algo);
+        hasher.update(header.as_bytes());
+        let written = (|| {
+            let writer = BufWriter::with_capacity(
+                STREAM_BUFFER_SIZE,
+                AppendWriter {
+                    filesystem: &mut self.filesystem,
+                    path: tmp.clone(),
+                },
+            );
+            let options = zlib::EncodeOptions::new().block_size(STREAM_BUFFER_SIZE);
+            let mut encoder = Encoder::with_options(writer, options)?;
+            encoder.write_all(header.as_bytes())?;
+            copy_exact(&mut reader, len, |chunk| {
+                hasher.update(chunk);
+                encoder.write_all(chunk)
+            })?;
+            encoder.finish().into_result()?.flush()
+        })();
+        if let Err(e) = written {
+            // 一時ファイルを消せなくても, 書けなかった理由の方を返す
+            let _ = self.filesystem.remove(tmp);
+            return Err(e);
+        }
+
+        let id = hasher.finish();
+        self.object_cache.borrow_mut().remove(&id);
+        if self.has_object(&id)? {
+            self.filesystem.remove(tmp)?;
+            return Ok(id);
+        }
+
+        let dir = self.object_dir(&id);
+        if !self.filesystem.exists(dir.clone()) {
+            self.filesystem.create_dir(dir)?;
+        }
+        self.filesystem.rename(tmp, self.object_path(&id))?;
+        Ok(id)
+    }
+
+    // stream_threshold を超える通常のファイルなら, その大きさを返す
+    pub fn stream_size(&self, path: String) -> io::Result<Option<u64>> {
+        if self.filesystem.read_link(path.clone()).is_ok() {
+            return Ok(None);
+        }
+        let size = self.filesystem.stat(path)?.size as u64;
+        Ok(Some(size).filter(|&x| x > self.stream_threshold))
+    }
+
+    // worktree のファイルを blob として書き込む. 大きなファイルは少しずつ読む
+    pub fn write_worktree_blob(&mut self, path: String) -> io::Result<ObjectId> {
+        if let Some(size) = self.stream_size(path.clone())? {
+            let reader = self.filesystem.open(path)?;
+            return self.write_object_stream(reader, size);
+        }
+        let bytes = self.read_worktree_file(path)?;
+        self.hash_object_write(&bytes)?.parse()
+    }
 
+    // index が無ければ空の index を返す
+    pub fn load_index(&self) -> io::Result<Index> {
+        match self.read_index() {
+            Ok(bytes) => self.ls_files_stage(&bytes),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Index::new(Vec::new())),
+            Err(e) => Err(e),
+        }
+    }
+
+    // worktree のファイルの中身. symlink は辿らずに, git と同じく指している先のパスを中身とする
+    // 指す先が無い symlink も読める
+    pub fn read_worktree_file(&self, path: String) -> io::Result<Vec<u8>> {
+        match self.filesystem.read_link(path.clone()) {
+            Ok(target) => Ok(target.into_bytes()),
+            Err(_) => self.filesystem.read(path),
+        }
+    }
+
+    // git add <file>. worktree のファイルを blob として書き, index の1つの entry だけを置き換えて書き戻す
+    // entry は名前順のまま入り, checksum は書き出す時に計算し直す
+    pub fn stage_file(&mut self, path: String) -> io::Result<()> {
+        let hash = self.write_worktree_blob(path.clone())?;
+        let entry = self.index_entry(&hash, path)?;
+
+        self.modify_index(|_, index| {
+            index.upsert(entry);
+            Ok(())
+        })
+    }
+
+    // worktree のファイルの stat から index の entry を作る
+    pub fn index_entry(&self, hash: &ObjectId, filename: String) -> io::Result<Entry> {
         let metadata = self.filesystem.stat(filename.clone())?;
-        let entry = Entry::new(
+        Ok(Entry::new(
             Utc.timestamp(metadata.ctime as i64, metadata.ctime_nsec),
             Utc.timestamp(metadata.mtime as i64, metadata.mtime_nsec),
             metadata.dev,
@@ -89,73 +944,286 @@ impl<F: FileSystem> Git<F> {
             metadata.uid,
             metadata.gid,
             metadata.size,
-            Vec::from(hash),
-            filename.clone(),
-        );
+            hash.clone(),
+            filename,
+        ))
+    }
 
-        let mut entries: Vec<Entry> = index
-            .entries
-            .into_iter()
-            // ファイル名が同じまたは hash 値が同じ場合, 同一ファイルなので取り除く
-            .filter(|x| x.name != entry.name && x.hash != entry.hash)
-            .collect();
-        entries.push(entry);
-        entries.sort_by(|a, b| a.name.cmp(&b.name));
+    // git ls-files -m
+    // stat か中身が index と変わった tracked なファイル. 消えたファイルは含まない
+    pub fn modified_files(&self, index: &Index) -> io::Result<Vec<String>> {
+        let mut files = Vec::new();
+        for entry in index.entries.iter() {
+            let metadata = match self.filesystem.stat(entry.name.clone()) {
+                Ok(metadata) => metadata,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let stat_changed = entry.m_time.timestamp() != metadata.mtime as i64
+                || entry.m_time.timestamp_subsec_nanos() != metadata.mtime_nsec
+                || entry.size != metadata.size
+                || entry.inode != metadata.ino
+                || entry.mode != metadata.mode;
+
+            let bytes = self.read_worktree_file(entry.name.clone())?;
+            let blob = self.hash_object(&bytes).map(GitObject::Blob)?;
+            if stat_changed || blob.calc_hash_with(self.hash_algo) != entry.hash {
+                files.push(entry.name.clone());
+            }
+        }
+
+        Ok(files)
+    }
+
+    // git ls-files -d
+    // worktree から消えた tracked なファイル
+    pub fn deleted_files(&self, index: &Index) -> io::Result<Vec<String>> {
+        let mut files = Vec::new();
+        for entry in index.entries.iter() {
+            match self.filesystem.stat(entry.name.clone()) {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => files.push(entry.name.clone()),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(files)
+    }
+
+    // worktree の dir 以下の全てのファイルを名前順に返す. 空文字列なら worktree 全体
+    // .git と git_dir は含めず, symlink はディレクトリを指していても辿らずにファイルとして返す
+    pub fn worktree_files(&self, dir: &str) -> io::Result<Vec<String>> {
+        let mut files = Vec::new();
+        let mut stack = vec![dir.trim_end_matches('/').to_string()];
+        while let Some(dir) = stack.pop() {
+            for name in self.filesystem.read_dir(dir.clone())? {
+                if name == ".git" {
+                    continue;
+                }
+                let path = if dir.is_empty() {
+                    name
+                } else {
+                    format!("{}/{}", dir, name)
+                };
+                if path == self.git_dir {
+                    continue;
+                }
+
+                if self.filesystem.is_dir(path.clone())? {
+                    stack.push(path);
+                } else {
+                    files.push(path);
+                }
+            }
+        }
+        files.sort();
 
-        Ok(Index::new(entries))
+        Ok(files)
     }
 
-    pub fn write_tree(&self) -> io::Result<Tree> {
+    // 読んだ index の filename の entry を置き換えたものを返す. 書き込みはしない
+    // 中身が同じ別のファイルの entry はそのまま残す
+    pub fn update_index(&self, hash: &ObjectId, filename: String) -> io::Result<Index> {
+        let mut index = self.load_index()?;
+        index.upsert(self.index_entry(hash, filename)?);
+
+        Ok(index)
+    }
+
+    // git update-index --remove. 名前が一致する entry を取り除く. 無ければそのまま返す
+    pub fn remove_from_index(&self, idx: Index, name: &str) -> Index {
+        let mut idx = idx;
+        idx.remove_entry(name);
+        idx
+    }
+
+    // index を一度だけ読み, ディレクトリごとの tree を深い方から作る
+    // 子の tree が親より先に並ぶ (hash, tree) の一覧を返し, 最後が一番上の tree になる
+    // 書き込みはしないので, 呼び出し側がまとめて write_objects する
+    pub fn write_tree(&self) -> io::Result<Vec<(ObjectId, Tree)>> {
         let bytes = self.read_index()?;
         let index = self.ls_files_stage(&bytes)?;
 
-        let contents = index
+        // stat の mode から 100644 / 100755 / 120000 / 160000 を決める
+        let files = index
             .entries
             .iter()
-            .map(|x| tree::File::new(100644, x.name.clone(), &x.hash)) // 今回はファイルにのみ対応するので mode は 100644 固定
-            .collect::<Vec<_>>();
+            .map(|x| {
+                let mode = FileMode::from_stat(x.mode as usize)
+                    .filter(|&x| x != FileMode::Tree)
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("invalid mode {:o} for {}", x.mode, x.name),
+                        )
+                    })?;
+                Ok((x.name.as_str(), mode, &x.hash))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut trees = Vec::new();
+        self.build_tree(&files, &mut trees);
+        Ok(trees)
+    }
 
-        Ok(Tree::new(contents))
+    // 名前順に並んだ (パス, mode, hash) から tree を作り, 中のディレクトリの tree と一緒に trees へ積む
+    fn build_tree(
+        &self,
+        files: &[(&str, FileMode, &ObjectId)],
+        trees: &mut Vec<(ObjectId, Tree)>,
+    ) -> ObjectId {
+        let mut contents = Vec::new();
+        let mut dirs: BTreeMap<&str, Vec<_>> = BTreeMap::new();
+        for &(path, mode, hash) in files {
+            match path.split_once('/') {
+                Some((dir, rest)) => dirs.entry(dir).or_default().push((rest, mode, hash)),
+                None => contents.push(tree::File::new(mode, path.to_string(), hash.clone())),
+            }
+        }
+        for (dir, files) in dirs {
+            let id = self.build_tree(&files, trees);
+            contents.push(tree::File::new(FileMode::Tree, dir.to_string(), id));
+        }
+
+        let tree = Tree::new(contents);
+        let id = tree.calc_hash_with(self.hash_algo);
+        trees.push((id.clone(), tree));
+        id
     }
 
+    // HEAD が指す commit の tree の hash 値. まだ commit が無ければ None
+    pub fn head_tree(&self) -> io::Result<Option<ObjectId>> {
+        match self.resolve_head() {
+            Ok(hash) => Ok(Some(self.read_commit(&hash)?.tree)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // HEAD の commit を parent にする
+    // committer が None なら author と同じにする. 他の人の patch を取り込む時は別にする
     pub fn commit_tree(
         &self,
-        name: String,
-        email: String,
-        tree_hash: String,
+        author: commit::User,
+        committer: Option<commit::User>,
+        tree: &ObjectId,
         message: String,
     ) -> io::Result<Commit> {
-        let parent = self.head_ref().and_then(|x| self.read_ref(x)).ok();
-        let offset = {
-            let local = Local::now();
-            *local.offset()
+        // HEAD が指す branch がまだ無いのは最初の commit
+        // HEAD 自体が無いなど読めなかった場合は, 親の無い commit にして履歴を切らずにエラーにする
+        let head = self.read_head()?;
+        let parents = match head.strip_prefix("ref: ") {
+            Some(refname) => match self.read_ref(refname.trim().to_string()) {
+                Ok(hash) => vec![hash.parse()?],
+                Err(e) if e.kind() == io::ErrorKind::NotFound => self
+                    .packed_refs()?
+                    .into_iter()
+                    .filter(|(name, _)| name == refname.trim())
+                    .map(|(_, hash)| hash.parse())
+                    .collect::<io::Result<Vec<_>>>()?,
+                Err(e) => return Err(e),
+            },
+            None => vec![self.resolve_head()?.parse()?],
         };
-        let ts = offset.from_utc_datetime(&Utc::now().naive_utc());
-        let author = commit::User::new(name.clone(), email.clone(), ts);
-        let commit = Commit::new(tree_hash, parent, author.clone(), author.clone(), message);
+        self.commit_tree_with_parents(author, committer, tree, parents, message)
+    }
 
-        Ok(commit)
+    // git commit-tree -p <parent>... と同じく parent を指定する. 2つ以上なら merge commit
+    pub fn commit_tree_with_parents(
+        &self,
+        author: commit::User,
+        committer: Option<commit::User>,
+        tree: &ObjectId,
+        parents: Vec<ObjectId>,
+        message: String,
+    ) -> io::Result<Commit> {
+        if self.check_commit_tree {
+            self.check_tree(tree)?;
+        }
+        let committer = committer.unwrap_or_else(|| author.clone());
+        Ok(Commit::new(
+            tree.clone(),
+            parents,
+            author,
+            committer,
+            message,
+        ))
+    }
+
+    // tree が object として揃っていて, 本当に tree か. 空の tree は git と同じく常にあるものとする
+    fn check_tree(&self, tree: &ObjectId) -> io::Result<()> {
+        if *tree == Tree::new(Vec::new()).calc_hash_with(self.hash_algo) {
+            return Ok(());
+        }
+        let (code, _) = self.read_decoded(tree).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not a valid object", tree.to_hex()),
+            ),
+            _ => e,
+        })?;
+        match code {
+            pack::OBJ_TREE => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a valid 'tree' object", tree.to_hex()),
+            )),
+        }
     }
 
     pub fn head_ref(&self) -> io::Result<String> {
-        let path = ".git/HEAD".to_string();
-        let file = self.filesystem.read(path)?;
-        let refs =
-            String::from_utf8(file).map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+        match self.read_head()?.strip_prefix("ref: ") {
+            Some(path) => Ok(path.trim().to_string()),
+            // `git checkout hash` で移動した後は ref ではなく hash が入っている
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "HEAD is not a symbolic ref",
+            )),
+        }
+    }
+
+    // HEAD が指す commit の hash. `ref: xxx` なら ref を辿り, 切り離された HEAD ならその hash
+    pub fn resolve_head(&self) -> io::Result<String> {
+        let head = self.read_head()?;
+        if let Some(path) = head.strip_prefix("ref: ") {
+            return self.read_ref(path.trim().to_string());
+        }
+
+        let hash = head.trim();
+        if hash.len() == self.hash_algo.width() * 2 && hash.bytes().all(|x| x.is_ascii_hexdigit()) {
+            Ok(hash.to_lowercase())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid HEAD: {}", hash),
+            ))
+        }
+    }
 
-        let (prefix, path) = refs.split_at(5);
+    fn read_head(&self) -> io::Result<String> {
+        let file = self.filesystem.read(self.git_path("HEAD"))?;
+        String::from_utf8(file).map_err(|_| io::Error::from(io::ErrorKind::InvalidData))
+    }
 
-        // 今回は `ref: xxx` のフォーマットのみ対応
-        // `git checkout hash` で移動した際には hash 値が入っていありえます
-        if prefix != "ref: " {
-            return Err(io::Error::from(io::ErrorKind::InvalidData));
+    // ref の名前から .git の中のパスを作る
+    // 名前は HEAD の中身や remote から来ることもあるので, 絶対パスや `..` で .git の外を指すものは拒む
+    pub fn ref_path(&self, name: &str) -> io::Result<String> {
+        if name.is_empty()
+            || name.starts_with('/')
+            || name.contains('\0')
+            || name.split('/').any(|x| x == "..")
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid ref name '{}'", name),
+            ));
         }
 
-        Ok(path.trim().to_string())
+        Ok(self.git_path(name))
     }
 
     pub fn read_ref(&self, path: String) -> io::Result<String> {
-        let path = format!(".git/{}", path);
+        let path = self.ref_path(&path)?;
         let file = self.filesystem.read(path)?;
         let hash =
             String::from_utf8(file).map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
@@ -163,19 +1231,419 @@ impl<F: FileSystem> Git<F> {
         Ok(hash.trim().to_string())
     }
 
-    pub fn update_ref(&mut self, path: String, hash: &[u8]) -> io::Result<()> {
-        self.write_ref(path, hash)
+    // refs/ 以下の全ての ref を (名前, hash) の組で名前順に返す
+    // .git/packed-refs にまとめられた ref も含める. 同じ名前ならファイルの方が新しい
+    pub fn list_refs(&self) -> io::Result<Vec<(String, String)>> {
+        let mut refs = Vec::new();
+        let mut stack = vec!["refs".to_string()];
+        while let Some(dir) = stack.pop() {
+            for name in self.filesystem.read_dir(self.git_path(&dir))? {
+                // 書いている途中の ref の lock は ref ではない
+                if name.ends_with(".lock") {
+                    continue;
+                }
+                let path = format!("{}/{}", dir, name);
+                match self.read_ref(path.clone()) {
+                    Ok(hash) => refs.push((path, hash)),
+                    Err(_) => stack.push(path),
+                }
+            }
+        }
+        for (name, hash) in self.packed_refs()? {
+            if !refs.iter().any(|x| x.0 == name) {
+                refs.push((name, hash));
+            }
+        }
+        refs.sort();
+
+        Ok(refs)
     }
 
-    fn write_ref(&mut self, path: String, hash: &[u8]) -> io::Result<()> {
-        let path = format!(".git/{}", path);
-        self.filesystem.write(path, hex::encode(hash).as_bytes())
+    // .git/packed-refs の `<hash> <ref>` の行を (名前, hash) にする
+    // `#` で始まる header と, annotated tag の指す先を表す `^<hash>` の行は読み飛ばす
+    pub fn packed_refs(&self) -> io::Result<Vec<(String, String)>> {
+        let bytes = match self.filesystem.read(self.git_path("packed-refs")) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        String::from_utf8_lossy(&bytes)
+            .lines()
+            .filter(|x| !x.is_empty() && !x.starts_with('#') && !x.starts_with('^'))
+            .map(|line| {
+                line.split_once(' ')
+                    .map(|(hash, name)| (name.to_string(), hash.to_string()))
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("bad packed-refs line: {}", line),
+                        )
+                    })
+            })
+            .collect()
     }
-}
+
+    // .git/config が無ければ空の設定として扱う
+    pub fn read_config(&self) -> io::Result<Config> {
+        match self.filesystem.read(self.git_path("config")) {
+            Ok(bytes) => Config::from(&bytes)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad config file")),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // index や ref と同じく `config.lock` に書いてから置き換えるので, 途中で止まっても壊れない
+    pub fn write_config(&mut self, config: &Config) -> io::Result<()> {
+        let path = self.git_path("config");
+        self.write_locked(path, &config.as_bytes())
+    }
+
+    // .git/info/exclude と worktree の各ディレクトリの .gitignore のパターン. 無いファイルは空として扱う
+    // 無視されたディレクトリの中の .gitignore は読まない
+    pub fn ignore(&self) -> io::Result<IgnoreMatcher> {
+        let mut ignore = IgnoreMatcher::new();
+        let exclude = self.git_path("info/exclude");
+        if let Some(text) = self.read_if_exists(&exclude)? {
+            ignore.add_exclude(&exclude, &text);
+        }
+
+        let mut stack = vec![String::new()];
+        while let Some(dir) = stack.pop() {
+            let join = |name: &str| {
+                if dir.is_empty() {
+                    name.to_string()
+                } else {
+                    format!("{}/{}", dir, name)
+                }
+            };
+            let source = join(".gitignore");
+            if let Some(text) = self.read_if_exists(&source)? {
+                ignore.add_gitignore(&source, &dir, &text);
+            }
+
+            let mut names = self.filesystem.read_dir(dir.clone())?;
+            // 兄弟の間に優先順位は無いが, 読む順番は揃えておく
+            names.sort();
+            for name in names.iter().rev().filter(|x| *x != ".git") {
+                let path = join(name);
+                if path != self.git_dir
+                    && self.filesystem.is_dir(path.clone())?
+                    && !ignore.is_ignored(&path, true)
+                {
+                    stack.push(path);
+                }
+            }
+        }
+
+        Ok(ignore)
+    }
+
+    fn read_if_exists(&self, path: &str) -> io::Result<Option<String>> {
+        match self.filesystem.read(path.to_string()) {
+            Ok(bytes) => Ok(Some(String::from_utf8_lossy(&bytes).into_owned())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // config に設定されている remote を出てきた順に返す
+    pub fn remotes(&self) -> io::Result<Vec<Remote>> {
+        let config = self.read_config()?;
+        Ok(config
+            .subsections("remote")
+            .into_iter()
+            .filter_map(|x| Remote::from(&config, x))
+            .collect())
+    }
+
+    pub fn add_remote(&mut self, remote: &Remote) -> io::Result<()> {
+        let mut config = self.read_config()?;
+        if config.subsections("remote").contains(&remote.name.as_str()) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("remote {} already exists", remote.name),
+            ));
+        }
+
+        remote.write_to(&mut config);
+        self.write_config(&config)
+    }
+
+    // remote の設定と refs/remotes/<name> 以下の ref を削除する
+    pub fn remove_remote(&mut self, name: &str) -> io::Result<()> {
+        let mut config = self.read_config()?;
+        if !config.remove_section("remote", Some(name)) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no such remote: {}", name),
+            ));
+        }
+        self.write_config(&config)?;
+
+        match self
+            .filesystem
+            .remove_dir_all(self.git_path(&format!("refs/remotes/{}", name)))
+        {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            r => r,
+        }
+    }
+
+    // HEAD を refs/heads/<name> などの ref を指すようにする
+    pub fn set_head(&mut self, refname: &str) -> io::Result<()> {
+        let path = self.git_path("HEAD");
+        self.write_locked(path, format!("ref: {}\n", refname).as_bytes())
+    }
+
+    // 途中のディレクトリも含めて作る. 既にあっても良い
+    pub fn create_dir_all(&mut self, dir: &str) -> io::Result<()> {
+        let mut path = String::new();
+        for name in dir.split('/').filter(|x| !x.is_empty()) {
+            if !path.is_empty() {
+                path.push('/');
+            }
+            path.push_str(name);
+            if !self.filesystem.exists(path.clone()) {
+                self.filesystem.create_dir(path.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    // tree 以下の全てのファイルを (パス, mode, hash) で名前順に返す. サブディレクトリの tree も辿る
+    pub fn read_tree_files(&self, tree: &ObjectId) -> io::Result<Vec<(String, usize, ObjectId)>> {
+        let mut files = Vec::new();
+        let mut stack = vec![(String::new(), tree.clone())];
+        while let Some((dir, id)) = stack.pop() {
+            let tree = match self.read_git_object(&id)? {
+                GitObject::Tree(tree) => tree,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} is not a tree", id),
+                    ))
+                }
+            };
+            for file in tree.contents {
+                let path = if dir.is_empty() {
+                    file.name.clone()
+                } else {
+                    format!("{}/{}", dir, file.name)
+                };
+                // 読む時にも確かめているが, worktree の外に書き出すパスは作らない
+                if !tree::verify_path(&path) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid path '{}' in tree {}", path, id),
+                    ));
+                }
+                if file.is_dir() {
+                    stack.push((path, file.hash));
+                } else {
+                    files.push((path, file.mode.to_octal(), file.hash));
+                }
+            }
+        }
+        files.sort();
+
+        Ok(files)
+    }
+
+    // read_tree_files の逆. (パス, mode, hash) の一覧からディレクトリごとに tree を書き込み,
+    // 一番上の tree を返す
+    pub fn write_tree_files(
+        &mut self,
+        files: &[(String, usize, ObjectId)],
+    ) -> io::Result<ObjectId> {
+        let files = files
+            .iter()
+            .map(|(path, mode, hash)| {
+                let mode = FileMode::from_octal(*mode).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{}: invalid mode {:o}", path, mode),
+                    )
+                })?;
+                Ok((path.as_str(), mode, hash))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut trees = Vec::new();
+        let id = self.build_tree(&files, &mut trees);
+        let trees = trees
+            .into_iter()
+            .map(|(_, tree)| GitObject::Tree(tree))
+            .collect::<Vec<_>>();
+        self.write_objects(&trees)?;
+
+        Ok(id)
+    }
+
+    pub fn update_ref(&mut self, path: String, id: &ObjectId) -> io::Result<()> {
+        self.write_ref(path, id.as_bytes())
+    }
+
+    pub fn delete_ref(&mut self, path: String) -> io::Result<()> {
+        let path = self.ref_path(&path)?;
+        self.filesystem.remove(path)
+    }
+
+    // HEAD, ORIG_HEAD, MERGE_HEAD, FETCH_HEAD の指す hash. 無ければ None
+    // MERGE_HEAD と FETCH_HEAD は複数行になることがあるので最初の hash を返す
+    pub fn read_special_ref(&self, name: &str) -> io::Result<Option<String>> {
+        let path = self.special_ref_path(name)?;
+        let file = match self.filesystem.read(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let content =
+            String::from_utf8(file).map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+
+        // HEAD は ref を辿る. まだ commit の無い branch なら None
+        if let Some(path) = content.strip_prefix("ref: ") {
+            return match self.read_ref(path.trim().to_string()) {
+                Ok(hash) => Ok(Some(hash)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            };
+        }
+        // FETCH_HEAD は "<hash>\t\tbranch 'x' of <url>" の形
+        let hash = content.split_whitespace().next().unwrap_or_default();
+        ObjectId::from_hex(hash)
+            .filter(|x| x.as_bytes().len() == self.hash_algo.width())
+            .map(|x| Some(x.to_hex()))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid {}: {}", name, hash),
+                )
+            })
+    }
+
+    // HEAD に書くと branch から切り離される
+    pub fn write_special_ref(&mut self, name: &str, id: &ObjectId) -> io::Result<()> {
+        let path = self.special_ref_path(name)?;
+        self.write_locked(path, format!("{}\n", id).as_bytes())
+    }
+
+    fn special_ref_path(&self, name: &str) -> io::Result<String> {
+        if !SPECIAL_REFS.contains(&name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a special ref", name),
+            ));
+        }
+        Ok(self.git_path(name))
+    }
+
+    fn write_ref(&mut self, path: String, hash: &[u8]) -> io::Result<()> {
+        let path = self.ref_path(&path)?;
+        self.write_locked(path, format!("{}\n", hex::encode(hash)).as_bytes())
+    }
+
+    // `<path>.lock` を取ってから書く. 他の rgit が書いている間は失敗する
+    fn write_locked(&mut self, path: String, data: &[u8]) -> io::Result<()> {
+        let mut lock = LockFile::acquire(&mut self.filesystem, path)?;
+        lock.write(data)?;
+        lock.commit()
+    }
+}
+
+fn deflate(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = Encoder::new(Vec::new())?;
+    encoder.write_all(bytes)?;
+    encoder.finish().into_result()
+}
+
+// FileSystem::append で書き足していく Write
+struct AppendWriter<'a, F: FileSystem> {
+    filesystem: &'a mut F,
+    path: String,
+}
+
+impl<F: FileSystem> Write for AppendWriter<'_, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.filesystem.append(self.path.clone(), buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// reader からちょうど len バイトを buffer ずつ f に渡す. 長さが合わなければ error
+fn copy_exact<R: Read>(
+    reader: &mut R,
+    len: u64,
+    mut f: impl FnMut(&[u8]) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut buf = vec![0; STREAM_BUFFER_SIZE];
+    let mut total = 0u64;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        total += n as u64;
+        if total > len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected {} bytes but read more", len),
+            ));
+        }
+        f(&buf[..n])?;
+    }
+
+    if total != len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("expected {} bytes but read {}", len, total),
+        ));
+    }
+    Ok(())
+}
+
+// 展開した loose object を header の type と中身に分ける
+fn decode_loose(id: &ObjectId, bytes: &[u8]) -> io::Result<Resolved> {
+    let broken = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} has a broken header", id),
+        )
+    };
+    let pos = bytes.iter().position(|&x| x == b'\0').ok_or_else(broken)?;
+    let header = String::from_utf8_lossy(&bytes[..pos]);
+    let (kind, size) = header.split_once(' ').ok_or_else(broken)?;
+    let code = pack::type_code_from_name(kind).ok_or_else(broken)?;
+
+    let content = bytes[(pos + 1)..].to_vec();
+    if size.parse::<usize>().ok() != Some(content.len()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} has a wrong size {}", id, size),
+        ));
+    }
+
+    Ok((code, Rc::new(content)))
+}
+
+fn inflate(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut d = Decoder::new(bytes)?;
+    let mut buf = Vec::new();
+    d.read_to_end(&mut buf)?;
+    Ok(buf)
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use fs::counting::CountingFileSystem;
+    use fs::inmem::InMemFileSystem;
     use fs::linux::LinuxFileSystem;
 
     #[test]
@@ -185,6 +1653,1143 @@ mod tests {
         let bytes = git.read_index();
         assert!(bytes.is_ok());
         let index = bytes.and_then(|x| git.ls_files_stage(&x)).unwrap();
-        assert!(index.to_string().len() > 0);
+        assert!(!index.to_string().is_empty());
+    }
+
+    #[test]
+    fn read_object_cache() {
+        let fs = CountingFileSystem::new(InMemFileSystem::init());
+        // 1 バイトの blob が 1 つだけ入る
+        let mut git = GitBuilder::new(fs).cache_capacity(1).build();
+        assert_eq!(git.cache_capacity(), 1);
+        let a = git.hash_object_write(b"a").unwrap().parse().unwrap();
+        let b = git.hash_object_write(b"b").unwrap().parse().unwrap();
+
+        // 展開した type と中身を覚える
+        let (code, content) = git.read_decoded(&a).unwrap();
+        assert_eq!((code, content.as_slice()), (pack::OBJ_BLOB, &b"a"[..]));
+        let count = git.filesystem.reads();
+        // 2回目は filesystem を読まない
+        assert_eq!(git.read_decoded(&a).unwrap().1, content);
+        assert_eq!(git.read_blob(&a.to_hex()).unwrap(), b"a");
+        assert_eq!(git.filesystem.reads(), count);
+
+        // 入りきらなくなったものは読み直す
+        git.read_decoded(&b).unwrap();
+        git.read_decoded(&a).unwrap();
+        assert_eq!(git.filesystem.reads(), count + 2);
+
+        // 書き込んだ object は cache から忘れる
+        git.write_raw_object(b"blob 1\0a").unwrap();
+        git.read_decoded(&a).unwrap();
+        assert_eq!(git.filesystem.reads(), count + 3);
+
+        // 消した object は cache からも返さない
+        git.remove_loose_object(&a.to_hex()).unwrap();
+        assert!(git.read_decoded(&a).is_err());
+
+        // 中身が capacity より大きな object は覚えない
+        let big = git.hash_object_write(b"bb").unwrap().parse().unwrap();
+        let count = git.filesystem.reads();
+        git.read_decoded(&big).unwrap();
+        git.read_decoded(&big).unwrap();
+        assert_eq!(git.filesystem.reads(), count + 2);
+
+        // cache しなければ毎回読む
+        git.set_cache_capacity(0);
+        let count = git.filesystem.reads();
+        git.read_decoded(&b).unwrap();
+        git.read_decoded(&b).unwrap();
+        assert_eq!(git.filesystem.reads(), count + 2);
+
+        // read_object は圧縮したままのバイト列を返すので cache を通さない
+        git.set_cache_capacity(1);
+        git.read_decoded(&b).unwrap();
+        let count = git.filesystem.reads();
+        assert_eq!(
+            inflate(&git.read_object(&b).unwrap()).unwrap(),
+            b"blob 1\0b"
+        );
+        assert_eq!(git.filesystem.reads(), count + 1);
+    }
+
+    #[test]
+    fn hash_object_write() {
+        let mut git = Git::new(InMemFileSystem::init());
+        // `echo -n "hello, git" | git hash-object -w --stdin`
+        let hash = git.hash_object_write(b"hello, git").unwrap();
+        assert_eq!(hash, "3edbc45b9a7f744c2345cd2cd073c3de091341ac");
+        assert!(git.has_object(&hash.parse().unwrap()).unwrap());
+        assert_eq!(git.read_blob(&hash).unwrap(), b"hello, git");
+
+        // 同じ中身なら何度書いても同じ hash
+        assert_eq!(git.hash_object_write(b"hello, git").unwrap(), hash);
+    }
+
+    #[test]
+    fn hash_object_stream() {
+        for algo in [HashAlgo::Sha1, HashAlgo::Sha256] {
+            let mut git = Git::with_hash_algo(InMemFileSystem::init(), algo);
+            let content = "hello, git\n".repeat(10000);
+            let expected = git.hash_object_write(content.as_bytes()).unwrap();
+
+            let len = content.len() as u64;
+            let hash = git.hash_object_stream(content.as_bytes(), len).unwrap();
+            assert_eq!(hash.to_hex(), expected);
+
+            // 同じ中身を書き込んでも一時ファイルは残らない
+            let written = git.write_object_stream(content.as_bytes(), len).unwrap();
+            assert_eq!(written, hash);
+            assert_eq!(git.read_blob(&expected).unwrap(), content.as_bytes());
+            assert_eq!(git.loose_objects().unwrap(), vec![expected.clone()]);
+        }
+    }
+
+    #[test]
+    fn write_object_stream() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let content = b"hello, git";
+        let hash = git.write_object_stream(&content[..], 10).unwrap();
+        assert_eq!(hash.to_hex(), "3edbc45b9a7f744c2345cd2cd073c3de091341ac");
+        assert_eq!(git.read_blob(&hash.to_hex()).unwrap(), content);
+        assert!(git.fsck_loose().unwrap().is_empty());
+
+        // 長さが合わなければ error にして何も残さない
+        for len in [9, 11] {
+            let err = git
+                .write_object_stream(&b"hello, rgit"[..10], len)
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(git.hash_object_stream(&content[..], len).is_err());
+        }
+        assert_eq!(git.loose_objects().unwrap(), vec![hash.to_hex()]);
+        assert!(!git
+            .filesystem
+            .read_dir(".git/objects".to_string())
+            .unwrap()
+            .iter()
+            .any(|x| x.starts_with("tmp_obj_")));
+    }
+
+    #[test]
+    fn stage_file_stream() {
+        let mut fs = InMemFileSystem::init();
+        let content = "hello, git\n".repeat(100);
+        fs.write("large.txt".to_string(), content.as_bytes())
+            .unwrap();
+        let mut git = GitBuilder::new(fs).stream_threshold(64).build();
+        git.init().unwrap();
+
+        git.stage_file("large.txt".to_string()).unwrap();
+        let index = git.load_index().unwrap();
+        let hash = git.hash_object_stream(content.as_bytes(), content.len() as u64);
+        assert_eq!(index.get_entry("large.txt").unwrap().hash, hash.unwrap());
+        assert_eq!(
+            git.read_blob(&index.entries[0].hash.to_hex()).unwrap(),
+            content.as_bytes()
+        );
+    }
+
+    #[test]
+    fn object_path() {
+        let mut git = GitBuilder::new(InMemFileSystem::init())
+            .git_dir("custom")
+            .build();
+        git.init().unwrap();
+        let id: ObjectId = "3edbc45b9a7f744c2345cd2cd073c3de091341ac".parse().unwrap();
+        assert_eq!(
+            git.object_path(&id),
+            "custom/objects/3e/dbc45b9a7f744c2345cd2cd073c3de091341ac"
+        );
+        assert_eq!(git.object_dir(&id), "custom/objects/3e");
+
+        // object id にならない名前のファイルは loose object として扱わない
+        git.filesystem
+            .create_dir("custom/objects/3e".to_string())
+            .unwrap();
+        git.filesystem
+            .write("custom/objects/3e/tmp".to_string(), b"")
+            .unwrap();
+        assert!(git.loose_objects().unwrap().is_empty());
+        assert!(git.fsck_loose().unwrap().is_empty());
+    }
+
+    #[test]
+    fn object_store_size() {
+        let mut git = GitBuilder::new(InMemFileSystem::init())
+            .user("rgit", "rgit@example.com")
+            .build();
+        git.init().unwrap();
+        assert_eq!(git.object_store_size().unwrap(), StoreStats::default());
+
+        let content = "hello, git\n".repeat(100);
+        git.hash_object_write(content.as_bytes()).unwrap();
+        let (author, committer) = ident::idents(&git).unwrap();
+        let tree = Tree::new(Vec::new()).calc_hash();
+        let commit = git
+            .commit_tree(author, Some(committer), &tree, "first".to_string())
+            .unwrap();
+        let commit = GitObject::Commit(commit);
+        git.write_object(&commit).unwrap();
+
+        let stats = git.object_store_size().unwrap();
+        assert_eq!(stats.blob.count, 1);
+        assert_eq!(stats.commit.count, 1);
+        assert_eq!(stats.tree, ObjectSizes::default());
+        assert_eq!(stats.get(ObjectType::Blob), &stats.blob);
+
+        // 展開した大きさは header 込み
+        let header = format!("blob {}\0", content.len());
+        assert!(stats.blob.inflated > header.len() as u64);
+        assert_eq!(stats.blob.inflated, (header.len() + content.len()) as u64);
+        assert_eq!(stats.commit.inflated, commit.as_bytes().len() as u64);
+        // 同じ内容が続く blob はよく縮む
+        assert!(stats.blob.compressed < stats.blob.inflated);
+
+        let total = stats.total();
+        assert_eq!(total.count, 2);
+        assert_eq!(
+            total.compressed,
+            stats.blob.compressed + stats.commit.compressed
+        );
+        assert_eq!(total.inflated, stats.blob.inflated + stats.commit.inflated);
+    }
+
+    #[test]
+    fn fsck_loose_objects() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let blob = GitObject::Blob(Blob::new("hello, git".to_string()));
+        git.write_object(&blob).unwrap();
+        assert_eq!(git.fsck_loose().unwrap(), vec![]);
+
+        // 別の object の中身を書き込んで壊す
+        let bytes = git
+            .read_object(&"3edbc45b9a7f744c2345cd2cd073c3de091341ac".parse().unwrap())
+            .unwrap();
+        git.filesystem
+            .create_dir(".git/objects/aa".to_string())
+            .unwrap();
+        git.filesystem
+            .write(
+                ".git/objects/aa/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".to_string(),
+                &bytes,
+            )
+            .unwrap();
+
+        let problems = git.fsck_loose().unwrap();
+        assert_eq!(
+            problems,
+            vec![FsckProblem {
+                path: ".git/objects/aa/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".to_string(),
+                expected: "aabbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".to_string(),
+                actual: Some("3edbc45b9a7f744c2345cd2cd073c3de091341ac".to_string()),
+            }]
+        );
+    }
+
+    // ref-delta だけを並べた pack と idx を置く. entries は (自分の hash, base の hash)
+    fn write_ref_delta_pack(git: &mut Git<InMemFileSystem>, entries: &[([u8; 20], [u8; 20])]) {
+        let mut data = b"PACK".to_vec();
+        data.extend(2u32.to_be_bytes());
+        data.extend((entries.len() as u32).to_be_bytes());
+        let mut idx_entries = Vec::new();
+        for (hash, base) in entries {
+            let offset = data.len() as u64;
+            let delta = [1, 1, 0x90, 1];
+            let mut entry = pack::encode_entry_header(pack::OBJ_REF_DELTA, delta.len());
+            entry.extend(base);
+            entry.extend(deflate(&delta).unwrap());
+            idx_entries.push(pack::idx::IdxEntry {
+                hash: hash.to_vec(),
+                crc32: pack::crc32(&entry),
+                offset,
+            });
+            data.extend(entry);
+        }
+        let checksum = git.hash_algo.digest(&data);
+        data.extend(&checksum);
+        let idx = pack::idx::write_idx(&mut idx_entries, &checksum, git.hash_algo);
+
+        git.filesystem
+            .create_dir(".git/objects/pack".to_string())
+            .unwrap();
+        git.filesystem
+            .write(".git/objects/pack/pack-cycle.pack".to_string(), &data)
+            .unwrap();
+        git.filesystem
+            .write(".git/objects/pack/pack-cycle.idx".to_string(), &idx)
+            .unwrap();
+        git.reload_packs();
+    }
+
+    #[test]
+    fn read_object_delta_cycle() {
+        let (a, b) = ([0xaa; 20], [0xbb; 20]);
+        let id = ObjectId::from_bytes(&a).unwrap();
+
+        // 自分自身を base にする ref-delta
+        let mut git = Git::new(InMemFileSystem::init());
+        write_ref_delta_pack(&mut git, &[(a, a)]);
+        let e = git.read_object(&id).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        // 互いを base にする ref-delta
+        let mut git = Git::new(InMemFileSystem::init());
+        write_ref_delta_pack(&mut git, &[(a, b), (b, a)]);
+        let e = git.read_object(&id).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn read_object_from_pack() {
+        let mut git = Git::new(InMemFileSystem::init());
+        git.filesystem
+            .create_dir(".git/objects/pack".to_string())
+            .unwrap();
+        git.filesystem
+            .write(
+                ".git/objects/pack/pack-test.pack".to_string(),
+                include_bytes!("../tests/fixtures/ofs-delta.pack"),
+            )
+            .unwrap();
+        git.filesystem
+            .write(
+                ".git/objects/pack/pack-test.idx".to_string(),
+                include_bytes!("../tests/fixtures/ofs-delta.idx"),
+            )
+            .unwrap();
+
+        for line in include_str!("../tests/fixtures/objects.txt").lines() {
+            let hash = line.split(' ').next().unwrap();
+            let bytes = git
+                .read_object(&hash.parse().unwrap())
+                .and_then(|x| inflate(&x));
+            assert_eq!(hex::encode(git.hash_algo.digest(&bytes.unwrap())), hash);
+        }
+
+        // delta で格納されている blob
+        let bytes = git
+            .read_object(&"7cab485a468c76adc53a3e32e3244bd11767b8d6".parse().unwrap())
+            .unwrap();
+        let blob = git.cat_file_p(&bytes).unwrap();
+        assert!(blob.to_string().starts_with("1\n2\n3\n"));
+
+        let commit = git
+            .read_commit("aaa9501870880010b016554a65bc400ade0e1842")
+            .unwrap();
+        assert_eq!(
+            commit.parents,
+            vec!["480f04d846af91091e7979df730bd7626eca9829".parse().unwrap()]
+        );
+
+        let e = git
+            .read_object(&"0000000000000000000000000000000000000000".parse().unwrap())
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn resolve_head() {
+        let mut git = Git::new(InMemFileSystem::init());
+        git.init().unwrap();
+        let id: ObjectId = "e83c5163316f89bfbde7d9ab23ca2e25604af290".parse().unwrap();
+
+        // まだ ref が無い
+        let e = git.resolve_head().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+
+        // `ref: refs/heads/master` を辿る
+        git.update_ref("refs/heads/master".to_string(), &id)
+            .unwrap();
+        assert_eq!(git.resolve_head().unwrap(), id.to_hex());
+        assert_eq!(git.rev_parse("HEAD").unwrap(), id.to_hex());
+
+        // git checkout <hash> の後の HEAD
+        let detached = "1a410efbd13591db07496601ebc7a059dd55cfe9";
+        git.filesystem
+            .write(
+                ".git/HEAD".to_string(),
+                format!("{}\n", detached.to_uppercase()).as_bytes(),
+            )
+            .unwrap();
+        assert_eq!(git.resolve_head().unwrap(), detached);
+        assert_eq!(git.rev_parse("HEAD").unwrap(), detached);
+        assert_eq!(
+            git.head_ref().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        for head in ["", "ref", "1a410efb", "not a hash"] {
+            git.filesystem
+                .write(".git/HEAD".to_string(), head.as_bytes())
+                .unwrap();
+            assert!(git.resolve_head().is_err(), "{}", head);
+            assert!(git.head_ref().is_err(), "{}", head);
+        }
+    }
+
+    #[test]
+    fn stage_file() {
+        let mut git = Git::new(InMemFileSystem::init());
+        git.init().unwrap();
+        git.filesystem.create_dir("src".to_string()).unwrap();
+        let mut index = Index::new(Vec::new());
+        for name in ["a.txt", "c.txt", "src/lib.rs"] {
+            git.filesystem
+                .write(name.to_string(), name.as_bytes())
+                .unwrap();
+            let hash = git
+                .hash_object_write(name.as_bytes())
+                .unwrap()
+                .parse()
+                .unwrap();
+            index.upsert(git.index_entry(&hash, name.to_string()).unwrap());
+        }
+        git.write_index(&index).unwrap();
+
+        git.filesystem.write("b.txt".to_string(), b"b").unwrap();
+        git.stage_file("b.txt".to_string()).unwrap();
+        // 既にある entry は置き換える
+        git.filesystem.write("c.txt".to_string(), b"c2").unwrap();
+        git.stage_file("c.txt".to_string()).unwrap();
+        assert!(git.stage_file("missing.txt".to_string()).is_err());
+
+        let bytes = git.read_index().unwrap();
+        let (content, checksum) = bytes.split_at(bytes.len() - 20);
+        assert_eq!(checksum, git.hash_algo.digest(content).as_slice());
+
+        let index = git.ls_files_stage(&bytes).unwrap();
+        let names = index
+            .entries
+            .iter()
+            .map(|x| x.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["a.txt", "b.txt", "c.txt", "src/lib.rs"]);
+        let entry = index.get_entry("c.txt").unwrap();
+        assert_eq!(entry.hash, Blob::new("c2").calc_hash());
+        assert!(git.has_object(&entry.hash).unwrap());
+        assert!(git.modified_files(&index).unwrap().is_empty());
+    }
+
+    #[test]
+    fn custom_git_dir() {
+        let mut fs = InMemFileSystem::init();
+        for dir in [
+            "custom-git-dir",
+            "custom-git-dir/objects",
+            "custom-git-dir/refs",
+            "custom-git-dir/refs/heads",
+        ] {
+            fs.create_dir(dir.to_string()).unwrap();
+        }
+        fs.write("custom-git-dir/HEAD".to_string(), b"ref: refs/heads/main\n")
+            .unwrap();
+        fs.write("README.md".to_string(), b"readme").unwrap();
+        let mut git = Git::with_git_dir(fs, "custom-git-dir/".to_string());
+        assert_eq!(git.git_dir(), "custom-git-dir");
+        assert_eq!(git.git_path("HEAD"), "custom-git-dir/HEAD");
+
+        let blob = GitObject::Blob(Blob::new("hello".to_string()));
+        git.write_object(&blob).unwrap();
+        let id = blob.calc_hash();
+        let (sub_dir, file) = id.split_at();
+        assert!(git
+            .filesystem
+            .exists(format!("custom-git-dir/objects/{}/{}", sub_dir, file)));
+        assert!(git.has_object(&id).unwrap());
+        assert!(git.read_object(&id).is_ok());
+
+        git.update_ref("refs/heads/main".to_string(), &id).unwrap();
+        assert_eq!(git.head_ref().unwrap(), "refs/heads/main");
+        assert_eq!(git.rev_parse("HEAD").unwrap(), id.to_hex());
+        assert_eq!(
+            git.list_refs().unwrap(),
+            vec![("refs/heads/main".to_string(), id.to_hex())]
+        );
+        // git_dir は worktree のファイルではない
+        assert_eq!(git.worktree_files("").unwrap(), vec!["README.md"]);
+        // 既定の .git は使わない
+        assert!(!git.filesystem.exists(".git/refs/heads/main".to_string()));
+    }
+
+    #[test]
+    fn update_index_same_content() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let hash: ObjectId = git.hash_object_write(b"x").unwrap().parse().unwrap();
+        for name in ["b.txt", "a.txt", "b.txt"] {
+            git.filesystem.write(name.to_string(), b"x").unwrap();
+            let index = git.update_index(&hash, name.to_string()).unwrap();
+            git.write_index(&index).unwrap();
+        }
+
+        // 同じ blob を指すファイルはどちらも残り, 同じ名前の entry は1つになる
+        let index = git.load_index().unwrap();
+        let names = index
+            .entries
+            .iter()
+            .map(|x| x.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["a.txt", "b.txt"]);
+        assert!(index.entries.iter().all(|x| x.hash == hash));
+    }
+
+    #[test]
+    fn remove_from_index() {
+        let mut git = Git::new(InMemFileSystem::init());
+        for name in ["a.txt", "b.txt"] {
+            git.filesystem.write(name.to_string(), b"x").unwrap();
+            let hash = git.hash_object_write(name.as_bytes()).unwrap();
+            let index = git
+                .update_index(&hash.parse().unwrap(), name.to_string())
+                .unwrap();
+            git.write_index(&index).unwrap();
+        }
+
+        let index = git.remove_from_index(git.load_index().unwrap(), "a.txt");
+        let names = index
+            .entries
+            .iter()
+            .map(|x| x.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["b.txt"]);
+        // 無い entry を取り除いても変わらない
+        let index = git.remove_from_index(index, "a.txt");
+        assert_eq!(index.entries.len(), 1);
+
+        git.write_index(&index).unwrap();
+        let (_, tree) = git.write_tree().unwrap().pop().unwrap();
+        let names = tree
+            .contents
+            .iter()
+            .map(|x| x.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["b.txt"]);
+    }
+
+    #[test]
+    fn write_index_and_ref_locked() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let id = ObjectId::from_hex(&"a".repeat(40)).unwrap();
+
+        // 他の rgit が lock を持っている間は書かない
+        git.filesystem
+            .write(".git/index.lock".to_string(), b"")
+            .unwrap();
+        git.filesystem
+            .write(".git/refs/heads/master.lock".to_string(), b"")
+            .unwrap();
+        let err = git.write_index(&Index::new(Vec::new())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(!git.filesystem.exists(".git/index".to_string()));
+        assert!(git
+            .update_ref("refs/heads/master".to_string(), &id)
+            .is_err());
+        assert!(git.read_ref("refs/heads/master".to_string()).is_err());
+
+        git.filesystem
+            .remove(".git/index.lock".to_string())
+            .unwrap();
+        git.filesystem
+            .remove(".git/refs/heads/master.lock".to_string())
+            .unwrap();
+        git.write_index(&Index::new(Vec::new())).unwrap();
+        git.update_ref("refs/heads/master".to_string(), &id)
+            .unwrap();
+        assert_eq!(
+            git.read_ref("refs/heads/master".to_string()).unwrap(),
+            "a".repeat(40)
+        );
+        // git と同じく改行で終える
+        assert_eq!(
+            git.filesystem
+                .read(".git/refs/heads/master".to_string())
+                .unwrap(),
+            format!("{}\n", "a".repeat(40)).into_bytes()
+        );
+        assert!(!git.filesystem.exists(".git/index.lock".to_string()));
+        assert!(!git
+            .filesystem
+            .exists(".git/refs/heads/master.lock".to_string()));
+    }
+
+    #[test]
+    fn modify_index_locked() {
+        let mut git = Git::new(InMemFileSystem::init());
+        git.filesystem.write("a.txt".to_string(), b"a").unwrap();
+        git.stage_file("a.txt".to_string()).unwrap();
+        let before = git.read_index().unwrap();
+
+        // lock を取れなければ index を読まずに失敗し, 書き換えもしない
+        git.filesystem
+            .write(".git/index.lock".to_string(), b"")
+            .unwrap();
+        git.filesystem.write("b.txt".to_string(), b"b").unwrap();
+        let err = git.stage_file("b.txt".to_string()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        let err = git.modify_index(|_, _| Ok(())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(git.read_index().unwrap(), before);
+
+        // f が失敗したら lock を外し, index はそのまま残す
+        git.filesystem
+            .remove(".git/index.lock".to_string())
+            .unwrap();
+        let err = git
+            .modify_index(|_, index| {
+                index.remove_entry("a.txt");
+                Err::<(), _>(io::Error::from(io::ErrorKind::Other))
+            })
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(git.read_index().unwrap(), before);
+        assert!(!git.filesystem.exists(".git/index.lock".to_string()));
+
+        let names = git
+            .modify_index(|_, index| {
+                index.remove_entry("a.txt");
+                Ok(index.entries.len())
+            })
+            .unwrap();
+        assert_eq!(names, 0);
+        assert!(git.load_index().unwrap().entries.is_empty());
+    }
+
+    #[test]
+    fn write_config_locked() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let mut config = Config::new();
+        config.set("user", None, "name", "rgit");
+        git.write_config(&config).unwrap();
+
+        // 他の rgit が lock を持っている間は書かない
+        git.filesystem
+            .write(".git/config.lock".to_string(), b"")
+            .unwrap();
+        config.set("user", None, "name", "other");
+        let err = git.write_config(&config).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(
+            git.read_config().unwrap().get("user", None, "name"),
+            Some("rgit")
+        );
+
+        git.filesystem
+            .remove(".git/config.lock".to_string())
+            .unwrap();
+        git.write_config(&config).unwrap();
+        assert_eq!(
+            git.read_config().unwrap().get("user", None, "name"),
+            Some("other")
+        );
+        assert!(!git.filesystem.exists(".git/config.lock".to_string()));
+    }
+
+    #[test]
+    fn write_tree_nested() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let blob = git.hash_object_write(b"hello\n").unwrap();
+        let blob = blob.parse().unwrap();
+        for dir in ["src", "src/object", "tests"] {
+            git.filesystem.create_dir(dir.to_string()).unwrap();
+        }
+        let mut index = Index::new(Vec::new());
+        for name in [
+            "README",
+            "src/main.rs",
+            "src/object/mod.rs",
+            "src/object/tree.rs",
+            "tests/a.rs",
+        ] {
+            git.filesystem.write(name.to_string(), b"hello\n").unwrap();
+            index.upsert(git.index_entry(&blob, name.to_string()).unwrap());
+        }
+        git.write_index(&index).unwrap();
+
+        // 子の tree が親より先に並び, 一番上の tree が最後になる
+        let trees = git.write_tree().unwrap();
+        let names = trees
+            .iter()
+            .map(|(_, tree)| {
+                tree.contents
+                    .iter()
+                    .map(|x| x.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "mod.rs tree.rs",
+                "main.rs object",
+                "a.rs",
+                "README src tests"
+            ]
+        );
+        for (id, tree) in trees.iter() {
+            assert_eq!(*id, tree.calc_hash());
+        }
+        // 親は子の hash を mode 40000 で指す
+        let (root_id, root) = trees.last().unwrap();
+        let src = root.contents.iter().find(|x| x.name == "src").unwrap();
+        assert_eq!(src.mode, FileMode::Tree);
+        assert_eq!(src.hash, trees[1].0);
+
+        // 書き込むのは呼び出し側で, write_tree_files と同じ tree になる
+        assert!(!git.has_object(root_id).unwrap());
+        let files = index
+            .entries
+            .iter()
+            .map(|x| (x.name.clone(), tree::MODE_FILE, x.hash.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(&git.write_tree_files(&files).unwrap(), root_id);
+        for (id, _) in trees.iter() {
+            assert!(git.has_object(id).unwrap());
+        }
+    }
+
+    #[test]
+    fn ref_path_traversal() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let id: ObjectId = "e83c5163316f89bfbde7d9ab23ca2e25604af290".parse().unwrap();
+        git.filesystem
+            .write("secret".to_string(), b"secret")
+            .unwrap();
+
+        for name in [
+            "../../etc/passwd",
+            "../secret",
+            "refs/../../secret",
+            "/etc/passwd",
+            "",
+        ] {
+            let e = git.read_ref(name.to_string()).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput, "{}", name);
+            assert!(git.update_ref(name.to_string(), &id).is_err(), "{}", name);
+            assert!(git.delete_ref(name.to_string()).is_err(), "{}", name);
+        }
+        assert_eq!(
+            git.filesystem.read("secret".to_string()).unwrap(),
+            b"secret"
+        );
+
+        // HEAD に書かれた ref も同じく確かめる
+        git.filesystem
+            .write(".git/HEAD".to_string(), b"ref: ../secret\n")
+            .unwrap();
+        assert!(git.resolve_head().is_err());
+
+        // object の hash は16進数でなければ ObjectId にならない
+        assert!("../../etc/passwd".parse::<ObjectId>().is_err());
+        // `..` を含むだけの名前は外を指さないので, 普通に探して見つからない
+        assert_eq!(
+            git.read_ref("refs/heads/..master".to_string())
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn special_refs() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let first: ObjectId = "e83c5163316f89bfbde7d9ab23ca2e25604af290".parse().unwrap();
+        let second: ObjectId = "8d14531846b95bfa3564b58ccfb7913a034323b8".parse().unwrap();
+        git.update_ref("refs/heads/master".to_string(), &second)
+            .unwrap();
+
+        assert_eq!(git.read_special_ref("ORIG_HEAD").unwrap(), None);
+        git.write_special_ref("ORIG_HEAD", &first).unwrap();
+        assert_eq!(
+            git.filesystem.read(".git/ORIG_HEAD".to_string()).unwrap(),
+            b"e83c5163316f89bfbde7d9ab23ca2e25604af290\n"
+        );
+        assert_eq!(
+            git.read_special_ref("ORIG_HEAD").unwrap(),
+            Some(first.to_hex())
+        );
+        assert_eq!(git.read_special_ref("HEAD").unwrap(), Some(second.to_hex()));
+        assert_eq!(git.read_special_ref("MERGE_HEAD").unwrap(), None);
+
+        // FETCH_HEAD は最初の行の hash
+        git.filesystem
+            .write(
+                ".git/FETCH_HEAD".to_string(),
+                format!(
+                    "{}\t\tbranch 'master' of ../origin\n{}\tnot-for-merge\tbranch 'topic' of ../origin\n",
+                    second, first
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        assert_eq!(
+            git.read_special_ref("FETCH_HEAD").unwrap(),
+            Some(second.to_hex())
+        );
+
+        let e = git.read_special_ref("refs/heads/master").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(git.write_special_ref("../ORIG_HEAD", &first).is_err());
+    }
+
+    #[test]
+    fn commit_tree_check_tree() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let user = commit::User::new(
+            "rgit".to_string(),
+            "rgit@example.com".to_string(),
+            Utc.timestamp(0, 0).into(),
+        );
+        let commit_tree = |git: &Git<InMemFileSystem>, tree: &str| {
+            git.commit_tree(
+                user.clone(),
+                None,
+                &tree.parse().unwrap(),
+                "message".to_string(),
+            )
+        };
+        let blob = git.hash_object_write(b"hello\n").unwrap();
+        let tree = GitObject::Tree(Tree::new(vec![tree::File::new(
+            FileMode::Regular,
+            "a.txt".to_string(),
+            blob.parse().unwrap(),
+        )]));
+        git.write_object(&tree).unwrap();
+        let tree = tree.calc_hash().to_hex();
+
+        assert_eq!(commit_tree(&git, &tree).unwrap().tree.to_hex(), tree);
+        // 空の tree は書き込まれていなくても使える
+        assert!(commit_tree(&git, "4b825dc642cb6eb9a060e54bf8d69288fbee4904").is_ok());
+
+        let missing = "0123456789abcdef0123456789abcdef01234567";
+        let e = commit_tree(&git, missing).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        assert_eq!(e.to_string(), format!("{} is not a valid object", missing));
+        let e = commit_tree(&git, &blob).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            format!("{} is not a valid 'tree' object", blob)
+        );
+
+        // 確かめないようにもできる
+        let git = GitBuilder::new(git.filesystem)
+            .check_commit_tree(false)
+            .build();
+        assert_eq!(commit_tree(&git, missing).unwrap().tree.to_hex(), missing);
+    }
+
+    #[test]
+    fn commit_tree_author_committer() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let author = commit::User::new(
+            "author".to_string(),
+            "author@example.com".to_string(),
+            chrono::FixedOffset::east(9 * 60 * 60).timestamp(1609642799, 0),
+        );
+        let committer = commit::User::new(
+            "maintainer".to_string(),
+            "maintainer@example.com".to_string(),
+            chrono::FixedOffset::east(2 * 60 * 60).timestamp(1609729199, 0),
+        );
+        let tree = "4b825dc642cb6eb9a060e54bf8d69288fbee4904".parse().unwrap();
+
+        let commit = git
+            .commit_tree(
+                author.clone(),
+                Some(committer.clone()),
+                &tree,
+                "apply patch".to_string(),
+            )
+            .unwrap();
+        let commit = GitObject::Commit(commit);
+        git.write_object(&commit).unwrap();
+
+        // 書き込んで読み直しても author と committer は別のまま
+        let read = git.read_commit(&commit.calc_hash().to_hex()).unwrap();
+        assert_eq!(read.author.to_string(), author.to_string());
+        assert_eq!(read.committer.to_string(), committer.to_string());
+        assert_ne!(read.author.to_string(), read.committer.to_string());
+        assert_eq!(read.committer.ts.offset().local_minus_utc(), 2 * 60 * 60);
+
+        // 省略すれば author と同じ
+        let commit = git
+            .commit_tree(author.clone(), None, &tree, "message".to_string())
+            .unwrap();
+        assert_eq!(commit.committer.to_string(), author.to_string());
+    }
+
+    #[test]
+    fn commit_tree_parent() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let user = commit::User::new(
+            "rgit".to_string(),
+            "rgit@example.com".to_string(),
+            Utc.timestamp(0, 0).into(),
+        );
+        let tree = "4b825dc642cb6eb9a060e54bf8d69288fbee4904".parse().unwrap();
+        let commit_tree = |git: &Git<InMemFileSystem>| {
+            git.commit_tree(user.clone(), None, &tree, "message".to_string())
+        };
+
+        // master がまだ無いので親の無い commit
+        let first = GitObject::Commit(commit_tree(&git).unwrap());
+        match &first {
+            GitObject::Commit(x) => assert!(x.parents.is_empty()),
+            _ => unreachable!(),
+        }
+        let hash = first.calc_hash();
+        git.update_ref("refs/heads/master".to_string(), &hash)
+            .unwrap();
+        assert_eq!(commit_tree(&git).unwrap().parents, vec![hash.clone()]);
+
+        // merge commit は parent を並べた順に持つ
+        let other: ObjectId = "0123456789abcdef0123456789abcdef01234567".parse().unwrap();
+        let merge = git
+            .commit_tree_with_parents(
+                user.clone(),
+                None,
+                &tree,
+                vec![hash.clone(), other.clone()],
+                "merge".to_string(),
+            )
+            .unwrap();
+        assert_eq!(merge.parents, vec![hash.clone(), other]);
+
+        // ref が壊れていて読めなければ親の無い commit にはしない
+        git.filesystem
+            .write(git.git_path("refs/heads/master"), b"\xff\xfe\n")
+            .unwrap();
+        let e = commit_tree(&git).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        // packed-refs にだけある branch も親にする
+        git.filesystem
+            .remove(git.git_path("refs/heads/master"))
+            .unwrap();
+        git.filesystem
+            .write(
+                git.git_path("packed-refs"),
+                format!("{} refs/heads/master\n", hash).as_bytes(),
+            )
+            .unwrap();
+        assert_eq!(commit_tree(&git).unwrap().parents, vec![hash.clone()]);
+
+        // HEAD 自体が無いのは最初の commit ではなく壊れたリポジトリ
+        git.filesystem.remove(git.git_path("HEAD")).unwrap();
+        let e = commit_tree(&git).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn open() {
+        let setup = || {
+            let mut fs = InMemFileSystem::init();
+            for dir in ["work", "work/.git", "bare.git", "empty"] {
+                fs.create_dir(dir.to_string()).unwrap();
+            }
+            fs.write("work/README.md".to_string(), b"work").unwrap();
+            fs.write("bare.git/HEAD".to_string(), b"ref: refs/heads/main\n")
+                .unwrap();
+            fs
+        };
+
+        // bare でないリポジトリはそのディレクトリが起点になる
+        let git = Git::open(setup(), "/work/").unwrap();
+        assert_eq!(
+            git.filesystem.read("README.md".to_string()).unwrap(),
+            b"work"
+        );
+
+        // bare リポジトリはディレクトリ自体が git_dir
+        let git = Git::open(setup(), "bare.git").unwrap();
+        assert_eq!(git.git_dir(), "bare.git");
+        assert_eq!(git.head_ref().unwrap(), "refs/heads/main");
+
+        let err = Git::open(git.filesystem, "empty").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn discover() {
+        // .git の2つ下のディレクトリから探す
+        let mut fs = InMemFileSystem::init();
+        for dir in [
+            "src",
+            "src/object",
+            "vendor",
+            "vendor/lib",
+            "vendor/lib/.git",
+        ] {
+            fs.create_dir(dir.to_string()).unwrap();
+        }
+        fs.write("README.md".to_string(), b"top").unwrap();
+        fs.write("vendor/lib/README.md".to_string(), b"lib")
+            .unwrap();
+        let git = Git::discover(fs, "src/object").unwrap();
+        assert_eq!(
+            git.filesystem.read("README.md".to_string()).unwrap(),
+            b"top"
+        );
+        assert_eq!(git.head_ref().unwrap(), "refs/heads/master");
+        assert_eq!(git.prefix, "src/object");
+
+        // 一番近い .git が使われ, そのディレクトリが起点になる
+        let git = Git::discover(git.filesystem, "vendor/lib/").unwrap();
+        assert_eq!(
+            git.filesystem.read("README.md".to_string()).unwrap(),
+            b"lib"
+        );
+        assert!(git.filesystem.is_dir(".git".to_string()).unwrap());
+        assert_eq!(git.prefix, "");
+
+        let mut fs = InMemFileSystem::init();
+        fs.remove_dir_all(".git".to_string()).unwrap();
+        fs.create_dir("src".to_string()).unwrap();
+        let e = Git::discover(fs, "src").err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn prefix_path() {
+        let mut git = Git::new(InMemFileSystem::init());
+        assert_eq!(git.prefix_path("a.txt").unwrap(), "a.txt");
+        assert_eq!(git.prefix_path(".").unwrap(), ".");
+
+        git.prefix = "src/object".to_string();
+        assert_eq!(git.prefix_path("a.txt").unwrap(), "src/object/a.txt");
+        assert_eq!(git.prefix_path("./a.txt").unwrap(), "src/object/a.txt");
+        assert_eq!(git.prefix_path("../a.txt").unwrap(), "src/a.txt");
+        assert_eq!(git.prefix_path("sub/").unwrap(), "src/object/sub/");
+        assert_eq!(git.prefix_path(".").unwrap(), "src/object");
+        assert_eq!(git.prefix_path("../..").unwrap(), ".");
+
+        let e = git.prefix_path("../../../a.txt").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(e.to_string().contains("outside repository"), "{}", e);
+    }
+
+    #[test]
+    fn init_sha256() {
+        let mut git = GitBuilder::new(InMemFileSystem::init())
+            .hash_algo(HashAlgo::Sha256)
+            .build();
+        git.init().unwrap();
+        let config = git.read_config().unwrap();
+        assert_eq!(
+            config.get("core", None, "repositoryformatversion"),
+            Some("1")
+        );
+        assert_eq!(
+            config.get("extensions", None, "objectformat"),
+            Some("sha256")
+        );
+
+        // 開き直すと config から hash の種類がわかる
+        let mut git = Git::open(git.filesystem, "").unwrap();
+        assert_eq!(git.hash_algo, HashAlgo::Sha256);
+        let hash = git.hash_object_write(b"hello\n").unwrap();
+        // sha256 のリポジトリで `echo hello | git hash-object --stdin`
+        assert_eq!(
+            hash,
+            "2cf8d83d9ee29543b34a87727421fdecb7e3f3a183d337639025de576db9ebb4"
+        );
+        assert_eq!(git.read_blob(&hash).unwrap(), b"hello\n");
+        let git = Git::discover(git.filesystem, "").unwrap();
+        assert_eq!(git.hash_algo, HashAlgo::Sha256);
+
+        // sha1 のリポジトリには extensions を書かない
+        let mut git = Git::new(InMemFileSystem::init());
+        git.init().unwrap();
+        assert!(git
+            .read_config()
+            .unwrap()
+            .get("extensions", None, "objectformat")
+            .is_none());
+        assert_eq!(
+            Git::open(git.filesystem, "").unwrap().hash_algo,
+            HashAlgo::Sha1
+        );
+
+        let mut git = Git::new(InMemFileSystem::init());
+        let mut config = Config::new();
+        config.set("extensions", None, "objectformat", "md5");
+        git.write_config(&config).unwrap();
+        let e = Git::open(git.filesystem, "").err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn write_objects_dedup() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let hello = GitObject::Blob(Blob::new("hello".to_string()));
+        let world = GitObject::Blob(Blob::new("world".to_string()));
+        let existing = GitObject::Blob(Blob::new("existing".to_string()));
+        git.write_object(&existing).unwrap();
+
+        let written = git
+            .write_objects(&[
+                GitObject::Blob(Blob::new("hello".to_string())),
+                world,
+                hello,
+                existing,
+            ])
+            .unwrap();
+        assert_eq!(written.len(), 2);
+        assert_eq!(git.loose_objects().unwrap().len(), 3);
+        for hash in written {
+            assert!(git.has_object(&hash.parse().unwrap()).unwrap());
+            let object = git
+                .read_object(&hash.parse().unwrap())
+                .and_then(|x| git.cat_file_p(&x))
+                .unwrap();
+            assert_eq!(hex::encode(object.calc_hash()), hash);
+        }
+        assert!(!git
+            .has_object(&"0000000000000000000000000000000000000000".parse().unwrap())
+            .unwrap());
+    }
+
+    #[test]
+    fn write_objects_cache() {
+        let mut git = GitBuilder::new(InMemFileSystem::init())
+            .cache_capacity(1024)
+            .build();
+        let hello = GitObject::Blob(Blob::new("hello".to_string()));
+        let id = hello.calc_hash();
+        git.write_objects(std::slice::from_ref(&hello)).unwrap();
+        git.read_decoded(&id).unwrap();
+        assert_eq!(git.object_cache.borrow().len(), 1);
+
+        // 消えた object を書き直したら cache の古い中身は使わない
+        git.filesystem.remove(git.object_path(&id)).unwrap();
+        git.write_objects(std::slice::from_ref(&hello)).unwrap();
+        assert!(git.object_cache.borrow().is_empty());
+    }
+
+    #[test]
+    fn read_blob() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let blob = GitObject::Blob(Blob::new("hello\n".to_string()));
+        git.write_object(&blob).unwrap();
+        let hash = hex::encode(blob.calc_hash());
+        assert_eq!(git.read_blob(&hash).unwrap(), b"hello\n");
+
+        let tree = GitObject::Tree(Tree::new(vec![tree::File::new(
+            FileMode::Regular,
+            "hello.txt".to_string(),
+            blob.calc_hash(),
+        )]));
+        git.write_object(&tree).unwrap();
+        let hash = hex::encode(tree.calc_hash());
+        let e = git.read_blob(&hash).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), format!("{} is a tree, not a blob", hash));
+
+        assert_eq!(
+            git.read_blob("0000000000000000000000000000000000000000")
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
     }
 }
@@ -1,35 +1,538 @@
-use rgit::{
-    cmd,
-    fs::{linux::LinuxFileSystem, FileSystem},
-    Git,
-};
-use std::io;
+use rgit::fs::{linux::LinuxFileSystem, FileSystem};
+use rgit::{cmd, hash::HashAlgo, object::GitObject, protocol, Git};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 
 fn main() -> io::Result<()> {
     let args: Vec<String> = std::env::args().collect();
-    let fs = LinuxFileSystem::init()?;
-    let mut git = Git::new(fs);
+    // サブディレクトリからでも使えるように .git を上に向かって探す
+    let cwd = std::env::current_dir()?;
+    // git clone <bundle> <dir>. リポジトリの外で使うので先に処理する
+    if args.get(1).map(String::as_str) == Some("clone") {
+        let (bundle, dir) = match (args.get(2), args.get(3)) {
+            (Some(bundle), Some(dir)) => (cwd.join(bundle), dir),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "usage: clone <bundle> <dir>",
+                ))
+            }
+        };
+        let mut fs = LinuxFileSystem::with_root(cwd);
+        fs.create_dir(dir.clone())?;
+        fs.change_root(dir.clone())?;
+        let mut git = Git::new(fs);
+        git.init()?;
+        return cmd::clone_bundle(&mut git, bundle.to_string_lossy().into_owned());
+    }
+    // git init [--object-format=<format>] [<dir>]. まだ .git が無いので先に処理する
+    if args.get(1).map(String::as_str) == Some("init") {
+        let mut hash_algo = HashAlgo::default();
+        let mut dir = None;
+        for arg in args.iter().skip(2) {
+            match arg.strip_prefix("--object-format=") {
+                Some(format) => {
+                    hash_algo = HashAlgo::from(format).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("unknown hash algorithm '{}'", format),
+                        )
+                    })?
+                }
+                None => dir = Some(arg.clone()),
+            }
+        }
+        let mut fs = LinuxFileSystem::with_root(cwd.clone());
+        let path = match dir {
+            Some(dir) => {
+                if !fs.exists(dir.clone()) {
+                    fs.create_dir(dir.clone())?;
+                }
+                fs.change_root(dir.clone())?;
+                cwd.join(dir)
+            }
+            None => cwd,
+        };
+        let mut git = Git::with_hash_algo(fs, hash_algo);
+        git.init()?;
+        println!(
+            "Initialized empty Git repository in {}/",
+            path.join(".git").display()
+        );
+        return Ok(());
+    }
+    // git upload-pack <dir>. git clone の ext:: や inetd から stdin/stdout で使う
+    if args.get(1).map(String::as_str) == Some("upload-pack") {
+        let dir = args.get(2).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "usage: upload-pack <dir>")
+        })?;
+        let fs = LinuxFileSystem::with_root(PathBuf::from("/"));
+        let git = Git::open(fs, &cwd.join(dir).to_string_lossy())?;
+        return cmd::upload_pack(&git, io::stdin().lock(), io::stdout().lock());
+    }
+    // git receive-pack <dir>. git push から stdin/stdout で使う
+    if args.get(1).map(String::as_str) == Some("receive-pack") {
+        let dir = args.get(2).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "usage: receive-pack <dir>")
+        })?;
+        let fs = LinuxFileSystem::with_root(PathBuf::from("/"));
+        let mut git = Git::open(fs, &cwd.join(dir).to_string_lossy())?;
+        return cmd::receive_pack(&mut git, io::stdin().lock(), io::stdout().lock());
+    }
+    // git ls-remote [<repository>]. remote の名前でなければリポジトリの外でも使える
+    if args.get(1).map(String::as_str) == Some("ls-remote") {
+        let target = args
+            .iter()
+            .skip(2)
+            .find(|x| !x.starts_with('-'))
+            .map_or("origin", String::as_str);
+        // 設定された remote の名前なら URL に置き換え, そうでなければ URL かパスとして扱う
+        let fs = LinuxFileSystem::with_root(PathBuf::from("/"));
+        let url = Git::discover(fs, &cwd.to_string_lossy())
+            .and_then(|git| git.remotes())
+            .unwrap_or_default()
+            .into_iter()
+            .find(|x| x.name == target)
+            .map_or_else(|| target.to_string(), |x| x.url);
+        let lines = match protocol::connect(&url)? {
+            Some(mut transport) => cmd::ls_remote_transport(&mut *transport, &args[2..])?,
+            None => cmd::ls_remote(&open_path(&cwd, &url)?, &args[2..])?,
+        };
+        for line in lines {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+    let fs = LinuxFileSystem::with_root(PathBuf::from("/"));
+    let mut git = Git::discover(fs, &cwd.to_string_lossy())?;
+    // log や tree を辿るコマンドは同じ object を何度も読む
+    git.set_cache_capacity(16 * 1024 * 1024);
 
-    let sub_cmd = args.get(1).unwrap().clone();
+    let sub_cmd = arg(&args, 1, "usage: rgit <command> [<args>]")?.clone();
     match sub_cmd.as_str() {
+        "cat-file" if args.get(2).map(String::as_str) == Some("-t") => {
+            let hash = arg(&args, 3, "usage: cat-file -t <object>")?;
+            println!("{}", cmd::cat_file_t(&git, git.rev_parse(hash)?)?);
+            Ok(())
+        }
+        // JSON にして字下げして出す
+        #[cfg(feature = "json")]
+        "cat-file" if args.get(2).map(String::as_str) == Some("--json") => {
+            let hash = git.rev_parse(arg(&args, 3, "usage: cat-file --json <object>")?)?;
+            let obj = git.read_git_object(&hash.parse()?)?;
+            println!("{}", rgit::json::to_string_pretty(&obj));
+            Ok(())
+        }
         "cat-file" => {
-            let obj = cmd::cat_file_p(args.get(2).unwrap().clone())?;
-            println!("{}", obj);
+            // -p は付けても付けなくても同じく中身を表示する
+            let hash = args.iter().skip(2).find(|x| !x.starts_with('-'));
+            let hash = hash.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "usage: cat-file [-p | -s] <object>",
+                )
+            })?;
+            // log や rev-list と同じく HEAD や branch 名も受け付ける
+            let hash = git.rev_parse(hash)?;
+            let obj = git.read_git_object(&hash.parse()?)?;
+            if args.iter().skip(2).any(|x| x == "-s") {
+                println!("{}", obj.size());
+                return Ok(());
+            }
+            match obj {
+                // git と同じく binary も含めて中身をそのまま出す
+                GitObject::Blob(blob) => io::stdout().write_all(&blob.content),
+                obj => {
+                    println!("{}", obj);
+                    Ok(())
+                }
+            }
+        }
+        "ls-tree" => {
+            let recursive = args.iter().skip(2).any(|x| x == "-r");
+            let rev = args.iter().skip(2).find(|x| !x.starts_with('-'));
+            let rev = rev.map(String::as_str).unwrap_or("HEAD");
+            for line in cmd::ls_tree(&git, rev, recursive)? {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        "mktree" => {
+            let mut input = Vec::new();
+            io::stdin().read_to_end(&mut input)?;
+            println!("{}", cmd::mktree(&mut git, &args[2..], &input)?);
             Ok(())
         }
         "hash-object" => {
-            let blob = cmd::hash_object(args.get(2).unwrap().clone())?;
-            println!("{}", hex::encode(blob.calc_hash()));
+            let write = args.iter().skip(2).any(|x| x == "-w");
+            let bytes = match args.iter().skip(2).find(|x| !x.starts_with('-')) {
+                Some(path) if !args.iter().any(|x| x == "--stdin") => fs::read(path)?,
+                _ => {
+                    let mut buf = Vec::new();
+                    io::stdin().read_to_end(&mut buf)?;
+                    buf
+                }
+            };
+            let hash = if write {
+                git.hash_object_write(&bytes)?
+            } else {
+                let blob = GitObject::Blob(git.hash_object(&bytes)?);
+                blob.calc_hash_with(git.hash_algo).to_hex()
+            };
+            println!("{}", hash);
             Ok(())
         }
+        "add" if matches!(args.get(2).map(String::as_str), Some("-A") | Some("--all")) => {
+            cmd::add_all(&mut git)
+        }
         "add" => {
-            let bytes = git.filesystem.read(args.get(2).unwrap().clone())?;
-            cmd::add(&mut git, args.get(2).unwrap().clone(), &bytes)
+            let path = arg(&args, 2, "usage: add [-A | <path>]")?.clone();
+            let prefixed = git.prefix_path(&path)?;
+            if cmd::add_path(&mut git, prefixed)?.is_empty() {
+                eprintln!("warning: '{}' contains no files to add", path);
+            }
+            Ok(())
+        }
+        "update-index" => {
+            let args = prefix_args(&git, &args[2..], &[])?;
+            cmd::update_index(&mut git, &args)
+        }
+        "commit" => cmd::commit_args(&mut git, &args[2..], io::stdin()),
+        "checkout" => cmd::checkout(&mut git, &args[2..]),
+        "switch" => cmd::switch(&mut git, &args[2..]),
+        "restore" => {
+            let args = prefix_args(&git, &args[2..], &["-s", "--source"])?;
+            cmd::restore(&mut git, &args)
+        }
+        "apply" => {
+            let check = args.iter().skip(2).any(|x| x == "--check");
+            let cached = args.iter().skip(2).any(|x| x == "--cached");
+            let path = args
+                .iter()
+                .skip(2)
+                .find(|x| !x.starts_with("--"))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "missing patch"))?;
+            let path = git.prefix_path(path)?;
+            cmd::apply(&mut git, path, check, cached)
+        }
+        "format-patch" => {
+            for line in cmd::format_patch(&mut git, &args[2..])? {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        "bundle" => {
+            for line in cmd::bundle(&mut git, &args[2..])? {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        "clean" => {
+            let dry_run = args.iter().skip(2).any(|x| x == "-n" || x == "--dry-run");
+            let force = args.iter().skip(2).any(|x| x == "-f" || x == "--force");
+            if !dry_run && !force {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "refusing to clean without -n or -f",
+                ));
+            }
+            for line in cmd::clean(&mut git, dry_run)? {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        "check-ignore" => {
+            let lines = cmd::check_ignore(&git, &prefix_args(&git, &args[2..], &[])?)?;
+            for line in lines.iter() {
+                println!("{}", line);
+            }
+            // 何も無視されなければ git と同じく 1 で終わる
+            if lines.is_empty() {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        "ls-files" if args.iter().skip(2).any(|x| x == "-o" || x == "--others") => {
+            let exclude_standard = args.iter().skip(2).any(|x| x == "--exclude-standard");
+            for line in cmd::ls_files_others(&git, exclude_standard)? {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        "push" => {
+            let force = args.iter().skip(2).any(|x| x == "-f" || x == "--force");
+            let names = args
+                .iter()
+                .skip(2)
+                .filter(|x| !x.starts_with('-'))
+                .collect::<Vec<_>>();
+            let (remote_name, branch) = match names.as_slice() {
+                [remote, branch] => (remote.as_str(), branch.as_str()),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "usage: push [--force] <remote> <branch>",
+                    ))
+                }
+            };
+            let url = git.remotes()?.into_iter().find(|x| x.name == remote_name);
+            let url = url.map(|x| x.url).unwrap_or_default();
+            let lines = match protocol::connect_push(&url)? {
+                Some(mut transport) => {
+                    cmd::push_transport(&mut git, &mut *transport, remote_name, branch, force)?
+                }
+                None => {
+                    let mut remote = open_remote(&git, &cwd, remote_name)?;
+                    cmd::push(&mut git, &mut remote, remote_name, branch, force)?
+                }
+            };
+            for line in lines {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        "fetch" => {
+            let prune = args.iter().skip(2).any(|x| x == "-p" || x == "--prune");
+            let remote_name = args
+                .iter()
+                .skip(2)
+                .find(|x| !x.starts_with('-'))
+                .map_or("origin", String::as_str);
+            let url = git.remotes()?.into_iter().find(|x| x.name == remote_name);
+            let url = url.map(|x| x.url).unwrap_or_default();
+            let lines = match protocol::connect(&url)? {
+                Some(mut transport) => {
+                    // server からの進捗はそのまま stderr に流す
+                    let mut progress = |x: &[u8]| {
+                        let _ = io::stderr().write_all(x);
+                    };
+                    cmd::fetch_transport(
+                        &mut git,
+                        &mut *transport,
+                        remote_name,
+                        prune,
+                        &mut progress,
+                    )?
+                }
+                None => {
+                    let remote = open_remote(&git, &cwd, remote_name)?;
+                    cmd::fetch(&mut git, &remote, remote_name, prune)?
+                }
+            };
+            for line in lines {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        "fast-export" => {
+            let stream = cmd::fast_export(&git)?;
+            io::stdout().write_all(&stream)
+        }
+        "fast-import" => {
+            let mut stream = Vec::new();
+            io::stdin().read_to_end(&mut stream)?;
+            for line in cmd::fast_import(&mut git, &stream)? {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        "am" => {
+            for line in cmd::am(&mut git, &args[2..])? {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        "describe" => {
+            // 省略されたら HEAD に名前を付ける
+            let commit = args.get(2).cloned().unwrap_or_else(|| "HEAD".to_string());
+            println!("{}", cmd::describe(&git, commit)?);
+            Ok(())
+        }
+        "name-rev" => {
+            let hashes = if args.get(2).map(|x| x.as_str()) == Some("--all") {
+                io::stdin().lines().collect::<io::Result<Vec<_>>>()?
+            } else {
+                args[2..].to_vec()
+            };
+            for line in cmd::name_rev(&git, &hashes)? {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        "pack-objects" => {
+            let base = arg(&args, 2, "usage: pack-objects <base-name>")?.clone();
+            let hashes = io::stdin().lines().collect::<io::Result<Vec<_>>>()?;
+            let hash = cmd::pack_objects(&mut git, base, &hashes)?;
+            println!("{}", hash);
+            Ok(())
+        }
+        "unpack-objects" => {
+            let path = git.prefix_path(arg(&args, 2, "usage: unpack-objects <pack>")?)?;
+            let count = cmd::unpack_objects(&mut git, path)?;
+            println!("Unpacked {} objects", count);
+            Ok(())
+        }
+        "index-pack" => {
+            let path = git.prefix_path(arg(&args, 2, "usage: index-pack <pack>")?)?;
+            let hash = cmd::index_pack(&mut git, path)?;
+            println!("{}", hash);
+            Ok(())
+        }
+        "prune" => {
+            let dry_run = args.iter().skip(2).any(|x| x == "-n" || x == "--dry-run");
+            for hash in cmd::prune(&mut git, dry_run)? {
+                println!("{}", hash);
+            }
+            Ok(())
+        }
+        "prune-packed" => {
+            let dry_run = args.iter().skip(2).any(|x| x == "-n" || x == "--dry-run");
+            for hash in cmd::prune_packed(&mut git, dry_run)? {
+                println!("{}", hash);
+            }
+            Ok(())
+        }
+        "remote" => {
+            for line in cmd::remote(&mut git, &args[2..])? {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        "log"
+            if args
+                .iter()
+                .skip(2)
+                .any(|x| x == "-g" || x == "--walk-reflogs") =>
+        {
+            let ref_name = args
+                .iter()
+                .skip(2)
+                .find(|x| !x.starts_with('-'))
+                .map_or("HEAD", String::as_str);
+            print_reflog(&git, ref_name)
+        }
+        "reflog" => {
+            // `reflog show <ref>` と `reflog <ref>` のどちらも受け付ける
+            let ref_name = args
+                .iter()
+                .skip(2)
+                .find(|x| x.as_str() != "show")
+                .map_or("HEAD", String::as_str);
+            print_reflog(&git, ref_name)
+        }
+        "log" => {
+            for line in cmd::log(&git, &args[2..])? {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        "notes" => {
+            for line in cmd::notes(&mut git, &args[2..])? {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        "count-objects" => {
+            for line in cmd::count_objects(&git)? {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        "fsck" => {
+            let unreachable = args.iter().skip(2).any(|x| x == "--unreachable");
+            for line in cmd::fsck(&git, unreachable)? {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        "verify" => {
+            let rev = args.get(2).map_or("HEAD", String::as_str);
+            for line in cmd::verify(&git, rev)? {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        "rev-list" => {
+            for line in cmd::rev_list(&git, &args[2..])? {
+                println!("{}", line);
+            }
+            Ok(())
         }
-        "commit" => cmd::commit(&mut git, args.get(2).unwrap().clone()),
         _ => {
             eprintln!("unexpected command: {}", sub_cmd.as_str());
             Ok(())
         }
     }
 }
+
+// n 番目の引数. 無ければ usage を error にする
+fn arg<'a>(args: &'a [String], n: usize, usage: &str) -> io::Result<&'a String> {
+    args.get(n)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, usage))
+}
+
+// cwd からのパスで指定された引数を worktree からのパスにする
+// `-` で始まる option と, with_value の option が取る値はそのまま渡す
+fn prefix_args<F: FileSystem>(
+    git: &Git<F>,
+    args: &[String],
+    with_value: &[&str],
+) -> io::Result<Vec<String>> {
+    let mut result = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if with_value.contains(&arg.as_str()) {
+            result.push(arg.clone());
+            result.extend(iter.next().cloned());
+        } else if arg.starts_with('-') {
+            result.push(arg.clone());
+        } else {
+            result.push(git.prefix_path(arg)?);
+        }
+    }
+
+    Ok(result)
+}
+
+// 設定されている remote のリポジトリを開く. file:// か手元のパスだけに対応する
+fn open_remote<F: FileSystem>(
+    git: &Git<F>,
+    cwd: &Path,
+    name: &str,
+) -> io::Result<Git<LinuxFileSystem>> {
+    let url = git
+        .remotes()?
+        .into_iter()
+        .find(|x| x.name == name)
+        .map(|x| x.url)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("'{}' does not appear to be a git remote", name),
+            )
+        })?;
+    open_path(cwd, &url)
+}
+
+// file:// か手元のパスで指定された別のリポジトリを開く
+fn open_path(cwd: &Path, url: &str) -> io::Result<Git<LinuxFileSystem>> {
+    let path = cwd.join(url.strip_prefix("file://").unwrap_or(url));
+    let fs = LinuxFileSystem::with_root(PathBuf::from("/"));
+    Git::open(fs, &path.to_string_lossy())
+}
+
+// git reflog と同じく新しい順に `<hash> <ref>@{n}: <message>` を出す
+fn print_reflog<F: FileSystem>(git: &Git<F>, ref_name: &str) -> io::Result<()> {
+    let entries = cmd::reflog(git, ref_name.to_string())?;
+    for (i, entry) in entries.iter().rev().enumerate() {
+        println!(
+            "{} {}@{{{}}}: {}",
+            &entry.new[..7.min(entry.new.len())],
+            ref_name,
+            i,
+            entry.message
+        );
+    }
+    Ok(())
+}
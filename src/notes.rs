@@ -0,0 +1,157 @@
+use crate::fs::FileSystem;
+use crate::object::blob::Blob;
+use crate::object::commit::{Commit, User};
+use crate::object::tree::{File, FileMode, Tree};
+use crate::object::GitObject;
+use crate::Git;
+use std::io;
+
+pub const NOTES_REF: &str = "refs/notes/commits";
+
+// commit に付けられた note を返す
+pub fn read<F: FileSystem>(git: &Git<F>, commit: &str) -> io::Result<Option<String>> {
+    let tree = match read_tree(git)? {
+        Some((_, tree)) => tree,
+        None => return Ok(None),
+    };
+
+    match tree.contents.iter().find(|x| x.name == commit) {
+        Some(file) => match git.read_git_object(&hex::encode(&file.hash).parse()?)? {
+            GitObject::Blob(blob) => Ok(Some(String::from_utf8_lossy(&blob.content).into_owned())),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("note for {} is not a blob", commit),
+            )),
+        },
+        None => Ok(None),
+    }
+}
+
+// commit の note を置き換える. note が None なら削除する
+// notes の tree を書き換えた commit を refs/notes/commits に積む
+pub fn write<F: FileSystem>(
+    git: &mut Git<F>,
+    commit: &str,
+    note: Option<String>,
+    author: User,
+    committer: User,
+) -> io::Result<()> {
+    let (parents, tree) = match read_tree(git)? {
+        Some((parent, tree)) => (vec![parent.parse()?], tree),
+        None => (Vec::new(), Tree::new(Vec::new())),
+    };
+
+    let existed = tree.contents.iter().any(|x| x.name == commit);
+    let mut contents = tree
+        .contents
+        .into_iter()
+        .filter(|x| x.name != commit)
+        .collect::<Vec<_>>();
+
+    let message = match note {
+        Some(note) => {
+            let blob = GitObject::Blob(Blob::new(note));
+            git.write_object(&blob)?;
+            contents.push(File::new(
+                FileMode::Regular,
+                commit.to_string(),
+                blob.calc_hash_with(git.hash_algo),
+            ));
+            "Notes added by 'git notes add'"
+        }
+        None if existed => "Notes removed by 'git notes remove'",
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("object {} has no note", commit),
+            ))
+        }
+    };
+
+    let tree = GitObject::Tree(Tree::new(contents));
+    git.write_object(&tree)?;
+    let notes = GitObject::Commit(Commit::new(
+        tree.calc_hash_with(git.hash_algo),
+        parents,
+        author,
+        committer,
+        message.to_string(),
+    ));
+    git.write_object(&notes)?;
+
+    git.filesystem.create_dir(git.git_path("refs/notes"))?;
+    git.update_ref(NOTES_REF.to_string(), &notes.calc_hash_with(git.hash_algo))
+}
+
+// notes の ref が指す commit とその tree
+fn read_tree<F: FileSystem>(git: &Git<F>) -> io::Result<Option<(String, Tree)>> {
+    let hash = match git.read_ref(NOTES_REF.to_string()) {
+        Ok(hash) => hash,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let commit = git.read_commit(&hash)?;
+    match git.read_git_object(&commit.tree)? {
+        GitObject::Tree(tree) => Ok(Some((hash, tree))),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a tree", commit.tree),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::inmem::InMemFileSystem;
+    use chrono::{FixedOffset, TimeZone};
+
+    fn user() -> User {
+        User::new(
+            String::from("rgit"),
+            String::from("rgit@example.com"),
+            FixedOffset::east(0).timestamp(0, 0),
+        )
+    }
+
+    #[test]
+    fn notes_write_read() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let commit = "aaa9501870880010b016554a65bc400ade0e1842";
+        assert_eq!(read(&git, commit).unwrap(), None);
+
+        write(
+            &mut git,
+            commit,
+            Some("first note\n".to_string()),
+            user(),
+            user(),
+        )
+        .unwrap();
+        assert_eq!(
+            read(&git, commit).unwrap(),
+            Some("first note\n".to_string())
+        );
+
+        // 書き換えると前の notes の commit が parent になる
+        let first = git.read_ref(NOTES_REF.to_string()).unwrap();
+        write(
+            &mut git,
+            commit,
+            Some("edited\n".to_string()),
+            user(),
+            user(),
+        )
+        .unwrap();
+        assert_eq!(read(&git, commit).unwrap(), Some("edited\n".to_string()));
+        let notes = git
+            .read_commit(&git.read_ref(NOTES_REF.to_string()).unwrap())
+            .unwrap();
+        assert_eq!(notes.parents, vec![first.parse().unwrap()]);
+
+        write(&mut git, commit, None, user(), user()).unwrap();
+        assert_eq!(read(&git, commit).unwrap(), None);
+        assert!(write(&mut git, commit, None, user(), user()).is_err());
+    }
+}
@@ -1,45 +1,89 @@
 use super::ObjectType;
+use crate::error::RgitError;
+use crate::hash::{HashAlgo, ObjectId};
 #[cfg(feature = "json")]
-use serde::Serialize;
-use sha1::{Digest, Sha1};
+use crate::json::{ToJson, Value};
 use std::fmt;
 
-#[cfg_attr(feature = "json", derive(Serialize))]
+// git が binary かどうかを決めるのに見る先頭の長さ
+const BINARY_CHECK_LEN: usize = 8000;
+
+// 中身は UTF-8 とは限らないのでバイト列のまま持つ
 pub struct Blob {
     pub size: usize,
-    pub content: String,
+    pub content: Vec<u8>,
 }
 
 impl Blob {
-    pub fn new(content: String) -> Self {
+    pub fn new<T: Into<Vec<u8>>>(content: T) -> Self {
+        let content = content.into();
         Self {
             size: content.len(),
             content,
         }
     }
 
+    // どんなバイト列も blob になる. 他の object と揃えて Option を返す
     pub fn from(bytes: &[u8]) -> Option<Self> {
-        let content = String::from_utf8(bytes.to_vec()).ok()?;
-        Some(Self {
-            size: content.len(),
-            content,
-        })
+        Self::try_from_bytes(bytes).ok()
+    }
+
+    // 他の object と揃えて Result を返すが, 失敗することは無い
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, RgitError> {
+        Ok(Self::new(bytes))
+    }
+
+    // git と同じく, 先頭に NUL を含むものを binary とみなす
+    pub fn is_binary(&self) -> bool {
+        self.content
+            .iter()
+            .take(BINARY_CHECK_LEN)
+            .any(|&x| x == b'\0')
     }
 
-    pub fn calc_hash(&self) -> Vec<u8> {
-        Vec::from(Sha1::digest(&self.as_bytes()).as_slice())
+    pub fn calc_hash(&self) -> ObjectId {
+        self.calc_hash_with(HashAlgo::default())
+    }
+
+    pub fn calc_hash_with(&self, algo: HashAlgo) -> ObjectId {
+        ObjectId::digest(algo, &self.as_bytes())
+    }
+
+    pub fn size(&self) -> usize {
+        self.content.len()
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
-        let header = format!("{} {}\0", ObjectType::Blob.to_string(), self.size);
-        let store = format!("{}{}", header, self.to_string());
-        Vec::from(store.as_bytes())
+        let header = format!("{} {}\0", ObjectType::Blob, self.size);
+        [header.as_bytes(), &self.content].concat()
     }
 }
 
+// binary は中身の代わりに大きさを示し, UTF-8 でないテキストは読めない文字を置き換えて表示する
+// 中身をそのまま出したい時は content を使う
 impl fmt::Display for Blob {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.content)
+        if self.is_binary() {
+            write!(f, "<binary blob, {} bytes>", self.size)
+        } else {
+            write!(f, "{}", String::from_utf8_lossy(&self.content))
+        }
+    }
+}
+
+// UTF-8 のテキストはそのまま文字列に, それ以外は base64 にして encoding で区別する
+#[cfg(feature = "json")]
+impl ToJson for Blob {
+    fn to_json(&self) -> Value {
+        let (encoding, content) = match std::str::from_utf8(&self.content) {
+            Ok(text) if !self.is_binary() => ("utf-8", text.to_string()),
+            _ => ("base64", crate::json::base64(&self.content)),
+        };
+        Value::object(vec![
+            ("size", self.size.into()),
+            ("encoding", encoding.into()),
+            ("content", content.into()),
+        ])
     }
 }
 
@@ -52,7 +96,7 @@ mod tests {
         let b = Blob::new(String::from("hello"));
 
         assert_eq!(b.size, 5);
-        assert_eq!(b.content, "hello");
+        assert_eq!(b.content, b"hello");
     }
 
     #[test]
@@ -61,13 +105,13 @@ mod tests {
         assert!(ob.is_some());
         let b = ob.unwrap();
         assert_eq!(b.size, 0);
-        assert_eq!(b.content, "");
+        assert_eq!(b.content, b"");
 
         let ob = Blob::from(b"aaabbbccc");
         assert!(ob.is_some());
         let b = ob.unwrap();
         assert_eq!(b.size, 9);
-        assert_eq!(b.content, "aaabbbccc");
+        assert_eq!(b.content, b"aaabbbccc");
     }
 
     #[test]
@@ -76,7 +120,7 @@ mod tests {
         assert!(ob.is_some());
         let b = ob.unwrap();
         assert_eq!(b.size, 9);
-        assert_eq!(b.content, "aaabbbccc");
+        assert_eq!(b.content, b"aaabbbccc");
         assert_eq!(b.as_bytes(), b"blob 9\0aaabbbccc");
     }
 
@@ -86,7 +130,20 @@ mod tests {
         let ob = Blob::from(b"aaabbbccc");
         let b = ob.unwrap();
         let hash = Vec::from(Sha1::digest(b"blob 9\0aaabbbccc").as_slice());
-        assert_eq!(b.calc_hash(), hash);
+        assert_eq!(b.calc_hash().as_bytes(), hash);
+    }
+
+    #[test]
+    fn calc_hash_with() {
+        let b = Blob::from(b"hello, git").unwrap();
+        assert_eq!(
+            hex::encode(b.calc_hash_with(HashAlgo::Sha1)),
+            "3edbc45b9a7f744c2345cd2cd073c3de091341ac"
+        );
+        assert_eq!(
+            hex::encode(b.calc_hash_with(HashAlgo::Sha256)),
+            "ec3c2c93acca398bbbc8e91467c9fa219c8ceb03ea8f858c0a2dd351c9505ba0"
+        );
     }
 
     #[test]
@@ -95,4 +152,44 @@ mod tests {
         let b = ob.unwrap();
         assert_eq!(b.to_string(), "aaabbbccc");
     }
+
+    #[test]
+    fn binary() {
+        // git hash-object で求めた hash と一致する
+        let empty = Blob::from(b"").unwrap();
+        assert_eq!(
+            hex::encode(empty.calc_hash()),
+            "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"
+        );
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\xff\xfe";
+        let b = Blob::from(png).unwrap();
+        assert_eq!(b.size, 18);
+        assert_eq!(b.as_bytes(), [b"blob 18\0".as_ref(), png].concat());
+        assert_eq!(
+            hex::encode(b.calc_hash()),
+            "ac46b867e6e844c117dd10ed279bb55d6c934c28"
+        );
+
+        assert!(b.is_binary());
+        assert_eq!(b.to_string(), "<binary blob, 18 bytes>");
+        // NUL が無ければテキストとして, 読めない文字は置き換えて表示する
+        let b = Blob::new(b"caf\xe9\n".to_vec());
+        assert!(!b.is_binary());
+        assert_eq!(b.to_string(), "caf\u{fffd}\n");
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn blob_json() {
+        let json = crate::json::to_string_pretty(&Blob::new("hi\n"));
+        assert_eq!(
+            json,
+            "{\n  \"size\": 3,\n  \"encoding\": \"utf-8\",\n  \"content\": \"hi\\n\"\n}"
+        );
+        let json = crate::json::to_string_pretty(&Blob::new(b"\0\xffab".to_vec()));
+        assert_eq!(
+            json,
+            "{\n  \"size\": 4,\n  \"encoding\": \"base64\",\n  \"content\": \"AP9hYg==\"\n}"
+        );
+    }
 }
@@ -1,138 +1,261 @@
 use super::ObjectType;
-use chrono::{DateTime, FixedOffset, TimeZone, Utc};
+use crate::error::RgitError;
+use crate::hash::{HashAlgo, ObjectId};
 #[cfg(feature = "json")]
-use serde::Serialize;
-use sha1::{Digest, Sha1};
+use crate::json::{ToJson, Value};
+use chrono::{DateTime, FixedOffset, TimeZone, Utc};
 use std::fmt;
 
-#[cfg_attr(feature = "json", derive(Serialize))]
 #[derive(Debug)]
 pub struct Commit {
-    pub tree: String,
-    pub parent: Option<String>,
+    pub tree: ObjectId,
+    // merge commit は複数の parent を持つ. 最初の commit は空
+    pub parents: Vec<ObjectId>,
     pub author: User,
-    pub comitter: User,
-    pub message: String,
+    pub committer: User,
+    // 古い rgit は `comitter` と綴っていた. 読んだ時の綴りのまま書き戻して hash を保つ
+    pub legacy_committer: bool,
+    // `encoding` header. None なら UTF-8
+    pub encoding: Option<String>,
+    // それ以外の header (gpgsig など). 値は継続行を改行で繋いだもの
+    pub extra_headers: Vec<(String, Vec<u8>)>,
+    // UTF-8 とは限らないのでバイト列のまま持つ
+    pub message: Vec<u8>,
 }
 
 impl Commit {
     pub fn new(
-        tree: String,
-        parent: Option<String>,
+        tree: ObjectId,
+        parents: Vec<ObjectId>,
         author: User,
-        comitter: User,
+        committer: User,
         message: String,
     ) -> Self {
+        // git と同じく message は改行で終える
+        let mut message = message.into_bytes();
+        if !message.ends_with(b"\n") {
+            message.push(b'\n');
+        }
+
         Self {
             tree,
-            parent,
+            parents,
             author,
-            comitter,
+            committer,
+            legacy_committer: false,
+            encoding: None,
+            extra_headers: Vec::new(),
             message,
         }
     }
 
+    pub fn with_encoding(mut self, encoding: String) -> Self {
+        self.encoding = Some(encoding);
+        self
+    }
+
     pub fn from(bytes: &[u8]) -> Option<Self> {
-        // 各プロパティが改行区切り
-        // commit message の間に空行が含まれるので空文字列を filter
-        let mut iter = bytes.split(|&x| x == b'\n').filter(|x| x != b"");
+        Self::try_from_bytes(bytes).ok()
+    }
 
-        let tree = iter
-            .next()
-            .map(|x| {
-                x.splitn(2, |&x| x == b' ')
-                    .skip(1) // 最初は tree で決まっているので不要
-                    .flatten()
-                    .map(|&x| x)
-                    .collect::<Vec<_>>()
-            })
-            .and_then(|x| String::from_utf8(x).ok())?;
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, RgitError> {
+        // header と message は最初の空行で区切られている
+        let pos = bytes
+            .windows(2)
+            .position(|x| x == b"\n\n")
+            .ok_or(RgitError::Truncated("commit header"))?;
+        let (header, body) = (&bytes[..pos], &bytes[(pos + 2)..]);
 
-        let parent = &iter
-            .next()
-            .map(|x| {
-                x.splitn(2, |&x| x == b' ')
-                    .map(Vec::from)
-                    .map(|x| String::from_utf8(x).ok().unwrap_or_default())
-                    .collect::<Vec<_>>()
-            })
-            .ok_or(Vec::new())
-            .and_then(|x| match x[0].as_str() {
-                "parent" => Ok(x[1].clone()),
-                _ => Err([x[0].as_bytes(), b" ", x[1].as_bytes()].concat()), // parent じゃないなら元の形式に戻して Err で返す
-            });
-
-        let author = match parent {
-            Ok(_) => iter.next().map(|x| Vec::from(x)), // parent なら iter から
-            Err(v) => Some(v.clone()),                  // Err ならその値を使う
-        }
-        .map(|x| {
-            x.splitn(2, |&x| x == b' ')
-                .skip(1)
-                .flatten()
-                .map(|&x| x)
-                .collect::<Vec<_>>()
-        })
-        .and_then(|x| User::from(x.as_slice()))?;
+        let mut iter = parse_headers(header).into_iter().peekable();
 
-        let comitter = iter
+        // tree, author, committer は位置で決まっているので key は見ない
+        // committer だけは古い綴りかどうかを覚えておく
+        let (_, tree) = iter
             .next()
-            .map(|x| {
-                x.splitn(2, |&x| x == b' ')
-                    .skip(1)
-                    .flatten()
-                    .map(|&x| x)
-                    .collect::<Vec<_>>()
-            })
-            .and_then(|x| User::from(x.as_slice()))?;
-
-        let message = iter
-            .next()
-            .map(Vec::from)
-            .and_then(|x| String::from_utf8(x).ok())?;
+            .ok_or_else(|| RgitError::BadHeader(String::from("missing tree")))?;
+        let tree = String::from_utf8(tree).map_err(|_| RgitError::InvalidUtf8("tree"))?;
+        let tree = ObjectId::try_from_hex(&tree)?;
+
+        // 続いている parent を全て読む
+        let mut parents = Vec::new();
+        while let Some((key, _)) = iter.peek() {
+            if key != "parent" {
+                break;
+            }
+            let (_, value) = iter.next().unwrap();
+            let parent = String::from_utf8(value).map_err(|_| RgitError::InvalidUtf8("parent"))?;
+            parents.push(ObjectId::try_from_hex(&parent)?);
+        }
 
-        Some(Self::new(
+        let mut user = |name: &str| {
+            let (key, value) = iter
+                .next()
+                .ok_or_else(|| RgitError::BadHeader(format!("missing {}", name)))?;
+            let user = User::from(&value).ok_or_else(|| {
+                RgitError::BadHeader(format!(
+                    "invalid {} {:?}",
+                    name,
+                    String::from_utf8_lossy(&value)
+                ))
+            })?;
+            Ok((key, user))
+        };
+        let (_, author) = user("author")?;
+        let (key, committer) = user("committer")?;
+        let legacy_committer = key == "comitter";
+
+        // UTF-8 として読めない encoding は extra header に残して bytes を失わないようにする
+        let encoding = match iter.peek() {
+            Some((key, value)) if key == "encoding" && std::str::from_utf8(value).is_ok() => iter
+                .next()
+                .and_then(|(_, value)| String::from_utf8(value).ok()),
+            _ => None,
+        };
+
+        // gpgsig や mergetag など残りの header は順番を保ったまま持っておく
+        let extra_headers = iter.collect();
+
+        // 空行や末尾の改行も含めてそのまま持つ
+        let message = body.to_vec();
+
+        Ok(Self {
             tree,
-            parent.clone().ok(),
+            parents,
             author,
-            comitter,
+            committer,
+            legacy_committer,
+            encoding,
+            extra_headers,
             message,
-        ))
+        })
+    }
+
+    #[deprecated(note = "use the committer field")]
+    pub fn comitter(&self) -> &User {
+        &self.committer
+    }
+
+    // 名前が一致する最初の extra header の値
+    // 複数行の値は継続行の先頭の空白を除いて改行で繋いだもの
+    pub fn header(&self, key: &str) -> Option<&[u8]> {
+        self.extra_headers
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_slice())
     }
 
-    pub fn calc_hash(&self) -> Vec<u8> {
-        Vec::from(Sha1::digest(&self.as_bytes()).as_slice())
+    // 署名された commit の gpgsig header. 署名の検証に使う
+    pub fn gpgsig(&self) -> Option<&[u8]> {
+        self.header("gpgsig")
+    }
+
+    // encoding header に従って message を文字列にする
+    pub fn message_text(&self) -> String {
+        match self.encoding.as_deref().map(|x| x.to_ascii_uppercase()) {
+            Some(ref x) if x == "ISO-8859-1" || x == "LATIN1" => {
+                self.message.iter().map(|&x| x as char).collect()
+            }
+            _ => String::from_utf8_lossy(&self.message).into_owned(),
+        }
+    }
+
+    pub fn calc_hash(&self) -> ObjectId {
+        self.calc_hash_with(HashAlgo::default())
+    }
+
+    pub fn calc_hash_with(&self, algo: HashAlgo) -> ObjectId {
+        ObjectId::digest(algo, &self.as_bytes())
+    }
+
+    // header を除いた中身の長さ
+    pub fn size(&self) -> usize {
+        self.as_body_bytes().len()
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
-        let content = format!("{}", self);
-        let header = format!("{} {}\0", ObjectType::Commit.to_string(), content.len());
-        let val = format!("{}{}", header, content);
+        let content = self.as_body_bytes();
+        let header = format!("{} {}\0", ObjectType::Commit, content.len());
 
-        Vec::from(val.as_bytes())
+        [header.as_bytes(), &content].concat()
     }
-}
 
-impl fmt::Display for Commit {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let tree = format!("{} {}", ObjectType::Tree.to_string(), self.tree);
+    // header を除いた commit の中身. 読んだ commit なら元の bytes と同じになる
+    pub fn as_body_bytes(&self) -> Vec<u8> {
+        let tree = format!("{} {}", ObjectType::Tree, self.tree);
         let parent = self
-            .parent
-            .clone()
+            .parents
+            .iter()
             .map(|x| format!("parent {}\n", x))
-            .unwrap_or_default();
+            .collect::<String>();
         let author = format!("author {}", self.author);
-        let comitter = format!("comitter {}", self.comitter);
+        let key = if self.legacy_committer {
+            "comitter"
+        } else {
+            "committer"
+        };
+        let committer = format!("{} {}", key, self.committer);
+        let encoding = self
+            .encoding
+            .clone()
+            .map(|x| format!("encoding {}\n", x))
+            .unwrap_or_default();
 
-        write!(
-            f,
-            "{}\n{}{}\n{}\n\n{}\n",
-            tree, parent, author, comitter, self.message,
-        )
+        let header = format!(
+            "{}\n{}{}\n{}\n{}",
+            tree, parent, author, committer, encoding
+        );
+
+        let mut buf = header.into_bytes();
+        for (key, value) in &self.extra_headers {
+            buf.extend_from_slice(key.as_bytes());
+            buf.push(b' ');
+            // 継続行は先頭に空白を付けて戻す
+            for (i, line) in value.split(|&x| x == b'\n').enumerate() {
+                if i > 0 {
+                    buf.extend_from_slice(b"\n ");
+                }
+                buf.extend_from_slice(line);
+            }
+            buf.push(b'\n');
+        }
+
+        [buf.as_slice(), b"\n", &self.message].concat()
+    }
+}
+
+// header の各行を key と value に分ける
+// 空白で始まる行は直前の header の値の続き
+fn parse_headers(bytes: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let mut headers: Vec<(String, Vec<u8>)> = Vec::new();
+    // header が無い時に空の header を1つ読んでしまわないようにする
+    if bytes.is_empty() {
+        return headers;
+    }
+    for line in bytes.split(|&x| x == b'\n') {
+        match line.strip_prefix(b" ") {
+            Some(rest) => {
+                if let Some((_, value)) = headers.last_mut() {
+                    value.push(b'\n');
+                    value.extend_from_slice(rest);
+                }
+            }
+            None => {
+                let mut iter = line.splitn(2, |&x| x == b' ');
+                let key = String::from_utf8_lossy(iter.next().unwrap_or_default()).into_owned();
+                let value = iter.next().unwrap_or_default().to_vec();
+                headers.push((key, value));
+            }
+        }
+    }
+    headers
+}
+
+impl fmt::Display for Commit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.as_body_bytes()))
     }
 }
 
-#[cfg_attr(feature = "json", derive(Serialize))]
 #[derive(Clone, Debug)]
 pub struct User {
     pub name: String,
@@ -148,22 +271,16 @@ impl User {
     pub fn from(bytes: &[u8]) -> Option<Self> {
         let name = String::from_utf8(
             bytes
-                .into_iter()
+                .iter()
                 .take_while(|&&x| x != b'<') // 関数が true を返す間の要素を得る
-                .map(|&x| x)
+                .copied()
                 .collect(),
         )
         .map(|x| String::from(x.trim())) // 最後の空白はいらない
         .ok()?;
 
-        let info = String::from_utf8(
-            bytes
-                .into_iter()
-                .skip_while(|&&x| x != b'<')
-                .map(|&x| x)
-                .collect(),
-        )
-        .ok()?;
+        let info =
+            String::from_utf8(bytes.iter().skip_while(|&&x| x != b'<').copied().collect()).ok()?;
 
         let mut into_iter = info.splitn(3, " "); // <EMAIL> TIME_STAMP OFFSET の3つだけ
 
@@ -171,16 +288,7 @@ impl User {
             .next()
             .map(|x| String::from(x.trim_matches(|x| x == '<' || x == '>')))?;
         let ts = Utc.timestamp(into_iter.next().and_then(|x| x.parse::<i64>().ok())?, 0);
-        let offset = into_iter
-            .next()
-            .and_then(|x| x.parse::<i32>().ok())
-            .map(|x| {
-                if x < 0 {
-                    FixedOffset::west(x / 100 * 60 * 60)
-                } else {
-                    FixedOffset::east(x / 100 * 60 * 60)
-                }
-            })?;
+        let offset = into_iter.next().and_then(parse_offset)?;
 
         Some(Self::new(
             name,
@@ -190,15 +298,77 @@ impl User {
     }
 }
 
+// JSON の key は struct の field 名に引きずられないように固定する
+// message や header の値は UTF-8 として読めない所を置き換えた文字列にする
+#[cfg(feature = "json")]
+impl ToJson for Commit {
+    fn to_json(&self) -> Value {
+        let extra_headers = self
+            .extra_headers
+            .iter()
+            .map(|(k, v)| {
+                Value::Array(vec![
+                    k.as_str().into(),
+                    String::from_utf8_lossy(v).into_owned().into(),
+                ])
+            })
+            .collect::<Vec<_>>();
+        Value::object(vec![
+            ("tree", self.tree.to_json()),
+            (
+                "parents",
+                Value::Array(self.parents.iter().map(ToJson::to_json).collect()),
+            ),
+            ("author", self.author.to_json()),
+            ("committer", self.committer.to_json()),
+            ("encoding", self.encoding.clone().into()),
+            ("extra_headers", extra_headers.into()),
+            (
+                "message",
+                String::from_utf8_lossy(&self.message).into_owned().into(),
+            ),
+        ])
+    }
+}
+
+// 時刻は git と同じ unix time と "+0900" 形式の timezone に分ける
+#[cfg(feature = "json")]
+impl ToJson for User {
+    fn to_json(&self) -> Value {
+        Value::object(vec![
+            ("name", self.name.as_str().into()),
+            ("email", self.email.as_str().into()),
+            ("timestamp", self.ts.timestamp().into()),
+            ("timezone", self.ts.format("%z").to_string().into()),
+        ])
+    }
+}
+
+// "+0900" や "-0530" の形式の timezone. 符号の後は時と分
+fn parse_offset(s: &str) -> Option<FixedOffset> {
+    let (sign, digits) = match s.strip_prefix('-') {
+        Some(digits) => (-1, digits),
+        None => (1, s.strip_prefix('+').unwrap_or(s)),
+    };
+    if digits.is_empty() || !digits.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    let value = digits.parse::<i32>().ok()?;
+    FixedOffset::east_opt(sign * (value / 100 * 60 * 60 + value % 100 * 60))
+}
+
 impl fmt::Display for User {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let offset = self.ts.offset().local_minus_utc();
         write!(
             f,
-            "{} <{}> {} {:+05}",
+            "{} <{}> {} {}{:02}{:02}",
             self.name,
             self.email,
             self.ts.timestamp(),
-            self.ts.offset().local_minus_utc() / 36
+            if offset < 0 { '-' } else { '+' },
+            offset.abs() / (60 * 60),
+            offset.abs() / 60 % 60
         )
     }
 }
@@ -228,8 +398,6 @@ mod tests {
         let ou = User::from(b"user <user@example.com> 0");
         assert!(ou.is_none());
 
-        // TODO: offset のテストが不十分
-
         // west
         let ou = User::from(b"user <user@example.com> 0 10");
         assert!(ou.is_some());
@@ -243,6 +411,64 @@ mod tests {
         assert!(ou.is_some());
         let u = ou.unwrap();
         assert_eq!(u.ts, ts);
+
+        // 時と分に分けて読み, 同じ形式で書き戻す
+        for (offset, seconds) in [("+0900", 9 * 3600), ("-0500", -5 * 3600), ("+0545", 20700)] {
+            let line = format!("user <user@example.com> 1609642799 {}", offset);
+            let u = User::from(line.as_bytes()).unwrap();
+            assert_eq!(u.ts.offset().local_minus_utc(), seconds);
+            assert_eq!(u.ts.timestamp(), 1609642799);
+            assert_eq!(u.to_string(), line);
+        }
+        assert!(User::from(b"user <user@example.com> 0 +09:00").is_none());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn commit_json() {
+        let bytes = [
+            "tree 01a0c85dd05755281466d29983dfcb15889e1a64",
+            "parent 8a2b9dc3e1e1a1dd4fb2bbeb9f7e1d1c8a0b6a07",
+            "author author <author@example.com> 1609642799 +0900",
+            "committer committer <committer@example.com> 1609642799 +0900",
+            "",
+            "first commit\n",
+        ]
+        .join("\n");
+        let commit = Commit::from(bytes.as_bytes()).unwrap();
+        let value = commit.to_json();
+
+        assert_eq!(
+            value.keys(),
+            vec![
+                "tree",
+                "parents",
+                "author",
+                "committer",
+                "encoding",
+                "extra_headers",
+                "message"
+            ]
+        );
+        let committer = value.get("committer").unwrap();
+        assert_eq!(
+            committer.keys(),
+            vec!["name", "email", "timestamp", "timezone"]
+        );
+        assert_eq!(
+            committer.get("timezone"),
+            Some(&crate::json::Value::String("+0900".to_string()))
+        );
+        assert_eq!(
+            value.get("message"),
+            Some(&crate::json::Value::String("first commit\n".to_string()))
+        );
+
+        let json = crate::json::to_string_pretty(&commit);
+        assert!(json.starts_with(
+            "{\n  \"tree\": \"01a0c85dd05755281466d29983dfcb15889e1a64\",\n  \"parents\": [\n    \"8a2b"
+        ));
+        assert!(json.contains("\"timestamp\": 1609642799,"));
     }
 
     #[test]
@@ -257,7 +483,7 @@ mod tests {
         assert!(oc.is_none());
 
         // first commit
-        let cs = vec![
+        let cs = [
             "tree 01a0c85dd05755281466d29983dfcb15889e1a64",
             "author author <author@example.com> 1609642799 +0900",
             "comitter comitter <comitter@example.com> 1609642799 +0900",
@@ -268,8 +494,8 @@ mod tests {
         let oc = Commit::from(cs.as_bytes());
         assert!(oc.is_some());
         let c = oc.unwrap();
-        assert_eq!(c.tree, "01a0c85dd05755281466d29983dfcb15889e1a64");
-        assert!(c.parent.is_none());
+        assert_eq!(c.tree.to_hex(), "01a0c85dd05755281466d29983dfcb15889e1a64");
+        assert!(c.parents.is_empty());
 
         let ts = DateTime::parse_from_rfc3339("2021-01-03T11:59:59+09:00").unwrap();
         let author = User::new(
@@ -285,11 +511,11 @@ mod tests {
         assert_eq!(c.author.name, author.name);
         assert_eq!(c.author.email, author.email);
         assert_eq!(c.author.ts, author.ts);
-        assert_eq!(c.comitter.name, comitter.name);
-        assert_eq!(c.comitter.email, comitter.email);
-        assert_eq!(c.comitter.ts, comitter.ts);
+        assert_eq!(c.committer.name, comitter.name);
+        assert_eq!(c.committer.email, comitter.email);
+        assert_eq!(c.committer.ts, comitter.ts);
 
-        let cs = vec![
+        let cs = [
             "tree adb7e67378d99ab8125f156442999f187db3d1a3",
             "parent 01a0c85dd05755281466d29983dfcb15889e1a64",
             "author author <author@example.com> 1609642799 +0900",
@@ -301,16 +527,281 @@ mod tests {
         let oc = Commit::from(cs.as_bytes());
         assert!(oc.is_some());
         let c = oc.unwrap();
-        assert_eq!(c.tree, "adb7e67378d99ab8125f156442999f187db3d1a3");
+        assert_eq!(c.tree.to_hex(), "adb7e67378d99ab8125f156442999f187db3d1a3");
+        assert_eq!(
+            c.parents,
+            vec!["01a0c85dd05755281466d29983dfcb15889e1a64".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn commit_octopus_merge() {
+        // git merge b c で作った3つの parent を持つ commit (git の hash は 73627541)
+        let git_bytes = [
+            "tree d11b5fac254c4b7a5a8e078cbad43ba15d6494ff",
+            "parent 16c3ffc5d56f894c65b45fec8b9a5d8b9eee10dd",
+            "parent bbef010aedcf0dfcf612b9b06eadeef924a0800b",
+            "parent cf44b0b459d53e2ce0b5c37a9db9bf007dc5e83a",
+            "author author <author@example.com> 1609642799 +0900",
+            "committer committer <committer@example.com> 1609642799 +0900",
+            "",
+            "octopus",
+            "",
+        ]
+        .join("\n");
+        let parents = [
+            "16c3ffc5d56f894c65b45fec8b9a5d8b9eee10dd",
+            "bbef010aedcf0dfcf612b9b06eadeef924a0800b",
+            "cf44b0b459d53e2ce0b5c37a9db9bf007dc5e83a",
+        ]
+        .iter()
+        .map(|x| x.parse().unwrap())
+        .collect::<Vec<ObjectId>>();
+        let c = Commit::from(git_bytes.as_bytes()).unwrap();
+        assert_eq!(c.tree.to_hex(), "d11b5fac254c4b7a5a8e078cbad43ba15d6494ff");
+        assert_eq!(c.parents, parents);
+        assert_eq!(c.author.name, "author");
+        assert_eq!(c.committer.name, "committer");
+        assert!(c.extra_headers.is_empty());
+        assert_eq!(c.message, b"octopus\n");
+
+        // parent は順番どおりに書き戻すので hash が変わらない
+        let object = format!("commit {}\0{}", git_bytes.len(), git_bytes);
+        assert_eq!(c.as_bytes(), object.as_bytes());
+        assert_eq!(
+            hex::encode(c.calc_hash()),
+            "7362754106a3dbc8e6126c5919f92510e4ba31ab"
+        );
+
+        let c = Commit::new(
+            c.tree,
+            parents,
+            c.author,
+            c.committer,
+            "octopus".to_string(),
+        );
+        assert_eq!(c.as_bytes(), object.as_bytes());
+    }
+
+    #[test]
+    fn commit_committer_spelling() {
+        let bytes = [
+            "tree 01a0c85dd05755281466d29983dfcb15889e1a64",
+            "author author <author@example.com> 1609642799 +0900",
+            "committer committer <committer@example.com> 1609642799 +0900",
+            "",
+            "first commit",
+            "",
+        ]
+        .join("\n");
+        let c = Commit::from(bytes.as_bytes()).unwrap();
+        assert_eq!(c.committer.name, "committer");
+        assert!(!c.legacy_committer);
+        assert_eq!(c.as_body_bytes(), bytes.as_bytes());
+
+        // 古い rgit が書いた commit も読めて, 同じ bytes に書き戻せる
+        let legacy = bytes.replace("\ncommitter ", "\ncomitter ");
+        let c = Commit::from(legacy.as_bytes()).unwrap();
+        assert_eq!(c.committer.name, "committer");
+        assert!(c.legacy_committer);
+        assert_eq!(c.as_body_bytes(), legacy.as_bytes());
+
+        // 新しく作る commit は git と同じ綴りになる
+        let c = Commit::new(
+            c.tree,
+            Vec::new(),
+            c.author,
+            c.committer,
+            "first commit".to_string(),
+        );
+        assert!(!c.legacy_committer);
+        assert_eq!(c.as_body_bytes(), bytes.as_bytes());
+    }
+
+    #[test]
+    fn commit_git_interop() {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+
+        let dir = std::env::temp_dir().join(format!("rgit-commit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let git = |args: &[&str], input: &[u8]| {
+            let mut child = Command::new("git")
+                .arg("-C")
+                .arg(&dir)
+                .args(args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()?;
+            child.stdin.take().unwrap().write_all(input)?;
+            let output = child.wait_with_output()?;
+            Ok::<_, std::io::Error>((output.status.success(), output.stdout))
+        };
+        let user = User::from(b"rgit <rgit@example.com> 1609642799 +0900").unwrap();
+        let c = Commit::new(
+            "4b825dc642cb6eb9a060e54bf8d69288fbee4904".parse().unwrap(),
+            Vec::new(),
+            user.clone(),
+            user,
+            "first".to_string(),
+        );
+        let result = git(&["init", "-q"], b"").and_then(|_| {
+            let (_, hash) = git(
+                &["hash-object", "-t", "commit", "-w", "--stdin"],
+                &c.as_body_bytes(),
+            )?;
+            let hash = String::from_utf8_lossy(&hash).trim().to_string();
+            let (ok, printed) = git(&["cat-file", "-p", &hash], b"")?;
+            // header の綴りが違うと git は committer を読めず空になる
+            let (_, committer) = git(&["log", "-1", "--format=%cn <%ce> %ct", &hash], b"")?;
+            Ok((ok, hash, printed, committer))
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+        let (ok, hash, printed, committer) = match result {
+            Ok(x) => x,
+            // git が使えない
+            Err(_) => return,
+        };
+
+        assert!(ok);
+        assert_eq!(hash, hex::encode(c.calc_hash()));
+        assert_eq!(printed, c.as_body_bytes());
+        assert_eq!(committer, b"rgit <rgit@example.com> 1609642799\n");
+    }
+
+    // 本物の git で作った commit を `git cat-file --batch` の形式で並べたもの
+    // merge, octopus merge, 署名, encoding, 空の message などを含む
+    fn commit_corpus() -> Vec<(String, Vec<u8>)> {
+        let mut rest = include_bytes!("../../tests/fixtures/commits.batch").as_ref();
+        let mut commits = Vec::new();
+        while !rest.is_empty() {
+            let end = rest.iter().position(|&x| x == b'\n').unwrap();
+            let header = std::str::from_utf8(&rest[..end]).unwrap();
+            let mut iter = header.split(' ');
+            let hash = iter.next().unwrap().to_string();
+            assert_eq!(iter.next(), Some("commit"));
+            let size = iter.next().unwrap().parse::<usize>().unwrap();
+            let body = &rest[(end + 1)..];
+            commits.push((hash, body[..size].to_vec()));
+            rest = &body[(size + 1)..];
+        }
+        commits
+    }
+
+    #[test]
+    fn commit_corpus_round_trip() {
+        let commits = commit_corpus();
+        assert_eq!(commits.len(), 16);
+        for (hash, bytes) in &commits {
+            let c = Commit::from(bytes).unwrap();
+            assert_eq!(&c.as_body_bytes(), bytes, "{}", hash);
+            assert_eq!(&hex::encode(c.calc_hash()), hash);
+        }
+
+        let parsed = commits
+            .iter()
+            .map(|(_, bytes)| Commit::from(bytes).unwrap())
+            .collect::<Vec<_>>();
+        let max_parents = parsed.iter().map(|x| x.parents.len()).max();
+        assert_eq!(max_parents, Some(3));
+        assert!(parsed.iter().any(|x| x.gpgsig().is_some()));
+        assert!(parsed
+            .iter()
+            .any(|x| x.encoding.as_deref() == Some("ISO-8859-1")));
+        assert!(parsed.iter().any(|x| x.message.is_empty()));
+        assert!(parsed.iter().any(|x| !x.message.ends_with(b"\n")));
+        assert!(parsed.iter().any(|x| x
+            .message
+            .starts_with(b"parent 0000000000000000000000000000000000000000")));
+    }
+
+    #[test]
+    fn commit_try_from_bytes() {
+        let tree = "tree 01a0c85dd05755281466d29983dfcb15889e1a64\n";
+        let author = "author author <author@example.com> 1609642799 +0900\n";
+        let comitter = "comitter comitter <comitter@example.com> 1609642799 +0900\n";
+        let err = |bytes: &[u8]| Commit::try_from_bytes(bytes).err();
+
+        // message との区切りの空行が無い
+        assert_eq!(
+            err(tree.as_bytes()),
+            Some(RgitError::Truncated("commit header"))
+        );
+        assert_eq!(
+            err(b"\n\nmessage"),
+            Some(RgitError::BadHeader(String::from("missing tree")))
+        );
+        assert_eq!(err(b"tree \xff\n\n"), Some(RgitError::InvalidUtf8("tree")));
+        assert_eq!(
+            err(&[tree.as_bytes(), b"parent \xff\n\n"].concat()),
+            Some(RgitError::InvalidUtf8("parent"))
+        );
+        assert_eq!(
+            err(format!("{}\n", tree).as_bytes()),
+            Some(RgitError::BadHeader(String::from("missing author")))
+        );
         assert_eq!(
-            c.parent,
-            Some(String::from("01a0c85dd05755281466d29983dfcb15889e1a64"))
+            err(format!("{}author nobody\n\n", tree).as_bytes()),
+            Some(RgitError::BadHeader(String::from(
+                "invalid author \"nobody\""
+            )))
+        );
+        assert_eq!(
+            err(format!("{}{}\n", tree, author).as_bytes()),
+            Some(RgitError::BadHeader(String::from("missing committer")))
+        );
+        assert!(
+            Commit::try_from_bytes(format!("{}{}{}\n", tree, author, comitter).as_bytes()).is_ok()
+        );
+    }
+
+    #[test]
+    fn commit_encoding() {
+        let bytes = [
+            b"tree adb7e67378d99ab8125f156442999f187db3d1a3\n".as_ref(),
+            b"author author <author@example.com> 1609642799 +0900\n",
+            b"comitter comitter <comitter@example.com> 1609642799 +0900\n",
+            b"encoding ISO-8859-1\n",
+            b"\n",
+            b"caf\xe9\n",
+        ]
+        .concat();
+
+        let c = Commit::from(&bytes).unwrap();
+        assert_eq!(c.encoding, Some(String::from("ISO-8859-1")));
+        assert_eq!(c.message, b"caf\xe9\n");
+        assert_eq!(c.message_text(), "caf\u{e9}\n");
+
+        let content = &c.as_bytes()[format!("commit {}\0", bytes.len()).len()..];
+        assert_eq!(content, bytes.as_slice());
+    }
+
+    #[test]
+    fn commit_invalid_encoding() {
+        let bytes = [
+            b"tree adb7e67378d99ab8125f156442999f187db3d1a3\n".as_ref(),
+            b"author author <author@example.com> 1609642799 +0900\n",
+            b"committer committer <committer@example.com> 1609642799 +0900\n",
+            b"encoding \xff\n",
+            b"\n",
+            b"m\n",
+        ]
+        .concat();
+
+        let c = Commit::from(&bytes).unwrap();
+        assert!(c.encoding.is_none());
+        assert_eq!(c.header("encoding").unwrap(), b"\xff".as_ref());
+        assert!(c.gpgsig().is_none());
+        assert_eq!(
+            hex::encode(c.calc_hash()),
+            "c19f8c5dc69b932dcd2f48a46ec80298eb55ce13"
         );
     }
 
     #[test]
     fn commit_as_bytes() {
-        let cs = vec![
+        let cs = [
             "tree adb7e67378d99ab8125f156442999f187db3d1a3",
             "parent 01a0c85dd05755281466d29983dfcb15889e1a64",
             "author author <author@example.com> 1609642799 +0900",
@@ -321,7 +812,7 @@ mod tests {
         .join("\n");
         let c = Commit::from(cs.as_bytes()).unwrap();
 
-        let content = format!("{}", c.to_string());
+        let content = format!("{}", c);
         let header = format!("commit {}\0", content.len());
 
         assert_eq!(c.as_bytes(), format!("{}{}", header, content).into_bytes(),);
@@ -329,7 +820,7 @@ mod tests {
 
     #[test]
     fn commit_to_string() {
-        let cs = vec![
+        let cs = [
             "tree adb7e67378d99ab8125f156442999f187db3d1a3",
             "parent 01a0c85dd05755281466d29983dfcb15889e1a64",
             "author author <author@example.com> 1609642799 +0900",
@@ -339,6 +830,108 @@ mod tests {
         ]
         .join("\n");
         let c = Commit::from(cs.as_bytes()).unwrap();
-        assert_eq!(c.to_string(), cs + "\n");
+        assert_eq!(c.to_string(), cs);
+    }
+
+    #[test]
+    fn commit_gpgsig() {
+        let bytes = [
+            b"tree adb7e67378d99ab8125f156442999f187db3d1a3\n".as_ref(),
+            b"parent 01a0c85dd05755281466d29983dfcb15889e1a64\n",
+            b"author author <author@example.com> 1609642799 +0900\n",
+            b"committer committer <committer@example.com> 1609642799 +0900\n",
+            b"gpgsig -----BEGIN PGP SIGNATURE-----\n",
+            b" \n",
+            b" iQEzBAABCAAdFiEE\n",
+            b" =abcd\n",
+            b" -----END PGP SIGNATURE-----\n",
+            b"\n",
+            b"signed commit\n",
+            b"\n",
+            b"\n",
+            b"body after blank lines\n",
+        ]
+        .concat();
+
+        let c = Commit::from(&bytes).unwrap();
+        assert_eq!(
+            c.parents,
+            vec!["01a0c85dd05755281466d29983dfcb15889e1a64".parse().unwrap()]
+        );
+        assert_eq!(c.committer.name, "committer");
+        assert_eq!(
+            c.gpgsig().unwrap(),
+            b"-----BEGIN PGP SIGNATURE-----\n\niQEzBAABCAAdFiEE\n=abcd\n-----END PGP SIGNATURE-----"
+                .as_ref()
+        );
+        assert_eq!(c.message, b"signed commit\n\n\nbody after blank lines\n");
+
+        let content = &c.as_bytes()[format!("commit {}\0", bytes.len()).len()..];
+        assert_eq!(content, bytes.as_slice());
+        // git hash-object -t commit で求めた hash
+        assert_eq!(
+            hex::encode(c.calc_hash()),
+            "7e8e1508cb8b11814d6f92ad4a733d629782e3c0"
+        );
+    }
+
+    #[test]
+    fn commit_multi_paragraph() {
+        let bytes = [
+            b"tree adb7e67378d99ab8125f156442999f187db3d1a3\n".as_ref(),
+            b"parent 01a0c85dd05755281466d29983dfcb15889e1a64\n",
+            b"author author <author@example.com> 1609642799 +0900\n",
+            b"comitter comitter <comitter@example.com> 1609642799 +0900\n",
+            b"\n",
+            b"Subject line\n",
+            b"\n",
+            b"parent is not a header here.\n",
+            b"\n",
+            b"\n",
+            b"  indented paragraph\n",
+            b"\n",
+        ]
+        .concat();
+
+        let c = Commit::from(&bytes).unwrap();
+        assert_eq!(
+            c.message,
+            b"Subject line\n\nparent is not a header here.\n\n\n  indented paragraph\n\n".as_ref()
+        );
+        let object = [format!("commit {}\0", bytes.len()).as_bytes(), &bytes].concat();
+        assert_eq!(c.as_bytes(), object);
+        assert_eq!(
+            c.calc_hash(),
+            ObjectId::digest(HashAlgo::default(), &object)
+        );
+
+        // 改行で終わらない message もそのまま戻す
+        let bytes = &bytes[..(bytes.len() - 2)];
+        let c = Commit::from(bytes).unwrap();
+        assert_eq!(
+            &c.as_bytes()[format!("commit {}\0", bytes.len()).len()..],
+            bytes
+        );
+    }
+
+    #[test]
+    fn commit_new_trailing_newline() {
+        let user = User::from(b"user <user@test.com> 1609643433 +0900").unwrap();
+        let c = Commit::new(
+            "adb7e67378d99ab8125f156442999f187db3d1a3".parse().unwrap(),
+            Vec::new(),
+            user.clone(),
+            user.clone(),
+            "first\n\nbody".to_string(),
+        );
+        assert_eq!(c.message, b"first\n\nbody\n");
+        let c = Commit::new(
+            "adb7e67378d99ab8125f156442999f187db3d1a3".parse().unwrap(),
+            Vec::new(),
+            user.clone(),
+            user,
+            "first\n".to_string(),
+        );
+        assert_eq!(c.message, b"first\n");
     }
 }
@@ -2,15 +2,16 @@ pub mod blob;
 pub mod commit;
 pub mod tree;
 
+use crate::hash::HashAlgo;
+pub use crate::hash::ObjectId;
+#[cfg(feature = "json")]
+use crate::json::{ToJson, Value};
 use blob::Blob;
 use commit::Commit;
-#[cfg(feature = "json")]
-use serde::ser::{Serialize, SerializeStruct, Serializer};
-use std::error::Error;
 use std::fmt;
-use std::result::Result;
 use tree::Tree;
 
+#[allow(clippy::large_enum_variant)]
 pub enum GitObject {
     Blob(Blob),
     Tree(Tree),
@@ -19,25 +20,65 @@ pub enum GitObject {
 
 impl GitObject {
     pub fn new(bytes: &[u8]) -> Option<Self> {
+        Self::new_with(bytes, HashAlgo::default())
+    }
+
+    pub fn new_with(bytes: &[u8], algo: HashAlgo) -> Option<Self> {
         let mut iter = bytes.splitn(2, |&bytes| bytes == b'\0'); // Tree で "\0" を使っている部分があるので header と body の2つに分割する
 
         let obj_type = iter
             .next()
             .and_then(|x| String::from_utf8(x.to_vec()).ok())
             .and_then(|x| ObjectType::from(&x))?;
+        // header が無い場合は全体を body として扱う
+        let body = iter.next().unwrap_or(bytes);
 
         match obj_type {
-            ObjectType::Blob => Blob::from(bytes).map(Self::Blob),
-            ObjectType::Tree => Tree::from(bytes).map(Self::Tree),
-            ObjectType::Commit => Commit::from(bytes).map(Self::Commit),
+            ObjectType::Blob => Blob::from(body).map(Self::Blob),
+            ObjectType::Tree => Tree::from_with(body, algo).map(Self::Tree),
+            ObjectType::Commit => Commit::from(body).map(Self::Commit),
         }
     }
 
-    pub fn calc_hash(&self) -> Vec<u8> {
+    // 中身は読まずに header の最初の空白までで型だけを決める
+    pub fn peek_type(bytes: &[u8]) -> Option<ObjectType> {
+        let end = bytes.iter().position(|&x| x == b' ')?;
+        ObjectType::from(std::str::from_utf8(&bytes[..end]).ok()?)
+    }
+
+    pub fn calc_hash(&self) -> ObjectId {
+        self.calc_hash_with(HashAlgo::default())
+    }
+
+    pub fn calc_hash_with(&self, algo: HashAlgo) -> ObjectId {
         match self {
-            Self::Blob(obj) => obj.calc_hash(),
-            Self::Tree(obj) => obj.calc_hash(),
-            Self::Commit(obj) => obj.calc_hash(),
+            Self::Blob(obj) => obj.calc_hash_with(algo),
+            Self::Tree(obj) => obj.calc_hash_with(algo),
+            Self::Commit(obj) => obj.calc_hash_with(algo),
+        }
+    }
+
+    pub fn object_type(&self) -> ObjectType {
+        match self {
+            Self::Blob(_) => ObjectType::Blob,
+            Self::Tree(_) => ObjectType::Tree,
+            Self::Commit(_) => ObjectType::Commit,
+        }
+    }
+
+    // header を除いた中身
+    pub fn content(&self) -> Vec<u8> {
+        let bytes = self.as_bytes();
+        let start = bytes.iter().position(|&x| x == b'\0').map_or(0, |x| x + 1);
+        bytes[start..].to_vec()
+    }
+
+    // header に書かれる中身の長さ. git cat-file -s
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Blob(obj) => obj.size(),
+            Self::Tree(obj) => obj.size(),
+            Self::Commit(obj) => obj.size(),
         }
     }
 
@@ -51,19 +92,17 @@ impl GitObject {
 }
 
 #[cfg(feature = "json")]
-impl Serialize for GitObject {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let mut s = serializer.serialize_struct("GitObject", 2)?;
-        match self {
-            GitObject::Blob(blob) => s.serialize_field("Blob", blob)?,
-            GitObject::Tree(tree) => s.serialize_field("Tree", tree)?,
-            GitObject::Commit(commit) => s.serialize_field("Commit", commit)?,
-        }
-        s.serialize_field("hash", &hex::encode(self.calc_hash()))?;
-        s.end()
+impl ToJson for GitObject {
+    fn to_json(&self) -> Value {
+        let (name, value) = match self {
+            GitObject::Blob(blob) => ("Blob", blob.to_json()),
+            GitObject::Tree(tree) => ("Tree", tree.to_json()),
+            GitObject::Commit(commit) => ("Commit", commit.to_json()),
+        };
+        Value::object(vec![
+            (name, value),
+            ("hash", hex::encode(self.calc_hash()).into()),
+        ])
     }
 }
 
@@ -77,7 +116,7 @@ impl fmt::Display for GitObject {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ObjectType {
     Blob,
     Tree,
@@ -95,12 +134,14 @@ impl ObjectType {
             _ => None,
         }
     }
+}
 
-    pub fn to_string(self) -> String {
+impl fmt::Display for ObjectType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            ObjectType::Blob => String::from("blob"),
-            ObjectType::Tree => String::from("tree"),
-            ObjectType::Commit => String::from("commit"),
+            ObjectType::Blob => write!(f, "blob"),
+            ObjectType::Tree => write!(f, "tree"),
+            ObjectType::Commit => write!(f, "commit"),
         }
     }
 }
@@ -133,27 +174,96 @@ mod tests {
         assert!(GitObject::new(b"hoge").is_none());
         assert!(GitObject::new(b"123").is_none());
         assert!(GitObject::new(b"blob").is_some());
-        assert!(GitObject::new(b"tree").is_some());
+        // tree の body として読むと "tree" は '\0' で終わらない壊れた entry
+        assert!(GitObject::new(b"tree").is_none());
+        assert!(GitObject::new(b"tree 0\0").is_some());
         assert!(GitObject::new(b"commit").is_none()); // commit はこれだけだと from で None になる
         let (g, _) = new_commit_git_object();
         assert!(g.is_some());
     }
 
+    #[test]
+    fn git_object_new_with_header() {
+        let blob = GitObject::new(b"blob 10\0hello, git").unwrap();
+        assert_eq!(blob.to_string(), "hello, git");
+        assert_eq!(blob.as_bytes(), b"blob 10\0hello, git");
+    }
+
+    #[test]
+    fn git_object_peek_type() {
+        assert_eq!(
+            GitObject::peek_type(b"blob 10\0hello, git"),
+            Some(ObjectType::Blob)
+        );
+        // 中身が壊れていても header だけで決まる
+        assert_eq!(
+            GitObject::peek_type(b"tree 100\0broken"),
+            Some(ObjectType::Tree)
+        );
+        assert_eq!(
+            GitObject::peek_type(b"commit 3\0???"),
+            Some(ObjectType::Commit)
+        );
+        assert!(GitObject::new(b"commit 3\0???").is_none());
+        assert_eq!(GitObject::peek_type(b"commit"), None);
+        assert_eq!(GitObject::peek_type(b"hoge 1\0x"), None);
+        assert_eq!(GitObject::peek_type(b""), None);
+    }
+
+    #[test]
+    fn git_object_content() {
+        let blob = GitObject::new(b"blob 10\0hello, git").unwrap();
+        assert_eq!(blob.object_type(), ObjectType::Blob);
+        assert_eq!(blob.content(), b"hello, git");
+    }
+
+    #[test]
+    fn git_object_size() {
+        let tree = tree::Tree::new(vec![
+            tree::File::new(
+                tree::FileMode::Regular,
+                "a b.txt".to_string(),
+                ObjectId::from_bytes(&[0; 20]).unwrap(),
+            ),
+            tree::File::new(
+                tree::FileMode::Tree,
+                "src".to_string(),
+                ObjectId::from_bytes(&[0xab; 20]).unwrap(),
+            ),
+        ]);
+        let (commit, _) = new_commit_git_object();
+        for obj in [
+            GitObject::Blob(Blob::new("")),
+            GitObject::Blob(Blob::new(b"\x89PNG\r\n\0\xff".as_ref())),
+            GitObject::Tree(tree::Tree::new(Vec::new())),
+            GitObject::Tree(tree),
+            commit.unwrap(),
+        ] {
+            let bytes = obj.as_bytes();
+            let body = &bytes[(bytes.iter().position(|&x| x == b'\0').unwrap() + 1)..];
+            assert_eq!(obj.size(), body.len(), "{}", obj.object_type());
+            assert_eq!(obj.size(), obj.content().len());
+            // header に書かれている大きさとも同じ
+            let header = format!("{} {}\0", obj.object_type(), obj.size());
+            assert!(bytes.starts_with(header.as_bytes()));
+        }
+    }
+
     #[test]
     fn git_object_as_bytes() {
         assert_eq!(
             GitObject::new(b"blob").unwrap().as_bytes(),
-            format!("blob 4\0blob").as_bytes()
+            "blob 4\0blob".to_string().as_bytes()
         );
         assert_eq!(
-            GitObject::new(b"tree").unwrap().as_bytes(),
-            format!("tree 0\0").as_bytes()
+            GitObject::new(b"tree 0\0").unwrap().as_bytes(),
+            "tree 0\0".to_string().as_bytes()
         );
 
         let (g, expected) = new_commit_git_object();
         assert_eq!(
             g.unwrap().as_bytes(),
-            format!("commit {}\0{}\n", expected.len() + 1, expected).as_bytes(),
+            format!("commit {}\0{}", expected.len(), expected).as_bytes(),
         );
     }
 
@@ -161,22 +271,22 @@ mod tests {
     fn git_object_calc_hash() {
         assert_eq!(
             GitObject::new(b"blob").unwrap().calc_hash(),
-            calc_hash(format!("blob 4\0blob").as_bytes())
+            calc_hash("blob 4\0blob".to_string().as_bytes())
         );
         assert_eq!(
-            GitObject::new(b"tree").unwrap().calc_hash(),
-            calc_hash(format!("tree 0\0").as_bytes())
+            GitObject::new(b"tree 0\0").unwrap().calc_hash(),
+            calc_hash("tree 0\0".to_string().as_bytes())
         );
 
         let (g, expected) = new_commit_git_object();
         assert_eq!(
             g.unwrap().calc_hash(),
-            calc_hash(format!("commit {}\0{}\n", expected.len() + 1, expected).as_bytes()),
+            calc_hash(format!("commit {}\0{}", expected.len(), expected).as_bytes()),
         );
     }
 
     fn new_commit_git_object() -> (Option<GitObject>, String) {
-        let cs = vec![
+        let cs = [
             "tree adb7e67378d99ab8125f156442999f187db3d1a3",
             "parent 01a0c85dd05755281466d29983dfcb15889e1a64",
             "author author <author@example.com> 1609642799 +0900",
@@ -187,14 +297,13 @@ mod tests {
         .join("\n")
         .trim_end()
         .to_owned();
-        let expected = format!("tree {}", cs.clone());
         (
-            GitObject::new(format!("commit {}", cs.clone()).as_bytes()),
-            expected,
+            GitObject::new(format!("commit {}\0{}", cs.len(), cs).as_bytes()),
+            cs,
         )
     }
 
-    fn calc_hash(bytes: &[u8]) -> Vec<u8> {
-        Vec::from(Sha1::digest(bytes).as_slice())
+    fn calc_hash(bytes: &[u8]) -> ObjectId {
+        ObjectId::from_bytes(Sha1::digest(bytes).as_slice()).unwrap()
     }
 }
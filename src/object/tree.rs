@@ -1,43 +1,160 @@
 use super::ObjectType;
+use crate::error::RgitError;
+use crate::hash::{HashAlgo, ObjectId};
+#[cfg(feature = "json")]
+use crate::json::{ToJson, Value};
 #[cfg(feature = "json")]
 use serde::Serialize;
-use sha1::{Digest, Sha1};
+use std::cmp::Ordering;
 use std::fmt;
 
+// ディレクトリ (tree) の mode
+pub const MODE_DIR: usize = 0o40000;
+pub const MODE_FILE: usize = 0o100644;
+pub const MODE_EXECUTABLE: usize = 0o100755;
+pub const MODE_SYMLINK: usize = 0o120000;
+pub const MODE_GITLINK: usize = 0o160000;
+// mode のうちファイルの種類を表す部分
+pub const MODE_TYPE_MASK: usize = 0o170000;
+
+// tree の entry の名前として使えるか. git の verify_path と同じく, worktree の外や .git の中を
+// 指してしまう `.`, `..`, `.git` (大文字小文字を問わない) と '/' を含む名前は受け付けない
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.eq_ignore_ascii_case(".git")
+        && !name.contains(['/', '\0'])
+}
+
+// `src/main.rs` のようなパスの全ての部分が is_valid_name か. checkout で書き出す前に確かめる
+pub fn verify_path(path: &str) -> bool {
+    path.split('/').all(is_valid_name)
+}
+
+// tree の entry として git が使う mode
 #[cfg_attr(feature = "json", derive(Serialize))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileMode {
+    // 100644
+    Regular,
+    // 100755
+    Executable,
+    // 120000
+    Symlink,
+    // 160000. submodule の commit
+    Gitlink,
+    // 40000
+    Tree,
+}
+
+impl FileMode {
+    pub fn from_octal(mode: usize) -> Option<Self> {
+        match mode {
+            MODE_FILE => Some(Self::Regular),
+            MODE_EXECUTABLE => Some(Self::Executable),
+            MODE_SYMLINK => Some(Self::Symlink),
+            MODE_GITLINK => Some(Self::Gitlink),
+            MODE_DIR => Some(Self::Tree),
+            _ => None,
+        }
+    }
+
+    // index に記録された stat の mode を git が tree に書く mode にする
+    // 通常のファイルは実行権限がどこかにあれば 100755, 無ければ 100644
+    pub fn from_stat(mode: usize) -> Option<Self> {
+        match mode & MODE_TYPE_MASK {
+            0o100000 if mode & 0o111 != 0 => Some(Self::Executable),
+            0o100000 => Some(Self::Regular),
+            MODE_SYMLINK => Some(Self::Symlink),
+            MODE_GITLINK => Some(Self::Gitlink),
+            MODE_DIR => Some(Self::Tree),
+            _ => None,
+        }
+    }
+
+    // entry が指す object の型. gitlink は別のリポジトリの commit
+    pub fn object_type(self) -> ObjectType {
+        match self {
+            Self::Tree => ObjectType::Tree,
+            Self::Gitlink => ObjectType::Commit,
+            _ => ObjectType::Blob,
+        }
+    }
+
+    pub fn to_octal(self) -> usize {
+        match self {
+            Self::Regular => MODE_FILE,
+            Self::Executable => MODE_EXECUTABLE,
+            Self::Symlink => MODE_SYMLINK,
+            Self::Gitlink => MODE_GITLINK,
+            Self::Tree => MODE_DIR,
+        }
+    }
+}
+
 pub struct Tree {
     pub contents: Vec<File>,
 }
 
 impl Tree {
-    pub fn new(contents: Vec<File>) -> Self {
+    // git と同じ hash になるように entry を名前順に並べる
+    pub fn new(mut contents: Vec<File>) -> Self {
+        contents.sort_by(File::cmp_name);
         Self { contents }
     }
 
     pub fn from(bytes: &[u8]) -> Option<Self> {
-        let contents: Vec<File> = Vec::new();
-        let mut iter = bytes.split(|&b| b == b'\0'); // 各 Entry は '\0' 区切り
+        Self::from_with(bytes, HashAlgo::default())
+    }
 
-        let mut header = iter.next()?; // 一番最初の header を取り出し
-        let contents = iter.try_fold(contents, |mut acc, x| {
-            let (hash, next_header) = x.split_at(20); // hash 値は 20bytes
-            let file = File::from(header, hash)?;
+    pub fn from_with(bytes: &[u8], algo: HashAlgo) -> Option<Self> {
+        Self::try_from_bytes_with(bytes, algo).ok()
+    }
+
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, RgitError> {
+        Self::try_from_bytes_with(bytes, HashAlgo::default())
+    }
+
+    // `<mode> <name>\0<hash>` を区切り無しで並べたものを最後まで読む
+    // hash は生の bytes なので '\0' を含むこともある
+    pub fn try_from_bytes_with(bytes: &[u8], algo: HashAlgo) -> Result<Self, RgitError> {
+        let mut contents = Vec::new();
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            let end = rest
+                .iter()
+                .position(|&x| x == b'\0')
+                .ok_or(RgitError::Truncated("tree entry header"))?;
+            let (header, body) = (&rest[..end], &rest[(end + 1)..]);
+            // 途中で切れた tree は hash の長さに足りない
+            if body.len() < algo.width() {
+                return Err(RgitError::Truncated("tree entry hash"));
+            }
+            let (hash, next) = body.split_at(algo.width()); // hash 値は sha1 なら 20bytes
+            contents.push(File::try_from_bytes(header, hash)?);
+            rest = next;
+        }
 
-            acc.push(file);
-            header = next_header;
-            Some(acc)
-        })?;
+        Ok(Self { contents })
+    }
+
+    pub fn calc_hash(&self) -> ObjectId {
+        self.calc_hash_with(HashAlgo::default())
+    }
 
-        Some(Self { contents })
+    pub fn calc_hash_with(&self, algo: HashAlgo) -> ObjectId {
+        ObjectId::digest(algo, &self.as_bytes())
     }
 
-    pub fn calc_hash(&self) -> Vec<u8> {
-        Vec::from(Sha1::digest(&self.as_bytes()).as_slice())
+    // header を除いた中身の長さ
+    pub fn size(&self) -> usize {
+        self.contents.iter().map(|x| x.encode().len()).sum()
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
         let content: Vec<u8> = self.contents.iter().flat_map(|x| x.encode()).collect();
-        let header = format!("{} {}\0", ObjectType::Tree.to_string(), content.len());
+        let header = format!("{} {}\0", ObjectType::Tree, content.len());
 
         [header.as_bytes(), content.as_slice()].concat()
     }
@@ -48,8 +165,8 @@ impl fmt::Display for Tree {
         write!(
             f,
             "{}",
-            (&self.contents)
-                .into_iter()
+            self.contents
+                .iter()
                 .map(|f| format!("{}", f))
                 .collect::<Vec<_>>()
                 .join("\n")
@@ -57,45 +174,105 @@ impl fmt::Display for Tree {
     }
 }
 
-#[cfg_attr(feature = "json", derive(Serialize))]
 pub struct File {
-    pub mode: usize,
+    pub mode: FileMode,
     pub name: String,
-    pub hash: Vec<u8>,
+    pub hash: ObjectId,
 }
 
 impl File {
-    pub fn new(mode: usize, name: String, hash: &[u8]) -> Self {
-        Self {
-            mode,
-            name,
-            hash: hash.to_vec(),
-        }
+    pub fn new(mode: FileMode, name: String, hash: ObjectId) -> Self {
+        Self { mode, name, hash }
     }
 
     pub fn from(header: &[u8], hash: &[u8]) -> Option<Self> {
-        let split_header = String::from_utf8(header.to_vec()).ok()?;
+        Self::try_from_bytes(header, hash).ok()
+    }
+
+    // header は `<mode> <name>`. name は空白を含んでもよい
+    pub fn try_from_bytes(header: &[u8], hash: &[u8]) -> Result<Self, RgitError> {
+        let split_header = String::from_utf8(header.to_vec())
+            .map_err(|_| RgitError::InvalidUtf8("tree entry name"))?;
+        let (mode, name) = split_header
+            .split_once(' ')
+            .ok_or_else(|| RgitError::BadHeader(format!("no mode in {:?}", split_header)))?;
+        let bad_mode = || RgitError::BadHeader(format!("invalid mode {:?}", mode));
+        if mode.is_empty() || !mode.bytes().all(|x| (b'0'..=b'7').contains(&x)) {
+            return Err(bad_mode());
+        }
 
-        let mut iter = split_header.split_whitespace();
+        // mode は8進数の文字列. git が使わない mode は壊れた tree として扱う
+        let mode = usize::from_str_radix(mode, 8)
+            .ok()
+            .and_then(FileMode::from_octal)
+            .ok_or_else(bad_mode)?;
+        if name.is_empty() {
+            return Err(RgitError::BadHeader(String::from("empty name")));
+        }
+        if !is_valid_name(name) {
+            return Err(RgitError::BadHeader(format!("invalid name {:?}", name)));
+        }
 
-        let mode = iter.next().and_then(|x| x.parse::<usize>().ok())?;
-        let name = iter.next()?;
+        Ok(Self::new(
+            mode,
+            String::from(name),
+            ObjectId::try_from_bytes(hash)?,
+        ))
+    }
 
-        Some(Self::new(mode, String::from(name), hash))
+    pub fn is_dir(&self) -> bool {
+        self.mode == FileMode::Tree
+    }
+
+    // tree の中での並び順. ディレクトリは名前の末尾に '/' があるものとして比べる
+    pub fn cmp_name(&self, other: &Self) -> Ordering {
+        let key = |x: &Self| {
+            let mut name = x.name.as_bytes().to_vec();
+            if x.is_dir() {
+                name.push(b'/');
+            }
+            name
+        };
+        key(self).cmp(&key(other))
     }
 
     pub fn encode(&self) -> Vec<u8> {
-        let header = format!("{} {}\0", self.mode, self.name);
-        [header.as_bytes(), &self.hash].concat()
+        let header = format!("{:o} {}\0", self.mode.to_octal(), self.name);
+        [header.as_bytes(), self.hash.as_bytes()].concat()
+    }
+}
+
+#[cfg(feature = "json")]
+impl ToJson for Tree {
+    fn to_json(&self) -> Value {
+        Value::object(vec![(
+            "entries",
+            Value::Array(self.contents.iter().map(ToJson::to_json).collect()),
+        )])
     }
 }
 
+// mode は git の表示と同じ8進数の文字列, hash は16進数の文字列にする
+#[cfg(feature = "json")]
+impl ToJson for File {
+    fn to_json(&self) -> Value {
+        Value::object(vec![
+            ("mode", format!("{:06o}", self.mode.to_octal()).into()),
+            ("type", self.mode.object_type().to_string().into()),
+            ("hash", self.hash.to_json()),
+            ("name", self.name.as_str().into()),
+        ])
+    }
+}
+
+// git cat-file -p と同じ `<mode> <type> <hash>\t<name>`. 型は mode から決める
 impl fmt::Display for File {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "{:>06} ??? {}\t{}",
-            self.mode,
+            "{:06o} {} {}\t{}",
+            self.mode.to_octal(),
+            self.mode.object_type(),
             hex::encode(&self.hash),
             self.name
         )
@@ -106,17 +283,43 @@ impl fmt::Display for File {
 mod tests {
     use super::*;
 
+    fn id(bytes: &[u8]) -> ObjectId {
+        ObjectId::from_bytes(bytes).unwrap()
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn tree_json() {
+        let tree = Tree::new(vec![
+            File::new(FileMode::Regular, "a.txt".to_string(), id(&[0xab; 20])),
+            File::new(FileMode::Tree, "src".to_string(), id(&[0xcd; 20])),
+        ]);
+        let json = crate::json::to_string_pretty(&tree);
+        assert_eq!(
+            json,
+            format!(
+                "{{\n  \"entries\": [\n    {{\n      \"mode\": \"100644\",\n      \"type\": \"blob\",\n      \"hash\": \"{}\",\n      \"name\": \"a.txt\"\n    }},\n    {{\n      \"mode\": \"040000\",\n      \"type\": \"tree\",\n      \"hash\": \"{}\",\n      \"name\": \"src\"\n    }}\n  ]\n}}",
+                "ab".repeat(20),
+                "cd".repeat(20)
+            )
+        );
+    }
+
     #[test]
     fn file_new() {
-        let f = File::new(0, String::from(""), b"");
-        assert_eq!(f.mode, 0);
+        let f = File::new(FileMode::Regular, String::from(""), id(&[0; 20]));
+        assert_eq!(f.mode, FileMode::Regular);
         assert_eq!(f.name, "");
-        assert_eq!(f.hash, []);
+        assert_eq!(f.hash.as_bytes(), [0; 20]);
 
-        let f = File::new(040000, String::from("hello"), b"hello");
-        assert_eq!(f.mode, 040000);
+        let f = File::new(
+            FileMode::Tree,
+            String::from("hello"),
+            id(b"hellohellohellohello"),
+        );
+        assert_eq!(f.mode, FileMode::Tree);
         assert_eq!(f.name, "hello");
-        assert_eq!(f.hash, b"hello".to_vec());
+        assert_eq!(f.hash.as_bytes(), b"hellohellohellohello");
     }
 
     #[test]
@@ -124,36 +327,45 @@ mod tests {
         let f = File::from(b"", b"");
         assert!(f.is_none());
 
-        // TODO: hash の例として正しいのかわからない
-        let hash = b"11a8200b08ffa1abdc05cd9195ca7af639ce8946";
-        let of = File::from(b"040000 test.txt hash", hash);
+        let hash = hex::decode("11a8200b08ffa1abdc05cd9195ca7af639ce8946").unwrap();
+        let of = File::from(b"040000 test.txt", &hash);
         let f = of.unwrap();
-        assert_eq!(f.mode, 040000);
+        assert_eq!(f.mode, FileMode::Tree);
+        assert!(f.is_dir());
+
+        // mode は8進数として読む
+        assert!(File::from(b"100855 test.txt", &hash).is_none());
         assert_eq!(f.name, "test.txt");
-        assert_eq!(f.hash, hash.to_vec());
+        assert_eq!(f.hash.as_bytes(), hash);
+
+        // hash は16進数の文字列ではなくバイト列で入っている
+        assert!(File::from(
+            b"40000 test.txt",
+            b"11a8200b08ffa1abdc05cd9195ca7af639ce8946"
+        )
+        .is_none());
     }
 
     #[test]
     fn file_encode() {
-        let mode = 040000;
+        let mode = 0o40000;
         let name = String::from("test.txt");
-        // TODO: hash の例として正しいのかわからない
-        let hash = b"11a8200b08ffa1abdc05cd9195ca7af639ce8946";
-        let header = format!("{} {}\0", mode, name);
+        let hash = hex::decode("11a8200b08ffa1abdc05cd9195ca7af639ce8946").unwrap();
+        let header = format!("{:o} {}\0", mode, name);
 
-        let f = File::new(mode, name, hash);
-        assert_eq!(f.encode(), [header.as_bytes(), hash].concat());
+        let f = File::new(FileMode::Tree, name, id(&hash));
+        assert_eq!(f.encode(), [header.as_bytes(), &hash].concat());
     }
 
     #[test]
     fn file_to_string() {
-        let mode = 040000;
+        let mode = 0o40000;
         let name = String::from("test.txt");
         let hash = b"aaaaaaaaaaaaaaaaaaaa";
-        let f = File::new(mode, name.clone(), hash);
+        let f = File::new(FileMode::Tree, name.clone(), id(hash));
         assert_eq!(
             f.to_string(),
-            format!("{:>06} ??? {}\t{}", mode, hex::encode(&hash), name)
+            format!("{:06o} tree {}\t{}", mode, hex::encode(hash), name)
         );
     }
 
@@ -164,13 +376,9 @@ mod tests {
         let t = ot.unwrap();
         assert_eq!(t.contents.len(), 0);
 
-        let ot = Tree::from(b"040000 test.txt");
-        assert!(ot.is_some());
-        let t = ot.unwrap();
-        assert_eq!(t.contents.len(), 0);
-
-        let t = Tree::from(b"040000 test.txt-aaaaaaaaaaaaaaaaaaaa").unwrap();
-        assert_eq!(t.contents.len(), 0);
+        // '\0' で終わらない entry は壊れている
+        assert!(Tree::from(b"040000 test.txt").is_none());
+        assert!(Tree::from(b"040000 test.txt-aaaaaaaaaaaaaaaaaaaa").is_none());
 
         let t = Tree::from(b"040000 test.txt\0aaaaaaaaaaaaaaaaaaaa").unwrap();
         assert_eq!(t.contents.len(), 1);
@@ -182,12 +390,83 @@ mod tests {
         assert_eq!(t.contents.len(), 2);
     }
 
+    #[test]
+    fn tree_from_canonical_mode() {
+        // git は directory の mode を先頭の 0 なしで書く
+        let t =
+            Tree::from(b"40000 test.txt\0aaaaaaaaaaaaaaaaaaaa40000 test.txt\0bbbbbbbbbbbbbbbbbbbb")
+                .unwrap();
+        assert_eq!(t.contents.len(), 2);
+        assert!(t.contents.iter().all(|f| f.mode == FileMode::Tree));
+
+        // 先頭に 0 があっても同じ entry になる
+        let padded = Tree::from(b"040000 test.txt\0aaaaaaaaaaaaaaaaaaaa").unwrap();
+        let canonical = Tree::from(b"40000 test.txt\0aaaaaaaaaaaaaaaaaaaa").unwrap();
+        assert_eq!(padded.as_bytes(), canonical.as_bytes());
+    }
+
+    #[test]
+    fn tree_from_truncated() {
+        // 最後の entry の hash が 10 bytes しかない
+        assert!(Tree::from(b"40000 test.txt\0aaaaaaaaaa").is_none());
+        assert!(
+            Tree::from(b"40000 test.txt\0aaaaaaaaaaaaaaaaaaaa100644 a.txt\0aaaaaaaaaa").is_none()
+        );
+        assert!(
+            Tree::from_with(b"40000 test.txt\0aaaaaaaaaaaaaaaaaaaa", HashAlgo::Sha256).is_none()
+        );
+    }
+
+    #[test]
+    fn tree_try_from_bytes() {
+        assert_eq!(
+            Tree::try_from_bytes(b"40000 test.txt").err(),
+            Some(RgitError::Truncated("tree entry header"))
+        );
+        assert_eq!(
+            Tree::try_from_bytes(b"40000 test.txt\0aaaaaaaaaa").err(),
+            Some(RgitError::Truncated("tree entry hash"))
+        );
+        assert_eq!(
+            Tree::try_from_bytes(b"40000 \xff\0aaaaaaaaaaaaaaaaaaaa").err(),
+            Some(RgitError::InvalidUtf8("tree entry name"))
+        );
+        assert_eq!(
+            Tree::try_from_bytes(b"test.txt\0aaaaaaaaaaaaaaaaaaaa").err(),
+            Some(RgitError::BadHeader(String::from(
+                "no mode in \"test.txt\""
+            )))
+        );
+        assert_eq!(
+            Tree::try_from_bytes(b"100600 test.txt\0aaaaaaaaaaaaaaaaaaaa").err(),
+            Some(RgitError::BadHeader(String::from(
+                "invalid mode \"100600\""
+            )))
+        );
+        assert_eq!(
+            Tree::try_from_bytes(b"40000 \0aaaaaaaaaaaaaaaaaaaa").err(),
+            Some(RgitError::BadHeader(String::from("empty name")))
+        );
+        assert!(Tree::try_from_bytes(b"40000 test.txt\0aaaaaaaaaaaaaaaaaaaa").is_ok());
+    }
+
+    #[test]
+    fn tree_from_with_sha256() {
+        let hash = [b'a'; 32];
+        let bytes = [b"40000 test.txt\0".as_ref(), &hash].concat();
+
+        let t = Tree::from_with(&bytes, HashAlgo::Sha256).unwrap();
+        assert_eq!(t.contents.len(), 1);
+        assert_eq!(t.contents[0].hash.as_bytes(), hash);
+        assert_eq!(t.calc_hash_with(HashAlgo::Sha256).as_bytes().len(), 32);
+    }
+
     #[test]
     fn tree_as_bytes() {
-        let mode = 040000;
+        let mode = 0o40000;
         let name = "test.txt";
         let hash: &[u8] = b"aaaaaaaaaaaaaaaaaaaa";
-        let content: Vec<u8> = [format!("{} {}\0", mode, name).as_bytes(), hash].concat();
+        let content: Vec<u8> = [format!("{:o} {}\0", mode, name).as_bytes(), hash].concat();
         let t = Tree::from(b"040000 test.txt\0aaaaaaaaaaaaaaaaaaaa").unwrap();
         assert_eq!(
             t.as_bytes(),
@@ -201,7 +480,7 @@ mod tests {
 
     #[test]
     fn tree_to_string() {
-        let mode = 040000;
+        let mode = 0o40000;
         let name = String::from("test.txt");
         let hash = b"aaaaaaaaaaaaaaaaaaaa";
         let t = Tree::from(
@@ -210,8 +489,331 @@ mod tests {
         .unwrap();
         assert_eq!(
             t.to_string(),
-            format!("{:>06} ??? {}\t{}", mode, hex::encode(&hash), name)
-                + &format!("\n{:>06} ??? {}\t{}", mode, hex::encode(&hash), name)
+            format!("{:06o} tree {}\t{}", mode, hex::encode(hash), name)
+                + &format!("\n{:06o} tree {}\t{}", mode, hex::encode(hash), name)
+        );
+    }
+
+    #[test]
+    fn tree_to_string_like_git() {
+        // "hello\n" の blob, "v2/" を指す symlink, その blob を main.rs として持つ tree から
+        // git mktree で作った tree を git cat-file -p で出力したもの
+        let blob = hex::decode("ce013625030ba8dba906f756967f9e9ca394464a").unwrap();
+        let link = hex::decode("523c98efad9352a25849f1a3e24b864813559930").unwrap();
+        let dir = hex::decode("393d617715d2d591ff46b9cce310c31be5156417").unwrap();
+        let t = Tree::new(vec![
+            File::new(FileMode::Tree, "src".to_string(), id(&dir)),
+            File::new(FileMode::Symlink, "latest".to_string(), id(&link)),
+            File::new(FileMode::Executable, "run.sh".to_string(), id(&blob)),
+            File::new(FileMode::Regular, "README".to_string(), id(&blob)),
+        ]);
+        assert_eq!(
+            format!("{}\n", t),
+            "100644 blob ce013625030ba8dba906f756967f9e9ca394464a\tREADME\n\
+             120000 blob 523c98efad9352a25849f1a3e24b864813559930\tlatest\n\
+             100755 blob ce013625030ba8dba906f756967f9e9ca394464a\trun.sh\n\
+             040000 tree 393d617715d2d591ff46b9cce310c31be5156417\tsrc\n"
+        );
+
+        assert_eq!(
+            hex::encode(t.calc_hash()),
+            "b3c31e013a46f51c86a783e320e7e32b6bc60ac5"
+        );
+
+        // 読み込んだ tree は保存されている順に出力する
+        let bytes = t.as_bytes();
+        let header = bytes.iter().position(|&x| x == 0).unwrap();
+        let read = Tree::from(&bytes[(header + 1)..]).unwrap();
+        assert_eq!(read.to_string(), t.to_string());
+    }
+
+    #[test]
+    fn tree_new_sorted() {
+        let hash = [b'a'; 20];
+        let t = Tree::new(vec![
+            File::new(FileMode::Regular, String::from("file.txt"), id(&hash)),
+            File::new(FileMode::Tree, String::from("file"), id(&hash)),
+            File::new(FileMode::Regular, String::from("file-a"), id(&hash)),
+            File::new(FileMode::Regular, String::from("File"), id(&hash)),
+        ]);
+        // "file/" は '-' (0x2d) と '.' (0x2e) より後ろ
+        let names = t
+            .contents
+            .iter()
+            .map(|x| x.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["File", "file-a", "file.txt", "file"]);
+
+        let t = Tree::new(vec![
+            File::new(FileMode::Regular, String::from("file.txt"), id(&hash)),
+            File::new(FileMode::Regular, String::from("file"), id(&hash)),
+        ]);
+        let names = t
+            .contents
+            .iter()
+            .map(|x| x.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["file", "file.txt"]);
+    }
+
+    #[test]
+    fn file_mode_octal() {
+        let hash = [b'a'; 20];
+        for (text, mode) in [
+            ("100644", FileMode::Regular),
+            ("100755", FileMode::Executable),
+            ("120000", FileMode::Symlink),
+            ("160000", FileMode::Gitlink),
+            ("40000", FileMode::Tree),
+        ] {
+            let f = File::from(format!("{} name", text).as_bytes(), &hash).unwrap();
+            assert_eq!(f.mode, mode);
+            assert_eq!(mode.to_octal(), usize::from_str_radix(text, 8).unwrap());
+            assert_eq!(FileMode::from_octal(mode.to_octal()), Some(mode));
+            assert_eq!(
+                f.encode(),
+                [format!("{} name\0", text).as_bytes(), &hash].concat()
+            );
+        }
+        assert_eq!(MODE_EXECUTABLE, 33261);
+        assert_eq!(MODE_DIR, 16384);
+    }
+
+    #[test]
+    fn file_mode_from_stat() {
+        for (mode, expected) in [
+            (0o100644, Some(FileMode::Regular)),
+            (0o100600, Some(FileMode::Regular)),
+            (0o100664, Some(FileMode::Regular)),
+            (0o100755, Some(FileMode::Executable)),
+            (0o100700, Some(FileMode::Executable)),
+            (0o100744, Some(FileMode::Executable)),
+            (0o120777, Some(FileMode::Symlink)),
+            (0o160000, Some(FileMode::Gitlink)),
+            (0o040755, Some(FileMode::Tree)),
+            (0o020644, None),
+            (0, None),
+        ] {
+            assert_eq!(FileMode::from_stat(mode), expected, "{:o}", mode);
+        }
+    }
+
+    #[test]
+    fn tree_gitlink() {
+        let commit = [0xab; 20];
+        let blob = [b'a'; 20];
+        let t = Tree::new(vec![
+            File::new(FileMode::Gitlink, String::from("vendor"), id(&commit)),
+            File::new(FileMode::Regular, String::from(".gitmodules"), id(&blob)),
+        ]);
+        let bytes = t.as_bytes();
+        let body = &bytes[(bytes.iter().position(|&x| x == 0).unwrap() + 1)..];
+        assert_eq!(
+            body,
+            [
+                b"100644 .gitmodules\0".as_ref(),
+                &blob,
+                b"160000 vendor\0",
+                &commit
+            ]
+            .concat()
+        );
+
+        let t = Tree::from(body).unwrap();
+        assert_eq!(t.contents[1].mode, FileMode::Gitlink);
+        assert_eq!(t.contents[1].hash.as_bytes(), commit);
+        assert_eq!(t.contents[1].mode.object_type(), ObjectType::Commit);
+        assert!(!t.contents[1].is_dir());
+        assert_eq!(t.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn file_rejects_bad_name() {
+        let hash = [b'a'; 20];
+        for name in [".", "..", ".git", ".GIT", "a/b", "../x", "/etc"] {
+            let header = format!("100644 {}", name);
+            assert!(File::from(header.as_bytes(), &hash).is_none(), "{}", name);
+            let bytes = [header.as_bytes(), b"\0", &hash].concat();
+            assert!(Tree::from(&bytes).is_none(), "{}", name);
+        }
+        assert!(File::from(b"100644 .gitignore", &hash).is_some());
+        assert!(File::from(b"100644 ...", &hash).is_some());
+        assert!(File::from(b"40000 .github", &hash).is_some());
+    }
+
+    #[test]
+    fn verify_path_components() {
+        assert!(verify_path("src/main.rs"));
+        assert!(verify_path(".gitignore"));
+        for path in [
+            "",
+            "a//b",
+            "../x",
+            "a/../../x",
+            "./a",
+            ".git/config",
+            "a/.Git/hooks",
+            "a/",
+        ] {
+            assert!(!verify_path(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn file_mode_rejects_unknown() {
+        let hash = [b'a'; 20];
+        assert_eq!(FileMode::from_octal(0o123456), None);
+        assert_eq!(FileMode::from_octal(0o100664), None);
+        assert!(File::from(b"123456 name", &hash).is_none());
+        assert!(Tree::from(b"123456 name\0aaaaaaaaaaaaaaaaaaaa").is_none());
+    }
+
+    // git mktree --missing に次の行を渡して作った tree. 空白を含む名前と '\0' を含む hash がある
+    const GIT_TREE_INPUT: &str = "\
+100644 blob ce013625030ba8dba906f756967f9e9ca394464a\thello world.txt
+100755 blob 00d1000000000000000000000000000000000020\trun.sh
+120000 blob 523c98efad9352a25849f1a3e24b864813559930\tlatest
+160000 commit 0000000000000000000000000000000000000001\tvendor
+040000 tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\tsub dir
+";
+    const GIT_TREE_HASH: &str = "4d8e1e34ca05dde82884fa1656d0e801c53dcccc";
+
+    // git cat-file tree で得られる内容
+    fn git_tree_body() -> Vec<u8> {
+        let entry = |header: &str, hash: &str| {
+            [
+                header.as_bytes(),
+                b"\0",
+                hex::decode(hash).unwrap().as_slice(),
+            ]
+            .concat()
+        };
+        [
+            entry(
+                "100644 hello world.txt",
+                "ce013625030ba8dba906f756967f9e9ca394464a",
+            ),
+            entry("120000 latest", "523c98efad9352a25849f1a3e24b864813559930"),
+            entry("100755 run.sh", "00d1000000000000000000000000000000000020"),
+            entry("40000 sub dir", "4b825dc642cb6eb9a060e54bf8d69288fbee4904"),
+            entry("160000 vendor", "0000000000000000000000000000000000000001"),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn tree_git_format() {
+        let body = git_tree_body();
+        let t = Tree::from(&body).unwrap();
+        let entries = t
+            .contents
+            .iter()
+            .map(|x| (x.mode, x.name.as_str(), hex::encode(&x.hash)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            vec![
+                (
+                    FileMode::Regular,
+                    "hello world.txt",
+                    "ce013625030ba8dba906f756967f9e9ca394464a".to_string()
+                ),
+                (
+                    FileMode::Symlink,
+                    "latest",
+                    "523c98efad9352a25849f1a3e24b864813559930".to_string()
+                ),
+                (
+                    FileMode::Executable,
+                    "run.sh",
+                    "00d1000000000000000000000000000000000020".to_string()
+                ),
+                (
+                    FileMode::Tree,
+                    "sub dir",
+                    "4b825dc642cb6eb9a060e54bf8d69288fbee4904".to_string()
+                ),
+                (
+                    FileMode::Gitlink,
+                    "vendor",
+                    "0000000000000000000000000000000000000001".to_string()
+                ),
+            ]
+        );
+
+        // 書き出すと git と同じ bytes と hash になる
+        let bytes = t.as_bytes();
+        assert_eq!(
+            bytes,
+            [format!("tree {}\0", body.len()).as_bytes(), &body].concat()
+        );
+        assert_eq!(hex::encode(t.calc_hash()), GIT_TREE_HASH);
+
+        // 並べ直して作っても同じ
+        let t = Tree::new(
+            Tree::from(&body)
+                .unwrap()
+                .contents
+                .into_iter()
+                .rev()
+                .collect(),
+        );
+        assert_eq!(hex::encode(t.calc_hash()), GIT_TREE_HASH);
+
+        // name の後の '\0' が無い, name が空, mode が8進数でない
+        assert!(Tree::from(&body[..(body.len() - 21)]).is_none());
+        assert!(Tree::from(&[b"100644 \0".as_ref(), &[b'a'; 20]].concat()).is_none());
+        assert!(Tree::from(&[b"+100644 a\0".as_ref(), &[b'a'; 20]].concat()).is_none());
+    }
+
+    #[test]
+    fn tree_git_interop() {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+
+        let dir = std::env::temp_dir().join(format!("rgit-tree-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let git = |args: &[&str], input: &[u8]| {
+            let mut child = Command::new("git")
+                .arg("-C")
+                .arg(&dir)
+                .args(args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()?;
+            child.stdin.take().unwrap().write_all(input)?;
+            Ok::<_, std::io::Error>(child.wait_with_output()?.stdout)
+        };
+        let result = git(&["init", "-q"], b"").and_then(|_| {
+            let hash = git(&["mktree", "--missing"], GIT_TREE_INPUT.as_bytes())?;
+            let hash = String::from_utf8_lossy(&hash).trim().to_string();
+            let raw = git(&["cat-file", "tree", &hash], b"")?;
+
+            // rgit が書いた tree を git に読ませる
+            let t = Tree::from(&git_tree_body()).unwrap();
+            let bytes = t.as_bytes();
+            let body = &bytes[(bytes.iter().position(|&x| x == 0).unwrap() + 1)..];
+            let written = git(&["hash-object", "-t", "tree", "-w", "--stdin"], body)?;
+            let written = String::from_utf8_lossy(&written).trim().to_string();
+            let listed = git(&["ls-tree", &written], b"")?;
+            Ok((hash, raw, written, listed))
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+        let (hash, raw, written, listed) = match result {
+            Ok(x) if !x.0.is_empty() => x,
+            // git が使えない
+            _ => return,
+        };
+
+        assert_eq!(hash, GIT_TREE_HASH);
+        let t = Tree::from(&raw).unwrap();
+        assert_eq!(
+            t.as_bytes(),
+            [format!("tree {}\0", raw.len()).as_bytes(), &raw].concat()
         );
+        assert_eq!(hex::encode(t.calc_hash()), hash);
+
+        assert_eq!(written, GIT_TREE_HASH);
+        assert_eq!(String::from_utf8(listed).unwrap(), format!("{}\n", t));
     }
 }
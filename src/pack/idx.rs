@@ -0,0 +1,221 @@
+use crate::hash::{HashAlgo, Hasher};
+
+const IDX_MAGIC: [u8; 4] = [0xff, b't', b'O', b'c'];
+const IDX_VERSION: u32 = 2;
+// offset がこれ以上なら 8byte の大きい offset テーブルを使う
+const LARGE_OFFSET: u64 = 0x8000_0000;
+
+// pack 内の object 1つ分の索引
+#[derive(Clone, Debug, PartialEq)]
+pub struct IdxEntry {
+    pub hash: Vec<u8>,
+    pub crc32: u32,
+    pub offset: u64,
+}
+
+// 読み込んだ idx v2
+pub struct PackIndex {
+    width: usize,
+    fanout: Vec<u32>,
+    hashes: Vec<u8>,
+    offsets: Vec<u64>,
+}
+
+impl PackIndex {
+    pub fn from(bytes: &[u8], algo: HashAlgo) -> Option<Self> {
+        if bytes.get(0..4)? != IDX_MAGIC || bytes.get(4..8)? != IDX_VERSION.to_be_bytes() {
+            return None;
+        }
+
+        let fanout = bytes
+            .get(8..8 + 256 * 4)?
+            .chunks(4)
+            .map(|x| u32::from_be_bytes([x[0], x[1], x[2], x[3]]))
+            .collect::<Vec<_>>();
+        // fanout は先頭1バイトごとの累計なので減ることは無い
+        if fanout.windows(2).any(|x| x[0] > x[1]) {
+            return None;
+        }
+        let count = *fanout.last()? as usize;
+
+        let width = algo.width();
+        let hashes_start = 8 + 256 * 4;
+        let crc_start = hashes_start + count * width;
+        let offsets_start = crc_start + count * 4;
+        let large_start = offsets_start + count * 4;
+
+        let hashes = bytes.get(hashes_start..crc_start)?.to_vec();
+        let offsets = bytes
+            .get(offsets_start..large_start)?
+            .chunks(4)
+            .map(|x| {
+                let offset = u32::from_be_bytes([x[0], x[1], x[2], x[3]]);
+                if offset & 0x8000_0000 == 0 {
+                    return Some(offset as u64);
+                }
+                let pos = large_start + (offset & 0x7fff_ffff) as usize * 8;
+                let large = bytes.get(pos..pos + 8)?;
+                let mut buf = [0u8; 8];
+                buf.copy_from_slice(large);
+                Some(u64::from_be_bytes(buf))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self {
+            width,
+            fanout,
+            hashes,
+            offsets,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    pub fn hash(&self, i: usize) -> &[u8] {
+        &self.hashes[i * self.width..(i + 1) * self.width]
+    }
+
+    // fanout で範囲を絞ってから二分探索して pack 内の offset を返す
+    pub fn find(&self, hash: &[u8]) -> Option<u64> {
+        let first = *hash.first()? as usize;
+        let start = if first == 0 {
+            0
+        } else {
+            self.fanout[first - 1] as usize
+        };
+        let end = self.fanout[first] as usize;
+
+        let (mut low, mut high) = (start, end);
+        while low < high {
+            let mid = (low + high) / 2;
+            match self.hash(mid).cmp(hash) {
+                std::cmp::Ordering::Equal => return Some(self.offsets[mid]),
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+            }
+        }
+        None
+    }
+}
+
+// idx v2 形式で書き出す
+// entries は hash 順に並べ替えられる
+pub fn write_idx(entries: &mut [IdxEntry], pack_hash: &[u8], algo: HashAlgo) -> Vec<u8> {
+    entries.sort_by(|a, b| a.hash.cmp(&b.hash));
+
+    let mut fanout = [0u32; 256];
+    for entry in entries.iter() {
+        fanout[entry.hash[0] as usize] += 1;
+    }
+    for i in 1..256 {
+        fanout[i] += fanout[i - 1];
+    }
+
+    let mut large_offsets = Vec::new();
+    let offsets = entries
+        .iter()
+        .map(|x| {
+            if x.offset < LARGE_OFFSET {
+                x.offset as u32
+            } else {
+                large_offsets.push(x.offset);
+                0x8000_0000 | (large_offsets.len() as u32 - 1)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&IDX_MAGIC);
+    buf.extend_from_slice(&IDX_VERSION.to_be_bytes());
+    fanout
+        .iter()
+        .for_each(|x| buf.extend_from_slice(&x.to_be_bytes()));
+    entries.iter().for_each(|x| buf.extend_from_slice(&x.hash));
+    entries
+        .iter()
+        .for_each(|x| buf.extend_from_slice(&x.crc32.to_be_bytes()));
+    offsets
+        .iter()
+        .for_each(|x| buf.extend_from_slice(&x.to_be_bytes()));
+    large_offsets
+        .iter()
+        .for_each(|x| buf.extend_from_slice(&x.to_be_bytes()));
+    buf.extend_from_slice(pack_hash);
+
+    let checksum = algo.digest(&buf);
+    buf.extend_from_slice(&checksum);
+
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idx_layout() {
+        let mut entries = vec![
+            IdxEntry {
+                hash: vec![0xff; 20],
+                crc32: 1,
+                offset: 12,
+            },
+            IdxEntry {
+                hash: vec![0x00; 20],
+                crc32: 2,
+                offset: 0x1_0000_0000,
+            },
+        ];
+        let bytes = write_idx(&mut entries, &[0xaa; 20], HashAlgo::Sha1);
+
+        assert_eq!(&bytes[0..8], &[0xff, b't', b'O', b'c', 0, 0, 0, 2]);
+        // fanout[0] = 1, fanout[255] = 2
+        assert_eq!(&bytes[8..12], &[0, 0, 0, 1]);
+        assert_eq!(&bytes[8 + 255 * 4..8 + 256 * 4], &[0, 0, 0, 2]);
+        // 4byte header + version + fanout + hashes + crc + offsets + large offsets + 2 checksum
+        assert_eq!(bytes.len(), 8 + 1024 + 40 + 8 + 8 + 8 + 40);
+        assert_eq!(entries[0].hash, vec![0x00; 20]);
+
+        let index = PackIndex::from(&bytes, HashAlgo::Sha1).unwrap();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.find(&[0x00; 20]), Some(0x1_0000_0000));
+        assert_eq!(index.find(&[0xff; 20]), Some(12));
+        assert_eq!(index.find(&[0x01; 20]), None);
+    }
+
+    #[test]
+    fn pack_index_from_git() {
+        let bytes = include_bytes!("../../tests/fixtures/ofs-delta.idx");
+        let index = PackIndex::from(bytes, HashAlgo::Sha1).unwrap();
+        assert_eq!(index.len(), 7);
+
+        let hash = hex::decode("aaa9501870880010b016554a65bc400ade0e1842").unwrap();
+        assert_eq!(index.find(&hash), Some(12));
+        let hash = hex::decode("7cab485a468c76adc53a3e32e3244bd11767b8d6").unwrap();
+        assert_eq!(index.find(&hash), Some(582));
+
+        assert!(PackIndex::from(b"hoge", HashAlgo::Sha1).is_none());
+    }
+
+    #[test]
+    fn pack_index_rejects_decreasing_fanout() {
+        let mut entries = vec![IdxEntry {
+            hash: vec![0x10; 20],
+            crc32: 1,
+            offset: 12,
+        }];
+        let mut bytes = write_idx(&mut entries, &[0xaa; 20], HashAlgo::Sha1);
+        assert!(PackIndex::from(&bytes, HashAlgo::Sha1).is_some());
+
+        // fanout[0x20] を 0 にして途中で減らす
+        let pos = 8 + 0x20 * 4;
+        bytes[pos..pos + 4].copy_from_slice(&[0; 4]);
+        assert!(PackIndex::from(&bytes, HashAlgo::Sha1).is_none());
+    }
+}
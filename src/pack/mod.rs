@@ -0,0 +1,708 @@
+pub mod idx;
+
+use crate::hash::{HashAlgo, Hasher};
+use crate::object::{GitObject, ObjectType};
+use idx::{IdxEntry, PackIndex};
+use libflate::zlib::{Decoder, Encoder};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Cursor, Read, Write};
+
+const PACK_SIGNATURE: &[u8; 4] = b"PACK";
+const PACK_VERSION: u32 = 2;
+
+// pack 内での object の種類を表す番号
+pub const OBJ_COMMIT: u8 = 1;
+pub const OBJ_TREE: u8 = 2;
+pub const OBJ_BLOB: u8 = 3;
+pub const OBJ_TAG: u8 = 4;
+pub const OBJ_OFS_DELTA: u8 = 6;
+pub const OBJ_REF_DELTA: u8 = 7;
+
+pub fn type_code(object_type: ObjectType) -> u8 {
+    match object_type {
+        ObjectType::Commit => OBJ_COMMIT,
+        ObjectType::Tree => OBJ_TREE,
+        ObjectType::Blob => OBJ_BLOB,
+    }
+}
+
+pub fn type_name(code: u8) -> Option<&'static str> {
+    match code {
+        OBJ_COMMIT => Some("commit"),
+        OBJ_TREE => Some("tree"),
+        OBJ_BLOB => Some("blob"),
+        OBJ_TAG => Some("tag"),
+        _ => None,
+    }
+}
+
+pub fn type_code_from_name(name: &str) -> Option<u8> {
+    match name {
+        "commit" => Some(OBJ_COMMIT),
+        "tree" => Some(OBJ_TREE),
+        "blob" => Some(OBJ_BLOB),
+        "tag" => Some(OBJ_TAG),
+        _ => None,
+    }
+}
+
+// loose object と同じ "<type> <size>\0<content>" の形式にする
+pub fn object_bytes(code: u8, content: &[u8]) -> Vec<u8> {
+    let header = format!(
+        "{} {}\0",
+        type_name(code).unwrap_or_default(),
+        content.len()
+    );
+    [header.as_bytes(), content].concat()
+}
+
+// .git/objects/pack 以下の pack と idx の組
+pub struct Pack {
+    // 拡張子を除いたファイル名
+    pub name: String,
+    pub index: PackIndex,
+    pub data: Vec<u8>,
+}
+
+// type と size を pack の可変長ヘッダにする
+// 1byte 目は MSB が継続ビット, 続く 3bit が type, 下位 4bit が size
+pub fn encode_entry_header(code: u8, size: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut byte = (code << 4) | (size & 0x0f) as u8;
+    let mut size = size >> 4;
+    while size > 0 {
+        bytes.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    bytes.push(byte);
+    bytes
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(bytes);
+    hasher.finalize()
+}
+
+// pack の可変長ヘッダを読んで (type, size, ヘッダの長さ) を返す
+pub fn decode_entry_header(bytes: &[u8]) -> io::Result<(u8, usize, usize)> {
+    let truncated = || invalid_data("truncated pack entry");
+    let first = *bytes.first().ok_or_else(truncated)?;
+    let code = (first >> 4) & 0x07;
+    let mut size = (first & 0x0f) as usize;
+    let mut shift = 4;
+    let mut len = 1;
+    let mut byte = first;
+    while byte & 0x80 != 0 {
+        byte = *bytes.get(len).ok_or_else(truncated)?;
+        size =
+            shift_or(size, byte, shift).ok_or_else(|| invalid_data("pack entry size overflows"))?;
+        shift += 7;
+        len += 1;
+    }
+    Ok((code, size, len))
+}
+
+// ofs-delta の base までの距離を読んで (距離, 長さ) を返す
+// 2byte 目以降は 1 を足してから shift するという git 独自の形式
+fn decode_offset(bytes: &[u8]) -> io::Result<(u64, usize)> {
+    let truncated = || invalid_data("truncated pack entry");
+    let mut byte = *bytes.first().ok_or_else(truncated)?;
+    let mut offset = (byte & 0x7f) as u64;
+    let mut len = 1;
+    while byte & 0x80 != 0 {
+        byte = *bytes.get(len).ok_or_else(truncated)?;
+        offset = offset
+            .checked_add(1)
+            .and_then(|x| x.checked_mul(1 << 7))
+            .ok_or_else(|| invalid_data("delta base offset overflows"))?
+            | (byte & 0x7f) as u64;
+        len += 1;
+    }
+    Ok((offset, len))
+}
+
+// delta の先頭にある元と結果のサイズ
+fn decode_size(bytes: &[u8], pos: &mut usize) -> io::Result<usize> {
+    let mut size = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes
+            .get(*pos)
+            .ok_or_else(|| invalid_data("truncated delta"))?;
+        *pos += 1;
+        size = shift_or(size, byte, shift).ok_or_else(|| invalid_data("delta size overflows"))?;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+    }
+}
+
+// byte の下位 7bit を shift して size に足す. usize に収まらなければ None
+fn shift_or(size: usize, byte: u8, shift: u32) -> Option<usize> {
+    let value = (byte & 0x7f) as usize;
+    let shifted = value.checked_shl(shift).filter(|x| x >> shift == value)?;
+    Some(size | shifted)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// delta の命令列を base に適用する
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> io::Result<Vec<u8>> {
+    let mut pos = 0;
+    let base_size = decode_size(delta, &mut pos)?;
+    let result_size = decode_size(delta, &mut pos)?;
+    if base_size != base.len() {
+        return Err(invalid_data("delta base size mismatch"));
+    }
+
+    // result_size は pack に書かれた値なので, そのまま確保せずに入力の大きさで抑える
+    let mut result = Vec::with_capacity(result_size.min(base.len() + delta.len()));
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+
+        if op & 0x80 != 0 {
+            // copy: 下位 4bit が offset, 続く 3bit が size のどのバイトがあるか
+            let mut args = [0u32; 2];
+            for i in 0..7 {
+                if op & (1 << i) != 0 {
+                    let byte = *delta
+                        .get(pos)
+                        .ok_or_else(|| invalid_data("truncated delta"))?;
+                    pos += 1;
+                    let (arg, shift) = if i < 4 { (0, i) } else { (1, i - 4) };
+                    args[arg] |= (byte as u32) << (shift * 8);
+                }
+            }
+            let offset = args[0] as usize;
+            let size = if args[1] == 0 {
+                0x10000
+            } else {
+                args[1] as usize
+            };
+            let chunk = offset
+                .checked_add(size)
+                .and_then(|end| base.get(offset..end))
+                .ok_or_else(|| invalid_data("delta copy out of range"))?;
+            result.extend_from_slice(chunk);
+        } else if op != 0 {
+            // insert: op バイトをそのまま追加
+            let chunk = delta
+                .get(pos..pos + op as usize)
+                .ok_or_else(|| invalid_data("truncated delta"))?;
+            result.extend_from_slice(chunk);
+            pos += op as usize;
+        } else {
+            return Err(invalid_data("invalid delta opcode"));
+        }
+    }
+
+    if result.len() != result_size {
+        return Err(invalid_data("delta result size mismatch"));
+    }
+    Ok(result)
+}
+
+// zlib で圧縮されたデータを展開して (展開後のデータ, 圧縮後の長さ) を返す
+fn inflate_at(bytes: &[u8]) -> io::Result<(Vec<u8>, usize)> {
+    let mut cursor = Cursor::new(bytes);
+    let mut decoder = Decoder::new(&mut cursor)?;
+    let mut buf = Vec::new();
+    decoder.read_to_end(&mut buf)?;
+    Ok((buf, cursor.position() as usize))
+}
+
+// delta の参照先
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeltaBase {
+    Offset(u64),
+    Hash(Vec<u8>),
+}
+
+// pack 内の1エントリ. delta の場合 data は delta の命令列
+pub struct RawEntry {
+    pub offset: u64,
+    pub code: u8,
+    pub base: Option<DeltaBase>,
+    pub data: Vec<u8>,
+    // 次のエントリの位置
+    pub end: u64,
+}
+
+// offset の位置にあるエントリを読む
+pub fn read_entry(pack: &[u8], offset: u64, algo: HashAlgo) -> io::Result<RawEntry> {
+    let start = offset as usize;
+    let bytes = pack
+        .get(start..)
+        .ok_or_else(|| invalid_data("pack offset out of range"))?;
+    let (code, size, mut pos) = decode_entry_header(bytes)?;
+
+    let base = match code {
+        OBJ_OFS_DELTA => {
+            let (distance, len) = decode_offset(&bytes[pos..])?;
+            pos += len;
+            // 距離が 0 だと自分自身を base にしてしまう
+            let base = offset
+                .checked_sub(distance)
+                .filter(|_| distance > 0)
+                .ok_or_else(|| invalid_data("delta base out of range"))?;
+            Some(DeltaBase::Offset(base))
+        }
+        OBJ_REF_DELTA => {
+            let hash = bytes
+                .get(pos..pos + algo.width())
+                .ok_or_else(|| invalid_data("truncated pack entry"))?;
+            pos += algo.width();
+            Some(DeltaBase::Hash(hash.to_vec()))
+        }
+        OBJ_COMMIT | OBJ_TREE | OBJ_BLOB | OBJ_TAG => None,
+        _ => return Err(invalid_data("unknown pack object type")),
+    };
+
+    let (data, len) = inflate_at(&bytes[pos..])?;
+    if data.len() != size {
+        return Err(invalid_data("pack entry size mismatch"));
+    }
+
+    Ok(RawEntry {
+        offset,
+        code,
+        base,
+        data,
+        end: offset + (pos + len) as u64,
+    })
+}
+
+// 末尾の checksum が中身と一致するか確認して object の数を返す
+pub fn verify_pack(pack: &[u8], algo: HashAlgo) -> io::Result<u32> {
+    let width = algo.width();
+    if pack.len() < 12 + width || &pack[0..4] != PACK_SIGNATURE {
+        return Err(invalid_data("not a pack file"));
+    }
+    if pack[4..8] != PACK_VERSION.to_be_bytes() {
+        return Err(invalid_data("unsupported pack version"));
+    }
+
+    let (content, checksum) = pack.split_at(pack.len() - width);
+    if algo.digest(content) != checksum {
+        return Err(invalid_data("pack checksum mismatch"));
+    }
+
+    Ok(u32::from_be_bytes([pack[8], pack[9], pack[10], pack[11]]))
+}
+
+// 相手が接続を閉じずに返事を待っていても読めるように, 末尾の checksum まで pack を読む
+// エントリが途中までしか届いていなければ続きを読んでから読み直す
+pub fn read_pack<R: Read>(mut reader: R, algo: HashAlgo) -> io::Result<Vec<u8>> {
+    let mut pack = Vec::new();
+    let mut fill = |pack: &mut Vec<u8>| -> io::Result<()> {
+        let mut buf = [0; 65536];
+        match reader.read(&mut buf)? {
+            0 => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "pack ended unexpectedly",
+            )),
+            n => {
+                pack.extend_from_slice(&buf[..n]);
+                Ok(())
+            }
+        }
+    };
+
+    while pack.len() < 12 {
+        fill(&mut pack)?;
+    }
+    if &pack[0..4] != PACK_SIGNATURE {
+        return Err(invalid_data("not a pack file"));
+    }
+    let count = u32::from_be_bytes([pack[8], pack[9], pack[10], pack[11]]);
+    let mut offset = 12;
+    for _ in 0..count {
+        offset = loop {
+            match read_entry(&pack, offset, algo) {
+                Ok(entry) => break entry.end,
+                Err(_) => fill(&mut pack)?,
+            }
+        };
+    }
+    let end = offset as usize + algo.width();
+    while pack.len() < end {
+        fill(&mut pack)?;
+    }
+    pack.truncate(end);
+    verify_pack(&pack, algo)?;
+
+    Ok(pack)
+}
+
+// delta を解決済みの object
+pub struct PackObject {
+    pub offset: u64,
+    pub crc32: u32,
+    pub code: u8,
+    pub content: Vec<u8>,
+}
+
+impl PackObject {
+    // loose object と同じ "<type> <size>\0<content>" の形式
+    pub fn as_bytes(&self) -> Vec<u8> {
+        object_bytes(self.code, &self.content)
+    }
+}
+
+// pack の全ての object を delta を解決して取り出す
+// ref-delta の base は pack 内にあるものだけ解決でき, 無ければ thin pack としてエラーにする
+pub fn unpack(pack: &[u8], algo: HashAlgo) -> io::Result<Vec<PackObject>> {
+    let count = verify_pack(pack, algo)?;
+    let end = (pack.len() - algo.width()) as u64;
+
+    let mut entries = Vec::new();
+    let mut offset = 12;
+    for _ in 0..count {
+        let entry = read_entry(pack, offset, algo)?;
+        if entry.end > end {
+            return Err(invalid_data("truncated pack entry"));
+        }
+        offset = entry.end;
+        entries.push(entry);
+    }
+
+    let mut resolved: Vec<Option<PackObject>> = entries.iter().map(|_| None).collect();
+    let mut by_offset: HashMap<u64, usize> = HashMap::new();
+    let mut by_hash: HashMap<Vec<u8>, usize> = HashMap::new();
+
+    // base が後ろにある ref-delta のために解決できなくなるまで繰り返す
+    loop {
+        let mut progress = false;
+        for (i, entry) in entries.iter().enumerate() {
+            if resolved[i].is_some() {
+                continue;
+            }
+
+            let base = match &entry.base {
+                None => None,
+                Some(DeltaBase::Offset(x)) => match by_offset.get(x) {
+                    Some(&j) => Some(j),
+                    None => continue,
+                },
+                Some(DeltaBase::Hash(x)) => match by_hash.get(x) {
+                    Some(&j) => Some(j),
+                    None => continue,
+                },
+            };
+
+            let (code, content) = match base {
+                None => (entry.code, entry.data.clone()),
+                Some(j) => {
+                    let base = resolved[j].as_ref().unwrap();
+                    (base.code, apply_delta(&base.content, &entry.data)?)
+                }
+            };
+            let object = PackObject {
+                offset: entry.offset,
+                crc32: crc32(&pack[entry.offset as usize..entry.end as usize]),
+                code,
+                content,
+            };
+
+            by_offset.insert(entry.offset, i);
+            by_hash.insert(algo.digest(&object.as_bytes()), i);
+            resolved[i] = Some(object);
+            progress = true;
+        }
+
+        if !progress {
+            break;
+        }
+    }
+
+    if resolved.iter().any(|x| x.is_none()) {
+        return Err(invalid_data(
+            "unresolved delta base (thin packs are not supported)",
+        ));
+    }
+
+    Ok(resolved.into_iter().flatten().collect())
+}
+
+// pack を走査して idx v2 を作る
+// thin pack は unpack と同じくエラーにする
+pub fn index_pack(pack: &[u8], algo: HashAlgo) -> io::Result<Vec<u8>> {
+    let objects = unpack(pack, algo)?;
+    let mut entries = objects
+        .iter()
+        .map(|x| IdxEntry {
+            hash: algo.digest(&x.as_bytes()),
+            crc32: x.crc32,
+            offset: x.offset,
+        })
+        .collect::<Vec<_>>();
+
+    let pack_hash = &pack[(pack.len() - algo.width())..];
+    Ok(idx::write_idx(&mut entries, pack_hash, algo))
+}
+
+// 書き出した pack と idx
+pub struct PackFile {
+    pub hash: Vec<u8>,
+    pub pack: Vec<u8>,
+    pub idx: Vec<u8>,
+}
+
+// delta を使わずに object をそのまま詰める pack の書き出し
+pub struct PackWriter {
+    algo: HashAlgo,
+    objects: Vec<u8>,
+    entries: Vec<IdxEntry>,
+    hashes: HashSet<Vec<u8>>,
+}
+
+impl PackWriter {
+    pub fn new() -> Self {
+        Self::with_hash_algo(HashAlgo::default())
+    }
+
+    pub fn with_hash_algo(algo: HashAlgo) -> Self {
+        Self {
+            algo,
+            objects: Vec::new(),
+            entries: Vec::new(),
+            hashes: HashSet::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // 同じ object を2回追加しても1つしか格納しない
+    pub fn add(&mut self, object: GitObject) -> io::Result<()> {
+        self.add_raw(&object.as_bytes())
+    }
+
+    // loose object と同じ "<type> <size>\0<content>" の形式のまま追加する
+    // 読み直すと元のバイト列に戻らない object もそのまま詰められる
+    pub fn add_raw(&mut self, raw: &[u8]) -> io::Result<()> {
+        let object_type =
+            GitObject::peek_type(raw).ok_or_else(|| invalid_data("bad object header"))?;
+        let start = raw
+            .iter()
+            .position(|&x| x == b'\0')
+            .ok_or_else(|| invalid_data("bad object header"))?;
+        let hash = self.algo.digest(raw);
+        if !self.hashes.insert(hash.clone()) {
+            return Ok(());
+        }
+
+        let content = &raw[(start + 1)..];
+        let mut encoder = Encoder::new(Vec::new())?;
+        encoder.write_all(content)?;
+        let compressed = encoder.finish().into_result()?;
+
+        let entry = [
+            encode_entry_header(type_code(object_type), content.len()),
+            compressed,
+        ]
+        .concat();
+
+        // offset は pack の先頭 12byte のヘッダを含めた位置
+        self.entries.push(IdxEntry {
+            hash,
+            crc32: crc32(&entry),
+            offset: 12 + self.objects.len() as u64,
+        });
+        self.objects.extend(entry);
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> PackFile {
+        let header = [
+            &PACK_SIGNATURE[..],
+            &PACK_VERSION.to_be_bytes(),
+            &(self.entries.len() as u32).to_be_bytes(),
+        ]
+        .concat();
+        let mut pack = [header, self.objects].concat();
+        let hash = self.algo.digest(&pack);
+        pack.extend_from_slice(&hash);
+
+        let idx = idx::write_idx(&mut self.entries, &hash, self.algo);
+
+        PackFile { hash, pack, idx }
+    }
+}
+
+impl Default for PackWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::blob::Blob;
+    use std::process::Command;
+
+    #[test]
+    fn test_encode_entry_header() {
+        assert_eq!(encode_entry_header(OBJ_BLOB, 10), vec![0x3a]);
+        assert_eq!(encode_entry_header(OBJ_COMMIT, 15), vec![0x1f]);
+        // 16 = 0b1_0000 -> 下位 4bit は 0, 残りは 1
+        assert_eq!(encode_entry_header(OBJ_BLOB, 16), vec![0xb0, 0x01]);
+        assert_eq!(encode_entry_header(OBJ_TREE, 300), vec![0xac, 0x12]);
+    }
+
+    #[test]
+    fn test_apply_delta() {
+        let base = b"hello, git";
+        // base 10byte, 結果 12byte, "hello" をコピーして ", pack" を挿入し "!" を挿入
+        let delta = [&[10, 12][..], &[0x90, 5], &[6], b", pack", &[1], b"!"].concat();
+        assert_eq!(apply_delta(base, &delta).unwrap(), b"hello, pack!");
+
+        // base のサイズが違う
+        assert!(apply_delta(b"hello", &delta).is_err());
+        // 範囲外のコピー
+        assert!(apply_delta(base, &[10, 5, 0x91, 8, 5]).is_err());
+
+        // 結果のサイズが大きすぎても先に確保しない
+        let huge = [&[10][..], &[0xff; 9], &[0x01], &[0x90, 5]].concat();
+        assert!(apply_delta(base, &huge).is_err());
+        // usize に収まらないサイズ
+        let overflow = [&[10][..], &[0xff; 10], &[0x7f]].concat();
+        let e = apply_delta(base, &overflow).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn decode_overflow() {
+        // 9byte までなら size は64bit に収まる
+        let mut header = vec![0xbf];
+        header.extend([0xff; 8]);
+        header.push(0x00);
+        assert!(decode_entry_header(&header).is_ok());
+
+        // 壊れた pack の長すぎる可変長の値は panic せずに error にする
+        let e = decode_entry_header(&[0xbf; 20]).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        let e = decode_offset(&[0xff; 20]).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(decode_offset(&[0x80, 0x00]).unwrap(), (128, 2));
+        assert!(decode_entry_header(&[0xbf]).is_err());
+    }
+
+    #[test]
+    fn read_entry_ofs_delta_to_itself() {
+        let mut pack = vec![0; 12];
+        pack.extend(encode_entry_header(OBJ_OFS_DELTA, 4));
+        // base までの距離が 0
+        pack.push(0);
+        let e = read_entry(&pack, 12, HashAlgo::Sha1).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn pack_writer() {
+        let mut writer = PackWriter::new();
+        writer
+            .add(GitObject::Blob(Blob::new("hello, git".to_string())))
+            .unwrap();
+        writer
+            .add(GitObject::Blob(Blob::new("hello, git".to_string())))
+            .unwrap();
+        writer
+            .add(GitObject::Blob(Blob::new("hello, pack".to_string())))
+            .unwrap();
+        assert_eq!(writer.len(), 2);
+
+        let pack = writer.finish();
+        assert_eq!(&pack.pack[0..12], b"PACK\0\0\0\x02\0\0\0\x02");
+        assert_eq!(
+            pack.hash,
+            HashAlgo::Sha1.digest(&pack.pack[..pack.pack.len() - 20])
+        );
+        assert_eq!(
+            &pack.idx[pack.idx.len() - 40..pack.idx.len() - 20],
+            &pack.hash[..]
+        );
+    }
+
+    #[test]
+    fn pack_read_pack() {
+        let mut writer = PackWriter::new();
+        writer
+            .add(GitObject::Blob(Blob::new("x".repeat(100000))))
+            .unwrap();
+        writer
+            .add(GitObject::Blob(Blob::new("hello, git".to_string())))
+            .unwrap();
+        let pack = writer.finish().pack;
+
+        // checksum の後ろは pack に含めない
+        let mut bytes = pack.clone();
+        bytes.extend_from_slice(b"0000");
+        assert_eq!(read_pack(bytes.as_slice(), HashAlgo::Sha1).unwrap(), pack);
+
+        // 1byte ずつしか届かなくても読める
+        struct Slow<'a>(&'a [u8]);
+        impl Read for Slow<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                (&mut self.0).take(1).read(buf)
+            }
+        }
+        assert_eq!(read_pack(Slow(&pack), HashAlgo::Sha1).unwrap(), pack);
+
+        let e = read_pack(&pack[..pack.len() - 1], HashAlgo::Sha1).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(read_pack(&b"PACX\0\0\0\x02\0\0\0\0"[..], HashAlgo::Sha1).is_err());
+    }
+
+    #[test]
+    fn pack_writer_verify_pack() {
+        // git が無い環境では確認しない
+        if Command::new("git").arg("--version").output().is_err() {
+            return;
+        }
+
+        let mut writer = PackWriter::new();
+        writer
+            .add(GitObject::Blob(Blob::new("hello, git".to_string())))
+            .unwrap();
+        writer
+            .add(GitObject::Blob(Blob::new("x".repeat(1000))))
+            .unwrap();
+        let pack = writer.finish();
+
+        let dir = std::env::temp_dir().join(format!("rgit-pack-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let name = format!("pack-{}", hex::encode(&pack.hash));
+        std::fs::write(dir.join(format!("{}.pack", name)), &pack.pack).unwrap();
+        std::fs::write(dir.join(format!("{}.idx", name)), &pack.idx).unwrap();
+
+        let output = Command::new("git")
+            .arg("verify-pack")
+            .arg("-v")
+            .arg(dir.join(format!("{}.idx", name)))
+            .output()
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("3edbc45b9a7f744c2345cd2cd073c3de091341ac blob"));
+    }
+}
@@ -0,0 +1,960 @@
+// unified diff 形式のパッチ
+// `diff --git` のヘッダ, `---` / `+++`, `@@` で始まる hunk を読み書きする
+use crate::ident;
+use crate::object::tree::MODE_FILE;
+use chrono::{DateTime, FixedOffset};
+use std::cmp::Ordering;
+use std::fmt;
+
+// hunk を探す時に元の位置からずれていても良い行数
+pub const MAX_OFFSET: usize = 10;
+// diff で変更の前後に付ける行数
+pub const CONTEXT: usize = 3;
+// git と同じく先頭のこのバイト数に NUL があれば binary とみなす
+const BINARY_CHECK_SIZE: usize = 8000;
+
+// 1つのファイルに対する変更. 新しいファイルなら old_path, 削除なら new_path が None
+// mode は `new file mode` や `old mode` / `new mode` の行に書かれていた時だけ持つ
+// binary なら hunk は持たず `Binary files ... differ` とだけ書く
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FilePatch {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    pub old_mode: Option<usize>,
+    pub new_mode: Option<usize>,
+    pub binary: bool,
+    pub hunks: Vec<Hunk>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hunk {
+    // `@@ -1,3 +1,4 @@` の行
+    pub header: String,
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    pub lines: Vec<Line>,
+}
+
+// 行の中身は改行を含む. `\ No newline at end of file` が続く行だけ改行が無い
+#[derive(Clone, Debug, PartialEq)]
+pub enum Line {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+impl FilePatch {
+    // 表示や エラーに使う名前
+    pub fn path(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or_default()
+    }
+}
+
+// hunk を当てる前と後の行
+impl Hunk {
+    fn before(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|x| match x {
+                Line::Context(s) | Line::Remove(s) => Some(s.as_str()),
+                Line::Add(_) => None,
+            })
+            .collect()
+    }
+
+    fn after(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|x| match x {
+                Line::Context(s) | Line::Add(s) => Some(s.as_str()),
+                Line::Remove(_) => None,
+            })
+            .collect()
+    }
+}
+
+// パッチ全体を読む. メールのヘッダなど最初の diff より前の行は読み飛ばす
+// 壊れていれば何行目かを返す
+pub fn parse(text: &str) -> Result<Vec<FilePatch>, usize> {
+    let lines = text.split_inclusive('\n').collect::<Vec<_>>();
+    let mut patches: Vec<FilePatch> = Vec::new();
+    // `diff --git` の後で, まだ `---` を読んでいない patch があるか
+    let mut in_header = false;
+
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim_end_matches('\n');
+        if let Some(rest) = line.strip_prefix("diff --git ") {
+            let (old, new) = parse_git_paths(rest).ok_or(i + 1)?;
+            patches.push(FilePatch {
+                old_path: Some(old),
+                new_path: Some(new),
+                ..FilePatch::default()
+            });
+            in_header = true;
+        } else if let (Some(mode), true) = (line.strip_prefix("new file mode "), in_header) {
+            let patch = patches.last_mut().ok_or(i + 1)?;
+            patch.old_path = None;
+            patch.new_mode = Some(parse_mode(mode).ok_or(i + 1)?);
+        } else if let (Some(mode), true) = (line.strip_prefix("deleted file mode "), in_header) {
+            let patch = patches.last_mut().ok_or(i + 1)?;
+            patch.new_path = None;
+            patch.old_mode = Some(parse_mode(mode).ok_or(i + 1)?);
+        } else if let (Some(mode), true) = (line.strip_prefix("old mode "), in_header) {
+            patches.last_mut().ok_or(i + 1)?.old_mode = Some(parse_mode(mode).ok_or(i + 1)?);
+        } else if let (Some(mode), true) = (line.strip_prefix("new mode "), in_header) {
+            patches.last_mut().ok_or(i + 1)?.new_mode = Some(parse_mode(mode).ok_or(i + 1)?);
+        } else if line.starts_with("Binary files ") && in_header {
+            patches.last_mut().ok_or(i + 1)?.binary = true;
+        } else if let Some(old) = line.strip_prefix("--- ") {
+            let new = lines
+                .get(i + 1)
+                .and_then(|x| x.trim_end_matches('\n').strip_prefix("+++ "))
+                .ok_or(i + 2)?;
+            let (old, new) = (parse_path(old, "a/"), parse_path(new, "b/"));
+            if !in_header {
+                patches.push(FilePatch::default());
+            }
+            let patch = patches.last_mut().ok_or(i + 1)?;
+            patch.old_path = old;
+            patch.new_path = new;
+            in_header = false;
+            i += 1;
+        } else if line.starts_with("@@ ") {
+            let patch = patches.last_mut().ok_or(i + 1)?;
+            let (hunk, next) = parse_hunk(&lines, i).ok_or(i + 1)?;
+            patch.hunks.push(hunk);
+            in_header = false;
+            i = next;
+            continue;
+        }
+        i += 1;
+    }
+
+    Ok(patches)
+}
+
+// hunks を content に当てはめる. 当てはまらなかった hunk を返す
+pub fn apply<'a>(content: &str, hunks: &'a [Hunk]) -> Result<String, &'a Hunk> {
+    let lines = content.split_inclusive('\n').collect::<Vec<_>>();
+    let mut result = String::new();
+    let mut cursor = 0;
+    for hunk in hunks {
+        let old = hunk.before();
+        // 行を含まない hunk は old_start 行目の後ろに入る
+        let expected = if hunk.old_lines == 0 {
+            hunk.old_start
+        } else {
+            hunk.old_start.saturating_sub(1)
+        };
+
+        let pos = (0..=MAX_OFFSET)
+            .flat_map(|x| {
+                let before = expected.checked_sub(x);
+                let after = if x == 0 { None } else { Some(expected + x) };
+                before.into_iter().chain(after)
+            })
+            .find(|&pos| {
+                pos >= cursor
+                    && pos + old.len() <= lines.len()
+                    && lines[pos..(pos + old.len())] == old[..]
+            })
+            .ok_or(hunk)?;
+
+        result.extend(lines[cursor..pos].iter().copied());
+        result.extend(hunk.after());
+        cursor = pos + old.len();
+    }
+    result.extend(lines[cursor..].iter().copied());
+
+    Ok(result)
+}
+
+// 先頭に NUL を含むか, 文字列として読めない内容は行の差分を取らない
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.iter().take(BINARY_CHECK_SIZE).any(|&x| x == 0) || std::str::from_utf8(bytes).is_err()
+}
+
+// old から new への差分
+// Myers の O(ND) の方法を, 真ん中の snake で分割していく線形の記憶域の形で使う
+// http://www.xmailserver.org/diff2.pdf
+pub fn diff(old: &str, new: &str) -> Vec<Hunk> {
+    let a = old.split_inclusive('\n').collect::<Vec<_>>();
+    let b = new.split_inclusive('\n').collect::<Vec<_>>();
+
+    // 編集グラフ上の点を (0, 0) から (a.len(), b.len()) まで繋いだもの
+    // 隣り合う点の間は高々1つの追加か削除と, 一致する行の並びになっている
+    let mut path = Vec::new();
+    find_path(&a, &b, (0, 0), (a.len(), b.len()), &mut path);
+
+    let mut lines = Vec::new();
+    // 変更が続く間の追加と削除. git と同じく削除を先に並べる
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    for points in path.windows(2) {
+        let ((mut x, mut y), (x2, y2)) = (points[0], points[1]);
+        let mut moves = Vec::new();
+        while x < x2 && y < y2 && a[x] == b[y] {
+            moves.push(Line::Context(a[x].to_string()));
+            x += 1;
+            y += 1;
+        }
+        match (x2 - x).cmp(&(y2 - y)) {
+            Ordering::Less => {
+                moves.push(Line::Add(b[y].to_string()));
+                y += 1;
+            }
+            Ordering::Greater => {
+                moves.push(Line::Remove(a[x].to_string()));
+                x += 1;
+            }
+            Ordering::Equal => {}
+        }
+        while x < x2 && y < y2 && a[x] == b[y] {
+            moves.push(Line::Context(a[x].to_string()));
+            x += 1;
+            y += 1;
+        }
+
+        for line in moves {
+            match line {
+                Line::Remove(_) => removed.push(line),
+                Line::Add(_) => added.push(line),
+                Line::Context(_) => {
+                    lines.append(&mut removed);
+                    lines.append(&mut added);
+                    lines.push(line);
+                }
+            }
+        }
+    }
+    lines.append(&mut removed);
+    lines.append(&mut added);
+
+    // 各行とその行より前にある old と new の行数
+    let mut script = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for line in lines {
+        let (di, dj) = match line {
+            Line::Context(_) => (1, 1),
+            Line::Remove(_) => (1, 0),
+            Line::Add(_) => (0, 1),
+        };
+        script.push((line, i, j));
+        i += di;
+        j += dj;
+    }
+
+    let changes = script
+        .iter()
+        .enumerate()
+        .filter(|(_, (line, _, _))| !matches!(line, Line::Context(_)))
+        .map(|(k, _)| k)
+        .collect::<Vec<_>>();
+
+    // 間の context が 2 * CONTEXT 行以下の変更は同じ hunk にまとめる
+    let mut hunks = Vec::new();
+    let mut k = 0;
+    while k < changes.len() {
+        let start = changes[k].saturating_sub(CONTEXT);
+        let mut end = changes[k] + 1;
+        k += 1;
+        while k < changes.len() && changes[k] <= end + 2 * CONTEXT {
+            end = changes[k] + 1;
+            k += 1;
+        }
+        let end = (end + CONTEXT).min(script.len());
+        hunks.push(Hunk::from_script(&script[start..end]));
+    }
+
+    hunks
+}
+
+// start から end までの最短編集を, 真ん中の snake の前と後に分けて求め, 通る点を path に足す
+// 範囲が空なら何もせずに false を返す
+fn find_path(
+    a: &[&str],
+    b: &[&str],
+    start: (usize, usize),
+    end: (usize, usize),
+    path: &mut Vec<(usize, usize)>,
+) -> bool {
+    let (snake_start, snake_end) = match middle_snake(a, b, start, end) {
+        Some(snake) => snake,
+        None => return false,
+    };
+    if !find_path(a, b, start, snake_start, path) {
+        path.push(snake_start);
+    }
+    if !find_path(a, b, snake_end, end, path) {
+        path.push(snake_end);
+    }
+    true
+}
+
+// 前と後ろから同時に最短編集を探し, 両方が重なった snake の始点と終点を返す
+// 始点の直前の1手の追加か削除も snake に含める
+#[allow(clippy::type_complexity)]
+fn middle_snake(
+    a: &[&str],
+    b: &[&str],
+    (left, top): (usize, usize),
+    (right, bottom): (usize, usize),
+) -> Option<((usize, usize), (usize, usize))> {
+    let (left, top, right, bottom) = (left as isize, top as isize, right as isize, bottom as isize);
+    let size = (right - left) + (bottom - top);
+    if size == 0 {
+        return None;
+    }
+    let max = (size + 1) / 2;
+    let delta = (right - left) - (bottom - top);
+    // 対角線の番号 k を添字にするためにずらす
+    let at = |k: isize| (k + max + 1) as usize;
+    let same = |x: isize, y: isize| a[x as usize] == b[y as usize];
+    let point = |x: isize, y: isize| (x as usize, y as usize);
+
+    // 対角線ごとに, 前から進んだ x と後ろから戻った y の到達点
+    let mut forward = vec![0; at(max + 1) + 1];
+    let mut backward = vec![0; at(max + 1) + 1];
+    forward[at(1)] = left;
+    backward[at(1)] = bottom;
+    for d in 0..=max {
+        for k in (-d..=d).rev().step_by(2) {
+            let (px, mut x) = if k == -d || (k != d && forward[at(k - 1)] < forward[at(k + 1)]) {
+                (forward[at(k + 1)], forward[at(k + 1)])
+            } else {
+                (forward[at(k - 1)], forward[at(k - 1)] + 1)
+            };
+            let mut y = top + (x - left) - k;
+            let py = if d == 0 || x != px { y } else { y - 1 };
+            while x < right && y < bottom && same(x, y) {
+                x += 1;
+                y += 1;
+            }
+            forward[at(k)] = x;
+            let c = k - delta;
+            if delta % 2 != 0 && -d < c && c < d && y >= backward[at(c)] {
+                return Some((point(px, py), point(x, y)));
+            }
+        }
+
+        for c in (-d..=d).rev().step_by(2) {
+            let (py, mut y) = if c == -d || (c != d && backward[at(c - 1)] > backward[at(c + 1)]) {
+                (backward[at(c + 1)], backward[at(c + 1)])
+            } else {
+                (backward[at(c - 1)], backward[at(c - 1)] - 1)
+            };
+            let k = c + delta;
+            let mut x = left + (y - top) + k;
+            let px = if d == 0 || y != py { x } else { x + 1 };
+            while x > left && y > top && same(x - 1, y - 1) {
+                x -= 1;
+                y -= 1;
+            }
+            backward[at(c)] = y;
+            if delta % 2 == 0 && -d <= k && k <= d && x <= forward[at(k)] {
+                return Some((point(x, y), point(px, py)));
+            }
+        }
+    }
+
+    None
+}
+
+impl Hunk {
+    fn from_script(script: &[(Line, usize, usize)]) -> Self {
+        let (_, old_before, new_before) = script[0];
+        let lines = script.iter().map(|x| x.0.clone()).collect::<Vec<_>>();
+        let old_lines = lines.iter().filter(|x| !matches!(x, Line::Add(_))).count();
+        let new_lines = lines
+            .iter()
+            .filter(|x| !matches!(x, Line::Remove(_)))
+            .count();
+        // 行を含まない側は直前の行番号を使う
+        let start = |before: usize, len: usize| if len == 0 { before } else { before + 1 };
+        let range = |start: usize, len: usize| match len {
+            1 => start.to_string(),
+            _ => format!("{},{}", start, len),
+        };
+        let (old_start, new_start) = (start(old_before, old_lines), start(new_before, new_lines));
+
+        Self {
+            header: format!(
+                "@@ -{} +{} @@",
+                range(old_start, old_lines),
+                range(new_start, new_lines)
+            ),
+            old_start,
+            old_lines,
+            new_start,
+            new_lines,
+            lines,
+        }
+    }
+}
+
+impl fmt::Display for FilePatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = self.path();
+        writeln!(f, "diff --git a/{} b/{}", path, path)?;
+        match (&self.old_path, &self.new_path, self.old_mode, self.new_mode) {
+            (None, _, _, mode) => writeln!(f, "new file mode {:o}", mode.unwrap_or(MODE_FILE))?,
+            (_, None, mode, _) => writeln!(f, "deleted file mode {:o}", mode.unwrap_or(MODE_FILE))?,
+            (_, _, Some(old), Some(new)) if old != new => {
+                writeln!(f, "old mode {:o}\nnew mode {:o}", old, new)?
+            }
+            _ => {}
+        }
+        let old = match &self.old_path {
+            Some(x) => format!("a/{}", x),
+            None => "/dev/null".to_string(),
+        };
+        let new = match &self.new_path {
+            Some(x) => format!("b/{}", x),
+            None => "/dev/null".to_string(),
+        };
+        if self.binary {
+            return writeln!(f, "Binary files {} and {} differ", old, new);
+        }
+        // mode だけの変更や空のファイルの追加では中身の行を書かない
+        if self.hunks.is_empty() {
+            return Ok(());
+        }
+        writeln!(f, "--- {}\n+++ {}", old, new)?;
+        self.hunks.iter().try_for_each(|x| write!(f, "{}", x))
+    }
+}
+
+impl fmt::Display for Hunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.header)?;
+        for line in self.lines.iter() {
+            let (kind, text) = match line {
+                Line::Context(s) => (' ', s),
+                Line::Remove(s) => ('-', s),
+                Line::Add(s) => ('+', s),
+            };
+            write!(f, "{}{}", kind, text)?;
+            if !text.ends_with('\n') {
+                write!(f, "\n\\ No newline at end of file\n")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// format-patch が書き出すメール 1 通
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mail {
+    pub name: String,
+    pub email: String,
+    pub date: DateTime<FixedOffset>,
+    // `[PATCH n/m]` を除いた Subject
+    pub subject: String,
+    // subject と本文を空行で繋いだもの
+    pub message: String,
+    // `---` 以降のパッチ
+    pub diff: String,
+}
+
+impl Mail {
+    pub fn parse(text: &str) -> Option<Self> {
+        let (header, body) = text.split_once("\n\n").unwrap_or((text, ""));
+
+        // 空白で始まる行は前の header の続き
+        let mut headers: Vec<(String, String)> = Vec::new();
+        for line in header.lines() {
+            if line.starts_with([' ', '\t']) {
+                let (_, value) = headers.last_mut()?;
+                value.push(' ');
+                value.push_str(line.trim());
+            } else if let Some((key, value)) = line.split_once(':') {
+                headers.push((key.to_lowercase(), value.trim().to_string()));
+            }
+        }
+        let get = |key: &str| headers.iter().find(|x| x.0 == key).map(|x| x.1.as_str());
+
+        let (name, email) = get("from")?.split_once('<')?;
+        let name = name.trim().trim_matches('"').to_string();
+        let email = email.trim_end_matches('>').to_string();
+        let date = ident::parse_date(get("date")?)?;
+        let subject = get("subject")?;
+        let subject = match subject.strip_prefix('[') {
+            Some(rest) => rest.split_once(']')?.1.trim(),
+            None => subject,
+        }
+        .to_string();
+
+        // 本文は `---` か diff の始まりまで
+        let lines = body.split_inclusive('\n').collect::<Vec<_>>();
+        let end = lines
+            .iter()
+            .position(|x| x.trim_end() == "---" || x.starts_with("diff --git "))
+            .unwrap_or(lines.len());
+        let description = lines[..end].concat();
+        let description = description.trim();
+        let message = if description.is_empty() {
+            format!("{}\n", subject)
+        } else {
+            format!("{}\n\n{}\n", subject, description)
+        };
+
+        Some(Self {
+            name,
+            email,
+            date,
+            subject,
+            message,
+            diff: lines[end..].concat(),
+        })
+    }
+}
+
+// mbox を空行の次の `From <送り主> <日時>` の行で 1 通ずつに分ける
+pub fn split_mbox(text: &str) -> Vec<String> {
+    let mut mails: Vec<String> = Vec::new();
+    let mut blank = true;
+    for line in text.split_inclusive('\n') {
+        match mails.last_mut() {
+            Some(mail) if !(blank && is_from_line(line)) => mail.push_str(line),
+            _ => mails.push(line.to_string()),
+        }
+        blank = line.trim_end().is_empty();
+    }
+
+    mails
+}
+
+// 本文の `From ` で始まる行と区別するため `hh:mm:ss` の時刻があるかも見る
+fn is_from_line(line: &str) -> bool {
+    line.strip_prefix("From ")
+        .map(|rest| {
+            rest.split_whitespace().skip(1).any(|x| {
+                let parts = x.split(':').collect::<Vec<_>>();
+                parts.len() == 3
+                    && parts
+                        .iter()
+                        .all(|x| x.len() == 2 && x.bytes().all(|b| b.is_ascii_digit()))
+            })
+        })
+        .unwrap_or_default()
+}
+
+// `a/foo b/foo`
+fn parse_git_paths(s: &str) -> Option<(String, String)> {
+    let (old, new) = s.split_once(" b/")?;
+    Some((old.strip_prefix("a/")?.to_string(), new.to_string()))
+}
+
+// `a/foo` や `/dev/null`. タブの後ろの日時は無視する
+fn parse_path(s: &str, prefix: &str) -> Option<String> {
+    let s = s.split('\t').next().unwrap_or_default().trim_end();
+    if s == "/dev/null" {
+        return None;
+    }
+    Some(s.strip_prefix(prefix).unwrap_or(s).to_string())
+}
+
+// `@@ -l,s +l,s @@` とそれに続く行. 次に読む行の位置も返す
+fn parse_hunk(lines: &[&str], start: usize) -> Option<(Hunk, usize)> {
+    let header = lines[start].trim_end_matches('\n');
+    let ranges = header.strip_prefix("@@ -")?.split(" @@").next()?;
+    let (old, new) = ranges.split_once(" +")?;
+    let (old_start, old_lines) = parse_range(old)?;
+    let (new_start, new_lines) = parse_range(new)?;
+
+    let mut hunk = Hunk {
+        header: header.to_string(),
+        old_start,
+        old_lines,
+        new_start,
+        new_lines,
+        lines: Vec::new(),
+    };
+    let (mut old_seen, mut new_seen) = (0, 0);
+    let mut i = start + 1;
+    while old_seen < old_lines || new_seen < new_lines {
+        let line = *lines.get(i)?;
+        // 空行は空白が削られた context として扱う
+        // 先頭が ASCII でない行は壊れた hunk なので, 文字の途中で切らずに諦める
+        let (kind, text) = match line.as_bytes().first() {
+            Some(b'\n') => (b' ', "\n"),
+            Some(&kind) if kind.is_ascii() => (kind, &line[1..]),
+            _ => return None,
+        };
+        match kind {
+            b' ' => {
+                hunk.lines.push(Line::Context(text.to_string()));
+                old_seen += 1;
+                new_seen += 1;
+            }
+            b'-' => {
+                hunk.lines.push(Line::Remove(text.to_string()));
+                old_seen += 1;
+            }
+            b'+' => {
+                hunk.lines.push(Line::Add(text.to_string()));
+                new_seen += 1;
+            }
+            b'\\' => strip_last_newline(&mut hunk),
+            _ => return None,
+        }
+        i += 1;
+    }
+    if old_seen != old_lines || new_seen != new_lines {
+        return None;
+    }
+    if lines
+        .get(i)
+        .map(|x| x.starts_with('\\'))
+        .unwrap_or_default()
+    {
+        strip_last_newline(&mut hunk);
+        i += 1;
+    }
+
+    Some((hunk, i))
+}
+
+// `\ No newline at end of file` は直前の行に改行が無いことを表す
+fn strip_last_newline(hunk: &mut Hunk) {
+    if let Some(Line::Context(s) | Line::Remove(s) | Line::Add(s)) = hunk.lines.last_mut() {
+        if s.ends_with('\n') {
+            s.pop();
+        }
+    }
+}
+
+// `100644` のような 8 進数の mode
+fn parse_mode(s: &str) -> Option<usize> {
+    usize::from_str_radix(s.trim(), 8).ok()
+}
+
+// `12,3` か `12` (1行)
+fn parse_range(s: &str) -> Option<(usize, usize)> {
+    match s.split_once(',') {
+        Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+        None => Some((s.parse().ok()?, 1)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATCH: &str = "From 1234 Mon Sep 17 00:00:00 2001
+Subject: [PATCH] update
+
+---
+diff --git a/hello.txt b/hello.txt
+index 1111111..2222222 100644
+--- a/hello.txt
++++ b/hello.txt
+@@ -1,3 +1,3 @@
+ one
+-two
++TWO
+ three
+@@ -8,2 +8,3 @@ seven
+ eight
+ nine
++ten
+\\ No newline at end of file
+diff --git a/new.txt b/new.txt
+new file mode 100644
+index 0000000..3333333
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1 @@
++new
+diff --git a/old.txt b/old.txt
+deleted file mode 100644
+index 4444444..0000000
+--- a/old.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-old
+";
+
+    #[test]
+    fn patch_parse() {
+        let patches = parse(PATCH).unwrap();
+        assert_eq!(patches.len(), 3);
+
+        assert_eq!(patches[0].old_path.as_deref(), Some("hello.txt"));
+        assert_eq!(patches[0].new_path.as_deref(), Some("hello.txt"));
+        assert_eq!(patches[0].hunks.len(), 2);
+        let hunk = &patches[0].hunks[1];
+        assert_eq!(hunk.header, "@@ -8,2 +8,3 @@ seven");
+        assert_eq!((hunk.old_start, hunk.old_lines), (8, 2));
+        assert_eq!((hunk.new_start, hunk.new_lines), (8, 3));
+        assert_eq!(hunk.lines[2], Line::Add("ten".to_string()));
+
+        assert_eq!(patches[1].old_path, None);
+        assert_eq!(patches[1].path(), "new.txt");
+        assert_eq!(
+            patches[1].hunks[0].lines,
+            vec![Line::Add("new\n".to_string())]
+        );
+        assert_eq!(patches[2].new_path, None);
+        assert_eq!(patches[2].path(), "old.txt");
+
+        // 行数が足りない hunk
+        assert_eq!(parse("--- a/x\n+++ b/x\n@@ -1,2 +1,2 @@\n a\n"), Err(3));
+        assert_eq!(parse("@@ -1 +1 @@\n-a\n+b\n"), Err(1));
+
+        // 行の先頭が多バイト文字でも panic しない
+        assert_eq!(parse("--- a/x\n+++ b/x\n@@ -1 +1 @@\nあ\n"), Err(3));
+        let patches = parse("--- a/x\n+++ b/x\n@@ -1 +1 @@\n-あ\n+い\n").unwrap();
+        assert_eq!(
+            patches[0].hunks[0].lines,
+            vec![
+                Line::Remove("あ\n".to_string()),
+                Line::Add("い\n".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn patch_apply() {
+        let patches = parse(PATCH).unwrap();
+        let hunks = &patches[0].hunks;
+        let content = (1..=9)
+            .map(|x| {
+                format!(
+                    "{}\n",
+                    ["one", "two", "three", "four", "five", "six", "seven", "eight", "nine"][x - 1]
+                )
+            })
+            .collect::<String>();
+        assert_eq!(
+            apply(&content, hunks).unwrap(),
+            "one\nTWO\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten"
+        );
+
+        // 前に行が増えていても少しのずれなら当てはまる
+        let shifted = format!("zero\nzero\n{}", content);
+        assert_eq!(
+            apply(&shifted, hunks).unwrap(),
+            "zero\nzero\none\nTWO\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten"
+        );
+
+        let far = format!("{}{}", "zero\n".repeat(MAX_OFFSET + 1), content);
+        assert_eq!(apply(&far, hunks).unwrap_err().header, "@@ -1,3 +1,3 @@");
+        let changed = content.replace("eight", "EIGHT");
+        assert_eq!(
+            apply(&changed, hunks).unwrap_err().header,
+            "@@ -8,2 +8,3 @@ seven"
+        );
+
+        assert_eq!(apply("", &patches[1].hunks).unwrap(), "new\n");
+        assert_eq!(apply("old\n", &patches[2].hunks).unwrap(), "");
+    }
+
+    #[test]
+    fn patch_mail() {
+        let mbox = format!(
+            "{}\n{}",
+            PATCH,
+            "From 5678 Mon Sep 17 00:00:00 2001
+From: \"Some One\" <some@example.com>
+Date: Sun, 3 Jan 2021 11:59:59 +0900
+Subject: [PATCH 2/2] a long
+ subject
+
+body line
+
+From the second paragraph
+---
+ hello.txt | 1 +
+diff --git a/hello.txt b/hello.txt
+-- 
+rgit
+"
+        );
+        let mails = split_mbox(&mbox);
+        assert_eq!(mails.len(), 2);
+        assert!(mails[0].starts_with("From 1234 "));
+        // 空行の後でなければ `From ` で区切らない
+        assert!(mails[1].contains("\nFrom the second paragraph\n"));
+
+        // Date が無いメール
+        assert_eq!(Mail::parse(&mails[0]), None);
+
+        let mail = Mail::parse(&mails[1]).unwrap();
+        assert_eq!(mail.name, "Some One");
+        assert_eq!(mail.email, "some@example.com");
+        assert_eq!(mail.date.timestamp(), 1609642799);
+        assert_eq!(mail.subject, "a long subject");
+        assert_eq!(
+            mail.message,
+            "a long subject\n\nbody line\n\nFrom the second paragraph\n"
+        );
+        assert!(mail.diff.starts_with("---\n hello.txt"));
+    }
+
+    #[test]
+    fn patch_diff() {
+        let old = (1..=20).map(|x| format!("{}\n", x)).collect::<String>();
+        let new = (1..=20)
+            .map(|x| match x {
+                2 => "two\n".to_string(),
+                5 => String::new(),
+                18 => "18\n18.5\n".to_string(),
+                x => format!("{}\n", x),
+            })
+            .collect::<String>();
+        let hunks = diff(&old, &new);
+        let headers = hunks.iter().map(|x| x.header.as_str()).collect::<Vec<_>>();
+        // 2 と 5 の変更は近いので 1 つの hunk になる
+        assert_eq!(headers, vec!["@@ -1,8 +1,7 @@", "@@ -16,5 +15,6 @@"]);
+        assert_eq!(apply(&old, &hunks).unwrap(), new);
+        assert!(diff(&old, &old).is_empty());
+
+        // 書き出したパッチを読み直しても同じになる
+        let patch = FilePatch {
+            old_path: Some("a.txt".to_string()),
+            new_path: Some("a.txt".to_string()),
+            hunks: diff("a\nb", "a\nc"),
+            ..FilePatch::default()
+        };
+        assert_eq!(
+            patch.to_string(),
+            "diff --git a/a.txt b/a.txt
+--- a/a.txt
++++ b/a.txt
+@@ -1,2 +1,2 @@
+ a
+-b
+\\ No newline at end of file
++c
+\\ No newline at end of file
+"
+        );
+        assert_eq!(parse(&patch.to_string()).unwrap(), vec![patch]);
+
+        let created = FilePatch {
+            old_path: None,
+            new_path: Some("new.txt".to_string()),
+            new_mode: Some(MODE_FILE),
+            hunks: diff("", "x\ny\n"),
+            ..FilePatch::default()
+        };
+        assert_eq!(created.hunks[0].header, "@@ -0,0 +1,2 @@");
+        assert!(created
+            .to_string()
+            .contains("new file mode 100644\n--- /dev/null\n"));
+        assert_eq!(parse(&created.to_string()).unwrap(), vec![created]);
+    }
+
+    #[test]
+    fn patch_diff_is_minimal() {
+        // 行を数えるだけの最長共通部分列の表
+        let lcs = |a: &[&str], b: &[&str]| {
+            let mut table = vec![vec![0; b.len() + 1]; a.len() + 1];
+            for i in (0..a.len()).rev() {
+                for j in (0..b.len()).rev() {
+                    table[i][j] = if a[i] == b[j] {
+                        table[i + 1][j + 1] + 1
+                    } else {
+                        table[i + 1][j].max(table[i][j + 1])
+                    };
+                }
+            }
+            table[0][0]
+        };
+        // 決まった種から作る a, b, c だけの行
+        let mut seed = 12345u32;
+        let mut text = |len: usize| {
+            (0..len)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                    ["a\n", "b\n", "c\n"][(seed >> 16) as usize % 3]
+                })
+                .collect::<String>()
+        };
+
+        for (n, m) in [(0, 5), (5, 0), (1, 1), (7, 6), (20, 25), (40, 13)] {
+            let (old, new) = (text(n), text(m));
+            let hunks = diff(&old, &new);
+            assert_eq!(apply(&old, &hunks).unwrap(), new, "{:?} {:?}", old, new);
+
+            let a = old.split_inclusive('\n').collect::<Vec<_>>();
+            let b = new.split_inclusive('\n').collect::<Vec<_>>();
+            let edits = hunks
+                .iter()
+                .flat_map(|x| x.lines.iter())
+                .filter(|x| !matches!(x, Line::Context(_)))
+                .count();
+            assert_eq!(edits, a.len() + b.len() - 2 * lcs(&a, &b));
+        }
+
+        // Myers の論文の例. 削除は追加より先に並ぶ
+        let hunks = diff("a\nb\nc\na\nb\nb\na\n", "c\nb\na\nb\na\nc\n");
+        let edits = hunks[0]
+            .lines
+            .iter()
+            .filter(|x| !matches!(x, Line::Context(_)))
+            .count();
+        assert_eq!(edits, 5);
+        let replaced = diff("x\n", "y\n");
+        assert_eq!(
+            replaced[0].lines,
+            vec![
+                Line::Remove("x\n".to_string()),
+                Line::Add("y\n".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn patch_diff_large() {
+        // 行数の積に比例する表を作ると終わらない大きさ
+        let old = (0..200_000).map(|x| format!("{}\n", x)).collect::<String>();
+        let new = old.replacen("100000\n", "changed\n", 1) + "end\n";
+        let hunks = diff(&old, &new);
+        let headers = hunks.iter().map(|x| x.header.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            headers,
+            vec!["@@ -99998,7 +99998,7 @@", "@@ -199998,3 +199998,4 @@"]
+        );
+        assert_eq!(apply(&old, &hunks).unwrap(), new);
+    }
+
+    #[test]
+    fn patch_mode_and_binary() {
+        assert!(is_binary(b"a\0b"));
+        assert!(is_binary(&[0xff, 0xfe]));
+        assert!(!is_binary("あ\n".as_bytes()));
+
+        let changed = FilePatch {
+            old_path: Some("run.sh".to_string()),
+            new_path: Some("run.sh".to_string()),
+            old_mode: Some(MODE_FILE),
+            new_mode: Some(0o100755),
+            ..FilePatch::default()
+        };
+        assert_eq!(
+            changed.to_string(),
+            "diff --git a/run.sh b/run.sh\nold mode 100644\nnew mode 100755\n"
+        );
+        assert_eq!(parse(&changed.to_string()).unwrap(), vec![changed]);
+
+        let binary = FilePatch {
+            old_path: None,
+            new_path: Some("image.png".to_string()),
+            new_mode: Some(0o100755),
+            binary: true,
+            ..FilePatch::default()
+        };
+        assert_eq!(
+            binary.to_string(),
+            "diff --git a/image.png b/image.png\nnew file mode 100755\nBinary files /dev/null and b/image.png differ\n"
+        );
+        assert_eq!(parse(&binary.to_string()).unwrap(), vec![binary]);
+    }
+}
@@ -0,0 +1,250 @@
+// `*.rs` や `src/**/*.toml` のようなパスのパターン
+// `*` と `?` は `/` をまたがず, `**` はまたぐ. `\` の次の文字はそのまま比べる
+// `/` を含まないパターンはファイル名だけと比べ, 末尾の `/` はディレクトリだけに当てはまる
+#[derive(Clone, Debug, PartialEq)]
+pub struct Matcher {
+    tokens: Vec<Token>,
+    // `/` を含まないパターン
+    basename: bool,
+    // 末尾が `/` のパターン
+    dir_only: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Char(char),
+    // `?`
+    One,
+    // `*`
+    Any,
+    // `**`
+    AnyDeep,
+    // `**/`. 0個以上のディレクトリ
+    Dirs,
+}
+
+impl Matcher {
+    pub fn new(pattern: &str) -> Self {
+        let dir_only = pattern.ends_with('/') && !pattern.ends_with("\\/");
+        let pattern = pattern.trim_end_matches('/');
+
+        let mut tokens = Vec::new();
+        let mut basename = true;
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => tokens.push(Token::Char(chars.next().unwrap_or('\\'))),
+                '?' => tokens.push(Token::One),
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        tokens.push(Token::Dirs);
+                    } else {
+                        tokens.push(Token::AnyDeep);
+                    }
+                    basename = false;
+                }
+                '*' => tokens.push(Token::Any),
+                '/' => {
+                    tokens.push(Token::Char('/'));
+                    basename = false;
+                }
+                c => tokens.push(Token::Char(c)),
+            }
+        }
+
+        Self {
+            tokens,
+            basename,
+            dir_only,
+        }
+    }
+
+    // `/` を含まなくてもファイル名ではなく先頭から比べるパターン
+    pub fn anchored(pattern: &str) -> Self {
+        Self {
+            basename: false,
+            ..Self::new(pattern)
+        }
+    }
+
+    // ワイルドカードを含まないパターン
+    pub fn is_literal(&self) -> bool {
+        self.tokens.iter().all(|x| matches!(x, Token::Char(_)))
+    }
+
+    // path そのものか, path を含むディレクトリのどれかに当てはまるか
+    pub fn matches(&self, path: &str) -> bool {
+        let path = path.trim_end_matches('/');
+        let components = path.split('/').collect::<Vec<_>>();
+
+        // ディレクトリだけのパターンは最後の要素 (ファイル) には当てはめない
+        let len = if self.dir_only {
+            components.len() - 1
+        } else {
+            components.len()
+        };
+
+        (1..=len).any(|n| {
+            let candidate = if self.basename {
+                components[n - 1].to_string()
+            } else {
+                components[..n].join("/")
+            };
+            let chars = candidate.chars().collect::<Vec<_>>();
+            match_tokens(&self.tokens, &chars)
+        })
+    }
+
+    // 含んでいるディレクトリは見ずに path そのものに当てはまるか. .gitignore で使う
+    pub fn matches_entry(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let path = path.trim_end_matches('/');
+        let candidate = if self.basename {
+            path.rsplit('/').next().unwrap_or(path)
+        } else {
+            path
+        };
+        let chars = candidate.chars().collect::<Vec<_>>();
+        match_tokens(&self.tokens, &chars)
+    }
+}
+
+fn match_tokens(tokens: &[Token], s: &[char]) -> bool {
+    match tokens.split_first() {
+        None => s.is_empty(),
+        Some((Token::Char(c), rest)) => s.first() == Some(c) && match_tokens(rest, &s[1..]),
+        Some((Token::One, rest)) => {
+            matches!(s.first(), Some(&x) if x != '/') && match_tokens(rest, &s[1..])
+        }
+        Some((Token::Any, rest)) => {
+            // `/` の手前までのどこで区切るか全て試す
+            let end = s.iter().position(|&x| x == '/').unwrap_or(s.len());
+            (0..=end).any(|i| match_tokens(rest, &s[i..]))
+        }
+        Some((Token::AnyDeep, rest)) => (0..=s.len()).any(|i| match_tokens(rest, &s[i..])),
+        Some((Token::Dirs, rest)) => {
+            // 0個のディレクトリか, `/` の直後から続ける
+            match_tokens(rest, s)
+                || s.iter()
+                    .enumerate()
+                    .filter(|(_, &x)| x == '/')
+                    .any(|(i, _)| match_tokens(rest, &s[(i + 1)..]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matcher_literal() {
+        let m = Matcher::new("src/main.rs");
+        assert!(m.is_literal());
+        assert!(m.matches("src/main.rs"));
+        assert!(!m.matches("src/main.rs.bak"));
+        assert!(!m.matches("main.rs"));
+
+        // ディレクトリの中のファイルにも当てはまる
+        let m = Matcher::new("src");
+        assert!(m.matches("src"));
+        assert!(m.matches("src/object/mod.rs"));
+        assert!(!m.matches("srcs/lib.rs"));
+    }
+
+    #[test]
+    fn matcher_star() {
+        let m = Matcher::new("*.rs");
+        assert!(!m.is_literal());
+        assert!(m.matches("main.rs"));
+        assert!(m.matches("src/object/mod.rs"));
+        assert!(!m.matches("Cargo.toml"));
+        assert!(!m.matches("src.rs.toml"));
+
+        let m = Matcher::new("src/*.rs");
+        assert!(m.matches("src/lib.rs"));
+        assert!(!m.matches("src/object/mod.rs"));
+        assert!(!m.matches("lib.rs"));
+
+        let m = Matcher::new("src/obj*");
+        assert!(m.matches("src/object/mod.rs"));
+    }
+
+    #[test]
+    fn matcher_question() {
+        let m = Matcher::new("src/???.rs");
+        assert!(m.matches("src/lib.rs"));
+        assert!(!m.matches("src/main.rs"));
+        assert!(!Matcher::new("a?b").matches("a/b"));
+    }
+
+    #[test]
+    fn matcher_double_star() {
+        let m = Matcher::new("src/**/*.toml");
+        assert!(m.matches("src/a.toml"));
+        assert!(m.matches("src/x/y/a.toml"));
+        assert!(!m.matches("a.toml"));
+        assert!(!m.matches("src/x/a.rs"));
+
+        let m = Matcher::new("**/fixtures");
+        assert!(m.matches("fixtures/a.pack"));
+        assert!(m.matches("tests/fixtures/a.pack"));
+
+        let m = Matcher::new("src/**");
+        assert!(m.matches("src/x/y/z"));
+        assert!(!m.matches("tests/x"));
+    }
+
+    #[test]
+    fn matcher_dir_only() {
+        let m = Matcher::new("fixtures/");
+        assert!(m.matches("fixtures/a.pack"));
+        assert!(m.matches("tests/fixtures/a.pack"));
+        // ファイルには当てはまらない
+        assert!(!m.matches("fixtures"));
+        assert!(!m.matches("tests/fixtures"));
+
+        let m = Matcher::new("src/*/");
+        assert!(m.matches("src/object/mod.rs"));
+        assert!(!m.matches("src/lib.rs"));
+    }
+
+    #[test]
+    fn matcher_anchored() {
+        let m = Matcher::anchored("build");
+        assert!(m.matches("build/out.o"));
+        assert!(!m.matches("src/build/out.o"));
+        assert!(Matcher::anchored("*.rs").matches("main.rs"));
+        assert!(!Matcher::anchored("*.rs").matches("src/main.rs"));
+    }
+
+    #[test]
+    fn matcher_entry() {
+        let m = Matcher::new("target/");
+        assert!(m.matches_entry("target", true));
+        assert!(m.matches_entry("sub/target", true));
+        assert!(!m.matches_entry("target", false));
+        // 中のファイルには当てはめない
+        assert!(!m.matches_entry("target/debug", true));
+
+        let m = Matcher::new("src/*.rs");
+        assert!(m.matches_entry("src/lib.rs", false));
+        assert!(!m.matches_entry("src/lib.rs/x", false));
+    }
+
+    #[test]
+    fn matcher_escape() {
+        let m = Matcher::new("\\*.rs");
+        assert!(m.is_literal());
+        assert!(m.matches("*.rs"));
+        assert!(!m.matches("main.rs"));
+
+        let m = Matcher::new("a\\?");
+        assert!(m.matches("a?"));
+        assert!(!m.matches("ab"));
+    }
+}
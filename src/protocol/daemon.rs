@@ -0,0 +1,205 @@
+use super::fetch::{self, Advertisement, Transport};
+use super::pktline;
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+// git daemon の既定のポート
+pub const DEFAULT_PORT: u16 = 9418;
+
+// git:// の upload-pack. 1つの TCP 接続で広告を受け取ってから want/have を送る
+// https://git-scm.com/docs/pack-protocol#_git_transport
+pub struct DaemonTransport {
+    host: String,
+    port: u16,
+    path: String,
+    pub timeout: Duration,
+    // advertisement を読んだ後の接続
+    stream: Option<TcpStream>,
+}
+
+impl DaemonTransport {
+    pub fn new(url: &str) -> io::Result<Self> {
+        let rest = url.strip_prefix("git://").ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: not a git:// url", url),
+            )
+        })?;
+        let (host, port, path) = super::split_url(url, rest, DEFAULT_PORT)?;
+        if path.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: no repository path", url),
+            ));
+        }
+
+        Ok(Self {
+            host,
+            port,
+            path,
+            timeout: super::TIMEOUT,
+            stream: None,
+        })
+    }
+
+    fn network_error(&self, e: io::Error) -> io::Error {
+        super::network_error(e, &self.host, self.port)
+    }
+}
+
+impl Transport for DaemonTransport {
+    // `git-upload-pack <path>\0host=<host>\0` を送ると広告が返ってくる
+    // daemon がリポジトリを見つけられなければ ERR を返すか, 何も返さずに接続を閉じる
+    fn advertisement(&mut self) -> io::Result<Advertisement> {
+        let mut stream = super::connect_tcp(&self.host, self.port, self.timeout)?;
+        let host = if self.port == DEFAULT_PORT {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        };
+        let request = format!("git-upload-pack {}\0host={}\0", self.path, host);
+        pktline::write_pkt(&mut stream, request.as_bytes()).map_err(|e| self.network_error(e))?;
+
+        let adv = match Advertisement::read(&mut stream) {
+            Ok(adv) => adv,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "git://{}{}: the remote end hung up (repository not exported?)",
+                        host, self.path
+                    ),
+                ))
+            }
+            Err(e) => return Err(self.network_error(e)),
+        };
+        self.stream = Some(stream);
+
+        Ok(adv)
+    }
+
+    fn fetch_pack(
+        &mut self,
+        adv: &Advertisement,
+        request: &[u8],
+        progress: &mut dyn FnMut(&[u8]),
+    ) -> io::Result<Vec<u8>> {
+        let mut stream = self.stream.take().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotConnected,
+                "fetch_pack called before advertisement",
+            )
+        })?;
+        stream
+            .write_all(request)
+            .map_err(|e| self.network_error(e))?;
+
+        fetch::read_pack(&mut stream, adv, progress).map_err(|e| self.network_error(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd;
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::remote::Remote;
+    use crate::Git;
+    use std::net::TcpListener;
+    use std::path::Path;
+    use std::process::{Command, Stdio};
+
+    #[test]
+    fn daemon_url() {
+        let t = DaemonTransport::new("git://example.com/repo.git").unwrap();
+        assert_eq!((t.host.as_str(), t.port), ("example.com", DEFAULT_PORT));
+        assert_eq!(t.path, "/repo.git");
+        let t = DaemonTransport::new("git://127.0.0.1:1234/~user/x").unwrap();
+        assert_eq!(t.port, 1234);
+        assert_eq!(t.path, "/~user/x");
+
+        assert!(DaemonTransport::new("git://example.com").is_err());
+        assert!(DaemonTransport::new("http://example.com/repo.git").is_err());
+        assert!(DaemonTransport::new("git://:9418/repo.git").is_err());
+    }
+
+    #[test]
+    fn daemon_fetch() {
+        let dir = std::env::temp_dir().join(format!("rgit-daemon-{}", std::process::id()));
+        let git_cmd = |args: &[&str]| {
+            Command::new("git")
+                .arg("-C")
+                .arg(&dir)
+                .args(["-c", "user.name=rgit", "-c", "user.email=rgit@example.com"])
+                .args(args)
+                .output()
+        };
+        std::fs::create_dir_all(&dir).unwrap();
+        // git が無ければ確認できない
+        if git_cmd(&["init", "-q", "work"]).is_err() {
+            std::fs::remove_dir_all(&dir).unwrap();
+            return;
+        }
+        std::fs::write(dir.join("work/a.txt"), "a\n").unwrap();
+        git_cmd(&["-C", "work", "add", "a.txt"]).unwrap();
+        git_cmd(&["-C", "work", "commit", "-q", "-m", "first"]).unwrap();
+        git_cmd(&["clone", "-q", "--bare", "work", "repo.git"]).unwrap();
+        let out = git_cmd(&["-C", "repo.git", "rev-parse", "master"]).unwrap();
+        let master = String::from_utf8(out.stdout).unwrap().trim().to_string();
+
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        // `git daemon` は git-daemon を子 process にするので, kill できるように直接動かす
+        let exec_path = git_cmd(&["--exec-path"]).unwrap().stdout;
+        let exec_path = String::from_utf8(exec_path).unwrap();
+        let mut daemon = Command::new(Path::new(exec_path.trim()).join("git-daemon"))
+            .arg("--reuseaddr")
+            .arg("--export-all")
+            .arg("--listen=127.0.0.1")
+            .arg(format!("--port={}", port))
+            .arg(format!("--base-path={}", dir.display()))
+            .arg(&dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        // 待ち受けを始めるまで待つ
+        for _ in 0..100 {
+            if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        let url = format!("git://127.0.0.1:{}/repo.git", port);
+        let mut git = Git::new(InMemFileSystem::init());
+        git.add_remote(&Remote::new("origin".to_string(), url.clone()))
+            .unwrap();
+        let mut transport = super::super::connect(&url).unwrap().unwrap();
+        let result = cmd::fetch_transport(&mut git, &mut *transport, "origin", false, &mut |_| ());
+        let missing = DaemonTransport::new(&format!("git://127.0.0.1:{}/missing.git", port))
+            .unwrap()
+            .advertisement();
+        daemon.kill().unwrap();
+        daemon.wait().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            result.unwrap(),
+            vec![
+                format!("From {}", url),
+                " * [new branch]      master -> origin/master".to_string(),
+            ]
+        );
+        assert_eq!(git.rev_parse("refs/remotes/origin/master").unwrap(), master);
+        assert!(git.read_commit(&master).is_ok());
+        // daemon は ERR の pkt-line で理由を返してくる
+        let e = missing.unwrap_err().to_string();
+        assert!(e.starts_with("remote error: "), "{}", e);
+    }
+}
@@ -0,0 +1,260 @@
+use super::pktline::{self, Pkt, PktReader, SideBandReader};
+use std::io::{self, Read};
+
+// こちらが使える capability. server が広告したものだけを want に付ける
+const CAPABILITIES: [&str; 3] = ["side-band-64k", "ofs-delta", "agent=rgit"];
+
+// upload-pack が最初に送ってくる ref の一覧と capability
+#[derive(Debug, Default, PartialEq)]
+pub struct Advertisement {
+    // (ref の名前, hash)
+    pub refs: Vec<(String, String)>,
+    pub capabilities: Vec<String>,
+}
+
+impl Advertisement {
+    // flush までの `<hash> <ref>` の行を読む. 最初の行だけ NUL の後に capability が続く
+    // 空のリポジトリは `capabilities^{}` という名前の ref だけを送ってくる
+    pub fn read<R: Read>(reader: R) -> io::Result<Self> {
+        let mut adv = Self::default();
+        for pkt in PktReader::new(reader) {
+            let data = match pkt? {
+                Pkt::Data(data) => data,
+                Pkt::Flush => return Ok(adv),
+                Pkt::Delim => return Err(invalid_data("unexpected delim in ref advertisement")),
+            };
+            let line = String::from_utf8(data)
+                .map_err(|_| invalid_data("ref advertisement is not UTF-8"))?;
+            let line = line.trim_end_matches('\n');
+            if let Some(message) = line.strip_prefix("ERR ") {
+                return Err(remote_error(message));
+            }
+
+            let (line, capabilities) = match line.split_once('\0') {
+                Some((line, capabilities)) => (line, Some(capabilities)),
+                None => (line, None),
+            };
+            if let Some(capabilities) = capabilities {
+                adv.capabilities = capabilities.split(' ').map(String::from).collect();
+            }
+            let (hash, name) = line
+                .split_once(' ')
+                .filter(|(hash, _)| hash.len() >= 40 && hash.bytes().all(|x| x.is_ascii_hexdigit()))
+                .ok_or_else(|| invalid_data(&format!("bad ref advertisement: {}", line)))?;
+            if name != "capabilities^{}" {
+                adv.refs.push((name.to_string(), hash.to_string()));
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "ref advertisement ended without flush",
+        ))
+    }
+
+    pub fn has_capability(&self, name: &str) -> bool {
+        self.capabilities
+            .iter()
+            .any(|x| x == name || x.split_once('=').is_some_and(|(key, _)| key == name))
+    }
+}
+
+// fetch を送る相手. ref の広告を受け取ってから want/have を送り pack を受け取る
+pub trait Transport {
+    fn advertisement(&mut self) -> io::Result<Advertisement>;
+
+    // request は want_request で作ったもの. 返すのは pack の中身
+    // server からの進捗は progress に渡す
+    fn fetch_pack(
+        &mut self,
+        adv: &Advertisement,
+        request: &[u8],
+        progress: &mut dyn FnMut(&[u8]),
+    ) -> io::Result<Vec<u8>>;
+}
+
+// want の後に flush, have を並べて done で終わる (multi_ack なしの一番単純な形)
+pub fn want_request(adv: &Advertisement, wants: &[String], haves: &[String]) -> Vec<u8> {
+    let capabilities = CAPABILITIES
+        .iter()
+        .filter(|x| adv.has_capability(x.split('=').next().unwrap_or(x)))
+        .copied()
+        .collect::<Vec<_>>();
+
+    let mut request = Vec::new();
+    for (i, want) in wants.iter().enumerate() {
+        let line = if i == 0 && !capabilities.is_empty() {
+            format!("want {} {}\n", want, capabilities.join(" "))
+        } else {
+            format!("want {}\n", want)
+        };
+        // 長さは hash と capability だけなので上限を超えない
+        pktline::write_pkt(&mut request, line.as_bytes()).unwrap();
+    }
+    pktline::write_flush(&mut request).unwrap();
+    for have in haves.iter() {
+        pktline::write_pkt(&mut request, format!("have {}\n", have).as_bytes()).unwrap();
+    }
+    pktline::write_pkt(&mut request, b"done\n").unwrap();
+
+    request
+}
+
+// NAK か ACK の行の後に続く pack を読む
+// side-band-64k なら channel 2 の進捗を progress に渡す
+pub fn read_pack<R: Read, P: FnMut(&[u8])>(
+    mut reader: R,
+    adv: &Advertisement,
+    progress: P,
+) -> io::Result<Vec<u8>> {
+    match PktReader::new(&mut reader).next().transpose()? {
+        Some(Pkt::Data(data)) if data.starts_with(b"NAK") || data.starts_with(b"ACK ") => (),
+        Some(Pkt::Data(data)) if data.starts_with(b"ERR ") => {
+            return Err(remote_error(&String::from_utf8_lossy(&data[4..])))
+        }
+        _ => return Err(invalid_data("expected NAK or ACK from upload-pack")),
+    }
+
+    let mut pack = Vec::new();
+    if adv.has_capability("side-band-64k") {
+        SideBandReader::new(reader, progress).read_to_end(&mut pack)?;
+    } else {
+        reader.read_to_end(&mut pack)?;
+    }
+    if !pack.starts_with(b"PACK") {
+        return Err(invalid_data("upload-pack did not send a pack"));
+    }
+
+    Ok(pack)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn remote_error(message: &str) -> io::Error {
+    io::Error::other(format!("remote error: {}", message.trim_end()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::pktline::{write_flush, write_pkt};
+
+    const HASH1: &str = "e83c5163316f89bfbde7d9ab23ca2e25604af290";
+    const HASH2: &str = "8f8e7b6cd38a1fbc1dd4d6f5d2a45e2d0a7a3f1b";
+
+    #[test]
+    fn advertisement_read() {
+        let mut bytes = Vec::new();
+        write_pkt(
+            &mut bytes,
+            format!(
+                "{} HEAD\0multi_ack side-band-64k ofs-delta symref=HEAD:refs/heads/master agent=git/2.39\n",
+                HASH1
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        write_pkt(
+            &mut bytes,
+            format!("{} refs/heads/master\n", HASH1).as_bytes(),
+        )
+        .unwrap();
+        write_pkt(&mut bytes, format!("{} refs/tags/v1\n", HASH2).as_bytes()).unwrap();
+        write_flush(&mut bytes).unwrap();
+
+        let adv = Advertisement::read(bytes.as_slice()).unwrap();
+        assert_eq!(
+            adv.refs,
+            vec![
+                ("HEAD".to_string(), HASH1.to_string()),
+                ("refs/heads/master".to_string(), HASH1.to_string()),
+                ("refs/tags/v1".to_string(), HASH2.to_string()),
+            ]
+        );
+        assert!(adv.has_capability("side-band-64k"));
+        assert!(adv.has_capability("agent"));
+        assert!(adv.has_capability("symref"));
+        assert!(!adv.has_capability("side-band"));
+
+        // 空のリポジトリ
+        let mut bytes = Vec::new();
+        write_pkt(
+            &mut bytes,
+            b"0000000000000000000000000000000000000000 capabilities^{}\0ofs-delta\n",
+        )
+        .unwrap();
+        write_flush(&mut bytes).unwrap();
+        let adv = Advertisement::read(bytes.as_slice()).unwrap();
+        assert!(adv.refs.is_empty());
+        assert_eq!(adv.capabilities, vec!["ofs-delta".to_string()]);
+
+        let mut bytes = Vec::new();
+        write_pkt(&mut bytes, b"ERR access denied\n").unwrap();
+        let e = Advertisement::read(bytes.as_slice()).unwrap_err();
+        assert_eq!(e.to_string(), "remote error: access denied");
+        assert!(Advertisement::read(b"0009hoge\n0000".as_ref()).is_err());
+        assert!(Advertisement::read(b"".as_ref()).is_err());
+    }
+
+    #[test]
+    fn fetch_want_request() {
+        let adv = Advertisement {
+            refs: Vec::new(),
+            capabilities: vec!["side-band-64k".to_string(), "agent=git/2.39".to_string()],
+        };
+        let request = want_request(
+            &adv,
+            &[HASH1.to_string(), HASH2.to_string()],
+            &[HASH2.to_string()],
+        );
+        assert_eq!(
+            String::from_utf8(request).unwrap(),
+            format!(
+                "004bwant {} side-band-64k agent=rgit\n0032want {}\n00000032have {}\n0009done\n",
+                HASH1, HASH2, HASH2
+            )
+        );
+    }
+
+    #[test]
+    fn fetch_read_pack() {
+        let side_band = Advertisement {
+            refs: Vec::new(),
+            capabilities: vec!["side-band-64k".to_string()],
+        };
+        let mut bytes = Vec::new();
+        write_pkt(&mut bytes, b"NAK\n").unwrap();
+        write_pkt(&mut bytes, b"\x02Counting objects: 1\r").unwrap();
+        write_pkt(&mut bytes, b"\x01PACK\0\0\0\x02").unwrap();
+        write_pkt(&mut bytes, b"\x01rest").unwrap();
+        write_flush(&mut bytes).unwrap();
+        let mut progress = Vec::new();
+        let pack = read_pack(bytes.as_slice(), &side_band, |x| {
+            progress.extend_from_slice(x)
+        })
+        .unwrap();
+        assert_eq!(pack, b"PACK\0\0\0\x02rest");
+        assert_eq!(progress, b"Counting objects: 1\r");
+
+        // side-band でなければ NAK の後はそのまま pack
+        let mut bytes = b"0008NAK\n".to_vec();
+        bytes.extend_from_slice(b"PACKdata");
+        let pack = read_pack(bytes.as_slice(), &Advertisement::default(), |_| ()).unwrap();
+        assert_eq!(pack, b"PACKdata");
+
+        // channel 3 のエラーは読めるメッセージにする
+        let mut bytes = Vec::new();
+        write_pkt(&mut bytes, b"NAK\n").unwrap();
+        write_pkt(&mut bytes, b"\x03upload-pack: not our ref\n").unwrap();
+        let e = read_pack(bytes.as_slice(), &side_band, |_| ()).unwrap_err();
+        assert_eq!(e.to_string(), "remote error: upload-pack: not our ref");
+
+        let mut bytes = Vec::new();
+        write_pkt(&mut bytes, b"ERR want is not valid\n").unwrap();
+        let e = read_pack(bytes.as_slice(), &side_band, |_| ()).unwrap_err();
+        assert_eq!(e.to_string(), "remote error: want is not valid");
+        assert!(read_pack(b"0000".as_ref(), &side_band, |_| ()).is_err());
+    }
+}
@@ -0,0 +1,563 @@
+use super::fetch::{self, Advertisement, Transport};
+use super::pktline::{Pkt, PktReader};
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+
+// smart HTTP の upload-pack
+// https は TLS の実装を持たないので, ssh と同じく子 process の `openssl s_client` に暗号化を任せ,
+// その stdin/stdout で平文の HTTP を話す
+// https://git-scm.com/docs/http-protocol
+pub struct HttpTransport {
+    host: String,
+    port: u16,
+    // リポジトリのパス. 末尾の `/` は取り除く
+    path: String,
+    tls: bool,
+    // TLS に使う openssl
+    pub openssl: String,
+    // server の証明書を確かめる CA. GIT_SSL_CAINFO があればそれを使い, 無ければ openssl の既定
+    pub ca_file: Option<String>,
+}
+
+impl HttpTransport {
+    pub fn new(url: &str) -> io::Result<Self> {
+        let (rest, tls, default_port) =
+            match (url.strip_prefix("http://"), url.strip_prefix("https://")) {
+                (Some(rest), _) => (rest, false, 80),
+                (_, Some(rest)) => (rest, true, 443),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{}: not an http url", url),
+                    ))
+                }
+            };
+        let (host, port, path) = super::split_url(url, rest, default_port)?;
+        // openssl のオプションとして読まれる host は受け付けない
+        if host.starts_with('-') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: strange hostname blocked", url),
+            ));
+        }
+
+        Ok(Self {
+            host,
+            port,
+            path: path.trim_end_matches('/').to_string(),
+            tls,
+            openssl: "openssl".to_string(),
+            ca_file: std::env::var("GIT_SSL_CAINFO").ok(),
+        })
+    }
+
+    fn scheme(&self) -> &str {
+        if self.tls {
+            "https"
+        } else {
+            "http"
+        }
+    }
+
+    // openssl s_client に request を渡し, server が閉じるまでの response を読む
+    // 証明書と host 名が確かめられなければ handshake で失敗させる
+    fn send_tls(&self, request: &[u8]) -> io::Result<Vec<u8>> {
+        let mut command = Command::new(&self.openssl);
+        command
+            .arg("s_client")
+            .arg("-quiet")
+            .arg("-verify_return_error")
+            .arg("-connect")
+            .arg(format!("{}:{}", self.host, self.port))
+            .arg("-servername")
+            .arg(&self.host)
+            .arg("-verify_hostname")
+            .arg(&self.host);
+        if let Some(ca_file) = &self.ca_file {
+            command.arg("-CAfile").arg(ca_file);
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", self.openssl, e)))?;
+
+        // 大きな request を書いている間に response で pipe が詰まらないよう, 別の thread で書く
+        let mut stdin = child.stdin.take().unwrap();
+        let request = request.to_vec();
+        let writer = std::thread::spawn(move || stdin.write_all(&request));
+        let output = child.wait_with_output()?;
+        let written = writer
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("writer panicked")));
+
+        if output.stdout.is_empty() && !output.status.success() {
+            // "Connecting to ..." などの進捗行は飛ばし, 最初のエラー行を拾う
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let reason = stderr
+                .lines()
+                .find(|l| l.contains("error"))
+                .or_else(|| stderr.lines().last())
+                .unwrap_or_default();
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!(
+                    "unable to connect to {}:{}: TLS handshake failed: {}",
+                    self.host, self.port, reason
+                ),
+            ));
+        }
+        written?;
+        Ok(output.stdout)
+    }
+
+    // Connection: close で送って, 閉じられるまでを response として読む
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> io::Result<Response> {
+        let mut head = format!(
+            "{} {}{} HTTP/1.1\r\nHost: {}:{}\r\nUser-Agent: git/rgit\r\nConnection: close\r\n",
+            method, self.path, path, self.host, self.port
+        );
+        for (name, value) in headers.iter() {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if method == "POST" {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        head.push_str("\r\n");
+
+        let bytes = if self.tls {
+            self.send_tls(&[head.as_bytes(), body].concat())?
+        } else {
+            let network_error = |e| super::network_error(e, &self.host, self.port);
+            let mut stream = super::connect_tcp(&self.host, self.port, super::TIMEOUT)?;
+            stream.write_all(head.as_bytes()).map_err(network_error)?;
+            stream.write_all(body).map_err(network_error)?;
+            let mut bytes = Vec::new();
+            stream.read_to_end(&mut bytes).map_err(network_error)?;
+            bytes
+        };
+
+        let response = Response::parse(&bytes)?;
+        if response.status != 200 {
+            return Err(io::Error::other(format!(
+                "{}://{}:{}{}{}: HTTP {}",
+                self.scheme(),
+                self.host,
+                self.port,
+                self.path,
+                path,
+                response.status
+            )));
+        }
+        Ok(response)
+    }
+}
+
+impl Transport for HttpTransport {
+    // GET info/refs?service=git-upload-pack
+    // `# service=git-upload-pack` の pkt-line と flush の後に ref の広告が続く
+    fn advertisement(&mut self) -> io::Result<Advertisement> {
+        let response = self.request("GET", "/info/refs?service=git-upload-pack", &[], b"")?;
+        if response.header("content-type") != Some("application/x-git-upload-pack-advertisement") {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "dumb http transport is not supported",
+            ));
+        }
+
+        let mut body = response.body.as_slice();
+        let mut pkts = PktReader::new(&mut body);
+        match pkts.next().transpose()? {
+            Some(Pkt::Data(data)) if data == b"# service=git-upload-pack\n" => (),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "missing service line in ref advertisement",
+                ))
+            }
+        }
+        if !matches!(pkts.next().transpose()?, Some(Pkt::Flush)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "missing flush after service line",
+            ));
+        }
+
+        Advertisement::read(body)
+    }
+
+    // POST git-upload-pack
+    fn fetch_pack(
+        &mut self,
+        adv: &Advertisement,
+        request: &[u8],
+        progress: &mut dyn FnMut(&[u8]),
+    ) -> io::Result<Vec<u8>> {
+        let response = self.request(
+            "POST",
+            "/git-upload-pack",
+            &[
+                ("Content-Type", "application/x-git-upload-pack-request"),
+                ("Accept", "application/x-git-upload-pack-result"),
+            ],
+            request,
+        )?;
+
+        fetch::read_pack(response.body.as_slice(), adv, progress)
+    }
+}
+
+struct Response {
+    status: u16,
+    // 名前は小文字にしておく
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let invalid =
+            |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        let end = bytes
+            .windows(4)
+            .position(|x| x == b"\r\n\r\n")
+            .ok_or_else(|| invalid("truncated http response"))?;
+        let head = std::str::from_utf8(&bytes[..end])
+            .map_err(|_| invalid("http response header is not UTF-8"))?;
+
+        let mut lines = head.split("\r\n");
+        let status = lines
+            .next()
+            .filter(|x| x.starts_with("HTTP/1."))
+            .and_then(|x| x.split(' ').nth(1))
+            .and_then(|x| x.parse::<u16>().ok())
+            .ok_or_else(|| invalid("bad http status line"))?;
+        let headers = lines
+            .filter_map(|x| x.split_once(':'))
+            .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+            .collect::<Vec<_>>();
+
+        let mut response = Self {
+            status,
+            headers,
+            body: Vec::new(),
+        };
+        let body = &bytes[(end + 4)..];
+        response.body = if response.header("transfer-encoding") == Some("chunked") {
+            dechunk(body).ok_or_else(|| invalid("bad chunked encoding"))?
+        } else if let Some(len) = response.header("content-length") {
+            let len = len
+                .parse::<usize>()
+                .map_err(|_| invalid("bad content-length"))?;
+            body.get(..len)
+                .ok_or_else(|| invalid("truncated http response"))?
+                .to_vec()
+        } else {
+            body.to_vec()
+        };
+
+        Ok(response)
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(x, _)| x == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// `<16進数の長さ>\r\n<data>\r\n` の繰り返しを長さ 0 の chunk まで繋げる
+fn dechunk(mut bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let end = bytes.windows(2).position(|x| x == b"\r\n")?;
+        let size = std::str::from_utf8(&bytes[..end]).ok()?;
+        // `;` の後は chunk の拡張なので無視する
+        let size = size.split(';').next()?.trim();
+        let len = usize::from_str_radix(size, 16).ok()?;
+        bytes = &bytes[(end + 2)..];
+        if len == 0 {
+            return Some(body);
+        }
+        body.extend_from_slice(bytes.get(..len)?);
+        bytes = bytes.get((len + 2)..)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd;
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::remote::Remote;
+    use crate::Git;
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+    use std::process::{Command, Stdio};
+
+    #[test]
+    fn http_url() {
+        let t = HttpTransport::new("http://example.com:8080/repo.git/").unwrap();
+        assert_eq!(t.host, "example.com");
+        assert_eq!(t.port, 8080);
+        assert_eq!(t.path, "/repo.git");
+        let t = HttpTransport::new("http://example.com").unwrap();
+        assert_eq!(t.port, 80);
+        assert_eq!(t.path, "");
+
+        let t = HttpTransport::new("https://example.com/repo.git").unwrap();
+        assert!(t.tls);
+        assert_eq!(t.port, 443);
+        assert_eq!(t.scheme(), "https");
+        assert!(HttpTransport::new("https://-connect/repo.git").is_err());
+        assert!(HttpTransport::new("git://example.com/repo.git").is_err());
+        assert!(HttpTransport::new("http://example.com:port/").is_err());
+        assert!(HttpTransport::new("http:///repo.git").is_err());
+    }
+
+    #[test]
+    fn http_response_parse() {
+        let response = Response::parse(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nTransfer-Encoding: chunked\r\n\r\n\
+              5\r\nhello\r\n7;x=y\r\n, world\r\n0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("content-type"), Some("text/plain"));
+        assert_eq!(response.body, b"hello, world");
+
+        let response =
+            Response::parse(b"HTTP/1.0 404 Not Found\r\nContent-Length: 3\r\n\r\nabcdef").unwrap();
+        assert_eq!(response.status, 404);
+        assert_eq!(response.body, b"abc");
+
+        assert!(Response::parse(b"HTTP/1.1 200 OK\r\n").is_err());
+        assert!(Response::parse(b"SSH-2.0\r\n\r\n").is_err());
+        assert!(
+            Response::parse(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel")
+                .is_err()
+        );
+    }
+
+    // git http-backend を CGI として動かす最小限の server. 1つの接続で1つの request を処理する
+    fn serve(root: PathBuf) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let _ = handle(&root, stream.unwrap());
+            }
+        });
+        port
+    }
+
+    fn handle(root: &Path, mut stream: TcpStream) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        let mut buf = [0; 4096];
+        let end = loop {
+            let n = stream.read(&mut buf)?;
+            bytes.extend_from_slice(&buf[..n]);
+            if let Some(end) = bytes.windows(4).position(|x| x == b"\r\n\r\n") {
+                break end;
+            }
+        };
+        let head = String::from_utf8_lossy(&bytes[..end]).into_owned();
+        let mut body = bytes[(end + 4)..].to_vec();
+        let mut lines = head.split("\r\n");
+        let mut request = lines.next().unwrap().split(' ');
+        let (method, target) = (request.next().unwrap(), request.next().unwrap());
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let header = |name: &str| {
+            head.split("\r\n")
+                .filter_map(|x| x.split_once(": "))
+                .find(|(x, _)| x.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.to_string())
+        };
+        let len = header("content-length").map_or(0, |x| x.parse::<usize>().unwrap());
+        while body.len() < len {
+            let n = stream.read(&mut buf)?;
+            body.extend_from_slice(&buf[..n]);
+        }
+
+        let mut child = Command::new("git")
+            .arg("http-backend")
+            .env("GIT_PROJECT_ROOT", root)
+            .env("GIT_HTTP_EXPORT_ALL", "1")
+            .env("REQUEST_METHOD", method)
+            .env("PATH_INFO", path)
+            .env("QUERY_STRING", query)
+            .env("CONTENT_TYPE", header("content-type").unwrap_or_default())
+            .env("CONTENT_LENGTH", len.to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        child.stdin.take().unwrap().write_all(&body)?;
+        let output = child.wait_with_output()?;
+
+        // CGI の header の Status を HTTP の status line にし, body は chunked で返す
+        let out = output.stdout;
+        let end = out.windows(4).position(|x| x == b"\r\n\r\n").unwrap();
+        let cgi_head = String::from_utf8_lossy(&out[..end]).into_owned();
+        let status = cgi_head
+            .split("\r\n")
+            .find_map(|x| x.strip_prefix("Status: "))
+            .unwrap_or("200 OK");
+        let mut response =
+            format!("HTTP/1.1 {}\r\nTransfer-Encoding: chunked\r\n", status).into_bytes();
+        for line in cgi_head
+            .split("\r\n")
+            .filter(|x| !x.starts_with("Status: "))
+        {
+            response.extend_from_slice(format!("{}\r\n", line).as_bytes());
+        }
+        response.extend_from_slice(b"\r\n");
+        for chunk in out[(end + 4)..].chunks(1000) {
+            response.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+            response.extend_from_slice(chunk);
+            response.extend_from_slice(b"\r\n");
+        }
+        response.extend_from_slice(b"0\r\n\r\n");
+        stream.write_all(&response)
+    }
+
+    #[test]
+    fn https_handshake_failure() {
+        if Command::new("openssl").arg("version").output().is_err() {
+            return;
+        }
+        // TLS を話さずにすぐ閉じる server
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                drop(stream);
+            }
+        });
+
+        let mut transport =
+            HttpTransport::new(&format!("https://127.0.0.1:{}/repo.git", port)).unwrap();
+        let e = transport.advertisement().err().unwrap();
+        assert!(e.to_string().contains("TLS handshake failed"), "{}", e);
+    }
+
+    #[test]
+    fn http_fetch() {
+        let dir = std::env::temp_dir().join(format!("rgit-http-{}", std::process::id()));
+        let git_cmd = |args: &[&str]| {
+            Command::new("git")
+                .arg("-C")
+                .arg(&dir)
+                .args(["-c", "user.name=rgit", "-c", "user.email=rgit@example.com"])
+                .args(args)
+                .output()
+        };
+        std::fs::create_dir_all(&dir).unwrap();
+        // git が無ければ確認できない
+        if git_cmd(&["init", "-q", "work"]).is_err() {
+            std::fs::remove_dir_all(&dir).unwrap();
+            return;
+        }
+        std::fs::write(dir.join("work/a.txt"), "a\n").unwrap();
+        git_cmd(&["-C", "work", "add", "a.txt"]).unwrap();
+        git_cmd(&["-C", "work", "commit", "-q", "-m", "first"]).unwrap();
+        git_cmd(&["-C", "work", "branch", "topic"]).unwrap();
+        git_cmd(&["clone", "-q", "--bare", "work", "repo.git"]).unwrap();
+        let rev = |name: &str| {
+            let out = git_cmd(&["-C", "repo.git", "rev-parse", name]).unwrap();
+            String::from_utf8(out.stdout).unwrap().trim().to_string()
+        };
+        let port = serve(dir.clone());
+
+        let url = format!("http://127.0.0.1:{}/repo.git", port);
+        let mut git = Git::new(InMemFileSystem::init());
+        git.add_remote(&Remote::new("origin".to_string(), url.clone()))
+            .unwrap();
+        let mut transport = HttpTransport::new(&url).unwrap();
+        let lines =
+            cmd::fetch_transport(&mut git, &mut transport, "origin", false, &mut |_| ()).unwrap();
+        assert_eq!(
+            lines,
+            vec![
+                format!("From {}", url),
+                " * [new branch]      master -> origin/master".to_string(),
+                " * [new branch]      topic -> origin/topic".to_string(),
+            ]
+        );
+        let master = git.rev_parse("refs/remotes/origin/master").unwrap();
+        assert_eq!(master, rev("master"));
+        assert!(git.read_commit(&master).is_ok());
+
+        // 2回目は手元にある commit を have にして新しい分だけ受け取る
+        std::fs::write(dir.join("work/b.txt"), "b\n").unwrap();
+        git_cmd(&["-C", "work", "add", "b.txt"]).unwrap();
+        git_cmd(&["-C", "work", "commit", "-q", "-m", "second"]).unwrap();
+        git_cmd(&["-C", "work", "push", "-q", "../repo.git", "master"]).unwrap();
+        let lines =
+            cmd::fetch_transport(&mut git, &mut transport, "origin", false, &mut |_| ()).unwrap();
+        assert_eq!(
+            lines[1],
+            format!(
+                "   {}..{}  master -> origin/master",
+                &master[..7],
+                &rev("master")[..7]
+            )
+        );
+        assert!(
+            cmd::fetch_transport(&mut git, &mut transport, "origin", false, &mut |_| ())
+                .unwrap()
+                .is_empty()
+        );
+
+        // 存在しないリポジトリは HTTP の status がエラーになる
+        let mut missing =
+            HttpTransport::new(&format!("http://127.0.0.1:{}/missing.git", port)).unwrap();
+        assert!(missing.advertisement().is_err());
+
+        // https は openssl s_client に渡す. 暗号化せずに -connect の先へ繋ぐだけの偽の openssl で,
+        // 証明書を確かめる引数と, その stdin/stdout で HTTP を話すことを確かめる
+        let openssl = dir.join("openssl");
+        std::fs::write(
+            &openssl,
+            "#!/bin/bash\n\
+             echo \"$@\" > \"$(dirname \"$0\")/openssl-args\"\n\
+             for x; do [ \"$prev\" = -connect ] && addr=$x; prev=$x; done\n\
+             exec 3<>/dev/tcp/${addr%:*}/${addr##*:}\n\
+             cat <&0 >&3 &\n\
+             cat <&3\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&openssl, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let url = format!("https://127.0.0.1:{}/repo.git", port);
+        let mut git = Git::new(InMemFileSystem::init());
+        git.add_remote(&Remote::new("origin".to_string(), url.clone()))
+            .unwrap();
+        let mut transport = HttpTransport::new(&url).unwrap();
+        transport.openssl = openssl.to_string_lossy().into_owned();
+        transport.ca_file = Some("ca.pem".to_string());
+        cmd::fetch_transport(&mut git, &mut transport, "origin", false, &mut |_| ()).unwrap();
+        assert_eq!(
+            git.rev_parse("refs/remotes/origin/master").unwrap(),
+            rev("master")
+        );
+        let args = std::fs::read_to_string(dir.join("openssl-args")).unwrap();
+        assert_eq!(
+            args.trim(),
+            format!(
+                "s_client -quiet -verify_return_error -connect 127.0.0.1:{} \
+                 -servername 127.0.0.1 -verify_hostname 127.0.0.1 -CAfile ca.pem",
+                port
+            )
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+// git の通信で使う形式
+pub mod daemon;
+pub mod fetch;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod pktline;
+pub mod push;
+pub mod receive_pack;
+#[cfg(feature = "ssh")]
+pub mod ssh;
+pub mod upload_pack;
+
+use fetch::Transport;
+use push::PushTransport;
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+// 接続と読み書きを待つ時間
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+// url の scheme から transport を選ぶ. ローカルのパスは transport を使わずに読むので None
+pub fn connect(url: &str) -> io::Result<Option<Box<dyn Transport>>> {
+    if url.starts_with("git://") {
+        return Ok(Some(Box::new(daemon::DaemonTransport::new(url)?)));
+    }
+    if url.starts_with("http://") || url.starts_with("https://") {
+        #[cfg(feature = "http")]
+        return Ok(Some(Box::new(http::HttpTransport::new(url)?)));
+        #[cfg(not(feature = "http"))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{}: rgit was built without the http feature", url),
+        ));
+    }
+
+    if is_ssh_url(url) {
+        #[cfg(feature = "ssh")]
+        return Ok(Some(Box::new(ssh::SshTransport::new(url)?)));
+        #[cfg(not(feature = "ssh"))]
+        return Err(without_ssh(url));
+    }
+
+    Ok(None)
+}
+
+// push に使う transport を選ぶ. ローカルのパスは None
+pub fn connect_push(url: &str) -> io::Result<Option<Box<dyn PushTransport>>> {
+    if is_ssh_url(url) {
+        #[cfg(feature = "ssh")]
+        return Ok(Some(Box::new(ssh::SshTransport::new(url)?)));
+        #[cfg(not(feature = "ssh"))]
+        return Err(without_ssh(url));
+    }
+    if url.contains("://") && !url.starts_with("file://") {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{}: pushing over this protocol is not supported", url),
+        ));
+    }
+
+    Ok(None)
+}
+
+// ssh:// か, scp 風の [user@]host:path. ':' より前に '/' があればローカルのパス
+fn is_ssh_url(url: &str) -> bool {
+    if let Some((scheme, _)) = url.split_once("://") {
+        return ["ssh", "git+ssh", "ssh+git"].contains(&scheme);
+    }
+    url.split_once(':')
+        .is_some_and(|(host, _)| !host.is_empty() && !host.contains('/'))
+}
+
+#[cfg(not(feature = "ssh"))]
+fn without_ssh(url: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{}: rgit was built without the ssh feature", url),
+    )
+}
+
+// scheme の後の `host[:port]/path` を分ける. port が無ければ default_port
+fn split_url(url: &str, rest: &str, default_port: u16) -> io::Result<(String, u16, String)> {
+    let invalid = |message: &str| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", url, message))
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, ""),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse::<u16>()
+                .map_err(|_| invalid("bad port number"))?,
+        ),
+        None => (authority, default_port),
+    };
+    if host.is_empty() {
+        return Err(invalid("no host"));
+    }
+
+    Ok((host.to_string(), port, path.to_string()))
+}
+
+// host:port に timeout 付きで繋ぐ. 接続できない理由は分かる言葉にする
+pub fn connect_tcp(host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+    let addrs = (host, port).to_socket_addrs().map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("unable to look up {} ({})", host, e),
+        )
+    })?;
+
+    let mut last = io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("unable to look up {}", host),
+    );
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+                return Ok(stream);
+            }
+            Err(e) => last = network_error(e, host, port),
+        }
+    }
+
+    Err(last)
+}
+
+// 接続後の読み書きのエラーも含めて, 相手が分かるメッセージにする
+pub fn network_error(e: io::Error, host: &str, port: u16) -> io::Error {
+    let reason = match e.kind() {
+        io::ErrorKind::ConnectionRefused => "connection refused",
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => "connection timed out",
+        io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted => {
+            "connection closed by remote"
+        }
+        _ => return e,
+    };
+    io::Error::new(
+        e.kind(),
+        format!("unable to connect to {}:{}: {}", host, port, reason),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    #[test]
+    fn connect_errors() {
+        // 閉じたポート
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let e = connect_tcp("127.0.0.1", port, TIMEOUT).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "unable to connect to 127.0.0.1:{}: connection refused",
+                port
+            )
+        );
+
+        // 接続はできるが何も送ってこない
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut stream = connect_tcp("127.0.0.1", port, Duration::from_millis(50)).unwrap();
+        let e = stream
+            .read(&mut [0; 4])
+            .map_err(|e| network_error(e, "127.0.0.1", port))
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "unable to connect to 127.0.0.1:{}: connection timed out",
+                port
+            )
+        );
+
+        assert!(connect_tcp("host.invalid", 9418, TIMEOUT).is_err());
+        assert!(connect("/tmp/repo").unwrap().is_none());
+        assert!(connect("git://").is_err());
+        assert!(connect_push("./repo").unwrap().is_none());
+        assert!(connect_push("git://example.com/repo").is_err());
+        assert!(is_ssh_url("git@example.com:repo.git"));
+        assert!(is_ssh_url("ssh://example.com/repo.git"));
+        assert!(!is_ssh_url("./a:b"));
+        assert!(!is_ssh_url("file:///tmp/repo"));
+    }
+}
@@ -0,0 +1,290 @@
+use std::io::{self, Read, Write};
+
+// 長さの4桁の16進数も含めた pkt-line の最大の長さ
+pub const MAX_PKT_LEN: usize = 65520;
+
+#[derive(Debug, PartialEq)]
+pub enum Pkt {
+    Data(Vec<u8>),
+    // 0000
+    Flush,
+    // 0001. protocol v2 で区切りに使う
+    Delim,
+}
+
+// 長さ (4桁の16進数) の後に data を書く
+pub fn write_pkt(w: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    let len = data.len() + 4;
+    if len > MAX_PKT_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("pkt-line too long: {} bytes", len),
+        ));
+    }
+    w.write_all(format!("{:04x}", len).as_bytes())?;
+    w.write_all(data)
+}
+
+pub fn write_flush(w: &mut impl Write) -> io::Result<()> {
+    w.write_all(b"0000")
+}
+
+pub fn write_delim(w: &mut impl Write) -> io::Result<()> {
+    w.write_all(b"0001")
+}
+
+// pkt-line を1つずつ読むイテレータ. 入力が pkt-line の境界で終われば None を返す
+pub struct PktReader<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> PktReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+
+    fn read_pkt(&mut self) -> io::Result<Option<Pkt>> {
+        let mut head = [0; 4];
+        let mut filled = 0;
+        while filled < head.len() {
+            match self.reader.read(&mut head[filled..])? {
+                0 if filled == 0 => return Ok(None),
+                0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                n => filled += n,
+            }
+        }
+
+        let len = std::str::from_utf8(&head)
+            .ok()
+            .filter(|x| x.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|x| usize::from_str_radix(x, 16).ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("bad pkt-line length: {:?}", String::from_utf8_lossy(&head)),
+                )
+            })?;
+        match len {
+            0 => Ok(Some(Pkt::Flush)),
+            1 => Ok(Some(Pkt::Delim)),
+            x if !(4..=MAX_PKT_LEN).contains(&x) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("bad pkt-line length: {}", len),
+            )),
+            _ => {
+                let mut data = vec![0; len - 4];
+                self.reader.read_exact(&mut data)?;
+                Ok(Some(Pkt::Data(data)))
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for PktReader<R> {
+    type Item = io::Result<Pkt>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_pkt().transpose()
+    }
+}
+
+// side-band-64k の pkt-line から channel 1 の data だけを読む
+// channel 2 (progress) は progress に渡し, channel 3 はエラーにする. flush で終わる
+pub struct SideBandReader<R: Read, P: FnMut(&[u8])> {
+    pkts: PktReader<R>,
+    progress: P,
+    buf: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<R: Read, P: FnMut(&[u8])> SideBandReader<R, P> {
+    pub fn new(reader: R, progress: P) -> Self {
+        Self {
+            pkts: PktReader::new(reader),
+            progress,
+            buf: Vec::new(),
+            pos: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read, P: FnMut(&[u8])> Read for SideBandReader<R, P> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() {
+            if self.done {
+                return Ok(0);
+            }
+            let data = match self.pkts.next().transpose()? {
+                Some(Pkt::Data(data)) => data,
+                Some(Pkt::Flush) | None => {
+                    self.done = true;
+                    continue;
+                }
+                Some(Pkt::Delim) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "unexpected delim in side-band",
+                    ))
+                }
+            };
+            match data.split_first() {
+                Some((1, rest)) => {
+                    self.buf = rest.to_vec();
+                    self.pos = 0;
+                }
+                Some((2, rest)) => (self.progress)(rest),
+                Some((3, rest)) => {
+                    return Err(io::Error::other(format!(
+                        "remote error: {}",
+                        String::from_utf8_lossy(rest).trim_end()
+                    )))
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "bad side-band channel",
+                    ))
+                }
+            }
+        }
+
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..(self.pos + n)]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // git upload-pack の出力から取り出したもの
+    const ADVERTISEMENT: &[u8] =
+        b"003f19ce0a2607af1d5ba6d5e20403885b5480c0f123 refs/heads/master\n0000";
+    const SIDE_BAND: &[u8] = b"0008NAK\n\
+        0023\x02Enumerating objects: 8, done.\n\
+        0019\x01\xcb\xd1%\xb1\xc3E\x04K^\xb0\xc6\xa5\xc8\xdd\xae\x13b0\x98\x10\
+        0006\x01\xd0\
+        0039\x02Total 8 (delta 0), reused 0 (delta 0), pack-reused 0\
+        0006\x02\n\
+        0000";
+
+    fn read_all(bytes: &[u8]) -> io::Result<Vec<Pkt>> {
+        PktReader::new(bytes).collect()
+    }
+
+    #[test]
+    fn pktline_write() {
+        let mut buf = Vec::new();
+        write_pkt(
+            &mut buf,
+            b"19ce0a2607af1d5ba6d5e20403885b5480c0f123 refs/heads/master\n",
+        )
+        .unwrap();
+        write_flush(&mut buf).unwrap();
+        assert_eq!(buf, ADVERTISEMENT);
+
+        let mut buf = Vec::new();
+        write_pkt(&mut buf, b"").unwrap();
+        write_delim(&mut buf).unwrap();
+        assert_eq!(buf, b"00040001");
+
+        // 長さを含めて 65520 bytes まで
+        let mut buf = Vec::new();
+        write_pkt(&mut buf, &[b'a'; MAX_PKT_LEN - 4]).unwrap();
+        assert!(buf.starts_with(b"fff0aaa"));
+        assert_eq!(buf.len(), MAX_PKT_LEN);
+        let err = write_pkt(&mut Vec::new(), &[b'a'; MAX_PKT_LEN - 3]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn pktline_read() {
+        assert_eq!(
+            read_all(ADVERTISEMENT).unwrap(),
+            vec![
+                Pkt::Data(b"19ce0a2607af1d5ba6d5e20403885b5480c0f123 refs/heads/master\n".to_vec()),
+                Pkt::Flush,
+            ]
+        );
+        assert_eq!(
+            read_all(b"000400010000").unwrap(),
+            vec![Pkt::Data(Vec::new()), Pkt::Delim, Pkt::Flush]
+        );
+        assert!(read_all(b"").unwrap().is_empty());
+
+        let max = [b"fff0".as_ref(), &[b'a'; MAX_PKT_LEN - 4]].concat();
+        assert_eq!(
+            read_all(&max).unwrap(),
+            vec![Pkt::Data(vec![b'a'; MAX_PKT_LEN - 4])]
+        );
+
+        for bytes in [
+            b"fff1".as_ref(),
+            b"ffff",
+            b"0002",
+            b"0003",
+            b"00x4",
+            b"+004",
+        ] {
+            assert_eq!(
+                read_all(bytes).unwrap_err().kind(),
+                io::ErrorKind::InvalidData,
+                "{:?}",
+                bytes
+            );
+        }
+        // 途中で切れている
+        assert_eq!(
+            read_all(b"000").unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        assert_eq!(
+            read_all(b"0009do").unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn pktline_side_band() {
+        let mut pkts = PktReader::new(SIDE_BAND);
+        assert_eq!(pkts.next().unwrap().unwrap(), Pkt::Data(b"NAK\n".to_vec()));
+
+        let mut progress = Vec::new();
+        let mut data = Vec::new();
+        SideBandReader::new(&SIDE_BAND[8..], |x: &[u8]| progress.push(x.to_vec()))
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(
+            data,
+            b"\xcb\xd1%\xb1\xc3E\x04K^\xb0\xc6\xa5\xc8\xdd\xae\x13b0\x98\x10\xd0"
+        );
+        assert_eq!(
+            progress,
+            vec![
+                b"Enumerating objects: 8, done.\n".to_vec(),
+                b"Total 8 (delta 0), reused 0 (delta 0), pack-reused 0".to_vec(),
+                b"\n".to_vec(),
+            ]
+        );
+
+        // flush より後は読まない
+        let mut data = Vec::new();
+        SideBandReader::new(b"0009\x01abcd00000009\x01efgh".as_ref(), |_: &[u8]| {})
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"abcd");
+
+        let err = SideBandReader::new(b"0013\x03access denied\n".as_ref(), |_: &[u8]| {})
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "remote error: access denied");
+        let err = SideBandReader::new(b"0006\x04x".as_ref(), |_: &[u8]| {})
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
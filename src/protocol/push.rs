@@ -0,0 +1,154 @@
+use super::fetch::Advertisement;
+use super::pktline::{self, Pkt, PktReader};
+use super::receive_pack::Command;
+use std::io::{self, Read};
+
+// こちらが使える capability. server が広告したものだけを最初の command に付ける
+const CAPABILITIES: [&str; 3] = ["report-status", "ofs-delta", "agent=rgit"];
+
+// push を送る相手. receive-pack の広告を受け取ってから command と pack を送る
+pub trait PushTransport {
+    fn receive_advertisement(&mut self) -> io::Result<Advertisement>;
+
+    // request は push_request で作ったもの. 返すのは report-status の内容
+    fn send_pack(&mut self, request: &[u8]) -> io::Result<Report>;
+}
+
+// command を並べて flush で終え, 削除だけでなければ pack を続ける
+pub fn push_request(adv: &Advertisement, commands: &[Command], pack: Option<&[u8]>) -> Vec<u8> {
+    let capabilities = CAPABILITIES
+        .iter()
+        .filter(|x| adv.has_capability(x.split('=').next().unwrap_or(x)))
+        .copied()
+        .collect::<Vec<_>>();
+
+    let mut request = Vec::new();
+    for (i, command) in commands.iter().enumerate() {
+        let line = format!("{} {} {}", command.old, command.new, command.name);
+        let line = if i == 0 {
+            format!("{}\0{}\n", line, capabilities.join(" "))
+        } else {
+            format!("{}\n", line)
+        };
+        // ref の名前は短いので上限を超えない
+        pktline::write_pkt(&mut request, line.as_bytes()).unwrap();
+    }
+    pktline::write_flush(&mut request).unwrap();
+    if let Some(pack) = pack {
+        request.extend_from_slice(pack);
+    }
+
+    request
+}
+
+// receive-pack が返す report-status
+#[derive(Debug, PartialEq)]
+pub struct Report {
+    pub unpack: Result<(), String>,
+    // (ref の名前, 更新できなかった理由)
+    pub statuses: Vec<(String, Result<(), String>)>,
+}
+
+impl Report {
+    // `unpack <結果>` に続く `ok <ref>` と `ng <ref> <理由>` を flush まで読む
+    pub fn read<R: Read>(reader: R) -> io::Result<Self> {
+        let mut lines = Vec::new();
+        for pkt in PktReader::new(reader) {
+            match pkt? {
+                Pkt::Data(data) => lines.push(
+                    String::from_utf8(data)
+                        .map_err(|_| invalid_data("report-status is not UTF-8"))?,
+                ),
+                Pkt::Flush => break,
+                Pkt::Delim => return Err(invalid_data("unexpected delim in report-status")),
+            }
+        }
+
+        let mut lines = lines.iter().map(|x| x.trim_end_matches('\n'));
+        let unpack = match lines.next().and_then(|x| x.strip_prefix("unpack ")) {
+            Some("ok") => Ok(()),
+            Some(reason) => Err(reason.to_string()),
+            None => return Err(invalid_data("report-status has no unpack line")),
+        };
+        let statuses = lines
+            .map(|line| {
+                if let Some(name) = line.strip_prefix("ok ") {
+                    return Ok((name.to_string(), Ok(())));
+                }
+                line.strip_prefix("ng ")
+                    .and_then(|x| x.split_once(' '))
+                    .map(|(name, reason)| (name.to_string(), Err(reason.to_string())))
+                    .ok_or_else(|| invalid_data(&format!("bad report-status line: {}", line)))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Self { unpack, statuses })
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::receive_pack::{self, Commands};
+
+    const HASH: &str = "e83c5163316f89bfbde7d9ab23ca2e25604af290";
+
+    #[test]
+    fn push_request_round_trip() {
+        let adv = Advertisement {
+            refs: Vec::new(),
+            capabilities: vec!["report-status".to_string(), "delete-refs".to_string()],
+        };
+        let commands = vec![Command {
+            old: "0".repeat(40),
+            new: HASH.to_string(),
+            name: "refs/heads/master".to_string(),
+        }];
+        let request = push_request(&adv, &commands, Some(b"PACK"));
+
+        // server 側でそのまま読める
+        let mut reader = request.as_slice();
+        let read = Commands::read(&mut reader).unwrap().unwrap();
+        assert_eq!(read.commands, commands);
+        assert_eq!(read.capabilities, vec!["report-status".to_string()]);
+        assert_eq!(reader, b"PACK");
+    }
+
+    #[test]
+    fn push_report_read() {
+        let mut bytes = Vec::new();
+        receive_pack::write_report(
+            &mut bytes,
+            &Ok(()),
+            &[
+                ("refs/heads/master".to_string(), Ok(())),
+                (
+                    "refs/heads/topic".to_string(),
+                    Err("non-fast-forward".to_string()),
+                ),
+            ],
+        )
+        .unwrap();
+        let report = Report::read(bytes.as_slice()).unwrap();
+        assert_eq!(report.unpack, Ok(()));
+        assert_eq!(
+            report.statuses,
+            vec![
+                ("refs/heads/master".to_string(), Ok(())),
+                (
+                    "refs/heads/topic".to_string(),
+                    Err("non-fast-forward".to_string())
+                ),
+            ]
+        );
+
+        let report = Report::read(b"0018unpack index failed\n0000".as_ref()).unwrap();
+        assert_eq!(report.unpack, Err("index failed".to_string()));
+        assert!(Report::read(b"0000".as_ref()).is_err());
+        assert!(Report::read(b"000eunpack ok\n0009hoge\n0000".as_ref()).is_err());
+    }
+}
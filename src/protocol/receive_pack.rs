@@ -0,0 +1,182 @@
+use super::pktline::{self, Pkt, PktReader};
+use std::io::{self, Read, Write};
+
+// receive-pack の server 側で広告する capability
+// side-band は使わず, thin pack は送らないように頼む
+pub const CAPABILITIES: [&str; 6] = [
+    "report-status",
+    "delete-refs",
+    "atomic",
+    "ofs-delta",
+    "no-thin",
+    "agent=rgit",
+];
+
+// `<old> <new> <ref>` の1行. 作成なら old が, 削除なら new が 0 を並べた hash になる
+#[derive(Debug, PartialEq)]
+pub struct Command {
+    pub old: String,
+    pub new: String,
+    pub name: String,
+}
+
+// client が送ってくる ref の更新と, 最初の行に付いた capability
+#[derive(Debug, Default, PartialEq)]
+pub struct Commands {
+    pub commands: Vec<Command>,
+    pub capabilities: Vec<String>,
+}
+
+impl Commands {
+    // flush までの更新の行を読む. 何も更新しない client はいきなり flush を送ってくるので None を返す
+    pub fn read<R: Read>(reader: R) -> io::Result<Option<Self>> {
+        let mut commands = Self::default();
+        for pkt in PktReader::new(reader) {
+            let line = match pkt? {
+                Pkt::Data(data) => String::from_utf8(data)
+                    .map_err(|_| invalid_data("command line is not UTF-8"))?,
+                Pkt::Flush if commands.commands.is_empty() => return Ok(None),
+                Pkt::Flush => return Ok(Some(commands)),
+                Pkt::Delim => return Err(invalid_data("unexpected delim in commands")),
+            };
+            let line = line.trim_end_matches('\n');
+            let line = match line.split_once('\0') {
+                Some((line, capabilities)) => {
+                    if commands.commands.is_empty() {
+                        commands.capabilities = capabilities
+                            .split(' ')
+                            .filter(|x| !x.is_empty())
+                            .map(String::from)
+                            .collect();
+                    }
+                    line
+                }
+                None => line,
+            };
+            if line.starts_with("shallow ") {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "pushing from a shallow clone is not supported",
+                ));
+            }
+            let mut words = line.splitn(3, ' ');
+            match (words.next(), words.next(), words.next()) {
+                (Some(old), Some(new), Some(name)) => commands.commands.push(Command {
+                    old: old.to_string(),
+                    new: new.to_string(),
+                    name: name.to_string(),
+                }),
+                _ => return Err(invalid_data(&format!("expected command, got '{}'", line))),
+            }
+        }
+
+        // 何も送らずに閉じた client
+        Ok(None)
+    }
+
+    pub fn has_capability(&self, name: &str) -> bool {
+        self.capabilities.iter().any(|x| x == name)
+    }
+}
+
+// report-status の形式で結果を返す. 失敗した ref は `ng <ref> <理由>` になる
+pub fn write_report<W: Write>(
+    w: &mut W,
+    unpack: &Result<(), String>,
+    statuses: &[(String, Result<(), String>)],
+) -> io::Result<()> {
+    match unpack {
+        Ok(()) => pktline::write_pkt(w, b"unpack ok\n")?,
+        Err(reason) => pktline::write_pkt(w, format!("unpack {}\n", reason).as_bytes())?,
+    }
+    for (name, status) in statuses.iter() {
+        let line = match status {
+            Ok(()) => format!("ok {}\n", name),
+            Err(reason) => format!("ng {} {}\n", name, reason),
+        };
+        pktline::write_pkt(w, line.as_bytes())?;
+    }
+    pktline::write_flush(w)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "e83c5163316f89bfbde7d9ab23ca2e25604af290";
+
+    #[test]
+    fn receive_pack_read_commands() {
+        let zero = "0".repeat(40);
+        let mut bytes = Vec::new();
+        pktline::write_pkt(
+            &mut bytes,
+            format!(
+                "{} {} refs/heads/master\0 report-status atomic agent=git/2.0\n",
+                zero, HASH
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        pktline::write_pkt(
+            &mut bytes,
+            format!("{} {} refs/heads/topic", HASH, zero).as_bytes(),
+        )
+        .unwrap();
+        pktline::write_flush(&mut bytes).unwrap();
+
+        let commands = Commands::read(bytes.as_slice()).unwrap().unwrap();
+        assert_eq!(
+            commands.commands,
+            vec![
+                Command {
+                    old: zero.clone(),
+                    new: HASH.to_string(),
+                    name: "refs/heads/master".to_string(),
+                },
+                Command {
+                    old: HASH.to_string(),
+                    new: zero,
+                    name: "refs/heads/topic".to_string(),
+                },
+            ]
+        );
+        assert!(commands.has_capability("report-status"));
+        assert!(commands.has_capability("atomic"));
+        assert!(!commands.has_capability("side-band-64k"));
+
+        assert_eq!(Commands::read(b"0000".as_ref()).unwrap(), None);
+        assert_eq!(Commands::read(b"".as_ref()).unwrap(), None);
+        assert!(Commands::read(b"0009hoge\n0000".as_ref()).is_err());
+        let mut bytes = Vec::new();
+        pktline::write_pkt(&mut bytes, format!("shallow {}\n", HASH).as_bytes()).unwrap();
+        let e = Commands::read(bytes.as_slice()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn receive_pack_write_report() {
+        let mut bytes = Vec::new();
+        write_report(
+            &mut bytes,
+            &Ok(()),
+            &[
+                ("refs/heads/master".to_string(), Ok(())),
+                (
+                    "refs/heads/topic".to_string(),
+                    Err("non-fast-forward".to_string()),
+                ),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            bytes,
+            b"000eunpack ok\n0019ok refs/heads/master\n\
+              0029ng refs/heads/topic non-fast-forward\n0000"
+        );
+    }
+}
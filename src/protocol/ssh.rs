@@ -0,0 +1,311 @@
+use super::fetch::{self, Advertisement, Transport};
+use super::push::{PushTransport, Report};
+use std::io::{self, Write};
+use std::process::{Child, Command, Stdio};
+
+// ssh で相手のマシンの git-upload-pack や git-receive-pack を動かし, その stdin/stdout で話す
+// ssh の stderr はそのまま流すので, host key の確認や認証のエラーは利用者に見える
+pub struct SshTransport {
+    // user@ を含めた接続先
+    host: String,
+    port: Option<u16>,
+    path: String,
+    // 使う ssh. GIT_SSH があればそれを使う
+    pub program: String,
+    child: Option<Child>,
+}
+
+impl SshTransport {
+    // ssh://[user@]host[:port]/path と scp 風の [user@]host:path に対応する
+    pub fn new(url: &str) -> io::Result<Self> {
+        let invalid = |message: &str| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", url, message))
+        };
+        let (host, port, path) = match ["ssh://", "git+ssh://", "ssh+git://"]
+            .iter()
+            .find_map(|x| url.strip_prefix(x))
+        {
+            Some(rest) => {
+                let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+                let (host, port) = match authority.rsplit_once(':') {
+                    Some((host, port)) => (
+                        host,
+                        Some(
+                            port.parse::<u16>()
+                                .map_err(|_| invalid("bad port number"))?,
+                        ),
+                    ),
+                    None => (authority, None),
+                };
+                // ssh://host/~user/repo は home からのパス
+                let path = match path.strip_prefix("/~") {
+                    Some(rest) => format!("~{}", rest),
+                    None => path.to_string(),
+                };
+                (host.to_string(), port, path)
+            }
+            None => {
+                let (host, path) = url
+                    .split_once(':')
+                    .filter(|(host, _)| !host.contains('/'))
+                    .ok_or_else(|| invalid("not an ssh url"))?;
+                (host.to_string(), None, path.to_string())
+            }
+        };
+        if host.is_empty() || host.ends_with('@') {
+            return Err(invalid("no host"));
+        }
+        if path.is_empty() {
+            return Err(invalid("no repository path"));
+        }
+        // `-oProxyCommand=...` のように ssh や git のオプションとして読まれるものは受け付けない
+        if host.starts_with('-') {
+            return Err(invalid("strange hostname blocked"));
+        }
+        if path.starts_with('-') {
+            return Err(invalid("strange pathname blocked"));
+        }
+
+        Ok(Self {
+            host,
+            port,
+            path,
+            program: std::env::var("GIT_SSH").unwrap_or_else(|_| "ssh".to_string()),
+            child: None,
+        })
+    }
+
+    // 相手の shell で service を動かして, 最初に送ってくる ref の広告を読む
+    fn spawn(&mut self, service: &str) -> io::Result<Advertisement> {
+        let mut command = Command::new(&self.program);
+        if let Some(port) = self.port {
+            command.arg("-p").arg(port.to_string());
+        }
+        let mut child = command
+            .arg("--")
+            .arg(&self.host)
+            .arg(format!("{} {}", service, quote(&self.path)))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", self.program, e)))?;
+
+        let adv = Advertisement::read(child.stdout.as_mut().unwrap());
+        self.child = Some(child);
+        adv.map_err(|e| self.failed(e))
+    }
+
+    // request を全部送ってから相手の出力を読み, ssh が終わるのを待つ
+    fn exchange<T>(
+        &mut self,
+        request: &[u8],
+        read: impl FnOnce(&mut dyn io::Read) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut child = self.child.take().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotConnected,
+                "request sent before advertisement",
+            )
+        })?;
+        // 送り終えたら閉じて, 相手がそれ以上待たないようにする
+        let written = child.stdin.take().unwrap().write_all(request);
+        let result = written.and_then(|_| read(child.stdout.as_mut().unwrap()));
+        self.child = Some(child);
+        let result = result.map_err(|e| self.failed(e))?;
+        self.wait()?;
+
+        Ok(result)
+    }
+
+    // ssh が失敗して終わっていれば, 読み書きのエラーより終了状態を伝える
+    fn failed(&mut self, e: io::Error) -> io::Error {
+        self.wait().err().unwrap_or(e)
+    }
+
+    fn wait(&mut self) -> io::Result<()> {
+        let mut child = match self.child.take() {
+            Some(child) => child,
+            None => return Ok(()),
+        };
+        drop(child.stdin.take());
+        let status = child.wait()?;
+        if status.success() {
+            return Ok(());
+        }
+        Err(io::Error::other(format!(
+            "ssh {}: {}",
+            self.host,
+            match status.code() {
+                Some(code) => format!("exited with status {}", code),
+                None => "killed by signal".to_string(),
+            }
+        )))
+    }
+}
+
+impl Transport for SshTransport {
+    fn advertisement(&mut self) -> io::Result<Advertisement> {
+        self.spawn("git-upload-pack")
+    }
+
+    fn fetch_pack(
+        &mut self,
+        adv: &Advertisement,
+        request: &[u8],
+        progress: &mut dyn FnMut(&[u8]),
+    ) -> io::Result<Vec<u8>> {
+        self.exchange(request, |stdout| fetch::read_pack(stdout, adv, progress))
+    }
+}
+
+impl PushTransport for SshTransport {
+    fn receive_advertisement(&mut self) -> io::Result<Advertisement> {
+        self.spawn("git-receive-pack")
+    }
+
+    fn send_pack(&mut self, request: &[u8]) -> io::Result<Report> {
+        self.exchange(request, |stdout| Report::read(stdout))
+    }
+}
+
+// 途中で止めずに終わった時も子 process を残さない
+impl Drop for SshTransport {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+// 相手の shell に渡すので ' で囲む. 中の ' は '\'' にする
+fn quote(path: &str) -> String {
+    format!("'{}'", path.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd;
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::object::commit::User;
+    use crate::object::GitObject;
+    use crate::remote::Remote;
+    use crate::Git;
+    use chrono::{FixedOffset, TimeZone};
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    #[test]
+    fn ssh_url() {
+        let t = SshTransport::new("git@example.com:user/repo.git").unwrap();
+        assert_eq!(
+            (t.host.as_str(), t.port, t.path.as_str()),
+            ("git@example.com", None, "user/repo.git")
+        );
+        let t = SshTransport::new("ssh://git@example.com:2222/srv/repo.git").unwrap();
+        assert_eq!(
+            (t.host.as_str(), t.port, t.path.as_str()),
+            ("git@example.com", Some(2222), "/srv/repo.git")
+        );
+        let t = SshTransport::new("ssh://example.com/~user/repo").unwrap();
+        assert_eq!(
+            (t.host.as_str(), t.path.as_str()),
+            ("example.com", "~user/repo")
+        );
+
+        assert!(SshTransport::new("ssh://example.com").is_err());
+        assert!(SshTransport::new("ssh://example.com:port/x").is_err());
+        assert!(SshTransport::new(":repo").is_err());
+        assert!(SshTransport::new("./dir/a:b").is_err());
+        assert!(SshTransport::new("ssh://-oProxyCommand=touch${IFS}x/repo").is_err());
+        assert!(SshTransport::new("-oProxyCommand=touch:repo").is_err());
+        assert!(SshTransport::new("git@example.com:-u/repo").is_err());
+        assert_eq!(quote("it's"), "'it'\\''s'");
+    }
+
+    // 引数の最後のコマンドを手元の shell で動かす偽の ssh
+    fn fake_ssh(dir: &Path, body: &str) -> String {
+        let path = dir.join(format!("ssh-{}", body.len()));
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn ssh_fetch_push() {
+        let dir = std::env::temp_dir().join(format!("rgit-ssh-{}", std::process::id()));
+        let git_cmd = |args: &[&str]| {
+            Command::new("git")
+                .arg("-C")
+                .arg(&dir)
+                .args(["-c", "user.name=rgit", "-c", "user.email=rgit@example.com"])
+                .args(args)
+                .output()
+        };
+        std::fs::create_dir_all(&dir).unwrap();
+        // git が無ければ確認できない
+        if git_cmd(&["init", "-q", "work"]).is_err() {
+            std::fs::remove_dir_all(&dir).unwrap();
+            return;
+        }
+        std::fs::write(dir.join("work/a.txt"), "a\n").unwrap();
+        git_cmd(&["-C", "work", "add", "a.txt"]).unwrap();
+        git_cmd(&["-C", "work", "commit", "-q", "-m", "first"]).unwrap();
+        git_cmd(&["clone", "-q", "--bare", "work", "repo.git"]).unwrap();
+        let out = git_cmd(&["-C", "repo.git", "rev-parse", "master"]).unwrap();
+        let master = String::from_utf8(out.stdout).unwrap().trim().to_string();
+        let program = fake_ssh(&dir, "for x; do last=$x; done; exec sh -c \"$last\"");
+
+        let url = format!("git@localhost:{}/repo.git", dir.display());
+        let mut git = Git::new(InMemFileSystem::init());
+        git.add_remote(&Remote::new("origin".to_string(), url.clone()))
+            .unwrap();
+        let mut transport = SshTransport::new(&url).unwrap();
+        transport.program = program.clone();
+        let fetched = cmd::fetch_transport(&mut git, &mut transport, "origin", false, &mut |_| ());
+
+        // 取ってきた commit に積んで push する
+        let user = User::new(
+            "rgit".to_string(),
+            "rgit@example.com".to_string(),
+            FixedOffset::east(0).timestamp(1609642799, 0),
+        );
+        let pushed = (|| {
+            git.update_ref("refs/heads/master".to_string(), &master.parse()?)?;
+            let tree = git.read_commit(&master)?.tree;
+            let commit = git.commit_tree(user, None, &tree, "second".to_string())?;
+            let commit = GitObject::Commit(commit);
+            git.write_object(&commit)?;
+            git.update_ref("refs/heads/master".to_string(), &commit.calc_hash())?;
+            let mut transport = SshTransport::new(&url)?;
+            transport.program = program.clone();
+            cmd::push_transport(&mut git, &mut transport, "origin", "master", false)
+        })();
+        let out = git_cmd(&["-C", "repo.git", "log", "--format=%s"]).unwrap();
+        let log = String::from_utf8(out.stdout).unwrap();
+
+        // ssh が失敗したらその終了状態を返す
+        let mut transport = SshTransport::new(&url).unwrap();
+        transport.program = fake_ssh(&dir, "echo 'Permission denied' >&2; exit 255");
+        let failed = transport.advertisement();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            fetched.unwrap(),
+            vec![
+                format!("From {}", url),
+                " * [new branch]      master -> origin/master".to_string(),
+            ]
+        );
+        let pushed = pushed.unwrap();
+        assert_eq!(pushed[0], format!("To {}", url));
+        assert!(pushed[1].ends_with("  master -> master"), "{:?}", pushed);
+        assert_eq!(log, "second\nfirst\n");
+        assert_eq!(
+            failed.unwrap_err().to_string(),
+            "ssh git@localhost: exited with status 255"
+        );
+    }
+}
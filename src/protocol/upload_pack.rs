@@ -0,0 +1,237 @@
+use super::pktline::{self, Pkt, PktReader};
+use crate::hash::ObjectId;
+use std::io::{self, Read, Write};
+
+// side-band の pkt-line 1つに入る data の長さ. 先頭の1byte は channel
+const SIDE_BAND_LEN: usize = pktline::MAX_PKT_LEN - 5;
+
+// upload-pack の server 側で広告する capability
+pub const CAPABILITIES: [&str; 2] = ["side-band-64k", "agent=rgit"];
+
+// `<hash> <ref>` を並べて flush で終える. 最初の行だけ NUL の後に capability を付ける
+// ref が1つも無ければ `capabilities^{}` という名前で capability だけを送る
+pub fn write_advertisement<W: Write>(
+    w: &mut W,
+    refs: &[(String, String)],
+    capabilities: &[String],
+    zero_hash: &str,
+) -> io::Result<()> {
+    let capabilities = capabilities.join(" ");
+    match refs.split_first() {
+        Some(((name, hash), rest)) => {
+            pktline::write_pkt(
+                w,
+                format!("{} {}\0{}\n", hash, name, capabilities).as_bytes(),
+            )?;
+            for (name, hash) in rest.iter() {
+                pktline::write_pkt(w, format!("{} {}\n", hash, name).as_bytes())?;
+            }
+        }
+        None => pktline::write_pkt(
+            w,
+            format!("{} capabilities^{{}}\0{}\n", zero_hash, capabilities).as_bytes(),
+        )?,
+    }
+    pktline::write_flush(w)
+}
+
+// client が送ってくる want の一覧と, 最初の want に付いた capability
+#[derive(Debug, Default, PartialEq)]
+pub struct Wants {
+    pub wants: Vec<ObjectId>,
+    pub capabilities: Vec<String>,
+}
+
+impl Wants {
+    // flush までの `want <hash>[ <capability>...]` を読む
+    // 何も欲しくない client はいきなり flush を送ってくるので None を返す
+    pub fn read<R: Read>(reader: R) -> io::Result<Option<Self>> {
+        let mut wants = Self::default();
+        for pkt in PktReader::new(reader) {
+            let line = match pkt? {
+                Pkt::Data(data) => {
+                    String::from_utf8(data).map_err(|_| invalid_data("want line is not UTF-8"))?
+                }
+                Pkt::Flush if wants.wants.is_empty() => return Ok(None),
+                Pkt::Flush => return Ok(Some(wants)),
+                Pkt::Delim => return Err(invalid_data("unexpected delim in want lines")),
+            };
+            let line = line.trim_end_matches('\n');
+            let mut words = line.split(' ');
+            match (words.next(), words.next()) {
+                (Some("want"), Some(hash)) => {
+                    if wants.wants.is_empty() {
+                        wants.capabilities = words.map(String::from).collect();
+                    }
+                    wants.wants.push(parse_id(hash)?);
+                }
+                (Some(x), _) if x == "shallow" || x.starts_with("deepen") => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "shallow clone is not supported",
+                    ))
+                }
+                _ => return Err(invalid_data(&format!("expected want, got '{}'", line))),
+            }
+        }
+
+        // 何も送らずに閉じた client
+        Ok(None)
+    }
+
+    pub fn has_capability(&self, name: &str) -> bool {
+        self.capabilities.iter().any(|x| x == name)
+    }
+}
+
+// have の行か区切り
+#[derive(Debug, PartialEq)]
+pub enum Have {
+    Have(ObjectId),
+    Flush,
+    Done,
+}
+
+// have/flush/done を1つ読む. done の前に閉じられたら None
+pub fn read_have<R: Read>(reader: R) -> io::Result<Option<Have>> {
+    let line = match PktReader::new(reader).next().transpose()? {
+        Some(Pkt::Data(data)) => data,
+        Some(Pkt::Flush) => return Ok(Some(Have::Flush)),
+        Some(Pkt::Delim) => return Err(invalid_data("unexpected delim in have lines")),
+        None => return Ok(None),
+    };
+    let line = String::from_utf8(line).map_err(|_| invalid_data("have line is not UTF-8"))?;
+    let line = line.trim_end_matches('\n');
+    if line == "done" {
+        return Ok(Some(Have::Done));
+    }
+    let hash = line
+        .strip_prefix("have ")
+        .ok_or_else(|| invalid_data(&format!("expected have, got '{}'", line)))?;
+    Ok(Some(Have::Have(parse_id(hash)?)))
+}
+
+// client が送ってきた hash. object のパスに使うので, 16進数の object id 以外は受け付けない
+fn parse_id(hash: &str) -> io::Result<ObjectId> {
+    ObjectId::from_hex(hash).ok_or_else(|| invalid_data(&format!("invalid object id '{}'", hash)))
+}
+
+// channel 付きの pkt-line に分けて書く
+pub fn write_side_band<W: Write>(w: &mut W, channel: u8, data: &[u8]) -> io::Result<()> {
+    for chunk in data.chunks(SIDE_BAND_LEN) {
+        let mut pkt = Vec::with_capacity(chunk.len() + 1);
+        pkt.push(channel);
+        pkt.extend_from_slice(chunk);
+        pktline::write_pkt(w, &pkt)?;
+    }
+    Ok(())
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::fetch::Advertisement;
+    use crate::protocol::pktline::SideBandReader;
+
+    const HASH: &str = "e83c5163316f89bfbde7d9ab23ca2e25604af290";
+
+    #[test]
+    fn upload_pack_advertisement() {
+        let refs = vec![
+            ("HEAD".to_string(), HASH.to_string()),
+            ("refs/heads/master".to_string(), HASH.to_string()),
+        ];
+        let capabilities = vec!["side-band-64k".to_string(), "agent=rgit".to_string()];
+        let mut bytes = Vec::new();
+        write_advertisement(&mut bytes, &refs, &capabilities, &"0".repeat(40)).unwrap();
+
+        // client 側でそのまま読める
+        let adv = Advertisement::read(bytes.as_slice()).unwrap();
+        assert_eq!(adv.refs, refs);
+        assert_eq!(adv.capabilities, capabilities);
+
+        let mut bytes = Vec::new();
+        write_advertisement(&mut bytes, &[], &capabilities, &"0".repeat(40)).unwrap();
+        assert_eq!(
+            bytes,
+            format!(
+                "0056{} capabilities^{{}}\0side-band-64k agent=rgit\n0000",
+                "0".repeat(40)
+            )
+            .as_bytes()
+        );
+        assert!(Advertisement::read(bytes.as_slice())
+            .unwrap()
+            .refs
+            .is_empty());
+    }
+
+    #[test]
+    fn upload_pack_read_wants() {
+        let mut bytes = Vec::new();
+        pktline::write_pkt(
+            &mut bytes,
+            format!("want {} side-band-64k thin-pack\n", HASH).as_bytes(),
+        )
+        .unwrap();
+        pktline::write_pkt(&mut bytes, format!("want {}\n", HASH).as_bytes()).unwrap();
+        pktline::write_flush(&mut bytes).unwrap();
+        pktline::write_pkt(&mut bytes, format!("have {}\n", HASH).as_bytes()).unwrap();
+        pktline::write_flush(&mut bytes).unwrap();
+        pktline::write_pkt(&mut bytes, b"done\n").unwrap();
+
+        let mut reader = bytes.as_slice();
+        let wants = Wants::read(&mut reader).unwrap().unwrap();
+        let id: ObjectId = HASH.parse().unwrap();
+        assert_eq!(wants.wants, vec![id.clone(), id.clone()]);
+        assert!(wants.has_capability("side-band-64k"));
+        assert!(!wants.has_capability("ofs-delta"));
+        assert_eq!(read_have(&mut reader).unwrap(), Some(Have::Have(id)));
+        assert_eq!(read_have(&mut reader).unwrap(), Some(Have::Flush));
+        assert_eq!(read_have(&mut reader).unwrap(), Some(Have::Done));
+        assert_eq!(read_have(&mut reader).unwrap(), None);
+
+        assert_eq!(Wants::read(b"0000".as_ref()).unwrap(), None);
+        let mut bytes = Vec::new();
+        pktline::write_pkt(&mut bytes, b"deepen 1\n").unwrap();
+        let e = Wants::read(bytes.as_slice()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+        assert!(Wants::read(b"0009hoge\n0000".as_ref()).is_err());
+
+        // object id でない hash は protocol の error にする
+        for hash in ["x", "../../config", &HASH[..39]] {
+            let mut bytes = Vec::new();
+            pktline::write_pkt(&mut bytes, format!("want {}\n", hash).as_bytes()).unwrap();
+            pktline::write_flush(&mut bytes).unwrap();
+            let e = Wants::read(bytes.as_slice()).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+            let mut bytes = Vec::new();
+            pktline::write_pkt(&mut bytes, format!("have {}\n", hash).as_bytes()).unwrap();
+            let e = read_have(bytes.as_slice()).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn upload_pack_side_band() {
+        let data = (0..100000).map(|x| x as u8).collect::<Vec<_>>();
+        let mut bytes = Vec::new();
+        write_side_band(&mut bytes, 1, &data).unwrap();
+        pktline::write_flush(&mut bytes).unwrap();
+
+        let pkts = PktReader::new(bytes.as_slice())
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(pkts.len(), 3);
+        let mut read = Vec::new();
+        SideBandReader::new(bytes.as_slice(), |_| ())
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, data);
+    }
+}
@@ -0,0 +1,335 @@
+use crate::fs::FileSystem;
+use crate::object::tree::FileMode;
+use crate::object::{GitObject, ObjectType};
+use crate::Git;
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+
+// 到達可能性の起点になる object を集める
+// refs, HEAD, reflog に記録された hash, index に登録された blob
+pub fn roots<F: FileSystem>(git: &Git<F>) -> io::Result<Vec<String>> {
+    let mut roots = git
+        .list_refs()
+        .or_else(not_found_as_empty)?
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .collect::<Vec<_>>();
+
+    // まだ commit が無いリポジトリでは HEAD が指す ref は存在しない
+    if let Ok(hash) = git.rev_parse("HEAD") {
+        roots.push(hash);
+    }
+
+    roots.extend(reflog_hashes(git)?);
+
+    match git.read_index() {
+        Ok(bytes) => {
+            let index = git.ls_files_stage(&bytes)?;
+            roots.extend(index.entries.iter().map(|x| hex::encode(&x.hash)));
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    Ok(roots)
+}
+
+// .git/logs 以下の reflog に出てくる hash
+// 各行は `<old> <new> <name> <<email>> <time> <offset>\t<message>`
+pub fn reflog_hashes<F: FileSystem>(git: &Git<F>) -> io::Result<Vec<String>> {
+    let zero = "0".repeat(git.hash_algo.width() * 2);
+
+    let mut hashes = Vec::new();
+    let mut stack = vec![git.git_path("logs")];
+    while let Some(dir) = stack.pop() {
+        let names = match git.filesystem.read_dir(dir.clone()) {
+            Ok(names) => names,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+
+        for name in names {
+            let path = format!("{}/{}", dir, name);
+            let bytes = match git.filesystem.read(path.clone()) {
+                Ok(bytes) => bytes,
+                // ファイルとして読めなければディレクトリ
+                Err(_) => {
+                    stack.push(path);
+                    continue;
+                }
+            };
+
+            for line in String::from_utf8_lossy(&bytes).lines() {
+                hashes.extend(
+                    line.split(' ')
+                        .take(2)
+                        .filter(|&x| x != zero)
+                        .map(String::from),
+                );
+            }
+        }
+    }
+
+    Ok(hashes)
+}
+
+// roots から commit -> tree -> blob と辿れる全ての object
+// 途中で object が読めなければエラーにする
+pub fn walk<F: FileSystem>(git: &Git<F>, roots: Vec<String>) -> io::Result<HashSet<String>> {
+    // 確かめない時は Broken にならず, 読めなかったエラーをそのまま返す
+    traverse(git, roots, false).map(|x| x.unwrap_or_default())
+}
+
+// verify で最初に見つかった壊れた object
+#[derive(Debug, PartialEq)]
+pub struct Broken {
+    pub hash: String,
+    // この hash を指していた object. 起点なら None
+    pub referrer: Option<(ObjectType, String)>,
+    pub reason: String,
+}
+
+impl fmt::Display for Broken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.referrer {
+            Some((object_type, referrer)) => write!(
+                f,
+                "broken link from {} {} to {}: {}",
+                object_type, referrer, self.hash, self.reason
+            ),
+            None => write!(f, "{}: {}", self.hash, self.reason),
+        }
+    }
+}
+
+// root から辿れる全ての object が揃っていて, 中身が hash と一致するかを確かめる
+// fsck と違い一つの起点だけを見て, 最初に壊れた object を見つけたところで止める
+// 問題が無ければ確かめた object の数を返す
+pub fn verify<F: FileSystem>(git: &Git<F>, root: String) -> io::Result<Result<usize, Broken>> {
+    traverse(git, vec![root], true).map(|x| x.map(|reachable| reachable.len()))
+}
+
+// walk と verify の共通部分. verify なら blob も読み, 読めない object を Broken にする
+fn traverse<F: FileSystem>(
+    git: &Git<F>,
+    roots: Vec<String>,
+    verify: bool,
+) -> io::Result<Result<HashSet<String>, Broken>> {
+    let mut reachable = HashSet::new();
+    // (hash, それを指していた object)
+    let mut stack = roots.into_iter().map(|x| (x, None)).collect::<Vec<_>>();
+    while let Some((hash, referrer)) = stack.pop() {
+        if !reachable.insert(hash.clone()) {
+            continue;
+        }
+
+        let object = if verify {
+            let object = hash.parse().and_then(|id| git.read_object_checked(&id));
+            match object {
+                Ok(object) => object,
+                Err(e) => {
+                    let reason = match e.kind() {
+                        io::ErrorKind::NotFound => "missing object".to_string(),
+                        _ => e.to_string(),
+                    };
+                    return Ok(Err(Broken {
+                        hash,
+                        referrer,
+                        reason,
+                    }));
+                }
+            }
+        } else {
+            git.read_git_object(&hash.parse()?)?
+        };
+        match object {
+            GitObject::Blob(_) => {}
+            GitObject::Tree(tree) => {
+                for file in tree.contents {
+                    let child = hex::encode(&file.hash);
+                    match file.mode {
+                        // gitlink は別のリポジトリの commit なので辿らない
+                        FileMode::Gitlink => {}
+                        FileMode::Tree => {
+                            stack.push((child, Some((ObjectType::Tree, hash.clone()))))
+                        }
+                        // blob は確かめるのでなければ中身を読む必要が無いのでここで登録する
+                        _ if verify => stack.push((child, Some((ObjectType::Tree, hash.clone())))),
+                        _ => {
+                            reachable.insert(child);
+                        }
+                    }
+                }
+            }
+            GitObject::Commit(commit) => {
+                // 祖先より先に自分の tree を確かめる
+                let referrer = Some((ObjectType::Commit, hash.clone()));
+                stack.extend(
+                    commit
+                        .parents
+                        .iter()
+                        .map(|x| (x.to_hex(), referrer.clone())),
+                );
+                stack.push((commit.tree.to_hex(), referrer));
+            }
+        }
+    }
+
+    Ok(Ok(reachable))
+}
+
+// どこからも辿れない loose object の hash を名前順に返す
+pub fn unreachable_loose<F: FileSystem>(git: &Git<F>) -> io::Result<Vec<String>> {
+    let reachable = walk(git, roots(git)?)?;
+    let mut hashes = git
+        .loose_objects()?
+        .into_iter()
+        .filter(|x| !reachable.contains(x))
+        .collect::<Vec<_>>();
+    hashes.sort();
+
+    Ok(hashes)
+}
+
+fn not_found_as_empty<T>(e: io::Error) -> io::Result<Vec<T>> {
+    match e.kind() {
+        io::ErrorKind::NotFound => Ok(Vec::new()),
+        _ => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::object::blob::Blob;
+    use crate::object::commit::{Commit, User};
+    use crate::object::tree::{File, Tree};
+    use chrono::{FixedOffset, TimeZone};
+
+    fn write(git: &mut Git<InMemFileSystem>, object: GitObject) -> String {
+        git.write_object(&object).unwrap();
+        hex::encode(object.calc_hash())
+    }
+
+    fn commit(git: &mut Git<InMemFileSystem>, tree: String, message: &str) -> String {
+        let user = User::new(
+            String::from("rgit"),
+            String::from("rgit@example.com"),
+            FixedOffset::east(0).timestamp(0, 0),
+        );
+        let commit = Commit::new(
+            tree.parse().unwrap(),
+            Vec::new(),
+            user.clone(),
+            user,
+            message.to_string(),
+        );
+        write(git, GitObject::Commit(commit))
+    }
+
+    #[test]
+    fn reachable_walk() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let blob = write(&mut git, GitObject::Blob(Blob::new("hello".to_string())));
+        let file = File::new(
+            FileMode::Regular,
+            "hello.txt".to_string(),
+            blob.parse().unwrap(),
+        );
+        let tree = write(&mut git, GitObject::Tree(Tree::new(vec![file])));
+        let first = commit(&mut git, tree.clone(), "first");
+        let amended = commit(&mut git, tree.clone(), "amended");
+        let orphan = write(&mut git, GitObject::Blob(Blob::new("orphan".to_string())));
+
+        git.update_ref("refs/heads/main".to_string(), &amended.parse().unwrap())
+            .unwrap();
+        let reachable = walk(&git, roots(&git).unwrap()).unwrap();
+        assert!(reachable.contains(&amended));
+        assert!(reachable.contains(&tree));
+        assert!(reachable.contains(&blob));
+        assert_eq!(unreachable_loose(&git).unwrap(), {
+            let mut x = vec![first.clone(), orphan.clone()];
+            x.sort();
+            x
+        });
+
+        // reflog に残っている commit は到達可能
+        for dir in [".git/logs", ".git/logs/refs", ".git/logs/refs/heads"] {
+            git.filesystem.create_dir(dir.to_string()).unwrap();
+        }
+        let zero = "0".repeat(40);
+        git.filesystem
+            .write(
+                ".git/logs/refs/heads/main".to_string(),
+                format!(
+                    "{} {} rgit <rgit@example.com> 0 +0000\tcommit\n{} {} rgit <rgit@example.com> 0 +0000\tcommit (amend)\n",
+                    zero, first, first, amended
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        assert_eq!(unreachable_loose(&git).unwrap(), vec![orphan]);
+    }
+
+    #[test]
+    fn reachable_verify() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let blob = write(&mut git, GitObject::Blob(Blob::new("hello".to_string())));
+        let file = |name: &str, hash: &str| {
+            File::new(FileMode::Regular, name.to_string(), hash.parse().unwrap())
+        };
+        let tree = write(
+            &mut git,
+            GitObject::Tree(Tree::new(vec![file("a.txt", &blob)])),
+        );
+        let first = commit(&mut git, tree.clone(), "first");
+        assert_eq!(verify(&git, first.clone()).unwrap(), Ok(3));
+
+        // 無い blob を指す tree
+        let missing = "0123456789abcdef0123456789abcdef01234567";
+        let broken_tree = write(
+            &mut git,
+            GitObject::Tree(Tree::new(vec![
+                file("a.txt", &blob),
+                file("b.txt", missing),
+            ])),
+        );
+        let second = commit(&mut git, broken_tree.clone(), "second");
+        let broken = verify(&git, second.clone()).unwrap().unwrap_err();
+        assert_eq!(
+            broken,
+            Broken {
+                hash: missing.to_string(),
+                referrer: Some((ObjectType::Tree, broken_tree.clone())),
+                reason: "missing object".to_string(),
+            }
+        );
+        assert_eq!(
+            broken.to_string(),
+            format!(
+                "broken link from tree {} to {}: missing object",
+                broken_tree, missing
+            )
+        );
+        // 確かめない walk では blob を読まない
+        assert!(walk(&git, vec![second]).unwrap().contains(missing));
+
+        // 中身が別の object にすり替わった blob
+        let other = write(&mut git, GitObject::Blob(Blob::new("other".to_string())));
+        let bytes = git.read_object(&other.parse().unwrap()).unwrap();
+        let (dir, name) = blob.split_at(2);
+        git.filesystem
+            .write(format!(".git/objects/{}/{}", dir, name), &bytes)
+            .unwrap();
+        let broken = verify(&git, first.clone()).unwrap().unwrap_err();
+        assert_eq!(broken.hash, blob);
+        assert_eq!(broken.referrer, Some((ObjectType::Tree, tree)));
+        assert_eq!(broken.reason, format!("hash mismatch (actual {})", other));
+
+        // 起点が無い
+        let broken = verify(&git, missing.to_string()).unwrap().unwrap_err();
+        assert_eq!(broken.to_string(), format!("{}: missing object", missing));
+    }
+}
@@ -0,0 +1,81 @@
+use crate::object::commit::User;
+use std::fmt;
+
+// .git/logs/<ref> の1行
+// `<old> <new> <name> <<email>> <time> <offset>\t<message>`
+#[derive(Debug)]
+pub struct ReflogEntry {
+    pub old: String,
+    pub new: String,
+    pub user: User,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    pub fn new(old: String, new: String, user: User, message: String) -> Self {
+        Self {
+            old,
+            new,
+            user,
+            message,
+        }
+    }
+
+    pub fn from(line: &str) -> Option<Self> {
+        // message は無いこともある
+        let (head, message) = match line.split_once('\t') {
+            Some((head, message)) => (head, message),
+            None => (line, ""),
+        };
+
+        let mut iter = head.splitn(3, ' ');
+        let old = iter.next().filter(|x| is_hex(x))?;
+        let new = iter.next().filter(|x| is_hex(x) && x.len() == old.len())?;
+        let user = User::from(iter.next()?.as_bytes())?;
+
+        Some(Self::new(
+            old.to_string(),
+            new.to_string(),
+            user,
+            message.to_string(),
+        ))
+    }
+}
+
+fn is_hex(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|x| x.is_ascii_hexdigit())
+}
+
+impl fmt::Display for ReflogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}\t{}",
+            self.old, self.new, self.user, self.message
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reflog_entry_from() {
+        let line = "0000000000000000000000000000000000000000 0f6a66d5e5e4a1cc5b3d22e49e3ca71a5acb2ab5 user <user@example.com> 1609642799 +0900\tcommit (initial): first";
+        let entry = ReflogEntry::from(line).unwrap();
+        assert_eq!(entry.old, "0".repeat(40));
+        assert_eq!(entry.new, "0f6a66d5e5e4a1cc5b3d22e49e3ca71a5acb2ab5");
+        assert_eq!(entry.user.name, "user");
+        assert_eq!(entry.user.email, "user@example.com");
+        assert_eq!(entry.user.ts.timestamp(), 1609642799);
+        assert_eq!(entry.message, "commit (initial): first");
+        assert_eq!(entry.to_string(), line);
+
+        assert!(ReflogEntry::from("").is_none());
+        assert!(ReflogEntry::from("xyz 0f6a user <u@x> 0 +0000\tm").is_none());
+        // message が無い行
+        let entry = ReflogEntry::from("00 0f user <u@x> 0 +0000").unwrap();
+        assert_eq!(entry.message, "");
+    }
+}
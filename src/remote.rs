@@ -0,0 +1,149 @@
+use crate::config::Config;
+use std::fmt;
+
+// .git/config の `[remote "<name>"]`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Remote {
+    pub name: String,
+    pub url: String,
+    pub fetch: Vec<RefSpec>,
+}
+
+impl Remote {
+    // `git remote add` で設定される refspec を持つ remote
+    pub fn new(name: String, url: String) -> Self {
+        let fetch = RefSpec::default_fetch(&name);
+        Self {
+            name,
+            url,
+            fetch: vec![fetch],
+        }
+    }
+
+    // config から name の remote を読む. url が無ければ None
+    pub fn from(config: &Config, name: &str) -> Option<Self> {
+        let url = config.get("remote", Some(name), "url")?.to_string();
+        let fetch = config
+            .get_all("remote", Some(name), "fetch")
+            .into_iter()
+            .map(RefSpec::from)
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self {
+            name: name.to_string(),
+            url,
+            fetch,
+        })
+    }
+
+    pub fn write_to(&self, config: &mut Config) {
+        config.set("remote", Some(&self.name), "url", &self.url);
+        for refspec in self.fetch.iter() {
+            config.add("remote", Some(&self.name), "fetch", &refspec.to_string());
+        }
+    }
+}
+
+// `+refs/heads/*:refs/remotes/origin/*` のような ref の対応
+#[derive(Clone, Debug, PartialEq)]
+pub struct RefSpec {
+    // 先頭の `+`. fast-forward でなくても更新する
+    pub force: bool,
+    pub src: String,
+    pub dst: String,
+}
+
+impl RefSpec {
+    pub fn from(s: &str) -> Option<Self> {
+        let (force, s) = match s.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let mut iter = s.splitn(2, ':');
+        let src = iter.next().filter(|x| !x.is_empty())?;
+        let dst = iter.next().unwrap_or_default();
+
+        // `*` は両側に1つずつしか使えない
+        if src.matches('*').count() > 1 || src.contains('*') != dst.contains('*') {
+            return None;
+        }
+
+        Some(Self {
+            force,
+            src: src.to_string(),
+            dst: dst.to_string(),
+        })
+    }
+
+    pub fn default_fetch(remote: &str) -> Self {
+        Self {
+            force: true,
+            src: "refs/heads/*".to_string(),
+            dst: format!("refs/remotes/{}/*", remote),
+        }
+    }
+
+    // src に当てはまる ref なら対応する dst を返す
+    pub fn map(&self, name: &str) -> Option<String> {
+        match self.src.split_once('*') {
+            Some((prefix, suffix)) => {
+                let matched = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+                Some(self.dst.replacen('*', matched, 1))
+            }
+            None if name == self.src => Some(self.dst.clone()),
+            None => None,
+        }
+    }
+}
+
+impl fmt::Display for RefSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.force {
+            write!(f, "+")?;
+        }
+        write!(f, "{}", self.src)?;
+        if !self.dst.is_empty() {
+            write!(f, ":{}", self.dst)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refspec_from() {
+        let refspec = RefSpec::from("+refs/heads/*:refs/remotes/origin/*").unwrap();
+        assert!(refspec.force);
+        assert_eq!(refspec, RefSpec::default_fetch("origin"));
+        assert_eq!(refspec.to_string(), "+refs/heads/*:refs/remotes/origin/*");
+        assert_eq!(
+            refspec.map("refs/heads/feature/x"),
+            Some("refs/remotes/origin/feature/x".to_string())
+        );
+        assert_eq!(refspec.map("refs/tags/v1"), None);
+
+        let refspec = RefSpec::from("refs/heads/main:refs/heads/main").unwrap();
+        assert!(!refspec.force);
+        assert_eq!(
+            refspec.map("refs/heads/main"),
+            Some("refs/heads/main".to_string())
+        );
+
+        assert!(RefSpec::from("").is_none());
+        assert!(RefSpec::from("refs/heads/*:refs/heads/main").is_none());
+    }
+
+    #[test]
+    fn remote_config() {
+        let mut config = Config::new();
+        let remote = Remote::new("origin".to_string(), "/srv/git/repo name?a=b".to_string());
+        remote.write_to(&mut config);
+
+        let config = Config::from(&config.as_bytes()).unwrap();
+        assert_eq!(Remote::from(&config, "origin"), Some(remote));
+        assert_eq!(Remote::from(&config, "upstream"), None);
+    }
+}
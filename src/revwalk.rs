@@ -0,0 +1,334 @@
+use crate::fs::FileSystem;
+use crate::hash::ObjectId;
+use crate::object::commit::Commit;
+use crate::Git;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+use std::io;
+
+// 履歴を committer の日時が新しい順に辿るイテレータ
+// 日時が同じ場合は先に積まれたものを優先する
+pub struct RevWalk<'a, F: FileSystem> {
+    git: &'a Git<F>,
+    queue: BinaryHeap<Pending>,
+    seen: HashSet<String>,
+    hidden: HashSet<String>,
+    seq: usize,
+    error: Option<io::Error>,
+}
+
+struct Pending {
+    time: i64,
+    seq: Reverse<usize>,
+    hash: String,
+    commit: Commit,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.time, self.seq).cmp(&(other.time, other.seq))
+    }
+}
+
+impl<'a, F: FileSystem> RevWalk<'a, F> {
+    pub fn new(git: &'a Git<F>) -> Self {
+        Self {
+            git,
+            queue: BinaryHeap::new(),
+            seen: HashSet::new(),
+            hidden: HashSet::new(),
+            seq: 0,
+            error: None,
+        }
+    }
+
+    // 探索の起点を追加する
+    pub fn push(&mut self, hash: String) -> io::Result<()> {
+        if self.hidden.contains(&hash) || !self.seen.insert(hash.clone()) {
+            return Ok(());
+        }
+
+        let commit = self.git.read_commit(&hash)?;
+        self.queue.push(Pending {
+            time: commit.committer.ts.timestamp(),
+            seq: Reverse(self.seq),
+            hash,
+            commit,
+        });
+        self.seq += 1;
+
+        Ok(())
+    }
+
+    // hash とその祖先を結果から除外する (`^B` や `B..A` の B)
+    // push より先に呼び出すこと
+    pub fn hide(&mut self, hash: String) -> io::Result<()> {
+        let mut stack = vec![hash];
+        while let Some(hash) = stack.pop() {
+            // 壊れた parent で循環していても止まるように訪問済みは辿らない
+            if !self.hidden.insert(hash.clone()) {
+                continue;
+            }
+            let commit = self.git.read_commit(&hash)?;
+            stack.extend(commit.parents.iter().map(ObjectId::to_hex));
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a, F: FileSystem> Iterator for RevWalk<'a, F> {
+    type Item = io::Result<(String, Commit)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+
+        loop {
+            let pending = self.queue.pop()?;
+            if self.hidden.contains(&pending.hash) {
+                continue;
+            }
+
+            // parent が読めなくても先にこの commit を返し, エラーは次の呼び出しで返す
+            for parent in pending.commit.parents.iter() {
+                if let Err(e) = self.push(parent.to_hex()) {
+                    self.error.get_or_insert(e);
+                }
+            }
+
+            return Some(Ok((pending.hash, pending.commit)));
+        }
+    }
+}
+
+// start から parent を深さ優先で辿るイテレータ. 最初の parent の系列を先に返す
+// 日時で並べ替えないので RevWalk より軽い
+pub struct CommitWalker<'a, F: FileSystem> {
+    git: &'a Git<F>,
+    stack: Vec<String>,
+    seen: HashSet<String>,
+    limit: Option<usize>,
+    first_parent_only: bool,
+}
+
+impl<'a, F: FileSystem> CommitWalker<'a, F> {
+    pub fn new(git: &'a Git<F>, start: String) -> Self {
+        Self {
+            git,
+            stack: vec![start],
+            seen: HashSet::new(),
+            limit: None,
+            first_parent_only: false,
+        }
+    }
+
+    // 最大で limit 個の commit を返す
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    // merge commit では最初の parent だけを辿る
+    pub fn first_parent_only(mut self, first_parent_only: bool) -> Self {
+        self.first_parent_only = first_parent_only;
+        self
+    }
+}
+
+impl<'a, F: FileSystem> Iterator for CommitWalker<'a, F> {
+    type Item = io::Result<Commit>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.limit == Some(0) {
+            return None;
+        }
+
+        loop {
+            let hash = self.stack.pop()?;
+            if !self.seen.insert(hash.clone()) {
+                continue;
+            }
+
+            let commit = match self.git.read_commit(&hash) {
+                Ok(commit) => commit,
+                Err(e) => {
+                    self.stack.clear();
+                    return Some(Err(e));
+                }
+            };
+
+            let parents = &commit.parents;
+            let parents = if self.first_parent_only {
+                &parents[..parents.len().min(1)]
+            } else {
+                &parents[..]
+            };
+            // 最初の parent が先に取り出されるように逆順に積む
+            self.stack
+                .extend(parents.iter().rev().map(ObjectId::to_hex));
+
+            if let Some(limit) = self.limit.as_mut() {
+                *limit -= 1;
+            }
+            return Some(Ok(commit));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::inmem::InMemFileSystem;
+    use crate::object::commit::User;
+    use crate::object::GitObject;
+    use chrono::{FixedOffset, TimeZone};
+
+    fn commit(git: &mut Git<InMemFileSystem>, parent: Option<String>, ts: i64) -> String {
+        merge(git, &parent.into_iter().collect::<Vec<_>>(), ts)
+    }
+
+    fn merge(git: &mut Git<InMemFileSystem>, parents: &[String], ts: i64) -> String {
+        let user = User::new(
+            String::from("rgit"),
+            String::from("rgit@example.com"),
+            FixedOffset::east(0).timestamp(ts, 0),
+        );
+        let commit = GitObject::Commit(Commit::new(
+            "4b825dc642cb6eb9a060e54bf8d69288fbee4904".parse().unwrap(),
+            parents.iter().map(|x| x.parse().unwrap()).collect(),
+            user.clone(),
+            user,
+            format!("commit at {}", ts),
+        ));
+        git.write_object(&commit).unwrap();
+        hex::encode(commit.calc_hash())
+    }
+
+    #[test]
+    fn rev_walk_order() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let first = commit(&mut git, None, 1);
+        let second = commit(&mut git, Some(first.clone()), 2);
+        let third = commit(&mut git, Some(second.clone()), 3);
+
+        let mut walk = RevWalk::new(&git);
+        walk.push(third.clone()).unwrap();
+        let hashes = walk.map(|x| x.unwrap().0).collect::<Vec<_>>();
+        assert_eq!(hashes, vec![third.clone(), second.clone(), first.clone()]);
+
+        let mut walk = RevWalk::new(&git);
+        walk.hide(first).unwrap();
+        walk.push(third.clone()).unwrap();
+        let hashes = walk.map(|x| x.unwrap().0).collect::<Vec<_>>();
+        assert_eq!(hashes, vec![third, second]);
+    }
+
+    #[test]
+    fn commit_walker() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let first = commit(&mut git, None, 1);
+        let second = commit(&mut git, Some(first), 2);
+        // 日時が戻っていても parent の順に辿る
+        let third = commit(&mut git, Some(second), 1);
+
+        let messages = |walker: CommitWalker<InMemFileSystem>| {
+            walker
+                .map(|x| x.unwrap().message_text())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            messages(CommitWalker::new(&git, third.clone())),
+            vec!["commit at 1\n", "commit at 2\n", "commit at 1\n"]
+        );
+        assert_eq!(
+            messages(CommitWalker::new(&git, third.clone()).first_parent_only(true)),
+            vec!["commit at 1\n", "commit at 2\n", "commit at 1\n"]
+        );
+        assert_eq!(
+            messages(CommitWalker::new(&git, third.clone()).with_limit(2)),
+            vec!["commit at 1\n", "commit at 2\n"]
+        );
+        assert!(CommitWalker::new(&git, third)
+            .with_limit(0)
+            .next()
+            .is_none());
+    }
+
+    #[test]
+    fn commit_walker_merge() {
+        // root - a - merge
+        //      \- b -/
+        let mut git = Git::new(InMemFileSystem::init());
+        let root = commit(&mut git, None, 1);
+        let a = commit(&mut git, Some(root.clone()), 2);
+        let b = commit(&mut git, Some(root), 3);
+        let merge = merge(&mut git, &[a, b], 4);
+
+        let messages = |walker: CommitWalker<InMemFileSystem>| {
+            walker
+                .map(|x| x.unwrap().message_text())
+                .collect::<Vec<_>>()
+        };
+        // 最初の parent の系列を root まで辿ってから b に戻り, root は一度だけ返す
+        assert_eq!(
+            messages(CommitWalker::new(&git, merge.clone())),
+            vec![
+                "commit at 4\n",
+                "commit at 2\n",
+                "commit at 1\n",
+                "commit at 3\n"
+            ]
+        );
+        // b には入らない
+        assert_eq!(
+            messages(CommitWalker::new(&git, merge).first_parent_only(true)),
+            vec!["commit at 4\n", "commit at 2\n", "commit at 1\n"]
+        );
+    }
+
+    #[test]
+    fn commit_walker_missing_parent() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let orphan = commit(
+            &mut git,
+            Some(String::from("0000000000000000000000000000000000000000")),
+            1,
+        );
+
+        let mut walker = CommitWalker::new(&git, orphan);
+        assert!(walker.next().unwrap().is_ok());
+        assert!(walker.next().unwrap().is_err());
+        assert!(walker.next().is_none());
+    }
+
+    #[test]
+    fn rev_walk_missing_parent() {
+        let mut git = Git::new(InMemFileSystem::init());
+        let orphan = commit(
+            &mut git,
+            Some(String::from("0000000000000000000000000000000000000000")),
+            1,
+        );
+
+        let mut walk = RevWalk::new(&git);
+        walk.push(orphan).unwrap();
+        assert!(walk.next().unwrap().is_ok());
+        assert!(walk.next().unwrap().is_err());
+    }
+}
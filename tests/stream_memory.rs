@@ -0,0 +1,98 @@
+// global allocator を差し替えて確保したバイト数を数えるので, 他の test とは別の binary にする
+#![cfg(target_os = "linux")]
+
+use rgit::cmd;
+use rgit::fs::linux::LinuxFileSystem;
+use rgit::Git;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+// この thread で確保中のバイト数と, その最大値
+struct PeakAlloc;
+
+thread_local! {
+    static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+    static PEAK: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATED.try_with(|x| {
+            x.set(x.get() + layout.size());
+            let _ = PEAK.try_with(|peak| peak.set(peak.get().max(x.get())));
+        });
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _ = ALLOCATED.try_with(|x| x.set(x.get().saturating_sub(layout.size())));
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAlloc = PeakAlloc;
+
+// 大きなファイルを 1MB ずつ書く
+fn write_large(path: &Path, line: &[u8], len: usize) {
+    let chunk = line.repeat(1024 * 1024 / line.len() + 1);
+    let mut file = File::create(path).unwrap();
+    let mut remaining = len;
+    while remaining > 0 {
+        let n = remaining.min(1024 * 1024);
+        file.write_all(&chunk[..n]).unwrap();
+        remaining -= n;
+    }
+}
+
+// f の中で確保した memory の最大値
+fn measure<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    PEAK.with(|x| x.set(ALLOCATED.with(|x| x.get())));
+    let base = PEAK.with(|x| x.get());
+    let result = f();
+    (result, PEAK.with(|x| x.get()) - base)
+}
+
+#[test]
+fn add_large_file_memory() {
+    let len = 32 * 1024 * 1024;
+    let dir = std::env::temp_dir().join(format!("rgit-stream-memory-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir(&dir).unwrap();
+    write_large(&dir.join("large.txt"), b"0123456789abcdef\n", len);
+
+    let mut git = Git::new(LinuxFileSystem::with_root(dir.clone()));
+    git.init().unwrap();
+
+    // git add で中身を memory に載せずに blob にする
+    let (added, peak) = measure(|| cmd::add_path(&mut git, "large.txt".to_string()).unwrap());
+    assert_eq!(added, vec!["large.txt".to_string()]);
+    assert!(peak < 4 * 1024 * 1024, "peak {} bytes", peak);
+
+    let hash = git
+        .hash_object_stream(File::open(dir.join("large.txt")).unwrap(), len as u64)
+        .unwrap();
+    let index = git.load_index().unwrap();
+    assert_eq!(index.get_entry("large.txt").unwrap().hash, hash);
+    assert!(git.has_object(&hash).unwrap());
+
+    // add -A も変わった大きなファイルと新しい大きなファイルを memory に載せない
+    write_large(&dir.join("large.txt"), b"fedcba9876543210\n", len);
+    write_large(&dir.join("new.txt"), b"new\n", len);
+    let ((), peak) = measure(|| cmd::add_all(&mut git).unwrap());
+    assert!(peak < 4 * 1024 * 1024, "peak {} bytes", peak);
+
+    let index = git.load_index().unwrap();
+    for name in ["large.txt", "new.txt"] {
+        let hash = git
+            .hash_object_stream(File::open(dir.join(name)).unwrap(), len as u64)
+            .unwrap();
+        assert_eq!(index.get_entry(name).unwrap().hash, hash);
+        assert!(git.has_object(&hash).unwrap());
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}